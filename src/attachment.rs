@@ -0,0 +1,547 @@
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::{ExitStatus, Stdio},
+};
+
+use imap::{Connection, Session};
+use imap_proto::types::{
+    BodyContentCommon, BodyContentSinglePart, BodyParams, BodyStructure, ContentEncoding,
+    SectionPath,
+};
+use mail_parser::{
+    decoders::{base64::base64_decode, quoted_printable::quoted_printable_decode},
+    parsers::MessageStream,
+    HeaderValue, Message, MimeHeaders,
+};
+
+use crate::ui::Ui;
+use crate::user::User;
+use crate::*;
+
+/// Represents an attachment of a message, located by its part number in the message's BODYSTRUCTURE.
+#[derive(Clone, Debug)]
+pub struct Attachment {
+    pub part: Vec<u32>,
+    pub filename: String,
+    pub mime_type: String,
+    /// Size of the part as stored on the server, before decoding.
+    pub size: u32,
+    encoding: TransferEncoding,
+}
+
+/// Represents a part's Content-Transfer-Encoding.
+#[derive(Clone, Copy, Debug)]
+enum TransferEncoding {
+    Identity,
+    Base64,
+    QuotedPrintable,
+}
+
+impl Attachment {
+    /// Builds an `Attachment` from a single part, if the part is one.
+    fn from_part(
+        part: Vec<u32>,
+        common: &BodyContentCommon,
+        other: &BodyContentSinglePart,
+    ) -> Option<Attachment> {
+        let disposition = common.disposition.as_ref();
+        let filename = disposition
+            .and_then(|d| param(&d.params, "filename"))
+            .or_else(|| param(&common.ty.params, "name"));
+        let is_attachment = disposition.is_some_and(|d| d.ty.eq_ignore_ascii_case("attachment"));
+        if !is_attachment && filename.is_none() {
+            return None;
+        }
+
+        let mime_type = format!("{}/{}", common.ty.ty, common.ty.subtype).to_lowercase();
+        let section = part
+            .iter()
+            .map(|n| n.to_string())
+            .collect::<Vec<_>>()
+            .join(".");
+        let filename = filename
+            .unwrap_or_else(|| format!("part-{}.{}", section, common.ty.subtype.to_lowercase()));
+        let encoding = match other.transfer_encoding {
+            ContentEncoding::Base64 => TransferEncoding::Base64,
+            ContentEncoding::QuotedPrintable => TransferEncoding::QuotedPrintable,
+            _ => TransferEncoding::Identity,
+        };
+
+        Some(Attachment {
+            part,
+            filename,
+            mime_type,
+            size: other.octets,
+            encoding,
+        })
+    }
+
+    /// Returns the IMAP section specifier of the attachment, eg. "2.1".
+    pub fn section(&self) -> String {
+        self.part
+            .iter()
+            .map(|n| n.to_string())
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+
+    /// Returns the approximate size of the attachment after decoding.
+    pub fn decoded_size(&self) -> u32 {
+        match self.encoding {
+            TransferEncoding::Base64 => self.size / 4 * 3,
+            _ => self.size,
+        }
+    }
+
+    /// Decodes the part's bytes fetched from the server into the attachment's content.
+    pub fn decode(&self, data: &[u8]) -> Vec<u8> {
+        match self.encoding {
+            TransferEncoding::Identity => None,
+            TransferEncoding::Base64 => base64_decode(data),
+            TransferEncoding::QuotedPrintable => quoted_printable_decode(data),
+        }
+        .unwrap_or_else(|| data.to_vec())
+    }
+}
+
+/// Collects all attachments of a message parsed locally, numbered by their order.
+///
+/// Their content is already decoded, see `parsed_attachment_content`.
+pub fn collect_parsed_attachments(message: &Message) -> Vec<Attachment> {
+    message
+        .attachments()
+        .enumerate()
+        .map(|(i, part)| {
+            let mime_type = part
+                .content_type()
+                .map(|ct| match ct.subtype() {
+                    Some(subtype) => format!("{}/{}", ct.ctype(), subtype),
+                    None => ct.ctype().to_string(),
+                })
+                .unwrap_or_else(|| "application/octet-stream".to_string())
+                .to_lowercase();
+            Attachment {
+                part: vec![i as u32 + 1],
+                filename: part
+                    .attachment_name()
+                    .map(String::from)
+                    .unwrap_or_else(|| format!("part-{}", i + 1)),
+                mime_type,
+                size: part.contents().len() as u32,
+                encoding: TransferEncoding::Identity,
+            }
+        })
+        .collect()
+}
+
+/// Returns the decoded content of an attachment collected by `collect_parsed_attachments`.
+pub fn parsed_attachment_content(message: &Message, attachment: &Attachment) -> Vec<u8> {
+    message
+        .attachment(attachment.part[0] - 1)
+        .map(|part| part.contents().to_vec())
+        .unwrap_or_default()
+}
+
+/// Collects all attachments described by a message's BODYSTRUCTURE, in part order.
+pub fn collect_attachments(structure: &BodyStructure) -> Vec<Attachment> {
+    let mut attachments = Vec::new();
+    walk(structure, Vec::new(), &mut attachments);
+    attachments
+}
+
+/// Walks a BODYSTRUCTURE recursively, an attached message is treated as a single attachment.
+fn walk(structure: &BodyStructure, part: Vec<u32>, attachments: &mut Vec<Attachment>) {
+    match structure {
+        BodyStructure::Multipart { bodies, .. } => {
+            for (i, body) in bodies.iter().enumerate() {
+                let mut child = part.clone();
+                child.push(i as u32 + 1);
+                walk(body, child, attachments);
+            }
+        }
+        BodyStructure::Basic { common, other, .. }
+        | BodyStructure::Text { common, other, .. }
+        | BodyStructure::Message { common, other, .. } => {
+            // A non-multipart message has its only part numbered "1"
+            let part = if part.is_empty() { vec![1] } else { part };
+            attachments.extend(Attachment::from_part(part, common, other));
+        }
+    }
+}
+
+/// Looks up a MIME parameter, decoding RFC 2047 encoded-words and RFC 2231 extended values.
+fn param(params: &BodyParams, name: &str) -> Option<String> {
+    let params = params.as_ref()?;
+
+    if let Some((_, value)) = params.iter().find(|(k, _)| k.eq_ignore_ascii_case(name)) {
+        return Some(decode_encoded_words(value));
+    }
+
+    // RFC 2231: `name*=charset''value` or continuations `name*0*=...`, `name*1*=...`
+    let mut segments = params
+        .iter()
+        .filter_map(|(k, v)| {
+            let rest = k
+                .get(..name.len())?
+                .eq_ignore_ascii_case(name)
+                .then(|| &k[name.len()..])?;
+            let rest = rest.strip_prefix('*')?;
+            let extended = rest.ends_with('*') || rest.is_empty();
+            let index = rest.trim_end_matches('*').parse::<u32>().unwrap_or(0);
+            Some((index, extended, v))
+        })
+        .collect::<Vec<_>>();
+    if segments.is_empty() {
+        return None;
+    }
+    segments.sort_by_key(|&(index, _, _)| index);
+
+    let mut bytes = Vec::new();
+    for (i, (_, extended, value)) in segments.iter().enumerate() {
+        let value: &str = value;
+        if !extended {
+            bytes.extend_from_slice(value.as_bytes());
+            continue;
+        }
+        // Only the first segment carries the `charset'language'` prefix
+        let value = match (i, value.splitn(3, '\'').collect::<Vec<_>>().as_slice()) {
+            (0, [_, _, v]) => v,
+            _ => value,
+        };
+        bytes.extend(percent_decode(value));
+    }
+
+    Some(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Decodes RFC 2047 encoded-words in a header parameter value.
+pub(crate) fn decode_encoded_words(value: &str) -> String {
+    let line = format!("{}\r\n", value);
+    match MessageStream::new(line.as_bytes()).parse_unstructured() {
+        HeaderValue::Text(text) => text.into_owned(),
+        _ => value.to_string(),
+    }
+}
+
+/// Decodes `%XX` escapes, leaving malformed ones as is.
+fn percent_decode(value: &str) -> Vec<u8> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
+
+/// Turns an attachment's name from the (untrusted) message into a safe filename,
+/// stripping directories, control characters and characters reserved on common filesystems.
+pub fn safe_filename(name: &str) -> String {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let name = name
+        .chars()
+        .filter(|c| !c.is_control())
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '|' | '?' | '*' => '_',
+            c => c,
+        })
+        .collect::<String>();
+    let name = name.trim_matches(|c: char| c == '.' || c.is_whitespace());
+
+    // Keep the name within 255 bytes without splitting a character
+    let mut end = name.len().min(255);
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    let name = &name[..end];
+
+    const RESERVED: [&str; 22] = [
+        "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+        "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+    ];
+    let stem = name.split('.').next().unwrap_or_default();
+    if name.is_empty() {
+        "attachment".to_string()
+    } else if RESERVED.iter().any(|r| r.eq_ignore_ascii_case(stem)) {
+        format!("_{}", name)
+    } else {
+        name.to_string()
+    }
+}
+
+/// Saves an attachment's content into a directory under a safe filename,
+/// appending a counter to the name instead of overwriting an existing file.
+///
+/// # Returns
+///
+/// - The path of the created file if saving succeeds.
+/// - An `Err` if it fails.
+pub fn save_attachment(dir: &Path, filename: &str, data: &[u8]) -> io::Result<PathBuf> {
+    let filename = safe_filename(filename);
+    let (stem, ext) = match filename.rfind('.') {
+        Some(i) if i > 0 => (&filename[..i], &filename[i..]),
+        _ => (filename.as_str(), ""),
+    };
+
+    let mut n = 0;
+    loop {
+        let candidate = match n {
+            0 => filename.clone(),
+            n => format!("{} ({}){}", stem, n, ext),
+        };
+        let path = dir.join(candidate);
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                file.write_all(data)?;
+                return Ok(path);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => n += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Runs a shell command with an attachment's content piped to its stdin.
+///
+/// # Returns
+///
+/// - The command's exit status if it could be run.
+/// - An `Err` if it fails.
+pub fn pipe_attachment(command: &str, data: &[u8]) -> io::Result<ExitStatus> {
+    let mut child = shell_command(command).stdin(Stdio::piped()).spawn()?;
+
+    // A command may exit without reading all of its input, which is not an error
+    if let Some(mut stdin) = child.stdin.take() {
+        match stdin.write_all(data) {
+            Err(e) if e.kind() != io::ErrorKind::BrokenPipe => return Err(e),
+            _ => {}
+        }
+    }
+
+    child.wait()
+}
+
+/// Formats a byte count for humans, eg. "12.3 KiB".
+pub fn human_size(size: u32) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut size = size as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} {}", size, UNITS[unit]),
+        _ => format!("{:.1} {}", size, UNITS[unit]),
+    }
+}
+
+impl User {
+    /// Fetches the list of attachments of a message, without downloading their content.
+    pub fn fetch_attachments(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+        message: &MessageRef,
+    ) -> error::Result<Vec<Attachment>> {
+        self.select_message(imap_cli, message)?;
+        let fetches = imap_cli.uid_fetch(message.uid.to_string(), "BODYSTRUCTURE")?;
+        let structure = fetches
+            .iter()
+            .find(|m| m.uid == Some(message.uid))
+            .and_then(|m| m.bodystructure())
+            .ok_or_else(|| error::Error::MessageNotFound {
+                mailbox: message.mailbox.to_string(),
+                uid: message.uid,
+            })?;
+
+        Ok(collect_attachments(structure))
+    }
+
+    /// Fetches and decodes the content of an attachment, downloading only its own part.
+    pub fn fetch_attachment(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+        message: &MessageRef,
+        attachment: &Attachment,
+    ) -> error::Result<Vec<u8>> {
+        self.select_message(imap_cli, message)?;
+        let fetches = imap_cli.uid_fetch(
+            message.uid.to_string(),
+            format!("BODY.PEEK[{}]", attachment.section()),
+        )?;
+        let path = SectionPath::Part(attachment.part.clone(), None);
+        let data = fetches
+            .iter()
+            .find(|m| m.uid == Some(message.uid))
+            .and_then(|m| m.section(&path))
+            .ok_or_else(|| error::Error::MessageNotFound {
+                mailbox: message.mailbox.to_string(),
+                uid: message.uid,
+            })?;
+
+        Ok(attachment.decode(data))
+    }
+
+    /// Lists the attachments of a message and lets the user save or pipe them.
+    pub fn view_attachments(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+        message: &MessageRef,
+        ui: &mut dyn Ui,
+        prompts: &Prompts,
+    ) -> error::Result<()> {
+        let attachments = self.fetch_attachments(imap_cli, message)?;
+        attachment_menu(
+            &attachments,
+            |attachment| self.fetch_attachment(imap_cli, message, attachment),
+            ui,
+            prompts,
+        )
+    }
+}
+
+/// Lists attachments and lets the user save or pipe them, `fetch` provides their content.
+pub fn attachment_menu<F>(
+    attachments: &[Attachment],
+    mut fetch: F,
+    ui: &mut dyn Ui,
+    prompts: &Prompts,
+) -> error::Result<()>
+where
+    F: FnMut(&Attachment) -> error::Result<Vec<u8>>,
+{
+    if attachments.is_empty() {
+        return Ok(());
+    }
+
+    ui.show(prompts.attachment_list);
+    for (i, attachment) in attachments.iter().enumerate() {
+        ui.show(&format!(
+            "  [{}] {} ({}, {})",
+            i + 1,
+            attachment.filename,
+            attachment.mime_type,
+            human_size(attachment.decoded_size())
+        ));
+    }
+
+    let range = RangeUsize::new(1, attachments.len());
+    loop {
+        ui.show(prompts.attachment_action_list);
+        match ui.read_selection(
+            prompts.action_selection,
+            prompts.invalid_literal,
+            prompts.action_literal,
+            prompts.should_be_one_of_below_literal,
+            &RangeUsize::new(0, 3),
+        )? {
+            0 => return Ok(()),
+            1 => {
+                let attachment = &attachments[ui.read_selection(
+                    prompts.attachment_selection,
+                    prompts.invalid_literal,
+                    prompts.attachment_literal,
+                    prompts.should_be_one_of_below_literal,
+                    &range,
+                )? - 1];
+                let dir = read_dir(ui, prompts)?;
+                let data = fetch(attachment)?;
+                let path = save_attachment(&dir, &attachment.filename, &data)?;
+                ui.show(&format!("{}{}.", prompts.attachment_saved, path.display()));
+            }
+            2 => {
+                let dir = read_dir(ui, prompts)?;
+                for attachment in attachments.iter() {
+                    let data = fetch(attachment)?;
+                    let path = save_attachment(&dir, &attachment.filename, &data)?;
+                    ui.show(&format!("{}{}.", prompts.attachment_saved, path.display()));
+                }
+            }
+            3 => {
+                let attachment = &attachments[ui.read_selection(
+                    prompts.attachment_selection,
+                    prompts.invalid_literal,
+                    prompts.attachment_literal,
+                    prompts.should_be_one_of_below_literal,
+                    &range,
+                )? - 1];
+                let command = ui.read_input(prompts.attachment_command)?;
+                let data = fetch(attachment)?;
+                let status = pipe_attachment(&command, &data)?;
+                ui.show(&format!("{}{}.", prompts.attachment_command_exit, status));
+            }
+            _ => unreachable!(), // selection from `read_selection()` should have matched one of the above
+        }
+    }
+}
+
+/// Reads the directory to save attachments into, defaults to the current directory.
+pub(crate) fn read_dir(ui: &mut dyn Ui, prompts: &Prompts) -> io::Result<PathBuf> {
+    match ui.read_input(prompts.attachment_save_dir)? {
+        dir if dir.is_empty() => Ok(PathBuf::from(".")),
+        dir => Ok(PathBuf::from(dir)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::*;
+
+    #[test]
+    fn safe_filename_strips_directories() {
+        assert_eq!(safe_filename("../../etc/passwd"), "passwd");
+        assert_eq!(safe_filename("..\\..\\Windows\\win.ini"), "win.ini");
+        assert_eq!(safe_filename("/tmp/"), "attachment");
+        assert_eq!(safe_filename(".."), "attachment");
+        assert_eq!(safe_filename("..."), "attachment");
+    }
+
+    #[test]
+    fn safe_filename_replaces_what_filesystems_reject() {
+        assert_eq!(safe_filename("a<b>:c\"d|e?f*.txt"), "a_b__c_d_e_f_.txt");
+        assert_eq!(safe_filename("evil\0name\r\n.txt"), "evilname.txt");
+        assert_eq!(safe_filename(" .hidden. "), "hidden");
+        assert_eq!(safe_filename("con.txt"), "_con.txt");
+        assert_eq!(safe_filename("LPT1"), "_LPT1");
+        assert_eq!(safe_filename("CONFIG.SYS"), "CONFIG.SYS");
+    }
+
+    #[test]
+    fn safe_filename_keeps_names_within_255_bytes() {
+        let name = safe_filename(&"文".repeat(100));
+
+        assert!(name.len() <= 255);
+        assert_eq!(name, "文".repeat(85));
+    }
+
+    #[test]
+    fn save_attachment_stays_in_its_directory() {
+        let dir = env::temp_dir().join(format!("eua-attachment-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let first = save_attachment(&dir, "../escape.txt", b"one").unwrap();
+        let second = save_attachment(&dir, "escape.txt", b"two").unwrap();
+
+        assert_eq!(first, dir.join("escape.txt"));
+        assert_eq!(second, dir.join("escape (1).txt"));
+        assert_eq!(fs::read(&first).unwrap(), b"one");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
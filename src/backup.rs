@@ -0,0 +1,523 @@
+use std::{
+    collections::HashSet,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, FixedOffset, Utc};
+use imap::{types::Flag, Connection, Session};
+use sha2::{Digest, Sha256};
+
+use crate::cache::escape;
+use crate::migrate::{Folder, FolderMap};
+use crate::ui::Ui;
+use crate::user::User;
+use crate::vault::{self, VaultWriter};
+use crate::*;
+
+/// Version of the backup format written, a backup in a newer format can't be read.
+pub const BACKUP_VERSION: u32 = 1;
+
+/// First bytes of every backup, followed by its format version.
+const BACKUP_MAGIC: &str = "EUA-BACKUP";
+
+/// Represents a message stored in a backup.
+#[derive(Clone, Debug, Default)]
+pub struct BackupMessage {
+    pub folder: String,
+    pub uid: u32,
+    pub flags: Vec<String>,
+    pub internal_date: Option<DateTime<FixedOffset>>,
+    pub checksum: String,
+    pub raw: Vec<u8>,
+}
+
+impl BackupMessage {
+    /// Checks whether the message's bytes match its recorded checksum.
+    pub fn is_intact(&self) -> bool {
+        sha256_hex(&self.raw) == self.checksum
+    }
+}
+
+/// Represents a backup of a whole account.
+///
+/// A backup is one file: a header line with the format version, the account & creation
+/// date, a line per folder, a line per message followed by its raw bytes, and an end line
+/// with the message count & a SHA-256 checksum of everything before it. Each message line
+/// carries the message's own checksum. The file is sealed if a vault is unlocked.
+#[derive(Clone, Debug, Default)]
+pub struct Backup {
+    pub version: u32,
+    pub account: String,
+    pub created: String,
+    pub folders: Vec<Folder>,
+    pub messages: Vec<BackupMessage>,
+    /// Whether the end line was found & the whole file's checksum matched it.
+    pub complete: bool,
+}
+
+/// Represents the outcome of verifying a backup.
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    pub folders: usize,
+    pub messages: usize,
+    pub corrupted: Vec<(String, u32)>,
+    pub complete: bool,
+}
+
+impl VerifyReport {
+    /// Checks whether the backup is complete & none of its messages are corrupted.
+    pub fn is_ok(&self) -> bool {
+        self.complete && self.corrupted.is_empty()
+    }
+}
+
+/// Represents the outcome of restoring a backup.
+#[derive(Debug, Default)]
+pub struct RestoreReport {
+    pub folders: usize,
+    pub appended: usize,
+    pub duplicates: usize,
+}
+
+/// Formats bytes in lowercase hex.
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Formats the SHA-256 digest of data in lowercase hex.
+pub(crate) fn sha256_hex(data: &[u8]) -> String {
+    to_hex(&Sha256::digest(data))
+}
+
+/// Returns the default path of a new backup of an account, named after it & the time.
+pub fn default_backup_path(email_addr: &str) -> PathBuf {
+    PathBuf::from(format!(
+        "{}-{}.euab",
+        escape(email_addr),
+        Utc::now().format("%Y%m%d-%H%M%S")
+    ))
+}
+
+/// Writes a backup while hashing everything written.
+struct BackupWriter {
+    writer: VaultWriter,
+    hasher: Sha256,
+}
+
+impl Write for BackupWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.writer.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Reads a backup, opening it if it's sealed.
+///
+/// # Returns
+///
+/// - The backup if its structure can be read, intact or not.
+/// - An `Err` of kind `InvalidData` if it isn't a backup or its format is newer.
+/// - An `Err` if reading fails otherwise.
+pub fn read_backup(path: &Path) -> io::Result<Backup> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    let data = vault::read_file(path)?;
+    let mut backup = Backup::default();
+    let mut pos = 0;
+
+    // Takes the next line, without its line break
+    let next_line = |pos: &mut usize| -> Option<String> {
+        let rest = data.get(*pos..)?;
+        let len = rest.iter().position(|&b| b == b'\n')?;
+        *pos += len + 1;
+        Some(String::from_utf8_lossy(&rest[..len]).into_owned())
+    };
+
+    let header = next_line(&mut pos).ok_or_else(|| invalid("not a backup"))?;
+    let mut columns = header.splitn(4, '\t');
+    if columns.next() != Some(BACKUP_MAGIC) {
+        return Err(invalid("not a backup"));
+    }
+    backup.version = columns.next().and_then(|v| v.parse().ok()).unwrap_or(0);
+    if backup.version == 0 || backup.version > BACKUP_VERSION {
+        return Err(invalid("unsupported backup version"));
+    }
+    backup.account = columns.next().unwrap_or_default().to_string();
+    backup.created = columns.next().unwrap_or_default().to_string();
+
+    loop {
+        let start = pos;
+        let Some(line) = next_line(&mut pos) else {
+            break;
+        };
+        let columns = line.split('\t').collect::<Vec<_>>();
+        match columns[..] {
+            ["folder", delimiter, selectable, name] => backup.folders.push(Folder {
+                name: name.to_string(),
+                delimiter: Some(delimiter.to_string()).filter(|d| !d.is_empty()),
+                selectable: selectable == "1",
+            }),
+            ["message", uid, internal_date, flags, len, checksum, folder] => {
+                let len = len
+                    .parse::<usize>()
+                    .map_err(|_| invalid("bad message length"))?;
+                let end = pos
+                    .checked_add(len)
+                    .ok_or_else(|| invalid("bad message length"))?;
+                let Some(raw) = data.get(pos..end) else {
+                    break;
+                };
+                pos = end + 1;
+                backup.messages.push(BackupMessage {
+                    folder: folder.to_string(),
+                    uid: uid.parse().unwrap_or_default(),
+                    flags: flags.split_whitespace().map(String::from).collect(),
+                    internal_date: DateTime::parse_from_rfc3339(internal_date).ok(),
+                    checksum: checksum.to_string(),
+                    raw: raw.to_vec(),
+                });
+            }
+            ["end", count, checksum] => {
+                backup.complete = count.parse() == Ok(backup.messages.len())
+                    && sha256_hex(&data[..start]) == checksum;
+                break;
+            }
+            _ => return Err(invalid("malformed backup")),
+        }
+    }
+
+    Ok(backup)
+}
+
+/// Verifies a backup offline: its structure, its checksum & every message's checksum.
+pub fn verify_backup(path: &Path) -> io::Result<VerifyReport> {
+    let backup = read_backup(path)?;
+    Ok(VerifyReport {
+        folders: backup.folders.len(),
+        messages: backup.messages.len(),
+        corrupted: backup
+            .messages
+            .iter()
+            .filter(|m| !m.is_intact())
+            .map(|m| (m.folder.clone(), m.uid))
+            .collect(),
+        complete: backup.complete,
+    })
+}
+
+impl User {
+    /// Backs up every folder of the account into one file, replacing an existing one.
+    ///
+    /// The folder hierarchy, and each message's raw bytes, flags, keywords & internal date
+    /// are kept. `on_folder` is called with each folder's name & number of messages.
+    ///
+    /// # Returns
+    ///
+    /// - The number of backed up messages if the process succeeds.
+    /// - An `Err` if it fails.
+    pub fn backup_account<F>(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+        path: &Path,
+        mut on_folder: F,
+    ) -> error::Result<usize>
+    where
+        F: FnMut(&str, usize),
+    {
+        let folders = self.list_folders(imap_cli)?;
+        let mut writer = BackupWriter {
+            writer: VaultWriter::create(path)?,
+            hasher: Sha256::new(),
+        };
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}",
+            BACKUP_MAGIC,
+            BACKUP_VERSION,
+            self.email_addr,
+            Utc::now().to_rfc3339()
+        )?;
+        for folder in folders.iter() {
+            writeln!(
+                writer,
+                "folder\t{}\t{}\t{}",
+                folder.delimiter.as_deref().unwrap_or_default(),
+                folder.selectable as u8,
+                folder.name
+            )?;
+        }
+
+        let mut count = 0;
+        for folder in folders.iter().filter(|f| f.selectable) {
+            let messages = self.list_messages(imap_cli, &folder.name)?;
+            self.fetch_archived_messages(imap_cli, &messages, |entry, raw| {
+                writeln!(
+                    writer,
+                    "message\t{}\t{}\t{}\t{}\t{}\t{}",
+                    entry.uid,
+                    entry
+                        .internal_date
+                        .map(|d| d.to_rfc3339())
+                        .unwrap_or_default(),
+                    entry.flags.join(" "),
+                    raw.len(),
+                    sha256_hex(raw),
+                    folder.name
+                )?;
+                writer.write_all(raw)?;
+                writer.write_all(b"\n")?;
+                count += 1;
+                Ok(())
+            })?;
+            on_folder(&folder.name, messages.len());
+        }
+
+        let checksum = to_hex(&writer.hasher.finalize());
+        writeln!(writer.writer, "end\t{}\t{}", count, checksum)?;
+        writer.writer.finish()?;
+        Ok(count)
+    }
+
+    /// Restores a backup into the account, which may be on any IMAP server.
+    ///
+    /// The backup is verified first & nothing is restored if it's damaged. Folders are mapped
+    /// with `map` from the backup's delimiters to the server's & created when missing, messages
+    /// whose "Message-ID" already exists in their folder are skipped, so an interrupted
+    /// restore can simply be run again.
+    pub fn restore_account(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+        path: &Path,
+        map: &FolderMap,
+    ) -> error::Result<RestoreReport> {
+        let backup = read_backup(path)?;
+        if !backup.complete || backup.messages.iter().any(|m| !m.is_intact()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "backup is damaged").into());
+        }
+
+        let mut report = RestoreReport::default();
+        let mut existing = self
+            .list_folders(imap_cli)?
+            .into_iter()
+            .map(|f| f.name)
+            .collect::<HashSet<_>>();
+        for folder in backup.folders.iter() {
+            let target = map.map(&folder.name, folder.delimiter.as_deref());
+            if existing.insert(target.clone()) {
+                imap_cli.create(&target)?;
+            }
+            report.folders += 1;
+            if !folder.selectable {
+                continue;
+            }
+
+            let present = self.list_messages(imap_cli, &target)?;
+            let mut message_ids = self
+                .fetch_summaries(imap_cli, &present)?
+                .into_iter()
+                .filter_map(|s| s.message_id)
+                .collect::<HashSet<_>>();
+            for message in backup.messages.iter().filter(|m| m.folder == folder.name) {
+                let message_id = MessageSummary::parse(message.uid, &message.raw).message_id;
+                if message_id.is_some_and(|id| !message_ids.insert(id)) {
+                    report.duplicates += 1;
+                    continue;
+                }
+                let mut append = imap_cli.append(&target, &message.raw);
+                append.flags(message.flags.iter().map(|f| Flag::from(f.as_str())));
+                if let Some(date) = message.internal_date {
+                    append.internal_date(date);
+                }
+                append.finish()?;
+                report.appended += 1;
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Backs up the account into a file chosen by the user, showing progress.
+    ///
+    /// # Returns
+    ///
+    /// - The number of backed up messages & the backup's path if the process succeeds.
+    /// - An `Err` if it fails.
+    pub fn backup(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+        ui: &mut dyn Ui,
+        prompts: &Prompts,
+    ) -> error::Result<(usize, PathBuf)> {
+        let path = match ui.read_input(prompts.backup_path)? {
+            path if path.is_empty() => default_backup_path(self.email_addr.as_ref()),
+            path => PathBuf::from(path),
+        };
+        let count = self.backup_account(imap_cli, &path, |folder, count| {
+            ui.show(&format!(
+                "{}\"{}\": {}.",
+                prompts.backup_folder, folder, count
+            ));
+        })?;
+        Ok((count, path))
+    }
+
+    /// Restores a backup chosen by the user into the account.
+    pub fn restore(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+        ui: &mut dyn Ui,
+        prompts: &Prompts,
+    ) -> error::Result<RestoreReport> {
+        let path = PathBuf::from(ui.read_input(prompts.restore_path)?);
+        let parent = ui.read_input(prompts.migrate_parent)?;
+        let delimiter = self.hierarchy_delimiter(imap_cli)?;
+        let map = FolderMap::new("", &parent, delimiter);
+        ui.show(prompts.restore_restoring);
+        self.restore_account(imap_cli, &path, &map)
+    }
+}
+
+/// Verifies a backup chosen by the user & shows the outcome.
+pub fn verify(ui: &mut dyn Ui, prompts: &Prompts) -> io::Result<()> {
+    let report = verify_backup(Path::new(&ui.read_input(prompts.verify_path)?))?;
+    for (folder, uid) in report.corrupted.iter() {
+        ui.show(&format!(
+            "{}\"{}\" UID {}.",
+            prompts.verify_corrupted, folder, uid
+        ));
+    }
+    if !report.complete {
+        ui.show(prompts.verify_incomplete);
+    }
+    if report.is_ok() {
+        ui.show(&format!(
+            "{}{}{}{}.",
+            prompts.verify_ok_folders, report.folders, prompts.verify_ok_messages, report.messages
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::*;
+
+    const FIRST: &[u8] = b"Subject: One\r\n\r\nfirst\r\n";
+    const SECOND: &[u8] = b"Subject: Two\r\n\r\nsecond\nmessage\ttabbed\r\n";
+
+    /// Writes a backup the way `backup_account()` does, into a file named after `name`.
+    fn write_backup(name: &str) -> PathBuf {
+        let mut data = format!(
+            "{}\t{}\tme@example.test\t2024-05-01T10:00:00+00:00\n",
+            BACKUP_MAGIC, BACKUP_VERSION
+        )
+        .into_bytes();
+        data.extend_from_slice(b"folder\t/\t1\tINBOX\nfolder\t/\t0\tProjects\n");
+        for (uid, folder, raw) in [(3, "INBOX", FIRST), (9, "Projects/2024", SECOND)] {
+            data.extend(
+                format!(
+                    "message\t{}\t2024-04-30T08:00:00+02:00\t\\Seen \\Flagged\t{}\t{}\t{}\n",
+                    uid,
+                    raw.len(),
+                    sha256_hex(raw),
+                    folder
+                )
+                .into_bytes(),
+            );
+            data.extend_from_slice(raw);
+            data.push(b'\n');
+        }
+        let end = format!("end\t2\t{}\n", sha256_hex(&data));
+        data.extend(end.into_bytes());
+
+        let path = env::temp_dir().join(format!("eua-{}-{}.euab", name, process::id()));
+        fs::write(&path, data).unwrap();
+        path
+    }
+
+    #[test]
+    fn reads_back_what_was_written() {
+        let path = write_backup("read");
+
+        let backup = read_backup(&path).unwrap();
+
+        assert!(backup.complete);
+        assert_eq!(backup.version, BACKUP_VERSION);
+        assert_eq!(backup.account, "me@example.test");
+        assert_eq!(backup.folders.len(), 2);
+        assert_eq!(backup.folders[0].delimiter.as_deref(), Some("/"));
+        assert!(backup.folders[0].selectable);
+        assert!(!backup.folders[1].selectable);
+        let second = &backup.messages[1];
+        assert_eq!((second.folder.as_str(), second.uid), ("Projects/2024", 9));
+        assert_eq!(second.raw, SECOND);
+        assert_eq!(second.flags, ["\\Seen", "\\Flagged"]);
+        assert_eq!(
+            second.internal_date.unwrap().to_rfc3339(),
+            "2024-04-30T08:00:00+02:00"
+        );
+        assert!(verify_backup(&path).unwrap().is_ok());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn verifying_finds_corrupted_messages() {
+        let path = write_backup("corrupted");
+        let mut data = fs::read(&path).unwrap();
+        let at = data.windows(6).position(|w| w == b"second").unwrap();
+        data[at] = b'S';
+        fs::write(&path, data).unwrap();
+
+        let report = verify_backup(&path).unwrap();
+
+        assert!(!report.is_ok());
+        assert!(!report.complete);
+        assert_eq!(report.messages, 2);
+        assert_eq!(report.corrupted, [("Projects/2024".to_string(), 9)]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn verifying_finds_truncated_backups() {
+        let path = write_backup("truncated");
+        let data = fs::read(&path).unwrap();
+        let at = data.windows(6).position(|w| w == b"second").unwrap();
+        fs::write(&path, &data[..at]).unwrap();
+
+        let report = verify_backup(&path).unwrap();
+
+        assert!(!report.complete);
+        assert_eq!(report.messages, 1);
+        assert!(report.corrupted.is_empty());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn rejects_other_files_and_newer_formats() {
+        let path = env::temp_dir().join(format!("eua-not-backup-{}.euab", process::id()));
+        fs::write(&path, b"From someone\nhello\n").unwrap();
+        assert_eq!(
+            read_backup(&path).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        let newer = format!(
+            "{}\t{}\tme@example.test\t\n",
+            BACKUP_MAGIC,
+            BACKUP_VERSION + 1
+        );
+        fs::write(&path, newer).unwrap();
+        assert_eq!(
+            read_backup(&path).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        fs::remove_file(path).unwrap();
+    }
+}
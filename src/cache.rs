@@ -0,0 +1,643 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+    str,
+};
+
+use imap::{
+    types::{Flag, UnsolicitedResponse},
+    Connection, Session,
+};
+use lettre::Address;
+use mail_parser::MessageParser;
+
+use crate::attachment::{attachment_menu, collect_parsed_attachments, parsed_attachment_content};
+use crate::thread::{read_thread_selection, thread_locally};
+use crate::ui::Ui;
+use crate::user::{uid_set, User};
+use crate::*;
+
+/// Number of messages downloaded per `UID FETCH` while syncing.
+const SYNC_BATCH: usize = 50;
+
+/// Name of the file keeping a cached mailbox's sync state.
+const STATE_FILE: &str = ".eua-state";
+
+/// Separator between a Maildir file's unique name and its info, ':' is not allowed on Windows.
+const INFO_SEPARATOR: char = if cfg!(windows) { '!' } else { ':' };
+
+/// Maildir info letters of IMAP system flags, in the ASCII order Maildir requires.
+const FLAG_LETTERS: [(char, &str); 5] = [
+    ('D', "\\Draft"),
+    ('F', "\\Flagged"),
+    ('R', "\\Answered"),
+    ('S', "\\Seen"),
+    ('T', "\\Deleted"),
+];
+
+/// Converts IMAP flags into Maildir info letters, flags without a letter are dropped.
+pub fn maildir_flags(flags: &[Flag]) -> String {
+    let flags = flags.iter().map(|f| f.to_string()).collect::<Vec<_>>();
+    FLAG_LETTERS
+        .iter()
+        .filter(|(_, flag)| flags.iter().any(|f| f == flag))
+        .map(|&(letter, _)| letter)
+        .collect()
+}
+
+/// Converts Maildir info letters into IMAP flags.
+pub fn imap_flags(letters: &str) -> Vec<&'static str> {
+    FLAG_LETTERS
+        .iter()
+        .filter(|(letter, _)| letters.contains(*letter))
+        .map(|&(_, flag)| flag)
+        .collect()
+}
+
+/// Sets or clears a Maildir info letter, keeping the letters in order.
+pub(crate) fn with_flag(letters: &str, letter: char, set: bool) -> String {
+    FLAG_LETTERS
+        .iter()
+        .map(|&(l, _)| l)
+        .filter(|&l| {
+            if l == letter {
+                set
+            } else {
+                letters.contains(l)
+            }
+        })
+        .collect()
+}
+
+/// Escapes a name into a single safe path component.
+pub(crate) fn escape(name: &str) -> String {
+    name.bytes()
+        .enumerate()
+        .map(|(i, b)| match b {
+            b'.' if i > 0 => ".".to_string(),
+            b if b.is_ascii_alphanumeric() || b"-_@".contains(&b) => (b as char).to_string(),
+            b => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Reverses `escape()`.
+fn unescape(name: &str) -> String {
+    let bytes = name.as_bytes();
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| str::from_utf8(h).ok());
+        match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+            Some(b) if bytes[i] == b'%' => {
+                unescaped.push(b);
+                i += 3;
+            }
+            _ => {
+                unescaped.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&unescaped).into_owned()
+}
+
+/// Represents the local Maildir cache of an account, one Maildir per mailbox.
+pub struct Cache {
+    root: PathBuf,
+    pub account: String,
+}
+
+impl Cache {
+    /// Opens the cache of an account, creating it if needed.
+    pub fn open(email_addr: &Address) -> io::Result<Cache> {
+        let root = data_dir().join("cache").join(escape(email_addr.as_ref()));
+        fs::create_dir_all(&root)?;
+        Ok(Cache {
+            root,
+            account: email_addr.to_string(),
+        })
+    }
+
+    /// Opens the caches of all accounts.
+    pub fn all() -> io::Result<Vec<Cache>> {
+        let dir = data_dir().join("cache");
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let mut caches = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                caches.push(Cache {
+                    root: entry.path(),
+                    account: unescape(&entry.file_name().to_string_lossy()),
+                });
+            }
+        }
+        caches.sort_by(|a, b| a.account.cmp(&b.account));
+        Ok(caches)
+    }
+
+    /// Lists the names of cached mailboxes.
+    pub fn mailboxes(&self) -> io::Result<Vec<String>> {
+        let mut names = Vec::new();
+        for entry in fs::read_dir(&self.root)? {
+            let state = match vault::read_file(&entry?.path().join(STATE_FILE)) {
+                Ok(state) => String::from_utf8_lossy(&state).into_owned(),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            names.extend(
+                state
+                    .lines()
+                    .find_map(|l| l.strip_prefix("mailbox "))
+                    .map(String::from),
+            );
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Opens a cached mailbox, creating it if needed.
+    pub fn mailbox(&self, name: &str) -> io::Result<CachedMailbox> {
+        let dir = self.root.join(escape(name));
+        for sub in ["cur", "new", "tmp"] {
+            fs::create_dir_all(dir.join(sub))?;
+        }
+        let mut mailbox = CachedMailbox::load(&dir)?;
+        mailbox.name = name.to_string();
+        Ok(mailbox)
+    }
+}
+
+/// Represents a cached mailbox, stored as a Maildir.
+///
+/// A message's flags live in its Maildir filename, flags changed locally differ from the
+/// ones last synced with the server until the next sync pushes them.
+pub struct CachedMailbox {
+    dir: PathBuf,
+    pub name: String,
+    pub uid_validity: u32,
+    pub highest_mod_seq: Option<u64>,
+    synced: BTreeMap<u32, String>,
+    files: BTreeMap<u32, PathBuf>,
+}
+
+impl CachedMailbox {
+    /// Loads a cached mailbox's state and scans its messages.
+    fn load(dir: &Path) -> io::Result<CachedMailbox> {
+        let mut mailbox = CachedMailbox {
+            dir: dir.to_path_buf(),
+            name: String::new(),
+            uid_validity: 0,
+            highest_mod_seq: None,
+            synced: BTreeMap::new(),
+            files: BTreeMap::new(),
+        };
+
+        let state = match vault::read_file(&dir.join(STATE_FILE)) {
+            Ok(state) => String::from_utf8_lossy(&state).into_owned(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        for line in state.lines() {
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "uidvalidity" => mailbox.uid_validity = value.parse().unwrap_or_default(),
+                "highestmodseq" => mailbox.highest_mod_seq = value.parse().ok(),
+                "flags" => {
+                    let (uid, letters) = value.split_once(' ').unwrap_or((value, ""));
+                    if let Ok(uid) = uid.parse() {
+                        mailbox.synced.insert(uid, letters.to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        for sub in ["new", "cur"] {
+            for entry in fs::read_dir(dir.join(sub))? {
+                let path = entry?.path();
+                let uid = path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .and_then(|name| name.split('.').next())
+                    .and_then(|uid| uid.parse().ok());
+                if let Some(uid) = uid {
+                    mailbox.files.insert(uid, path);
+                }
+            }
+        }
+
+        Ok(mailbox)
+    }
+
+    /// Saves the mailbox's sync state.
+    pub fn save(&self) -> io::Result<()> {
+        let mut state = format!("mailbox {}\nuidvalidity {}\n", self.name, self.uid_validity);
+        if let Some(mod_seq) = self.highest_mod_seq {
+            state += &format!("highestmodseq {}\n", mod_seq);
+        }
+        for (uid, letters) in self.synced.iter() {
+            state += &format!("flags {} {}\n", uid, letters);
+        }
+
+        // Write & rename, so an interrupted save never leaves a truncated state behind
+        let tmp = self.dir.join("tmp").join(STATE_FILE);
+        vault::write_file(&tmp, state.as_bytes())?;
+        fs::rename(tmp, self.dir.join(STATE_FILE))
+    }
+
+    /// Returns the UIDs of cached messages in ascending order.
+    pub fn uids(&self) -> Vec<u32> {
+        self.files.keys().copied().collect()
+    }
+
+    /// Returns the greatest cached UID, or 0 if the mailbox is empty.
+    pub fn last_uid(&self) -> u32 {
+        self.files.keys().next_back().copied().unwrap_or(0)
+    }
+
+    /// Returns a cached message's current flags as Maildir info letters.
+    pub fn flags(&self, uid: u32) -> Option<String> {
+        let name = self.files.get(&uid)?.file_name()?.to_str()?;
+        let (_, info) = name.split_once(INFO_SEPARATOR)?;
+        Some(info.strip_prefix("2,").unwrap_or_default().to_string())
+    }
+
+    /// Returns a cached message's flags as last synced with the server.
+    pub fn synced_flags(&self, uid: u32) -> Option<&str> {
+        self.synced.get(&uid).map(|s| s.as_str())
+    }
+
+    /// Reads a cached message's raw bytes.
+    pub fn read(&self, uid: u32) -> io::Result<Vec<u8>> {
+        match self.files.get(&uid) {
+            Some(path) => vault::read_file(path),
+            None => Err(io::Error::from(io::ErrorKind::NotFound)),
+        }
+    }
+
+    fn file_path(&self, uid: u32, letters: &str) -> PathBuf {
+        self.dir
+            .join("cur")
+            .join(format!("{}.eua{}2,{}", uid, INFO_SEPARATOR, letters))
+    }
+
+    /// Stores a message fetched from the server with its flags.
+    pub fn insert(&mut self, uid: u32, letters: &str, raw: &[u8]) -> io::Result<()> {
+        // Maildir delivery: write into "tmp", then move into place
+        let tmp = self.dir.join("tmp").join(uid.to_string());
+        vault::write_file(&tmp, raw)?;
+        let path = self.file_path(uid, letters);
+        fs::rename(tmp, &path)?;
+        if let Some(old) = self.files.insert(uid, path.clone()) {
+            if old != path {
+                fs::remove_file(old)?;
+            }
+        }
+        self.synced.insert(uid, letters.to_string());
+        Ok(())
+    }
+
+    /// Changes a cached message's flags locally, they are pushed to the server on the next sync.
+    pub fn set_flags(&mut self, uid: u32, letters: &str) -> io::Result<()> {
+        let Some(old) = self.files.get(&uid) else {
+            return Err(io::Error::from(io::ErrorKind::NotFound));
+        };
+        let path = self.file_path(uid, letters);
+        if *old != path {
+            fs::rename(old, &path)?;
+            self.files.insert(uid, path);
+        }
+        Ok(())
+    }
+
+    /// Records flags as in sync with the server, changing them locally as well.
+    fn set_synced_flags(&mut self, uid: u32, letters: &str) -> io::Result<()> {
+        self.set_flags(uid, letters)?;
+        self.synced.insert(uid, letters.to_string());
+        Ok(())
+    }
+
+    /// Removes a message from the cache.
+    pub fn remove(&mut self, uid: u32) -> io::Result<()> {
+        self.synced.remove(&uid);
+        match self.files.remove(&uid) {
+            Some(path) => fs::remove_file(path),
+            None => Ok(()),
+        }
+    }
+
+    /// Drops every cached message, as UIDs are stale after the mailbox's UIDVALIDITY changed.
+    pub fn clear(&mut self, uid_validity: u32) -> io::Result<()> {
+        for uid in self.uids() {
+            self.remove(uid)?;
+        }
+        self.uid_validity = uid_validity;
+        self.highest_mod_seq = None;
+        Ok(())
+    }
+
+    /// Parses the summaries of cached messages, in ascending UID order.
+    pub fn summaries(&self) -> io::Result<Vec<MessageSummary>> {
+        self.uids()
+            .into_iter()
+            .map(|uid| Ok(MessageSummary::parse(uid, &self.read(uid)?)))
+            .collect()
+    }
+}
+
+/// Represents the outcome of syncing a mailbox.
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    pub fetched: usize,
+    pub updated: usize,
+    pub removed: usize,
+    pub pushed: usize,
+}
+
+impl User {
+    /// Syncs a mailbox into the cache incrementally.
+    ///
+    /// Flags changed offline are pushed first. Flag changes & expunges on the server are then
+    /// pulled using CONDSTORE/QRESYNC when the server offers them, or by comparing UID ranges
+    /// otherwise, and finally messages newer than the cached ones are downloaded.
+    pub fn sync_mailbox(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+        cache: &Cache,
+        mailbox: &str,
+    ) -> error::Result<SyncReport> {
+        let mut cached = cache.mailbox(mailbox)?;
+        let mut report = SyncReport::default();
+
+        // Servers may refuse ENABLE once a mailbox has been selected, CONDSTORE is used then
+        let capabilities = imap_cli.capabilities()?;
+        let qresync = capabilities.has_str("QRESYNC")
+            && imap_cli.run_command_and_check_ok("ENABLE QRESYNC").is_ok();
+        let condstore = qresync || capabilities.has_str("CONDSTORE");
+
+        // The cache knows its own UIDVALIDITY, a change within the session is checked below
+        let selected = match self.select_mailbox(imap_cli, mailbox) {
+            Err(error::Error::UidValidityChanged { .. }) => {
+                self.select_mailbox(imap_cli, mailbox)?
+            }
+            selected => selected?,
+        };
+        let uid_validity = selected.uid_validity.unwrap_or(0);
+        if cached.uid_validity != uid_validity {
+            cached.clear(uid_validity)?;
+        }
+        let mod_seq = cached.highest_mod_seq.filter(|_| condstore);
+
+        // Push flags changed offline
+        for uid in cached.uids() {
+            let local = cached.flags(uid).unwrap_or_default();
+            let synced = cached.synced_flags(uid).unwrap_or_default().to_string();
+            if local == synced {
+                continue;
+            }
+            let added = imap_flags(&local)
+                .into_iter()
+                .filter(|f| !imap_flags(&synced).contains(f))
+                .collect::<Vec<_>>();
+            let removed = imap_flags(&synced)
+                .into_iter()
+                .filter(|f| !imap_flags(&local).contains(f))
+                .collect::<Vec<_>>();
+            if !added.is_empty() {
+                imap_cli.uid_store(
+                    uid.to_string(),
+                    format!("+FLAGS.SILENT ({})", added.join(" ")),
+                )?;
+            }
+            if !removed.is_empty() {
+                imap_cli.uid_store(
+                    uid.to_string(),
+                    format!("-FLAGS.SILENT ({})", removed.join(" ")),
+                )?;
+            }
+            cached.set_synced_flags(uid, &local)?;
+            report.pushed += 1;
+        }
+
+        // Pull flag changes & expunges of cached messages
+        let known = cached.uids().into_iter().collect::<BTreeSet<_>>();
+        if let Some(&last_uid) = known.last() {
+            let range = format!("1:{}", last_uid);
+            let query = match mod_seq {
+                Some(mod_seq) if qresync => {
+                    format!("(UID FLAGS) (CHANGEDSINCE {} VANISHED)", mod_seq)
+                }
+                Some(mod_seq) => format!("(UID FLAGS) (CHANGEDSINCE {})", mod_seq),
+                None => "(UID FLAGS)".to_string(),
+            };
+            let changes = imap_cli.uid_fetch(&range, query)?;
+            for change in changes.iter() {
+                let Some(uid) = change.uid.filter(|uid| known.contains(uid)) else {
+                    continue;
+                };
+                let letters = maildir_flags(change.flags());
+                if cached.flags(uid).as_deref() != Some(letters.as_str()) {
+                    cached.set_synced_flags(uid, &letters)?;
+                    report.updated += 1;
+                }
+            }
+
+            let vanished = if qresync && mod_seq.is_some() {
+                imap_cli
+                    .take_all_unsolicited()
+                    .filter_map(|response| match response {
+                        UnsolicitedResponse::Vanished { uids, .. } => Some(uids),
+                        _ => None,
+                    })
+                    .flatten()
+                    .flat_map(|range| known.range(range).copied())
+                    .collect::<Vec<_>>()
+            } else {
+                let existing = imap_cli
+                    .uid_search(format!("UID {}", range))?
+                    .into_iter()
+                    .collect::<HashSet<_>>();
+                known
+                    .iter()
+                    .copied()
+                    .filter(|uid| !existing.contains(uid))
+                    .collect()
+            };
+            for uid in vanished {
+                cached.remove(uid)?;
+                report.removed += 1;
+            }
+        }
+
+        // Download new messages, without marking them as seen
+        let messages = self.list_messages_after(imap_cli, mailbox, cached.last_uid())?;
+        for batch in messages.chunks(SYNC_BATCH) {
+            let uids = batch.iter().map(|m| m.uid).collect::<Vec<_>>();
+            for message in imap_cli
+                .uid_fetch(uid_set(&uids), "(UID FLAGS BODY.PEEK[])")?
+                .iter()
+            {
+                if let (Some(uid), Some(body)) = (message.uid, message.body()) {
+                    cached.insert(uid, &maildir_flags(message.flags()), body)?;
+                    report.fetched += 1;
+                }
+            }
+        }
+
+        cached.highest_mod_seq = selected.highest_mod_seq.filter(|_| condstore);
+        cached.save()?;
+        Ok(report)
+    }
+
+    /// Syncs mailboxes chosen by the user into the offline cache.
+    pub fn sync_cache(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+        ui: &mut dyn Ui,
+        prompts: &Prompts,
+    ) -> error::Result<()> {
+        ui.show(prompts.fetch_mailbox);
+        let mailboxes = self.list_mailboxes(imap_cli)?;
+        for (i, mailbox) in mailboxes.iter().enumerate() {
+            ui.show(&format!("  [{}] {}", i + 1, mailbox));
+        }
+        let selections = ui.read_selections(
+            prompts.cache_mailbox_selection,
+            prompts.invalid_literal,
+            prompts.fetch_mailbox_literal,
+            prompts.should_be_one_of_below_literal,
+            &RangeUsize::new(1, mailboxes.len()),
+        )?;
+
+        let cache = Cache::open(&self.email_addr)?;
+        for selection in selections {
+            let mailbox = &mailboxes[selection - 1];
+            ui.show(&format!("{}\"{}\"...", prompts.cache_syncing, mailbox));
+            match self.sync_mailbox(imap_cli, &cache, mailbox) {
+                Ok(report) => ui.show(&format!(
+                    "{}{}{}{}{}{}{}{}.",
+                    prompts.cache_sync_fetched,
+                    report.fetched,
+                    prompts.cache_sync_updated,
+                    report.updated,
+                    prompts.cache_sync_removed,
+                    report.removed,
+                    prompts.cache_sync_pushed,
+                    report.pushed
+                )),
+                Err(e) => ui.show(&format!(
+                    "{}\"{}\": {:?}",
+                    prompts.cache_sync_fail, mailbox, e
+                )),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Lets the user choose a cached message and read its conversation, without connecting.
+pub fn browse_cache(cache: &Cache, ui: &mut dyn Ui, prompts: &Prompts) -> error::Result<()> {
+    let mailboxes = cache.mailboxes()?;
+    if mailboxes.is_empty() {
+        ui.show(prompts.cache_empty);
+        return Ok(());
+    }
+    ui.show(prompts.fetch_mailbox);
+    for (i, mailbox) in mailboxes.iter().enumerate() {
+        ui.show(&format!("  [{}] {}", i + 1, mailbox));
+    }
+    let mailbox = &mailboxes[ui.read_selection(
+        prompts.fetch_mailbox_selection,
+        prompts.invalid_literal,
+        prompts.fetch_mailbox_literal,
+        prompts.should_be_one_of_below_literal,
+        &RangeUsize::new(1, mailboxes.len()),
+    )? - 1];
+
+    browse_cached_mailbox(&mut cache.mailbox(mailbox)?, ui, prompts)
+}
+
+/// Lets the user choose a message of a cached mailbox and read its conversation.
+pub fn browse_cached_mailbox(
+    cached: &mut CachedMailbox,
+    ui: &mut dyn Ui,
+    prompts: &Prompts,
+) -> error::Result<()> {
+    let summaries = cached.summaries()?;
+    if summaries.is_empty() {
+        ui.show(&format!(
+            "> \"{}\"{}",
+            cached.name, prompts.fetch_mailbox_empty
+        ));
+        return Ok(());
+    }
+    let threads = thread_locally(&summaries);
+    let (uids, position) = read_thread_selection(ui, &threads, &summaries, prompts)?;
+    view_cached_conversation(cached, &uids, position, ui, prompts)
+}
+
+/// Shows a cached message and lets the user step through its conversation and change its flags.
+pub fn view_cached_conversation(
+    cached: &mut CachedMailbox,
+    uids: &[u32],
+    mut position: usize,
+    ui: &mut dyn Ui,
+    prompts: &Prompts,
+) -> error::Result<()> {
+    let mut shown = None;
+    loop {
+        let uid = uids[position];
+        if shown != Some(uid) {
+            let raw = cached.read(uid)?;
+            // Reading a message marks it as seen, like fetching it from the server does
+            let letters = cached.flags(uid).unwrap_or_default();
+            cached.set_flags(uid, &with_flag(&letters, 'S', true))?;
+
+            show_body(ui, String::from_utf8_lossy(&raw).into_owned(), prompts);
+            if let Some(message) = MessageParser::default().parse(&raw) {
+                let attachments = collect_parsed_attachments(&message);
+                let fetch = |attachment: &_| Ok(parsed_attachment_content(&message, attachment));
+                if let Err(e) = attachment_menu(&attachments, fetch, ui, prompts) {
+                    ui.show_error(&format!("{}{:?}", prompts.attachment_fail, e));
+                }
+            }
+            shown = Some(uid);
+        }
+
+        let letters = cached.flags(uid).unwrap_or_default();
+        ui.show(&format!(
+            "{}{}/{}.",
+            prompts.conversation_position,
+            position + 1,
+            uids.len()
+        ));
+        ui.show(&format!(
+            "{}{}",
+            prompts.cache_flags,
+            imap_flags(&letters).join(" ")
+        ));
+        ui.show(prompts.cache_action_list);
+        match ui.read_selection(
+            prompts.action_selection,
+            prompts.invalid_literal,
+            prompts.action_literal,
+            prompts.should_be_one_of_below_literal,
+            &RangeUsize::new(0, 4),
+        )? {
+            0 => return Ok(()),
+            1 => position = position.saturating_sub(1),
+            2 => position = (position + 1).min(uids.len() - 1),
+            3 => cached.set_flags(uid, &with_flag(&letters, 'S', !letters.contains('S')))?,
+            4 => cached.set_flags(uid, &with_flag(&letters, 'F', !letters.contains('F')))?,
+            _ => unreachable!(), // selection from `read_selection()` should have matched one of the above
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use std::{error::Error, io, ops::Range};
+
+use imap::{Connection, Session};
+use lettre::Message;
+
+use crate::store::{ImapStore, MailStore};
+use crate::transport::MailTransport;
+use crate::ui::Ui;
+use crate::user::{fetch_message, User};
+use crate::*;
+
+/// Represents a user logged in to a mail transport & an IMAP server.
+///
+/// Messages are sent through the `MailTransport` & read through the IMAP server as a
+/// `MailStore`, only the interactive flows built on them read from or write to the `Ui`.
+pub struct Client {
+    pub user: User,
+    transport: Box<dyn MailTransport>,
+    imap_cli: Session<Connection>,
+}
+
+/// Connects & logs in to an account's SMTP & IMAP servers, the way the `User` says.
+///
+/// # Returns
+///
+/// - A `Client` if both logins succeed.
+/// - An `Err` if either fails.
+pub fn connect(user: User) -> error::Result<Client> {
+    let smtp_cli = user.connect_smtp()?;
+    let imap_cli = user.connect_imap()?;
+    Ok(Client::new(user, Box::new(smtp_cli), imap_cli))
+}
+
+/// Logs in to an account's SMTP & IMAP servers, asking the user for a new login until both
+/// succeed.
+///
+/// # Returns
+///
+/// - A `Client` once both logins succeed.
+/// - An `Err` if reading user input fails.
+pub fn login(mut user: User, ui: &mut dyn Ui, prompts: &Prompts) -> io::Result<Client> {
+    let smtp_cli = user.login_smtp(ui, prompts)?;
+    let imap_cli = user.login_imap(ui, prompts)?;
+    Ok(Client::new(user, Box::new(smtp_cli), imap_cli))
+}
+
+impl Client {
+    /// Constructs a `Client` of a user sending through a transport, logged in to IMAP.
+    pub fn new(
+        user: User,
+        transport: Box<dyn MailTransport>,
+        imap_cli: Session<Connection>,
+    ) -> Client {
+        Client {
+            user,
+            transport,
+            imap_cli,
+        }
+    }
+
+    /// Borrows the IMAP server as a `MailStore`.
+    pub fn store(&mut self) -> ImapStore<'_> {
+        ImapStore::new(&mut self.user, &mut self.imap_cli)
+    }
+
+    /// Borrows the user & the IMAP session, for what only IMAP offers, e.g. IDLE, APPEND or
+    /// moving whole mailboxes.
+    pub fn imap(&mut self) -> (&mut User, &mut Session<Connection>) {
+        (&mut self.user, &mut self.imap_cli)
+    }
+
+    /// Sends a message through the transport.
+    pub fn send(&mut self, message: &Message) -> error::Result<()> {
+        self.transport.send(message)
+    }
+
+    /// Lists the names of mailboxes.
+    pub fn mailboxes(&mut self) -> error::Result<Vec<String>> {
+        self.store().mailboxes()
+    }
+
+    /// Lists the summaries of a range of messages in a mailbox, positions counted in
+    /// ascending UID order from 0, a range past the end is cut short.
+    pub fn list(
+        &mut self,
+        mailbox: &str,
+        range: Range<usize>,
+    ) -> error::Result<Vec<(MessageRef, MessageSummary)>> {
+        let mut store = self.store();
+        let messages = store.messages(mailbox)?;
+        let end = range.end.min(messages.len());
+        let messages = &messages[range.start.min(end)..end];
+        let summaries = store.summaries(messages)?;
+        Ok(messages.iter().cloned().zip(summaries).collect())
+    }
+
+    /// Fetches the raw RFC822 bytes of a message.
+    pub fn fetch(&mut self, message: &MessageRef) -> error::Result<Vec<u8>> {
+        self.store().fetch(message)
+    }
+
+    /// Composes a message from user input & sends it through the transport.
+    ///
+    /// # Returns
+    ///
+    /// - The recipient if the message was sent, `None` if the user cancelled.
+    /// - An `Err` if composing or sending fails.
+    pub fn compose_and_send(
+        &mut self,
+        ui: &mut dyn Ui,
+        prompts: &Prompts,
+    ) -> Result<Option<String>, Box<dyn Error>> {
+        self.user
+            .compose_and_send(&mut *self.transport, ui, prompts)
+    }
+
+    /// Lets the user choose a message through the store & step through its conversation.
+    pub fn read_conversation(&mut self, ui: &mut dyn Ui, prompts: &Prompts) -> error::Result<()> {
+        match fetch_message(&mut self.store(), ui, prompts)? {
+            Some(conversation) => {
+                self.user
+                    .view_conversation(&mut self.imap_cli, conversation, ui, prompts)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Logs out from the IMAP server.
+    pub fn logout(mut self) -> error::Result<()> {
+        self.imap_cli.logout()?;
+        Ok(())
+    }
+}
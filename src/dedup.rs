@@ -0,0 +1,359 @@
+use std::collections::{BTreeMap, HashMap};
+
+use imap::{types::Flag, Connection, Session};
+use mail_parser::MessageParser;
+
+use crate::backup::sha256_hex;
+use crate::ui::Ui;
+use crate::user::{uid_set, User};
+use crate::*;
+
+/// Number of messages fetched per FETCH command while looking for duplicates.
+const DEDUP_BATCH: usize = 50;
+
+/// Represents a copy of a message found while looking for duplicates.
+#[derive(Clone, Debug)]
+pub struct DuplicateCopy {
+    pub message: MessageRef,
+    pub summary: MessageSummary,
+    pub flags: Vec<String>,
+}
+
+impl DuplicateCopy {
+    /// Counts the flags that make a copy worth keeping, "\Recent" & "\Deleted" don't count.
+    fn richness(&self) -> usize {
+        self.flags
+            .iter()
+            .filter(|f| !matches!(f.as_str(), "\\Recent" | "\\Deleted"))
+            .count()
+    }
+}
+
+/// Represents copies of the same message, the one to keep & the extras to delete.
+#[derive(Clone, Debug)]
+pub struct DuplicateGroup {
+    pub keep: DuplicateCopy,
+    pub extras: Vec<DuplicateCopy>,
+}
+
+impl DuplicateGroup {
+    /// Groups copies of a message, keeping the one with the most flags, the one found first
+    /// if several have as many.
+    ///
+    /// # Panics
+    ///
+    /// If `copies` is empty.
+    fn from_copies(mut copies: Vec<DuplicateCopy>) -> DuplicateGroup {
+        let richest = copies.iter().map(|c| c.richness()).max().unwrap_or(0);
+        let keep = copies
+            .iter()
+            .position(|c| c.richness() == richest)
+            .unwrap_or(0);
+        DuplicateGroup {
+            keep: copies.remove(keep),
+            extras: copies,
+        }
+    }
+}
+
+/// Hashes what makes a message the same message, ignoring how it was delivered & stored.
+///
+/// Only the subject, sender, date, text bodies & attachments count, with whitespace
+/// collapsed, so copies differing in "Received" headers or line endings hash the same.
+pub fn content_hash(raw: &[u8]) -> Option<String> {
+    let message = MessageParser::default().parse(raw)?;
+    let normalize = |text: &str| text.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    let mut content = Vec::new();
+    content.extend(normalize(message.subject().unwrap_or_default()).into_bytes());
+    content.push(0);
+    let from = message.from().and_then(|f| f.first());
+    content.extend(
+        from.and_then(|f| f.address())
+            .unwrap_or_default()
+            .as_bytes(),
+    );
+    content.push(0);
+    let date = message.date().map(|d| d.to_timestamp()).unwrap_or_default();
+    content.extend(date.to_string().into_bytes());
+    for text in (0..).map_while(|i| message.body_text(i)) {
+        content.push(0);
+        content.extend(normalize(&text).into_bytes());
+    }
+    for attachment in message.attachments() {
+        content.push(0);
+        content.extend_from_slice(attachment.contents());
+    }
+
+    Some(sha256_hex(&content))
+}
+
+impl User {
+    /// Finds messages stored more than once within or across mailboxes.
+    ///
+    /// Copies are matched by "Message-ID", or by `content_hash()` if `by_content` is true,
+    /// which also catches messages without one but fetches whole messages. Of each group,
+    /// the copy with the most flags is kept, the one found first if several have as many.
+    pub fn find_duplicates(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+        mailboxes: &[String],
+        by_content: bool,
+    ) -> error::Result<Vec<DuplicateGroup>> {
+        let query = match by_content {
+            true => "(UID FLAGS BODY.PEEK[])",
+            false => "(UID FLAGS BODY.PEEK[HEADER])",
+        };
+
+        // Group copies by key, keeping the order groups were found in
+        let mut keys = HashMap::new();
+        let mut groups: Vec<Vec<DuplicateCopy>> = Vec::new();
+        for mailbox in mailboxes {
+            let messages = self.list_messages(imap_cli, mailbox)?;
+            for batch in messages.chunks(DEDUP_BATCH) {
+                let uids = batch.iter().map(|m| m.uid).collect::<Vec<_>>();
+                let fetches = imap_cli.uid_fetch(uid_set(&uids), query)?;
+                let mut fetched = fetches
+                    .iter()
+                    .filter_map(|m| Some((m.uid?, m)))
+                    .collect::<HashMap<_, _>>();
+
+                for message in batch {
+                    let Some(fetch) = fetched.remove(&message.uid) else {
+                        continue;
+                    };
+                    let Some(raw) = fetch.body().or(fetch.header()) else {
+                        continue;
+                    };
+                    let summary = MessageSummary::parse(message.uid, raw);
+                    let key = match by_content {
+                        true => content_hash(raw),
+                        false => summary.message_id.clone(),
+                    };
+                    let Some(key) = key else {
+                        continue;
+                    };
+
+                    let copy = DuplicateCopy {
+                        message: message.clone(),
+                        summary,
+                        flags: fetch
+                            .flags()
+                            .iter()
+                            .filter(|f| **f != Flag::Recent)
+                            .map(|f| f.to_string())
+                            .collect(),
+                    };
+                    let group = *keys.entry(key).or_insert_with(|| {
+                        groups.push(Vec::new());
+                        groups.len() - 1
+                    });
+                    groups[group].push(copy);
+                }
+            }
+        }
+
+        Ok(groups
+            .into_iter()
+            .filter(|copies| copies.len() > 1)
+            .map(DuplicateGroup::from_copies)
+            .collect())
+    }
+
+    /// Deletes the extra copies of duplicate groups, keeping one copy of each message.
+    ///
+    /// # Returns
+    ///
+    /// - The number of deleted copies if the process succeeds.
+    /// - An `Err` if it fails.
+    pub fn remove_duplicates(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+        groups: &[DuplicateGroup],
+    ) -> error::Result<usize> {
+        let mut extras: BTreeMap<&str, Vec<u32>> = BTreeMap::new();
+        for copy in groups.iter().flat_map(|g| g.extras.iter()) {
+            extras
+                .entry(&copy.message.mailbox)
+                .or_default()
+                .push(copy.message.uid);
+        }
+
+        let mut count = 0;
+        for (mailbox, uids) in extras {
+            self.delete_messages(imap_cli, mailbox, &uids)?;
+            count += uids.len();
+        }
+        Ok(count)
+    }
+
+    /// Finds duplicates in mailboxes chosen by the user, deleting them once confirmed.
+    ///
+    /// # Returns
+    ///
+    /// - The number of deleted copies if the process succeeds, 0 if the user declines.
+    /// - An `Err` if it fails.
+    pub fn dedup(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+        ui: &mut dyn Ui,
+        prompts: &Prompts,
+    ) -> error::Result<usize> {
+        // Choose mailboxes & how to match copies
+        ui.show(prompts.fetch_mailbox);
+        let mailboxes = self.list_mailboxes(imap_cli)?;
+        for (i, mailbox) in mailboxes.iter().enumerate() {
+            ui.show(&format!("  [{}] {}", i + 1, mailbox));
+        }
+        let mailboxes = ui
+            .read_selections(
+                prompts.dedup_mailbox_selection,
+                prompts.invalid_literal,
+                prompts.fetch_mailbox_literal,
+                prompts.should_be_one_of_below_literal,
+                &RangeUsize::new(1, mailboxes.len()),
+            )?
+            .into_iter()
+            .map(|selection| mailboxes[selection - 1].clone())
+            .collect::<Vec<_>>();
+        ui.show(prompts.dedup_match_list);
+        let by_content = ui.read_selection(
+            prompts.action_selection,
+            prompts.invalid_literal,
+            prompts.action_literal,
+            prompts.should_be_one_of_below_literal,
+            &RangeUsize::new(1, 2),
+        )? == 2;
+
+        // Report duplicates
+        ui.show(prompts.dedup_searching);
+        let groups = self.find_duplicates(imap_cli, &mailboxes, by_content)?;
+        if groups.is_empty() {
+            ui.show(prompts.dedup_none);
+            return Ok(0);
+        }
+        for group in groups.iter() {
+            let keep = &group.keep;
+            ui.show(&format!(
+                "  {} - {}\n    {}\"{}\" UID {} [{}]",
+                keep.summary.from,
+                keep.summary.subject,
+                prompts.dedup_keep,
+                keep.message.mailbox,
+                keep.message.uid,
+                keep.flags.join(" ")
+            ));
+            for extra in group.extras.iter() {
+                ui.show(&format!(
+                    "    {}\"{}\" UID {} [{}]",
+                    prompts.dedup_extra,
+                    extra.message.mailbox,
+                    extra.message.uid,
+                    extra.flags.join(" ")
+                ));
+            }
+        }
+        let extras = groups.iter().map(|g| g.extras.len()).sum::<usize>();
+        ui.show(&format!("{}{}.", prompts.dedup_found, extras));
+
+        // Delete extras once confirmed
+        ui.show(prompts.dedup_confirm_list);
+        let confirmed = ui.read_selection(
+            prompts.action_selection,
+            prompts.invalid_literal,
+            prompts.action_literal,
+            prompts.should_be_one_of_below_literal,
+            &RangeUsize::new(0, 1),
+        )? == 1;
+        if !confirmed {
+            return Ok(0);
+        }
+        self.remove_duplicates(imap_cli, &groups)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSAGE: &[u8] = b"Received: from mx1.example.test by mx.example.test\r\n\
+From: Bob <bob@example.test>\r\n\
+Subject: Quarterly figures\r\n\
+Date: Mon, 1 Jan 2024 09:00:00 +0000\r\n\
+Message-ID: <1@example.test>\r\n\
+\r\n\
+The numbers\r\nare in.\r\n";
+
+    fn copy(uid: u32, flags: &[&str]) -> DuplicateCopy {
+        DuplicateCopy {
+            message: MessageRef::new("INBOX", 1, uid),
+            summary: MessageSummary::default(),
+            flags: flags.iter().map(|f| f.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn content_hash_ignores_received_headers_and_line_endings() {
+        let relayed = [
+            b"Received: from mx2.example.test by mx.example.test\r\n".as_slice(),
+            b"Received: from relay.example.test by mx2.example.test\r\n",
+            MESSAGE,
+        ]
+        .concat();
+        let lf = String::from_utf8(MESSAGE.to_vec())
+            .unwrap()
+            .replace("\r\n", "\n");
+
+        let hash = content_hash(MESSAGE).unwrap();
+
+        assert_eq!(content_hash(&relayed).unwrap(), hash);
+        assert_eq!(content_hash(lf.as_bytes()).unwrap(), hash);
+    }
+
+    #[test]
+    fn content_hash_tells_different_bodies_apart() {
+        let edited = String::from_utf8(MESSAGE.to_vec())
+            .unwrap()
+            .replace("are in", "are late");
+
+        assert_ne!(content_hash(edited.as_bytes()), content_hash(MESSAGE));
+    }
+
+    #[test]
+    fn the_copy_with_the_most_flags_is_kept() {
+        let group = DuplicateGroup::from_copies(vec![
+            copy(1, &["\\Seen"]),
+            copy(2, &["\\Seen", "\\Flagged"]),
+            copy(3, &[]),
+        ]);
+
+        assert_eq!(group.keep.message.uid, 2);
+        let extras = group
+            .extras
+            .iter()
+            .map(|c| c.message.uid)
+            .collect::<Vec<_>>();
+        assert_eq!(extras, [1, 3]);
+    }
+
+    #[test]
+    fn recent_and_deleted_flags_make_no_copy_richer() {
+        let group = DuplicateGroup::from_copies(vec![
+            copy(1, &["\\Recent", "\\Deleted"]),
+            copy(2, &["\\Seen"]),
+        ]);
+
+        assert_eq!(group.keep.message.uid, 2);
+    }
+
+    #[test]
+    fn the_first_copy_is_kept_on_ties() {
+        let group = DuplicateGroup::from_copies(vec![
+            copy(4, &["\\Seen", "\\Deleted"]),
+            copy(5, &["\\Answered"]),
+            copy(6, &["\\Flagged"]),
+        ]);
+
+        assert_eq!(group.keep.message.uid, 4);
+    }
+}
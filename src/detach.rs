@@ -0,0 +1,416 @@
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+use imap::{types::Flag, Connection, Session};
+use mail_parser::{MessageParser, MimeHeaders};
+
+use crate::attachment::{human_size, read_dir, save_attachment};
+use crate::ui::Ui;
+use crate::user::{uid_set, User};
+use crate::*;
+
+/// Number of messages whose sizes are fetched per FETCH command.
+const SIZE_BATCH: usize = 200;
+
+/// Number of messages listed by the largest messages finder.
+pub const LARGEST_LIMIT: usize = 20;
+
+/// Represents a message listed by its size on the server.
+#[derive(Clone, Debug)]
+pub struct LargeMessage {
+    pub message: MessageRef,
+    pub summary: MessageSummary,
+    pub size: u32,
+}
+
+/// Represents an attachment detached from a message & saved locally.
+#[derive(Clone, Debug)]
+pub struct DetachedAttachment {
+    pub filename: String,
+    pub mime_type: String,
+    pub size: usize,
+    pub path: PathBuf,
+}
+
+/// Saves a message's attachments into a directory, replacing each by a text stub.
+///
+/// Each stub is a plain text part naming the attachment & where it was saved. An attachment
+/// that is the message's whole body is left alone, as there's no part to replace. The new
+/// message is parsed back, so a broken one is never handed on to replace the original.
+///
+/// # Returns
+///
+/// - The message's new raw bytes & its detached attachments if the process succeeds, the
+///   message is unchanged if none could be detached.
+/// - An `Err` of kind `InvalidData` if the new message doesn't parse or still has
+///   attachments.
+/// - An `Err` if saving fails.
+pub fn detach_attachments(
+    raw: &[u8],
+    dir: &Path,
+) -> io::Result<(Vec<u8>, Vec<DetachedAttachment>)> {
+    let Some(message) = MessageParser::default().parse(raw) else {
+        return Ok((raw.to_vec(), Vec::new()));
+    };
+    let line_break = match raw.windows(2).any(|w| w == b"\r\n") {
+        true => "\r\n",
+        false => "\n",
+    };
+
+    let mut replacements = Vec::new();
+    let mut detached = Vec::new();
+    for &id in message.attachments.iter().filter(|&&id| id != 0) {
+        let Some(part) = message.parts.get(id as usize) else {
+            continue;
+        };
+        let filename = part
+            .attachment_name()
+            .map(String::from)
+            .unwrap_or_else(|| format!("part-{}", id));
+        let mime_type = part
+            .content_type()
+            .map(|ct| match ct.subtype() {
+                Some(subtype) => format!("{}/{}", ct.ctype(), subtype),
+                None => ct.ctype().to_string(),
+            })
+            .unwrap_or_else(|| "application/octet-stream".to_string())
+            .to_lowercase();
+        let contents = part.contents();
+        let path = save_attachment(dir, &filename, contents)?;
+
+        let stub = [
+            "Content-Type: text/plain; charset=utf-8",
+            "Content-Disposition: inline",
+            "Content-Transfer-Encoding: 8bit",
+            "",
+            &format!(
+                "[Attachment \"{}\" ({}, {}) was detached & saved as \"{}\"]",
+                filename,
+                mime_type,
+                human_size(contents.len() as u32),
+                path.display()
+            ),
+        ]
+        .join(line_break);
+        replacements.push((part.offset_header as usize, part.offset_end as usize, stub));
+        detached.push(DetachedAttachment {
+            filename,
+            mime_type,
+            size: contents.len(),
+            path,
+        });
+    }
+
+    // Splice stubs in from the end, so earlier offsets stay valid
+    let mut raw = raw.to_vec();
+    replacements.sort_by_key(|&(start, _, _)| start);
+    for (start, end, stub) in replacements.into_iter().rev() {
+        raw.splice(start..end, stub.into_bytes());
+    }
+
+    let is_detached = MessageParser::default()
+        .parse(&raw)
+        .is_some_and(|m| m.attachments.iter().all(|&id| id == 0));
+    if !is_detached {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the message with detached attachments doesn't parse",
+        ));
+    }
+    Ok((raw, detached))
+}
+
+impl User {
+    /// Finds the largest messages in mailboxes by their RFC822.SIZE, largest first.
+    pub fn find_largest(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+        mailboxes: &[String],
+        limit: usize,
+    ) -> error::Result<Vec<LargeMessage>> {
+        let mut sizes = Vec::new();
+        for mailbox in mailboxes {
+            let messages = self.list_messages(imap_cli, mailbox)?;
+            for batch in messages.chunks(SIZE_BATCH) {
+                let uids = batch.iter().map(|m| m.uid).collect::<Vec<_>>();
+                let fetches = imap_cli.uid_fetch(uid_set(&uids), "(UID RFC822.SIZE)")?;
+                let fetched = fetches
+                    .iter()
+                    .filter_map(|m| Some((m.uid?, m.size?)))
+                    .collect::<HashMap<_, _>>();
+                sizes.extend(
+                    batch
+                        .iter()
+                        .filter_map(|m| Some((m.clone(), *fetched.get(&m.uid)?))),
+                );
+            }
+        }
+        sizes.sort_by_key(|(_, size)| Reverse(*size));
+        sizes.truncate(limit);
+
+        // Summaries are only fetched for the messages listed
+        let mut largest = Vec::new();
+        for (message, size) in sizes {
+            let summary = self
+                .fetch_summaries(imap_cli, std::slice::from_ref(&message))?
+                .remove(0);
+            largest.push(LargeMessage {
+                message,
+                summary,
+                size,
+            });
+        }
+        Ok(largest)
+    }
+
+    /// Detaches a message's attachments into a directory, keeping its flags & internal date.
+    ///
+    /// The message with stubs in place of its attachments is checked to parse & appended to
+    /// its mailbox before the original is deleted, so the message is never lost halfway.
+    ///
+    /// # Returns
+    ///
+    /// - The detached attachments if the process succeeds, none if the message has none,
+    ///   in which case it's left untouched.
+    /// - An `Err` if it fails.
+    pub fn detach_message(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+        message: &MessageRef,
+        dir: &Path,
+    ) -> error::Result<Vec<DetachedAttachment>> {
+        let mut fetched = None;
+        self.fetch_archived_messages(imap_cli, std::slice::from_ref(message), |entry, raw| {
+            fetched = Some((entry, raw.to_vec()));
+            Ok(())
+        })?;
+        let (entry, raw) = fetched.ok_or_else(|| error::Error::MessageNotFound {
+            mailbox: message.mailbox.to_string(),
+            uid: message.uid,
+        })?;
+
+        let (stripped, detached) = detach_attachments(&raw, dir)?;
+        if detached.is_empty() {
+            return Ok(detached);
+        }
+        let mut append = imap_cli.append(&message.mailbox, &stripped);
+        append.flags(entry.flags.iter().map(|f| Flag::from(f.as_str())));
+        if let Some(date) = entry.internal_date {
+            append.internal_date(date);
+        }
+        append.finish()?;
+        self.delete_messages(imap_cli, &message.mailbox, &[message.uid])?;
+
+        Ok(detached)
+    }
+
+    /// Lists the largest messages in mailboxes chosen by the user, offering to detach the
+    /// attachments of one of them.
+    pub fn largest_messages(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+        ui: &mut dyn Ui,
+        prompts: &Prompts,
+    ) -> error::Result<()> {
+        // Choose mailboxes
+        ui.show(prompts.fetch_mailbox);
+        let mailboxes = self.list_mailboxes(imap_cli)?;
+        for (i, mailbox) in mailboxes.iter().enumerate() {
+            ui.show(&format!("  [{}] {}", i + 1, mailbox));
+        }
+        let mailboxes = ui
+            .read_selections(
+                prompts.largest_mailbox_selection,
+                prompts.invalid_literal,
+                prompts.fetch_mailbox_literal,
+                prompts.should_be_one_of_below_literal,
+                &RangeUsize::new(1, mailboxes.len()),
+            )?
+            .into_iter()
+            .map(|selection| mailboxes[selection - 1].clone())
+            .collect::<Vec<_>>();
+
+        // List the largest messages & choose one
+        let largest = self.find_largest(imap_cli, &mailboxes, LARGEST_LIMIT)?;
+        if largest.is_empty() {
+            ui.show(prompts.largest_none);
+            return Ok(());
+        }
+        ui.show(prompts.largest_list);
+        for (i, large) in largest.iter().enumerate() {
+            ui.show(&format!(
+                "  [{}] {} \"{}\" {} - {}",
+                i + 1,
+                human_size(large.size),
+                large.message.mailbox,
+                large.summary.from,
+                large.summary.subject
+            ));
+        }
+        let selection = ui.read_selection(
+            prompts.largest_selection,
+            prompts.invalid_literal,
+            prompts.fetch_message_literal,
+            prompts.should_be_one_of_below_literal,
+            &RangeUsize::new(0, largest.len()),
+        )?;
+        if selection == 0 {
+            return Ok(());
+        }
+
+        // Detach its attachments into a directory
+        let dir = read_dir(ui, prompts)?;
+        let detached = self.detach_message(imap_cli, &largest[selection - 1].message, &dir)?;
+        if detached.is_empty() {
+            ui.show(prompts.largest_no_attachments);
+        }
+        for attachment in detached.iter() {
+            ui.show(&format!(
+                "{}{} ({}) -> {}",
+                prompts.largest_detached,
+                attachment.filename,
+                human_size(attachment.size as u32),
+                attachment.path.display()
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::*;
+
+    const MIXED: &str = "From: Bob <bob@example.test>\r\n\
+Subject: Figures\r\n\
+MIME-Version: 1.0\r\n\
+Content-Type: multipart/mixed; boundary=\"outer\"\r\n\
+\r\n\
+--outer\r\n\
+Content-Type: text/plain\r\n\
+\r\n\
+The numbers are attached.\r\n\
+--outer\r\n\
+Content-Type: application/pdf; name=\"q3.pdf\"\r\n\
+Content-Disposition: attachment; filename=\"q3.pdf\"\r\n\
+Content-Transfer-Encoding: base64\r\n\
+\r\n\
+JVBERi0xLjQK\r\n\
+--outer--\r\n";
+
+    const NESTED: &str = "From: Bob <bob@example.test>\r\n\
+Subject: Photos\r\n\
+MIME-Version: 1.0\r\n\
+Content-Type: multipart/mixed; boundary=\"outer\"\r\n\
+\r\n\
+--outer\r\n\
+Content-Type: multipart/alternative; boundary=\"inner\"\r\n\
+\r\n\
+--inner\r\n\
+Content-Type: text/plain\r\n\
+\r\n\
+See the photo.\r\n\
+--inner\r\n\
+Content-Type: text/html\r\n\
+\r\n\
+<p>See the photo.</p>\r\n\
+--inner--\r\n\
+--outer\r\n\
+Content-Type: image/png\r\n\
+Content-Disposition: attachment; filename=\"beach.png\"\r\n\
+Content-Transfer-Encoding: base64\r\n\
+\r\n\
+iVBORw0KGgo=\r\n\
+--outer\r\n\
+Content-Type: text/plain\r\n\
+\r\n\
+Regards, Bob\r\n\
+--outer--\r\n";
+
+    /// Detaches attachments into a directory of its own, returning the new message & the
+    /// detached file names.
+    fn detach(name: &str, raw: &str) -> (String, Vec<String>) {
+        let dir = env::temp_dir().join(format!("eua-detach-{}-{}", name, process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (stripped, detached) = detach_attachments(raw.as_bytes(), &dir).unwrap();
+        for attachment in detached.iter() {
+            assert!(attachment.path.starts_with(&dir));
+            assert_eq!(fs::read(&attachment.path).unwrap().len(), attachment.size);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+        let names = detached.into_iter().map(|a| a.filename).collect();
+        (String::from_utf8(stripped).unwrap(), names)
+    }
+
+    /// Returns the text bodies of a message & the number of its attachments.
+    fn texts(raw: &str) -> (Vec<String>, usize) {
+        let message = MessageParser::default().parse(raw.as_bytes()).unwrap();
+        let texts = (0..)
+            .map_while(|i| message.body_text(i))
+            .map(|t| t.trim().to_string())
+            .collect();
+        (texts, message.attachments.len())
+    }
+
+    #[test]
+    fn the_last_part_is_replaced_by_a_stub() {
+        let (stripped, names) = detach("last", MIXED);
+
+        assert_eq!(names, ["q3.pdf"]);
+        assert!(!stripped.contains("JVBERi0xLjQK"));
+        assert!(stripped.ends_with("--outer--\r\n"));
+        let (texts, attachments) = texts(&stripped);
+        assert_eq!(texts[0], "The numbers are attached.");
+        assert!(texts[1].starts_with("[Attachment \"q3.pdf\" (application/pdf, "));
+        assert_eq!(attachments, 0);
+    }
+
+    #[test]
+    fn attachments_are_detached_beside_nested_multiparts() {
+        let (stripped, names) = detach("nested", NESTED);
+
+        assert_eq!(names, ["beach.png"]);
+        assert!(!stripped.contains("iVBORw0KGgo="));
+        assert!(stripped.contains("--inner--\r\n--outer\r\n"));
+        let message = MessageParser::default().parse(stripped.as_bytes()).unwrap();
+        assert_eq!(
+            message.body_html(0).unwrap().trim(),
+            "<p>See the photo.</p>"
+        );
+        let (texts, attachments) = texts(&stripped);
+        assert_eq!(texts.first().unwrap(), "See the photo.");
+        assert_eq!(texts.last().unwrap(), "Regards, Bob");
+        assert!(texts
+            .iter()
+            .any(|t| t.contains("\"beach.png\" (image/png, ")));
+        assert_eq!(attachments, 0);
+    }
+
+    #[test]
+    fn lf_messages_get_lf_stubs() {
+        let lf = MIXED.replace("\r\n", "\n");
+
+        let (stripped, names) = detach("lf", &lf);
+
+        assert_eq!(names, ["q3.pdf"]);
+        assert!(!stripped.contains('\r'));
+        assert!(stripped.contains("Content-Disposition: inline\nContent-Transfer-Encoding"));
+        assert_eq!(texts(&stripped).1, 0);
+    }
+
+    #[test]
+    fn a_message_without_attachments_is_left_unchanged() {
+        let plain = "From: Bob <bob@example.test>\r\nSubject: Hi\r\n\r\nHello\r\n";
+
+        let (stripped, names) = detach("none", plain);
+
+        assert!(names.is_empty());
+        assert_eq!(stripped, plain);
+    }
+}
@@ -0,0 +1,771 @@
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{IpAddr, TcpStream, ToSocketAddrs},
+    time::{Duration, Instant},
+};
+
+use chrono::DateTime;
+use native_tls::{Protocol, TlsConnector, TlsStream};
+use sha2::{Digest, Sha256};
+use x509_parser::{certificate::X509Certificate, extensions::GeneralName, prelude::FromDer};
+
+use crate::trust::{load_certificates, load_trust, TrustSettings};
+use crate::ui::Ui;
+use crate::user::{Security, User, IMAPS_PORT, SMTPS_PORT};
+use crate::*;
+
+/// How long to wait for a connection, or for a server to answer.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Ports of SMTP submission & relay, which start in plaintext & may offer STARTTLS.
+const SMTP_PLAIN_PORTS: [u16; 2] = [587, 25];
+
+/// Port of IMAP, which starts in plaintext & may offer STARTTLS.
+const IMAP_PLAIN_PORT: u16 = 143;
+
+/// TLS versions probed for, newest first.
+const TLS_VERSIONS: [(Protocol, &str); 4] = [
+    (Protocol::Tlsv13, "TLS 1.3"),
+    (Protocol::Tlsv12, "TLS 1.2"),
+    (Protocol::Tlsv11, "TLS 1.1"),
+    (Protocol::Tlsv10, "TLS 1.0"),
+];
+
+/// Represents a mail service the doctor examines.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Service {
+    Smtp,
+    Imap,
+}
+
+impl Service {
+    /// Returns the service's name, e.g. "SMTP".
+    pub fn name(&self) -> &'static str {
+        match self {
+            Service::Smtp => "SMTP",
+            Service::Imap => "IMAP",
+        }
+    }
+}
+
+/// Represents the certificate a server presented.
+#[derive(Clone, Debug)]
+pub struct CertificateReport {
+    pub subject: String,
+    pub issuer: String,
+    pub not_before: String,
+    pub not_after: String,
+    pub expired: bool,
+    pub names: Vec<String>,
+    pub fingerprint: String,
+}
+
+/// Represents what was learnt from a TLS handshake with a server.
+#[derive(Clone, Debug)]
+pub struct TlsReport {
+    /// The newest TLS version the server accepted, if any could be told.
+    pub version: Option<&'static str>,
+    /// Whether the certificate chain is trusted for the server's name, the reason if not.
+    pub trusted: Result<(), String>,
+    pub certificate: Option<CertificateReport>,
+}
+
+/// Represents what was learnt from a port of a server.
+#[derive(Clone, Debug)]
+pub struct PortReport {
+    pub port: u16,
+    pub security: Security,
+    /// How long connecting took, or why it failed.
+    pub connected: Result<Duration, String>,
+    /// What the TLS handshake revealed, for ports speaking TLS from the first byte or
+    /// offering STARTTLS.
+    pub tls: Option<Result<TlsReport, String>>,
+    /// SMTP EHLO extensions or IMAP capabilities, if the port was reached.
+    pub capabilities: Option<Result<Vec<String>, String>>,
+}
+
+impl PortReport {
+    /// Checks whether the port was reached & spoke TLS if it should have.
+    fn usable(&self) -> bool {
+        self.connected.is_ok()
+            && !(self.security == Security::Tls && matches!(self.tls, Some(Err(_))))
+    }
+
+    /// Checks whether the port offers upgrading the connection with STARTTLS.
+    fn offers_starttls(&self) -> bool {
+        matches!(&self.capabilities, Some(Ok(capabilities)) if capabilities
+            .iter()
+            .any(|c| c.eq_ignore_ascii_case("STARTTLS")))
+    }
+
+    /// Checks whether the port offers a way to log in.
+    fn offers_login(&self, service: Service) -> bool {
+        let Some(Ok(capabilities)) = &self.capabilities else {
+            return true;
+        };
+        match service {
+            Service::Smtp => capabilities
+                .iter()
+                .any(|c| c.to_uppercase().starts_with("AUTH")),
+            Service::Imap => !capabilities
+                .iter()
+                .any(|c| c.eq_ignore_ascii_case("LOGINDISABLED")),
+        }
+    }
+}
+
+/// Represents what was learnt from a server.
+#[derive(Clone, Debug)]
+pub struct HostReport {
+    pub service: Service,
+    pub domain: String,
+    /// The port the user agent is configured to use.
+    pub configured_port: u16,
+    /// The addresses the server's name resolved to, or why it didn't.
+    pub addresses: Result<Vec<IpAddr>, String>,
+    pub ports: Vec<PortReport>,
+    /// The port logging in was tried on & how it went, if any port was usable.
+    pub login: Option<(u16, Result<(), String>)>,
+}
+
+/// Represents a problem found by the doctor.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Finding {
+    /// The server's name doesn't resolve.
+    Unresolved { domain: String, error: String },
+    /// No port of the server could be reached.
+    Unreachable { domain: String },
+    /// The configured port is unusable, but another one works.
+    OtherPort {
+        domain: String,
+        port: u16,
+        starttls: bool,
+    },
+    /// The certificate isn't trusted for the server's name.
+    Untrusted {
+        domain: String,
+        port: u16,
+        error: String,
+    },
+    /// The server accepts nothing newer than TLS 1.1.
+    OutdatedTls {
+        domain: String,
+        port: u16,
+        version: &'static str,
+    },
+    /// The port offers no way to log in.
+    NoLogin { domain: String, port: u16 },
+    /// The server rejected logging in.
+    LoginRejected { domain: String, error: String },
+}
+
+/// Returns the SHA-256 fingerprint of a DER-encoded certificate, as colon separated pairs
+/// of uppercase hex digits.
+pub fn fingerprint(der: &[u8]) -> String {
+    Sha256::digest(der)
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// Describes a DER-encoded certificate.
+fn describe_certificate(der: &[u8]) -> Option<CertificateReport> {
+    let (_, certificate) = X509Certificate::from_der(der).ok()?;
+    let date = |timestamp| {
+        DateTime::from_timestamp(timestamp, 0)
+            .map(|d| d.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+            .unwrap_or_default()
+    };
+    let validity = certificate.validity();
+    let names = match certificate.subject_alternative_name() {
+        Ok(Some(extension)) => extension
+            .value
+            .general_names
+            .iter()
+            .filter_map(|name| match name {
+                GeneralName::DNSName(name) => Some(name.to_string()),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+    Some(CertificateReport {
+        subject: certificate.subject().to_string(),
+        issuer: certificate.issuer().to_string(),
+        not_before: date(validity.not_before.timestamp()),
+        not_after: date(validity.not_after.timestamp()),
+        expired: !validity.is_valid(),
+        names,
+        fingerprint: fingerprint(der),
+    })
+}
+
+/// Connects to a port of a server, with timeouts for reading & writing.
+fn connect(addresses: &[IpAddr], port: u16) -> io::Result<TcpStream> {
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no address to connect to");
+    for address in addresses {
+        match TcpStream::connect_timeout(&(*address, port).into(), TIMEOUT) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(TIMEOUT))?;
+                stream.set_write_timeout(Some(TIMEOUT))?;
+                return Ok(stream);
+            }
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+/// Performs a TLS handshake, optionally accepting untrusted certificates & only a version.
+///
+/// Certificates are verified against the CAs of the account's trust settings too.
+fn handshake(
+    domain: &str,
+    stream: TcpStream,
+    verify: bool,
+    version: Option<Protocol>,
+    trust: &TrustSettings,
+) -> Result<TlsStream<TcpStream>, String> {
+    let mut builder = TlsConnector::builder();
+    for path in trust.ca_files.iter() {
+        for certificate in load_certificates(path).map_err(|e| e.to_string())? {
+            builder.add_root_certificate(certificate);
+        }
+    }
+    let connector = builder
+        .danger_accept_invalid_certs(!verify)
+        .danger_accept_invalid_hostnames(!verify)
+        .min_protocol_version(version)
+        .max_protocol_version(version)
+        .build()
+        .map_err(|e| e.to_string())?;
+    connector.connect(domain, stream).map_err(|e| e.to_string())
+}
+
+/// Reads an SMTP reply, which may span several lines.
+///
+/// # Returns
+///
+/// - The text of each line, after the reply code, if the reply has the expected code.
+/// - An `Err` otherwise.
+fn read_smtp_reply<R: BufRead>(reader: &mut R, code: &str) -> io::Result<Vec<String>> {
+    let mut lines = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let line = line.trim_end();
+        if !line.starts_with(code) {
+            return Err(io::Error::other(format!("unexpected reply \"{}\"", line)));
+        }
+        lines.push(line.get(4..).unwrap_or_default().to_string());
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok(lines);
+        }
+    }
+}
+
+/// Reads an IMAP server's greeting.
+fn read_imap_greeting<R: BufRead>(reader: &mut R) -> io::Result<()> {
+    let mut greeting = String::new();
+    reader.read_line(&mut greeting)?;
+    match greeting.starts_with("* OK") || greeting.starts_with("* PREAUTH") {
+        true => Ok(()),
+        false => Err(io::Error::other(format!(
+            "unexpected greeting \"{}\"",
+            greeting.trim_end()
+        ))),
+    }
+}
+
+/// Reads an IMAP response up to the completion of a tagged command.
+///
+/// # Returns
+///
+/// - The untagged lines if the command completed with OK.
+/// - An `Err` otherwise.
+fn read_imap_response<R: BufRead>(reader: &mut R, tag: &str) -> io::Result<Vec<String>> {
+    let mut lines = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let line = line.trim_end();
+        match line.strip_prefix(tag).and_then(|l| l.strip_prefix(' ')) {
+            Some(status) if status.starts_with("OK") => return Ok(lines),
+            Some(_) => {
+                return Err(io::Error::other(format!(
+                    "unexpected response \"{}\"",
+                    line
+                )))
+            }
+            None => lines.push(line.to_string()),
+        }
+    }
+}
+
+/// Upgrades a plaintext connection with STARTTLS, leaving it ready for the TLS handshake.
+fn start_tls(service: Service, stream: &TcpStream) -> io::Result<()> {
+    let mut reader = BufReader::new(stream);
+    match service {
+        Service::Smtp => {
+            read_smtp_reply(&mut reader, "220")?;
+            reader.get_mut().write_all(b"EHLO localhost\r\n")?;
+            read_smtp_reply(&mut reader, "250")?;
+            reader.get_mut().write_all(b"STARTTLS\r\n")?;
+            read_smtp_reply(&mut reader, "220")?;
+        }
+        Service::Imap => {
+            read_imap_greeting(&mut reader)?;
+            reader.get_mut().write_all(b"d1 STARTTLS\r\n")?;
+            read_imap_response(&mut reader, "d1")?;
+        }
+    }
+    Ok(())
+}
+
+/// Asks a server what it supports, the EHLO extensions of SMTP or the capabilities of IMAP.
+fn read_capabilities<S: Read + Write>(service: Service, stream: S) -> io::Result<Vec<String>> {
+    let mut reader = BufReader::new(stream);
+    match service {
+        Service::Smtp => {
+            read_smtp_reply(&mut reader, "220")?;
+            reader.get_mut().write_all(b"EHLO localhost\r\n")?;
+            let extensions = read_smtp_reply(&mut reader, "250")?;
+            let _ = reader.get_mut().write_all(b"QUIT\r\n");
+            Ok(extensions.into_iter().skip(1).collect())
+        }
+        Service::Imap => {
+            read_imap_greeting(&mut reader)?;
+            reader.get_mut().write_all(b"d1 CAPABILITY\r\n")?;
+            let capabilities = read_imap_response(&mut reader, "d1")?
+                .iter()
+                .filter_map(|l| l.strip_prefix("* CAPABILITY "))
+                .flat_map(|rest| rest.split(' ').map(String::from))
+                .collect();
+            let _ = reader.get_mut().write_all(b"d2 LOGOUT\r\n");
+            Ok(capabilities)
+        }
+    }
+}
+
+/// Examines a port of a server.
+fn examine_port(
+    service: Service,
+    domain: &str,
+    addresses: &[IpAddr],
+    port: u16,
+    security: Security,
+    trust: &TrustSettings,
+) -> PortReport {
+    let mut report = PortReport {
+        port,
+        security,
+        connected: Err(String::new()),
+        tls: None,
+        capabilities: None,
+    };
+    let start = Instant::now();
+    let stream = match connect(addresses, port) {
+        Ok(stream) => stream,
+        Err(e) => {
+            report.connected = Err(e.to_string());
+            return report;
+        }
+    };
+    report.connected = Ok(start.elapsed());
+
+    // A plaintext port is looked at over TLS too if it offers STARTTLS
+    let starttls = security == Security::Plaintext;
+    let open = || -> Result<TcpStream, String> {
+        let stream = connect(addresses, port).map_err(|e| e.to_string())?;
+        if starttls {
+            start_tls(service, &stream).map_err(|e| e.to_string())?;
+        }
+        Ok(stream)
+    };
+    let stream = match starttls {
+        true => {
+            report.capabilities =
+                Some(read_capabilities(service, stream).map_err(|e| e.to_string()));
+            if !report.offers_starttls() {
+                return report;
+            }
+            match open() {
+                Ok(stream) => stream,
+                Err(e) => {
+                    report.tls = Some(Err(e));
+                    return report;
+                }
+            }
+        }
+        false => stream,
+    };
+
+    // Verify the certificate, then look at it anyway if it isn't trusted
+    let (trusted, stream) = match handshake(domain, stream, true, None, trust) {
+        Ok(stream) => (Ok(()), stream),
+        Err(error) => {
+            let retried = open().and_then(|stream| handshake(domain, stream, false, None, trust));
+            match retried {
+                Ok(stream) => (Err(error), stream),
+                Err(_) => {
+                    report.tls = Some(Err(error));
+                    return report;
+                }
+            }
+        }
+    };
+    let certificate = stream
+        .peer_certificate()
+        .ok()
+        .flatten()
+        .and_then(|c| c.to_der().ok())
+        .and_then(|der| describe_certificate(&der));
+    let version = TLS_VERSIONS.iter().find_map(|(protocol, name)| {
+        let stream = open().ok()?;
+        handshake(domain, stream, false, Some(*protocol), trust).ok()?;
+        Some(*name)
+    });
+    report.tls = Some(Ok(TlsReport {
+        version,
+        trusted,
+        certificate,
+    }));
+    // After STARTTLS the server doesn't greet again, its capabilities were read before
+    if !starttls {
+        report.capabilities = Some(read_capabilities(service, stream).map_err(|e| e.to_string()));
+    }
+    report
+}
+
+/// Tries logging in to a server on a port, without pinning its certificate.
+fn try_login(user: &User, service: Service, port: u16) -> Result<(), String> {
+    let mut user = user.clone();
+    user.pins_first_use = false;
+    match service {
+        Service::Smtp => {
+            user.smtp_port = port;
+            user.connect_smtp()
+                .map(|_| ())
+                .map_err(|e| error::chain(&e))
+        }
+        Service::Imap => {
+            user.imap_port = port;
+            let mut session = user.connect_imap().map_err(|e| error::chain(&e))?;
+            let _ = session.logout();
+            Ok(())
+        }
+    }
+}
+
+/// Examines one of a user's servers: resolves its name, checks the configured port & the
+/// service's other usual ports, then tries logging in.
+///
+/// Over TLS, the usual ports are 465, 587 & 25 for SMTP, 993 & 143 for IMAP. Over
+/// plaintext, only the configured port is checked. Logging in is only tried on a port
+/// using the configured security, so credentials are never sent in the clear unless the
+/// user asked for it.
+pub fn examine(user: &User, service: Service) -> HostReport {
+    let (domain, configured_port) = match service {
+        Service::Smtp => (user.smtp_domain.clone(), user.smtp_port),
+        Service::Imap => (user.imap_domain.clone(), user.imap_port),
+    };
+    let mut report = HostReport {
+        service,
+        domain: domain.clone(),
+        configured_port,
+        addresses: Err(String::new()),
+        ports: Vec::new(),
+        login: None,
+    };
+    let addresses = match (domain.as_str(), configured_port).to_socket_addrs() {
+        Ok(addresses) => {
+            let mut ips = Vec::new();
+            for address in addresses {
+                if !ips.contains(&address.ip()) {
+                    ips.push(address.ip());
+                }
+            }
+            ips
+        }
+        Err(e) => {
+            report.addresses = Err(e.to_string());
+            return report;
+        }
+    };
+    report.addresses = Ok(addresses.clone());
+
+    let trust = load_trust(user.email_addr.as_ref()).unwrap_or_default();
+    let mut candidates = vec![(configured_port, user.security)];
+    if user.security == Security::Tls {
+        let (tls_port, plain_ports) = match service {
+            Service::Smtp => (SMTPS_PORT, SMTP_PLAIN_PORTS.to_vec()),
+            Service::Imap => (IMAPS_PORT, vec![IMAP_PLAIN_PORT]),
+        };
+        candidates.push((tls_port, Security::Tls));
+        candidates.extend(plain_ports.into_iter().map(|p| (p, Security::Plaintext)));
+    }
+    for (port, security) in candidates {
+        if report.ports.iter().all(|p| p.port != port) {
+            let port_report = examine_port(service, &domain, &addresses, port, security, &trust);
+            report.ports.push(port_report);
+        }
+    }
+
+    let login_port = report
+        .ports
+        .iter()
+        .find(|p| p.security == user.security && p.usable())
+        .map(|p| p.port);
+    report.login = login_port.map(|port| (port, try_login(user, service, port)));
+    report
+}
+
+/// Finds the problems shown by reports on a user's servers.
+pub fn diagnose(reports: &[HostReport]) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    for report in reports {
+        let domain = report.domain.clone();
+        if let Err(error) = &report.addresses {
+            findings.push(Finding::Unresolved {
+                domain,
+                error: error.clone(),
+            });
+            continue;
+        }
+        if report.ports.iter().all(|p| p.connected.is_err()) {
+            findings.push(Finding::Unreachable { domain });
+            continue;
+        }
+
+        let configured = report
+            .ports
+            .iter()
+            .find(|p| p.port == report.configured_port);
+        if !configured.is_some_and(|p| p.usable()) {
+            if let Some(other) = report.ports.iter().find(|p| p.usable()) {
+                findings.push(Finding::OtherPort {
+                    domain: domain.clone(),
+                    port: other.port,
+                    starttls: other.offers_starttls(),
+                });
+            }
+        }
+        for port in report.ports.iter() {
+            let Some(Ok(tls)) = &port.tls else {
+                continue;
+            };
+            if let Err(error) = &tls.trusted {
+                findings.push(Finding::Untrusted {
+                    domain: domain.clone(),
+                    port: port.port,
+                    error: error.clone(),
+                });
+            }
+            if let Some(version @ ("TLS 1.1" | "TLS 1.0")) = tls.version {
+                findings.push(Finding::OutdatedTls {
+                    domain: domain.clone(),
+                    port: port.port,
+                    version,
+                });
+            }
+        }
+        if let Some((port, result)) = &report.login {
+            let login_port = report.ports.iter().find(|p| p.port == *port);
+            if !login_port.is_some_and(|p| p.offers_login(report.service)) {
+                findings.push(Finding::NoLogin {
+                    domain: domain.clone(),
+                    port: *port,
+                });
+            }
+
+            // Logging in fails on an untrusted certificate before credentials are sent
+            let untrusted = login_port.is_some_and(|p| {
+                p.security == Security::Tls
+                    && matches!(&p.tls, Some(Ok(tls)) if tls.trusted.is_err())
+            });
+            if let (Err(error), false) = (result, untrusted) {
+                findings.push(Finding::LoginRejected {
+                    domain,
+                    error: error.clone(),
+                });
+            }
+        }
+    }
+    findings
+}
+
+/// Shows a report on a server.
+pub fn show_report(ui: &mut dyn Ui, report: &HostReport, prompts: &Prompts) {
+    ui.show(&format!(
+        "{}{} {}...",
+        prompts.doctor_checking,
+        report.service.name(),
+        report.domain
+    ));
+    match &report.addresses {
+        Ok(addresses) => {
+            let addresses = addresses.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+            ui.show(&format!(
+                "{}{}",
+                prompts.doctor_resolved,
+                addresses.join(", ")
+            ));
+        }
+        Err(e) => ui.show_error(&format!("{}{}", prompts.doctor_resolve_fail, e)),
+    }
+
+    for port in report.ports.iter() {
+        let mode = match port.security {
+            Security::Tls => "TLS",
+            Security::Plaintext if port.offers_starttls() => "STARTTLS",
+            Security::Plaintext => "plaintext",
+        };
+        match &port.connected {
+            Ok(elapsed) => ui.show(&format!(
+                "{}{} ({}), {} ms",
+                prompts.doctor_port_open,
+                port.port,
+                mode,
+                elapsed.as_millis()
+            )),
+            Err(e) => {
+                ui.show_error(&format!(
+                    "{}{}: {}",
+                    prompts.doctor_port_unreachable, port.port, e
+                ));
+                continue;
+            }
+        }
+        match &port.tls {
+            Some(Ok(tls)) => {
+                ui.show(&format!(
+                    "{}{}",
+                    prompts.doctor_tls_version,
+                    tls.version.unwrap_or("?")
+                ));
+                match &tls.trusted {
+                    Ok(()) => ui.show(prompts.doctor_cert_trusted),
+                    Err(e) => ui.show_error(&format!("{}{}", prompts.doctor_cert_untrusted, e)),
+                }
+                if let Some(certificate) = &tls.certificate {
+                    ui.show(&format!(
+                        "{}{}",
+                        prompts.doctor_cert_subject, certificate.subject
+                    ));
+                    ui.show(&format!(
+                        "{}{}",
+                        prompts.doctor_cert_issuer, certificate.issuer
+                    ));
+                    ui.show(&format!(
+                        "{}{} ~ {}",
+                        prompts.doctor_cert_validity, certificate.not_before, certificate.not_after
+                    ));
+                    ui.show(&format!(
+                        "{}{}",
+                        prompts.doctor_cert_names,
+                        certificate.names.join(", ")
+                    ));
+                    ui.show(&format!(
+                        "{}{}",
+                        prompts.doctor_cert_fingerprint, certificate.fingerprint
+                    ));
+                }
+            }
+            Some(Err(e)) => ui.show_error(&format!("{}{}", prompts.doctor_tls_fail, e)),
+            None => {}
+        }
+        match &port.capabilities {
+            Some(Ok(capabilities)) => ui.show(&format!(
+                "{}{}",
+                prompts.doctor_capabilities,
+                capabilities.join(", ")
+            )),
+            Some(Err(e)) => ui.show_error(&format!("{}{}", prompts.doctor_capabilities_fail, e)),
+            None => {}
+        }
+    }
+
+    match &report.login {
+        Some((port, Ok(()))) => ui.show(&format!("{}{}.", prompts.doctor_login_succeed, port)),
+        Some((port, Err(e))) => {
+            ui.show_error(&format!("{}{}: {}", prompts.doctor_login_fail, port, e))
+        }
+        None => {}
+    }
+}
+
+/// Shows the problems found by the doctor, each with a hint to remedy it.
+pub fn show_diagnosis(ui: &mut dyn Ui, findings: &[Finding], prompts: &Prompts) {
+    ui.show(prompts.doctor_diagnosis);
+    if findings.is_empty() {
+        ui.show(prompts.doctor_healthy);
+    }
+    for finding in findings {
+        let text = match finding {
+            Finding::Unresolved { domain, error } => {
+                format!("{}{}: {}", prompts.doctor_hint_unresolved, domain, error)
+            }
+            Finding::Unreachable { domain } => {
+                format!("{}{}", prompts.doctor_hint_unreachable, domain)
+            }
+            Finding::OtherPort {
+                domain,
+                port,
+                starttls,
+            } => format!(
+                "{}{}:{}{}",
+                prompts.doctor_hint_other_port,
+                domain,
+                port,
+                if *starttls { " (STARTTLS)" } else { "" }
+            ),
+            Finding::Untrusted {
+                domain,
+                port,
+                error,
+            } => format!(
+                "{}{}:{}: {}",
+                prompts.doctor_hint_untrusted, domain, port, error
+            ),
+            Finding::OutdatedTls {
+                domain,
+                port,
+                version,
+            } => format!(
+                "{}{}:{}: {}",
+                prompts.doctor_hint_outdated_tls, domain, port, version
+            ),
+            Finding::NoLogin { domain, port } => {
+                format!("{}{}:{}", prompts.doctor_hint_no_login, domain, port)
+            }
+            Finding::LoginRejected { domain, error } => {
+                format!("{}{}: {}", prompts.doctor_hint_login, domain, error)
+            }
+        };
+        ui.show_error(&text);
+    }
+}
+
+impl User {
+    /// Examines the user's SMTP & IMAP servers, showing what's learnt & a diagnosis.
+    ///
+    /// # Returns
+    ///
+    /// The problems found.
+    pub fn doctor(&self, ui: &mut dyn Ui, prompts: &Prompts) -> Vec<Finding> {
+        let mut reports = Vec::new();
+        for service in [Service::Smtp, Service::Imap] {
+            let report = examine(self, service);
+            show_report(ui, &report, prompts);
+            reports.push(report);
+        }
+        let findings = diagnose(&reports);
+        show_diagnosis(ui, &findings, prompts);
+        findings
+    }
+}
@@ -0,0 +1,105 @@
+use std::{fmt, io};
+
+/// Represents an error raised by the user agent's mail operations.
+#[derive(Debug)]
+pub enum Error {
+    /// An error reported by the IMAP client.
+    Imap(imap::Error),
+    /// An I/O error.
+    Io(io::Error),
+    /// The server reported a different UIDVALIDITY for a mailbox than the one recorded,
+    /// so every UID previously obtained from it is stale.
+    UidValidityChanged {
+        mailbox: String,
+        recorded: u32,
+        reported: u32,
+    },
+    /// The message addressed by a UID no longer exists in its mailbox.
+    MessageNotFound { mailbox: String, uid: u32 },
+    /// An error reported by the local search index.
+    Index(tantivy::TantivyError),
+    /// A negative response from the POP3 server, or one that couldn't be understood.
+    Pop3(String),
+    /// An error reported by the JMAP server, or a response that couldn't be understood.
+    Jmap(String),
+    /// A negative response from the SMTP server, or one that couldn't be understood.
+    Smtp(String),
+    /// An operation the mail backend in use can't perform.
+    Unsupported(String),
+}
+
+/// A `Result` with `Error` as its error type.
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Imap(e) => write!(f, "IMAP error: {}", e),
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::UidValidityChanged {
+                mailbox,
+                recorded,
+                reported,
+            } => write!(
+                f,
+                "UIDVALIDITY of \"{}\" changed from {} to {}",
+                mailbox, recorded, reported
+            ),
+            Error::MessageNotFound { mailbox, uid } => {
+                write!(f, "no message with UID {} in \"{}\"", uid, mailbox)
+            }
+            Error::Index(e) => write!(f, "index error: {}", e),
+            Error::Pop3(e) => write!(f, "POP3 error: {}", e),
+            Error::Jmap(e) => write!(f, "JMAP error: {}", e),
+            Error::Smtp(e) => write!(f, "SMTP error: {}", e),
+            Error::Unsupported(e) => write!(f, "not supported by this backend: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Imap(e) => Some(e),
+            Error::Io(e) => Some(e),
+            Error::Index(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<imap::Error> for Error {
+    fn from(e: imap::Error) -> Self {
+        Error::Imap(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<tantivy::TantivyError> for Error {
+    fn from(e: tantivy::TantivyError) -> Self {
+        Error::Index(e)
+    }
+}
+
+impl From<lettre::transport::smtp::Error> for Error {
+    fn from(e: lettre::transport::smtp::Error) -> Self {
+        Error::Smtp(e.to_string())
+    }
+}
+
+/// Formats an error followed by each of its sources, e.g. "SMTP error: ...: Connection
+/// refused (os error 111)".
+pub fn chain(e: &dyn std::error::Error) -> String {
+    let mut text = e.to_string();
+    let mut source = e.source();
+    while let Some(e) = source {
+        text.push_str(&format!(": {}", e));
+        source = e.source();
+    }
+    text
+}
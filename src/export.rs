@@ -0,0 +1,434 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, FixedOffset, Utc};
+use imap::{types::Flag, Connection, Session};
+
+use crate::thread::read_thread_selection;
+use crate::ui::Ui;
+use crate::user::{uid_set, User};
+use crate::vault::{self, VaultWriter};
+use crate::*;
+
+/// Number of messages fetched per `UID FETCH` while exporting.
+const EXPORT_BATCH: usize = 50;
+
+/// First line of a manifest, naming its tab-separated columns.
+pub const MANIFEST_HEADER: &str =
+    "mailbox\tuidvalidity\tuid\tmessage-id\tflags\tinternal-date\tlocation";
+
+/// Represents the format of an archive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    /// A single mbox file, with "From " lines quoted the mboxrd way.
+    Mboxrd,
+    /// A directory of `.eml` files, one per message.
+    Eml,
+}
+
+impl ArchiveFormat {
+    /// Returns the path of the manifest belonging to an archive.
+    pub fn manifest_path(&self, path: &Path) -> PathBuf {
+        match self {
+            ArchiveFormat::Mboxrd => {
+                let mut name = path.as_os_str().to_owned();
+                name.push(".manifest.tsv");
+                PathBuf::from(name)
+            }
+            ArchiveFormat::Eml => path.join("manifest.tsv"),
+        }
+    }
+}
+
+/// Represents a message recorded in an archive's manifest.
+///
+/// `location` is the byte offset of the message's "From " line in an mbox file, or the
+/// message's file name in an `.eml` directory.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub mailbox: String,
+    pub uid_validity: u32,
+    pub uid: u32,
+    pub message_id: Option<String>,
+    pub flags: Vec<String>,
+    pub internal_date: Option<DateTime<FixedOffset>>,
+    pub location: String,
+}
+
+impl ManifestEntry {
+    /// Formats the entry as a manifest line, without the line break.
+    pub fn to_line(&self) -> String {
+        [
+            self.mailbox.clone(),
+            self.uid_validity.to_string(),
+            self.uid.to_string(),
+            self.message_id.clone().unwrap_or_default(),
+            self.flags.join(" "),
+            self.internal_date
+                .map(|d| d.to_rfc3339())
+                .unwrap_or_default(),
+            self.location.clone(),
+        ]
+        .join("\t")
+    }
+
+    /// Parses a manifest line, the header & malformed lines yield `None`.
+    pub fn parse(line: &str) -> Option<ManifestEntry> {
+        let columns = line
+            .trim_end_matches(['\r', '\n'])
+            .split('\t')
+            .collect::<Vec<_>>();
+        let [mailbox, uid_validity, uid, message_id, flags, internal_date, location] = columns[..]
+        else {
+            return None;
+        };
+
+        Some(ManifestEntry {
+            mailbox: mailbox.to_string(),
+            uid_validity: uid_validity.parse().ok()?,
+            uid: uid.parse().ok()?,
+            message_id: Some(message_id.to_string()).filter(|id| !id.is_empty()),
+            flags: flags.split_whitespace().map(String::from).collect(),
+            internal_date: DateTime::parse_from_rfc3339(internal_date).ok(),
+            location: location.to_string(),
+        })
+    }
+}
+
+/// Formats the "From " line starting a message in an mbox file.
+pub fn mbox_from_line(internal_date: Option<DateTime<FixedOffset>>) -> String {
+    let date = internal_date
+        .map(|d| d.with_timezone(&Utc))
+        .unwrap_or_default();
+    format!(
+        "From MAILER-DAEMON {}\n",
+        date.format("%a %b %e %H:%M:%S %Y")
+    )
+}
+
+/// Writes a message to an mbox file the mboxrd way, returning the number of bytes written.
+///
+/// Lines of the message matching `>*From ` are quoted with an additional '>', which is
+/// reversible, so the message's raw bytes can be restored exactly. A line break is added if
+/// the message doesn't end with one, then an empty line separates it from the next message.
+pub fn write_mboxrd<W: Write>(
+    writer: &mut W,
+    raw: &[u8],
+    internal_date: Option<DateTime<FixedOffset>>,
+) -> io::Result<u64> {
+    let from_line = mbox_from_line(internal_date);
+    writer.write_all(from_line.as_bytes())?;
+    let mut written = from_line.len() as u64;
+
+    for line in raw.split_inclusive(|&b| b == b'\n') {
+        let unquoted = &line[line.iter().take_while(|&&b| b == b'>').count()..];
+        if unquoted.starts_with(b"From ") {
+            writer.write_all(b">")?;
+            written += 1;
+        }
+        writer.write_all(line)?;
+        written += line.len() as u64;
+    }
+
+    let ending: &[u8] = if raw.ends_with(b"\n") { b"\n" } else { b"\n\n" };
+    writer.write_all(ending)?;
+    Ok(written + ending.len() as u64)
+}
+
+/// Writes messages into an archive along with its manifest.
+pub struct Exporter {
+    format: ArchiveFormat,
+    path: PathBuf,
+    mbox: Option<VaultWriter>,
+    offset: u64,
+    manifest: VaultWriter,
+    pub count: usize,
+}
+
+impl Exporter {
+    /// Creates an archive at `path`.
+    ///
+    /// An mbox file must be new, so an earlier export isn't overwritten. An `.eml` directory
+    /// must be new or empty, so its files can't be mixed up with the ones of another export. The archive & its manifest are sealed if a vault is unlocked for
+    /// the session.
+    ///
+    /// # Returns
+    ///
+    /// - The `Exporter` if the process succeeds.
+    /// - An `Err` of kind `AlreadyExists` if the mbox file exists or the `.eml` directory
+    ///   isn't empty.
+    /// - An `Err` if creating the archive fails otherwise.
+    pub fn create(path: &Path, format: ArchiveFormat) -> io::Result<Exporter> {
+        let mbox = match format {
+            ArchiveFormat::Mboxrd => {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                Some(VaultWriter::create_new(path)?)
+            }
+            ArchiveFormat::Eml => {
+                let is_empty = match fs::read_dir(path) {
+                    Ok(mut entries) => entries.next().is_none(),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => true,
+                    Err(e) => return Err(e),
+                };
+                if !is_empty {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!("{} isn't empty", path.display()),
+                    ));
+                }
+                fs::create_dir_all(path)?;
+                None
+            }
+        };
+        let mut manifest = VaultWriter::create(&format.manifest_path(path))?;
+        writeln!(manifest, "{}", MANIFEST_HEADER)?;
+
+        Ok(Exporter {
+            format,
+            path: path.to_path_buf(),
+            mbox,
+            offset: 0,
+            manifest,
+            count: 0,
+        })
+    }
+
+    /// Writes a message's raw bytes into the archive & records it in the manifest.
+    pub fn write(&mut self, mut entry: ManifestEntry, raw: &[u8]) -> io::Result<()> {
+        entry.location = match (self.format, self.mbox.as_mut()) {
+            (ArchiveFormat::Mboxrd, Some(mbox)) => {
+                let location = self.offset.to_string();
+                self.offset += write_mboxrd(mbox, raw, entry.internal_date)?;
+                location
+            }
+            _ => {
+                let name = format!("{}.eml", entry.uid);
+                vault::write_file(&self.path.join(&name), raw)?;
+                name
+            }
+        };
+        writeln!(self.manifest, "{}", entry.to_line())?;
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Flushes the archive & its manifest, returning the number of exported messages.
+    pub fn finish(self) -> io::Result<usize> {
+        if let Some(mbox) = self.mbox {
+            mbox.finish()?;
+        }
+        self.manifest.finish()?;
+        Ok(self.count)
+    }
+}
+
+impl User {
+    /// Searches a mailbox with IMAP SEARCH criteria, in ascending UID order.
+    pub fn search_messages(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+        mailbox: &str,
+        criteria: &str,
+    ) -> error::Result<Vec<MessageRef>> {
+        self.select_mailbox(imap_cli, mailbox)?;
+        let uid_validity = self.uid_validity(mailbox).unwrap_or_default();
+
+        let mut uids = imap_cli
+            .uid_search(criteria)?
+            .into_iter()
+            .collect::<Vec<_>>();
+        uids.sort_unstable();
+
+        Ok(uids
+            .into_iter()
+            .map(|uid| MessageRef::new(mailbox, uid_validity, uid))
+            .collect())
+    }
+
+    /// Fetches messages of a mailbox in batches, with what a manifest records about them.
+    ///
+    /// `f` is called in the order of `messages` with each message's entry & raw bytes, the
+    /// entry's location is left empty. Expunged messages are skipped.
+    pub fn fetch_archived_messages<F>(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+        messages: &[MessageRef],
+        mut f: F,
+    ) -> error::Result<()>
+    where
+        F: FnMut(ManifestEntry, &[u8]) -> error::Result<()>,
+    {
+        let Some(first) = messages.first() else {
+            return Ok(());
+        };
+        self.select_message(imap_cli, first)?;
+
+        for batch in messages.chunks(EXPORT_BATCH) {
+            let uids = batch.iter().map(|m| m.uid).collect::<Vec<_>>();
+            let fetches =
+                imap_cli.uid_fetch(uid_set(&uids), "(UID FLAGS INTERNALDATE BODY.PEEK[])")?;
+            let mut fetched = fetches
+                .iter()
+                .filter_map(|m| Some((m.uid?, m)))
+                .collect::<HashMap<_, _>>();
+
+            for message in batch {
+                let Some(fetch) = fetched.remove(&message.uid) else {
+                    continue;
+                };
+                let Some(raw) = fetch.body() else {
+                    continue;
+                };
+                let entry = ManifestEntry {
+                    mailbox: message.mailbox.clone(),
+                    uid_validity: message.uid_validity,
+                    uid: message.uid,
+                    message_id: MessageSummary::parse(message.uid, raw).message_id,
+                    // "\Recent" is session state, it can't be set again
+                    flags: fetch
+                        .flags()
+                        .iter()
+                        .filter(|f| **f != Flag::Recent)
+                        .map(|f| f.to_string())
+                        .collect(),
+                    internal_date: fetch.internal_date(),
+                    location: String::new(),
+                };
+                f(entry, raw)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Exports messages of a mailbox into an archive, keeping their raw bytes.
+    ///
+    /// Messages are written in the order of `messages`, expunged ones are skipped.
+    pub fn export_messages(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+        messages: &[MessageRef],
+        exporter: &mut Exporter,
+    ) -> error::Result<()> {
+        self.fetch_archived_messages(imap_cli, messages, |entry, raw| {
+            Ok(exporter.write(entry, raw)?)
+        })
+    }
+
+    /// Exports a mailbox, a search result or a single message chosen by the user.
+    ///
+    /// # Returns
+    ///
+    /// - The number of exported messages & the archive's path if the process succeeds.
+    /// - An `Err` if it fails.
+    pub fn export(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+        ui: &mut dyn Ui,
+        prompts: &Prompts,
+    ) -> error::Result<(usize, PathBuf)> {
+        // Choose mailbox
+        ui.show(prompts.fetch_mailbox);
+        let mailboxes = self.list_mailboxes(imap_cli)?;
+        for (i, mailbox) in mailboxes.iter().enumerate() {
+            ui.show(&format!("  [{}] {}", i + 1, mailbox));
+        }
+        let mailbox = &mailboxes[ui.read_selection(
+            prompts.fetch_mailbox_selection,
+            prompts.invalid_literal,
+            prompts.fetch_mailbox_literal,
+            prompts.should_be_one_of_below_literal,
+            &RangeUsize::new(1, mailboxes.len()),
+        )? - 1];
+
+        // Choose messages
+        ui.show(prompts.export_scope_list);
+        let messages = match ui.read_selection(
+            prompts.export_scope_selection,
+            prompts.invalid_literal,
+            prompts.action_literal,
+            prompts.should_be_one_of_below_literal,
+            &RangeUsize::new(1, 3),
+        )? {
+            1 => self.list_messages(imap_cli, mailbox)?,
+            2 => {
+                let criteria = ui.read_input(prompts.export_search)?;
+                self.search_messages(imap_cli, mailbox, &criteria)?
+            }
+            3 => {
+                let messages = self.list_messages(imap_cli, mailbox)?;
+                if messages.is_empty() {
+                    messages
+                } else {
+                    let summaries = self.fetch_summaries(imap_cli, &messages)?;
+                    let threads = self.fetch_threads(imap_cli, &messages, &summaries)?;
+                    let (uids, position) =
+                        read_thread_selection(ui, &threads, &summaries, prompts)?;
+                    messages
+                        .into_iter()
+                        .filter(|m| m.uid == uids[position])
+                        .collect()
+                }
+            }
+            _ => unreachable!(), // selection from `read_selection()` should have matched one of the above
+        };
+        if messages.is_empty() {
+            ui.show(&format!("> \"{}\"{}", mailbox, prompts.fetch_mailbox_empty));
+            return Ok((0, PathBuf::new()));
+        }
+
+        // Choose format & path
+        ui.show(prompts.export_format_list);
+        let format = match ui.read_selection(
+            prompts.export_format_selection,
+            prompts.invalid_literal,
+            prompts.export_format_literal,
+            prompts.should_be_one_of_below_literal,
+            &RangeUsize::new(1, 2),
+        )? {
+            1 => ArchiveFormat::Mboxrd,
+            2 => ArchiveFormat::Eml,
+            _ => unreachable!(), // selection from `read_selection()` should have matched one of the above
+        };
+        let path = PathBuf::from(ui.read_input(prompts.export_path)?);
+
+        // Export
+        ui.show(&format!(
+            "{}{}...",
+            prompts.export_exporting,
+            messages.len()
+        ));
+        let mut exporter = Exporter::create(&path, format)?;
+        self.export_messages(imap_cli, &messages, &mut exporter)?;
+        Ok((exporter.finish()?, path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    #[test]
+    fn an_existing_mbox_is_left_alone() {
+        let dir = env::temp_dir().join(format!("eua-export-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("earlier.mbox");
+        fs::write(&path, "From earlier export\n").unwrap();
+
+        let Err(e) = Exporter::create(&path, ArchiveFormat::Mboxrd) else {
+            panic!("an existing mbox should be refused");
+        };
+
+        assert_eq!(e.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read(&path).unwrap(), b"From earlier export\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
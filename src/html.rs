@@ -0,0 +1,132 @@
+use mail_parser::{Message, PartType};
+
+/// Width in columns that rendered HTML is wrapped at.
+pub const RENDER_WIDTH: usize = 76;
+
+/// Renders HTML as plain text for the terminal.
+///
+/// Paragraphs, lists, simple tables and quotes keep their layout, links are turned into
+/// numbered footnotes. Nothing is ever fetched, images are replaced by their alt text.
+pub fn render_html(html: &str, width: usize) -> String {
+    html2text::config::plain()
+        .link_footnotes(true)
+        .string_from_read(html.as_bytes(), width)
+        .unwrap_or_else(|_| html.to_string())
+}
+
+/// Returns the HTML body of a message, if it has no text/plain alternative.
+pub fn html_only_body<'x>(message: &'x Message<'x>) -> Option<&'x str> {
+    let has_plain = message
+        .text_bodies()
+        .any(|part| matches!(part.body, PartType::Text(_)));
+    if has_plain {
+        return None;
+    }
+
+    message.html_bodies().find_map(|part| match &part.body {
+        PartType::Html(html) => Some(html.as_ref()),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, net::TcpListener};
+
+    use mail_parser::MessageParser;
+
+    use super::*;
+
+    fn render(html: &str) -> String {
+        render_html(html, RENDER_WIDTH)
+    }
+
+    #[test]
+    fn paragraphs_are_separated_by_blank_lines() {
+        assert_eq!(
+            render("<p>First paragraph.</p><p>Second one.</p>"),
+            "First paragraph.\n\nSecond one.\n"
+        );
+    }
+
+    #[test]
+    fn lists_keep_their_bullets_and_numbers() {
+        let text =
+            render("<ul><li>apples</li><li>pears</li></ul><ol><li>one</li><li>two</li></ol>");
+
+        assert!(text.contains("* apples\n* pears\n"));
+        assert!(text.contains("1. one\n2. two\n"));
+    }
+
+    #[test]
+    fn links_become_numbered_footnotes() {
+        let text = render(
+            "<p>See <a href=\"https://example.test/a\">the site</a> \
+             and <a href=\"https://example.test/b\">more</a>.</p>",
+        );
+
+        assert!(text.starts_with("See [the site][1] and [more][2]."));
+        assert!(text.contains("\n[1]: https://example.test/a\n[2]: https://example.test/b\n"));
+    }
+
+    #[test]
+    fn tables_keep_their_columns() {
+        let text = render(
+            "<table><tr><th>Name</th><th>Qty</th></tr>\
+             <tr><td>Apples</td><td>3</td></tr></table>",
+        );
+
+        let rows = text.lines().collect::<Vec<_>>();
+        assert!(rows.contains(&"Name  │Qty"));
+        assert!(rows.iter().any(|row| row.trim_end() == "Apples│3"));
+    }
+
+    #[test]
+    fn quotes_are_prefixed() {
+        let text = render("<blockquote><p>Earlier words</p></blockquote><p>Reply</p>");
+
+        assert!(text.starts_with("> Earlier words\n"));
+        assert!(text.contains("\nReply\n"));
+    }
+
+    #[test]
+    fn images_are_never_fetched_nor_shown_as_urls() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let url = format!("http://{}/pixel.gif", listener.local_addr().unwrap());
+
+        let text = render(&format!(
+            "<p>Hi<img src=\"{0}\" alt=\"logo\"></p>\
+             <a href=\"https://example.test/\"><img src=\"{0}\">Home</a>",
+            url
+        ));
+
+        assert!(text.contains("Hi[logo]"));
+        assert!(!text.contains(&url));
+        assert_eq!(text.matches("http").count(), 1);
+        assert!(text.contains("[1]: https://example.test/"));
+        let accepted = listener.accept().map(|_| ());
+        assert_eq!(accepted.unwrap_err().kind(), io::ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn html_is_only_used_without_a_plain_text_part() {
+        let alternative = b"Content-Type: multipart/alternative; boundary=\"b\"\r\n\
+\r\n\
+--b\r\n\
+Content-Type: text/plain\r\n\
+\r\n\
+Plain\r\n\
+--b\r\n\
+Content-Type: text/html\r\n\
+\r\n\
+<p>Rich</p>\r\n\
+--b--\r\n";
+        let html_only = b"Content-Type: text/html\r\n\r\n<p>Rich</p>\r\n";
+
+        let message = MessageParser::default().parse(&alternative[..]).unwrap();
+        assert_eq!(html_only_body(&message), None);
+        let message = MessageParser::default().parse(&html_only[..]).unwrap();
+        assert_eq!(html_only_body(&message).map(str::trim), Some("<p>Rich</p>"));
+    }
+}
@@ -0,0 +1,389 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, FixedOffset, NaiveDateTime};
+use imap::{types::Flag, Connection, Session};
+
+use crate::export::{ArchiveFormat, ManifestEntry};
+use crate::ui::Ui;
+use crate::user::User;
+use crate::vault;
+use crate::*;
+
+/// Represents a message read from an archive.
+///
+/// `location` identifies the message within its archive the way manifests do: the byte
+/// offset of its "From " line in an mbox file, or its file name in an `.eml` directory.
+/// `expunged` is set for messages Thunderbird expunged but left in its mbox file.
+#[derive(Clone, Debug, Default)]
+pub struct ArchivedMessage {
+    pub location: String,
+    pub raw: Vec<u8>,
+    pub flags: Vec<String>,
+    pub internal_date: Option<DateTime<FixedOffset>>,
+    pub expunged: bool,
+}
+
+/// Represents the outcome of importing an archive.
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub appended: usize,
+    pub duplicates: usize,
+    pub expunged: usize,
+    pub resumed: usize,
+}
+
+/// Splits an mboxrd file into messages, undoing the quoting of "From " lines.
+///
+/// # Returns
+///
+/// The byte offset, "From " line & raw bytes of each message.
+pub fn parse_mboxrd(mbox: &[u8]) -> Vec<(usize, String, Vec<u8>)> {
+    let mut messages: Vec<(usize, String, Vec<u8>)> = Vec::new();
+    let mut offset = 0;
+    for line in mbox.split_inclusive(|&b| b == b'\n') {
+        if line.starts_with(b"From ") {
+            let from_line = String::from_utf8_lossy(line).trim_end().to_string();
+            messages.push((offset, from_line, Vec::new()));
+        } else if let Some((_, _, raw)) = messages.last_mut() {
+            let quotes = line.iter().take_while(|&&b| b == b'>').count();
+            if quotes > 0 && line[quotes..].starts_with(b"From ") {
+                raw.extend_from_slice(&line[1..]);
+            } else {
+                raw.extend_from_slice(line);
+            }
+        }
+        offset += line.len();
+    }
+
+    // Drop the empty line separating each message from the next one, LF or CRLF
+    for (_, _, raw) in messages.iter_mut() {
+        if raw.ends_with(b"\r\n") {
+            raw.truncate(raw.len() - 2);
+        } else if raw.ends_with(b"\n") {
+            raw.pop();
+        }
+    }
+    messages
+}
+
+/// Converts bare LF line breaks to CRLF, as IMAP servers expect messages to be appended.
+pub fn to_crlf(raw: &[u8]) -> Vec<u8> {
+    let mut converted = Vec::with_capacity(raw.len() + raw.len() / 32);
+    for (i, &byte) in raw.iter().enumerate() {
+        if byte == b'\n' && (i == 0 || raw[i - 1] != b'\r') {
+            converted.push(b'\r');
+        }
+        converted.push(byte);
+    }
+    converted
+}
+
+/// Parses the date of an mbox "From " line, eg. "From sender Thu Jan  1 00:00:00 1970".
+fn parse_from_line_date(from_line: &str) -> Option<DateTime<FixedOffset>> {
+    let words = from_line.split_whitespace().collect::<Vec<_>>();
+    let date = words.get(2..7)?.join(" ");
+    NaiveDateTime::parse_from_str(&date, "%a %b %d %H:%M:%S %Y")
+        .ok()
+        .map(|d| d.and_utc().fixed_offset())
+}
+
+/// Reads flags from the status headers mail clients write into mbox files.
+///
+/// `Status` & `X-Status` are written by mutt & friends, `X-Mozilla-Status` by Thunderbird.
+/// Thunderbird's 0x0008 bit doesn't mean `\Deleted` but that the message was expunged, it
+/// stays in the mbox file until the folder is compacted.
+///
+/// # Returns
+///
+/// The flags, and whether Thunderbird expunged the message.
+fn parse_status_flags(raw: &[u8]) -> (Vec<String>, bool) {
+    let mut flags = Vec::new();
+    let Some(message) = mail_parser::MessageParser::default().parse_headers(raw) else {
+        return (flags, false);
+    };
+    let header = |name| message.header_raw(name).map(|v| v.trim().to_string());
+
+    let mut status = header("Status").unwrap_or_default();
+    status += &header("X-Status").unwrap_or_default();
+    let mozilla = header("X-Mozilla-Status").and_then(|v| u32::from_str_radix(&v, 16).ok());
+    let mozilla = mozilla.unwrap_or_default();
+
+    for (letter, bit, flag) in [
+        ('R', Some(0x0001), "\\Seen"),
+        ('A', Some(0x0002), "\\Answered"),
+        ('F', Some(0x0004), "\\Flagged"),
+        ('D', None, "\\Deleted"),
+    ] {
+        if status.contains(letter) || bit.is_some_and(|bit| mozilla & bit != 0) {
+            flags.push(flag.to_string());
+        }
+    }
+    (flags, mozilla & 0x0008 != 0)
+}
+
+/// Reads the messages of an mbox file or `.eml` directory, in archive order.
+///
+/// Flags & internal dates come from the archive's manifest if it has one. Otherwise flags
+/// are read from status headers, and internal dates from "From " lines or "Date" headers.
+pub fn read_archive(path: &Path) -> io::Result<Vec<ArchivedMessage>> {
+    let format = if path.is_dir() {
+        ArchiveFormat::Eml
+    } else {
+        ArchiveFormat::Mboxrd
+    };
+
+    let mut messages = match format {
+        ArchiveFormat::Mboxrd => parse_mboxrd(&vault::read_file(path)?)
+            .into_iter()
+            .map(|(offset, from_line, raw)| {
+                let (flags, expunged) = parse_status_flags(&raw);
+                ArchivedMessage {
+                    location: offset.to_string(),
+                    flags,
+                    internal_date: parse_from_line_date(&from_line),
+                    raw,
+                    expunged,
+                }
+            })
+            .collect::<Vec<_>>(),
+        ArchiveFormat::Eml => {
+            let mut names = fs::read_dir(path)?
+                .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+                .filter(|name| name.to_ascii_lowercase().ends_with(".eml"))
+                .collect::<Vec<_>>();
+            names.sort_by_key(|name| (name.len(), name.clone()));
+
+            let mut messages = Vec::new();
+            for name in names {
+                let raw = vault::read_file(&path.join(&name))?;
+                let internal_date = mail_parser::MessageParser::default()
+                    .parse_headers(&raw[..])
+                    .and_then(|m| m.date().cloned())
+                    .and_then(|d| DateTime::parse_from_rfc3339(&d.to_rfc3339()).ok());
+                let (flags, expunged) = parse_status_flags(&raw);
+                messages.push(ArchivedMessage {
+                    location: name,
+                    flags,
+                    internal_date,
+                    raw,
+                    expunged,
+                });
+            }
+            messages
+        }
+    };
+
+    // A manifest written on export knows the messages' flags & internal dates on the server,
+    // its messages weren't expunged there
+    if let Ok(manifest) = vault::read_file(&format.manifest_path(path)) {
+        let entries = String::from_utf8_lossy(&manifest)
+            .lines()
+            .filter_map(ManifestEntry::parse)
+            .map(|entry| (entry.location.clone(), entry))
+            .collect::<HashMap<_, _>>();
+        for message in messages.iter_mut() {
+            if let Some(entry) = entries.get(&message.location) {
+                message.flags = entry.flags.clone();
+                message.internal_date = entry.internal_date.or(message.internal_date);
+                message.expunged = false;
+            }
+        }
+    }
+
+    Ok(messages)
+}
+
+/// Returns the path of the journal recording what has been imported from an archive.
+pub fn journal_path(path: &Path) -> PathBuf {
+    if path.is_dir() {
+        path.join("import.journal")
+    } else {
+        let mut name = path.as_os_str().to_owned();
+        name.push(".import.journal");
+        PathBuf::from(name)
+    }
+}
+
+/// Reads the locations already imported from an archive into a mailbox.
+fn read_journal(path: &Path, mailbox: &str) -> io::Result<HashSet<String>> {
+    let journal = match fs::read_to_string(path) {
+        Ok(journal) => journal,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashSet::new()),
+        Err(e) => return Err(e),
+    };
+
+    Ok(journal
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .filter(|(m, _)| *m == mailbox)
+        .map(|(_, location)| location.to_string())
+        .collect())
+}
+
+impl User {
+    /// Imports an archive into a mailbox via APPEND, keeping flags & internal dates.
+    ///
+    /// Messages whose "Message-ID" already exists in the mailbox are skipped, so are the ones
+    /// Thunderbird expunged rather than appended as `\Deleted`. Each handled message is
+    /// recorded in the archive's journal, so an interrupted import resumes where it stopped
+    /// when run again. `on_progress` is called with the number of handled messages & the
+    /// total after each one.
+    pub fn import_archive<F>(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+        path: &Path,
+        mailbox: &str,
+        mut on_progress: F,
+    ) -> error::Result<ImportReport>
+    where
+        F: FnMut(usize, usize),
+    {
+        let messages = read_archive(path)?;
+        let journal_path = journal_path(path);
+        let imported = read_journal(&journal_path, mailbox)?;
+        let mut journal = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&journal_path)?;
+
+        let existing = self.list_messages(imap_cli, mailbox)?;
+        let mut message_ids = self
+            .fetch_summaries(imap_cli, &existing)?
+            .into_iter()
+            .filter_map(|s| s.message_id)
+            .collect::<HashSet<_>>();
+
+        let mut report = ImportReport::default();
+        for (i, message) in messages.iter().enumerate() {
+            if imported.contains(&message.location) {
+                report.resumed += 1;
+            } else if message.expunged {
+                report.expunged += 1;
+                writeln!(journal, "{}\t{}", mailbox, message.location)?;
+            } else {
+                let message_id = MessageSummary::parse(0, &message.raw).message_id;
+                let is_duplicate = message_id
+                    .as_ref()
+                    .is_some_and(|id| !message_ids.insert(id.clone()));
+                if is_duplicate {
+                    report.duplicates += 1;
+                } else {
+                    let raw = to_crlf(&message.raw);
+                    let mut append = imap_cli.append(mailbox, &raw);
+                    append.flags(message.flags.iter().map(|f| Flag::from(f.as_str())));
+                    if let Some(date) = message.internal_date {
+                        append.internal_date(date);
+                    }
+                    append.finish()?;
+                    report.appended += 1;
+                }
+                writeln!(journal, "{}\t{}", mailbox, message.location)?;
+            }
+            on_progress(i + 1, messages.len());
+        }
+
+        Ok(report)
+    }
+
+    /// Imports an archive chosen by the user into a mailbox, showing progress.
+    pub fn import(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+        ui: &mut dyn Ui,
+        prompts: &Prompts,
+    ) -> error::Result<ImportReport> {
+        let path = PathBuf::from(ui.read_input(prompts.import_path)?);
+
+        // Choose mailbox
+        ui.show(prompts.fetch_mailbox);
+        let mailboxes = self.list_mailboxes(imap_cli)?;
+        for (i, mailbox) in mailboxes.iter().enumerate() {
+            ui.show(&format!("  [{}] {}", i + 1, mailbox));
+        }
+        let mailbox = &mailboxes[ui.read_selection(
+            prompts.import_mailbox_selection,
+            prompts.invalid_literal,
+            prompts.fetch_mailbox_literal,
+            prompts.should_be_one_of_below_literal,
+            &RangeUsize::new(1, mailboxes.len()),
+        )? - 1];
+
+        let report = self.import_archive(imap_cli, &path, mailbox, |done, total| {
+            ui.show_progress(&format!("{}{}/{}", prompts.import_progress, done, total));
+        });
+        ui.show("");
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::write_mboxrd;
+
+    #[test]
+    fn mboxrd_round_trips_messages() {
+        let messages: [&[u8]; 3] = [
+            b"Subject: One\n\nFrom the start\n>From quoted\n>>From twice\n",
+            b"Subject: Two\r\n\r\nFrom here\r\n.\r\n",
+            b"Subject: Three\n\n\n\nTrailing blank lines\n\n",
+        ];
+        let mut mbox = Vec::new();
+        for raw in messages {
+            write_mboxrd(&mut mbox, raw, None).unwrap();
+        }
+
+        let parsed = parse_mboxrd(&mbox);
+
+        assert_eq!(parsed.len(), 3);
+        for ((offset, from_line, raw), expected) in parsed.iter().zip(messages) {
+            assert!(mbox[*offset..].starts_with(from_line.as_bytes()));
+            assert!(from_line.starts_with("From MAILER-DAEMON "));
+            assert_eq!(raw, expected);
+        }
+    }
+
+    #[test]
+    fn mboxrd_ends_messages_with_a_line_break() {
+        let mut mbox = Vec::new();
+        write_mboxrd(&mut mbox, b"Subject: Cut\n\nno line break", None).unwrap();
+        write_mboxrd(&mut mbox, b"Subject: Next\n\nnext\n", None).unwrap();
+
+        let parsed = parse_mboxrd(&mbox);
+
+        assert_eq!(parsed[0].2, b"Subject: Cut\n\nno line break\n");
+        assert_eq!(parsed[1].2, b"Subject: Next\n\nnext\n");
+    }
+
+    #[test]
+    fn thunderbird_expunged_messages_are_marked_rather_than_deleted() {
+        let (flags, expunged) =
+            parse_status_flags(b"X-Mozilla-Status: 0009\r\nSubject: Gone\r\n\r\nbody\r\n");
+
+        assert_eq!(flags, ["\\Seen"]);
+        assert!(expunged);
+    }
+
+    #[test]
+    fn status_d_marks_messages_deleted() {
+        let (flags, expunged) =
+            parse_status_flags(b"Status: RO\nX-Status: AD\nSubject: Old\n\nbody\n");
+
+        assert_eq!(flags, ["\\Seen", "\\Answered", "\\Deleted"]);
+        assert!(!expunged);
+    }
+
+    #[test]
+    fn mboxrd_ignores_text_before_the_first_message() {
+        let parsed = parse_mboxrd(b"junk\nFrom a@b Thu Jan  1 00:00:00 1970\nbody\n");
+
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].0, 5);
+        assert_eq!(parsed[0].2, b"body");
+    }
+}
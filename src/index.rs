@@ -0,0 +1,467 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use mail_parser::{Message, MessageParser};
+use tantivy::{
+    collector::TopDocs,
+    directory::{Directory, RamDirectory},
+    query::QueryParser,
+    schema::{Field, Schema, Value, INDEXED, STORED, STRING, TEXT},
+    Index, IndexSettings, IndexWriter, TantivyDocument, Term,
+};
+
+use crate::attachment::{collect_parsed_attachments, decode_encoded_words};
+use crate::cache::Cache;
+use crate::import::read_archive;
+use crate::ui::Ui;
+use crate::vault;
+use crate::*;
+
+/// Memory budget of the index writer, in bytes.
+const WRITER_MEMORY: usize = 50_000_000;
+
+/// Maximum number of search results shown.
+pub const SEARCH_LIMIT: usize = 20;
+
+/// Name of the file a sealed index is kept in.
+const SEALED_INDEX: &str = "index.sealed";
+
+/// Represents a message found in the local index.
+///
+/// Cached messages are found by `account`, `mailbox` & `uid`, archived ones by `path` &
+/// `location`, the way manifests address them.
+#[derive(Clone, Debug, Default)]
+pub struct SearchHit {
+    pub score: f32,
+    pub account: String,
+    pub mailbox: String,
+    pub uid: u32,
+    pub path: String,
+    pub location: String,
+    pub subject: String,
+    pub from: String,
+    pub date: String,
+}
+
+/// Fields of the index's schema.
+struct Fields {
+    origin: Field,
+    account: Field,
+    mailbox: Field,
+    uid: Field,
+    path: Field,
+    location: Field,
+    subject: Field,
+    from: Field,
+    date: Field,
+    headers: Field,
+    body: Field,
+    attachments: Field,
+}
+
+/// Represents the local full-text index over cached & archived messages.
+///
+/// Messages are indexed from their raw bytes, decoded with their declared charsets &
+/// transfer encodings, so mail that isn't UTF-8 is searchable as well.
+///
+/// If a vault is unlocked for the session, the index lives in memory & is written to disk
+/// as a single sealed file after each change. Each commit then seals & rewrites the whole
+/// index, which takes as long as the index is large, not the change.
+pub struct MailIndex {
+    index: Index,
+    fields: Fields,
+    sealed: bool,
+}
+
+impl MailIndex {
+    /// Opens the index, creating it if needed.
+    pub fn open() -> error::Result<MailIndex> {
+        let mut builder = Schema::builder();
+        let fields = Fields {
+            origin: builder.add_text_field("origin", STRING),
+            account: builder.add_text_field("account", STRING | STORED),
+            mailbox: builder.add_text_field("mailbox", STRING | STORED),
+            uid: builder.add_u64_field("uid", INDEXED | STORED),
+            path: builder.add_text_field("path", STRING | STORED),
+            location: builder.add_text_field("location", STRING | STORED),
+            subject: builder.add_text_field("subject", TEXT | STORED),
+            from: builder.add_text_field("from", TEXT | STORED),
+            date: builder.add_text_field("date", STRING | STORED),
+            headers: builder.add_text_field("headers", TEXT),
+            body: builder.add_text_field("body", TEXT),
+            attachments: builder.add_text_field("attachments", TEXT),
+        };
+        let schema = builder.build();
+
+        let sealed = vault::session().is_some();
+        let index = if sealed {
+            let path = data_dir().join(SEALED_INDEX);
+            let dir = RamDirectory::create();
+            if path.is_file() {
+                for (name, data) in unpack(&vault::read_file(&path)?) {
+                    dir.atomic_write(Path::new(&name), &data)?;
+                }
+                Index::open(dir)?
+            } else {
+                Index::create(dir, schema, IndexSettings::default())?
+            }
+        } else {
+            let dir = data_dir().join("index");
+            if dir.join("meta.json").is_file() {
+                Index::open_in_dir(&dir)?
+            } else {
+                fs::create_dir_all(&dir)?;
+                Index::create_in_dir(&dir, schema)?
+            }
+        };
+
+        Ok(MailIndex {
+            index,
+            fields,
+            sealed,
+        })
+    }
+
+    /// Commits a writer's changes, then writes the index to disk if it's sealed.
+    fn commit(&self, mut writer: IndexWriter) -> error::Result<()> {
+        writer.commit()?;
+        writer.wait_merging_threads()?;
+        if !self.sealed {
+            return Ok(());
+        }
+
+        let directory = self.index.directory();
+        let mut files = directory
+            .list_managed_files()
+            .into_iter()
+            .collect::<Vec<_>>();
+        files.extend(["meta.json", ".managed.json"].map(PathBuf::from));
+        files.sort();
+        files.dedup();
+        let files = files
+            .into_iter()
+            .filter_map(|file| {
+                Some((
+                    file.to_string_lossy().into_owned(),
+                    directory.atomic_read(&file).ok()?,
+                ))
+            })
+            .collect::<Vec<_>>();
+
+        // Write & rename, so an interrupted write never leaves a truncated index behind
+        let path = data_dir().join(SEALED_INDEX);
+        let tmp = path.with_extension("tmp");
+        vault::write_file(&tmp, &pack(&files))?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    /// Builds the document of a message, `None` if it can't be parsed.
+    fn document(&self, raw: &[u8]) -> Option<TantivyDocument> {
+        let message = MessageParser::default().parse(raw)?;
+        let mut document = TantivyDocument::default();
+
+        document.add_text(self.fields.subject, message.subject().unwrap_or_default());
+        document.add_text(self.fields.from, format_from(&message));
+        document.add_text(
+            self.fields.date,
+            message.date().map(|d| d.to_rfc3339()).unwrap_or_default(),
+        );
+        for (name, value) in message.headers_raw() {
+            let value = decode_encoded_words(value.trim());
+            document.add_text(self.fields.headers, format!("{}: {}", name, value));
+        }
+        for text in (0..).map_while(|i| message.body_text(i)) {
+            document.add_text(self.fields.body, text);
+        }
+        for attachment in collect_parsed_attachments(&message) {
+            document.add_text(self.fields.attachments, attachment.filename);
+        }
+
+        Some(document)
+    }
+
+    /// Indexes every message in an account's cache, replacing what was indexed from it before.
+    ///
+    /// # Returns
+    ///
+    /// - The number of indexed messages if the process succeeds.
+    /// - An `Err` if it fails.
+    pub fn index_cache(&self, cache: &Cache) -> error::Result<usize> {
+        let writer: IndexWriter = self.index.writer(WRITER_MEMORY)?;
+        let origin = format!("cache\t{}", cache.account);
+        writer.delete_term(Term::from_field_text(self.fields.origin, &origin));
+
+        let mut count = 0;
+        for name in cache.mailboxes()? {
+            let mailbox = cache.mailbox(&name)?;
+            for uid in mailbox.uids() {
+                let Some(mut document) = self.document(&mailbox.read(uid)?) else {
+                    continue;
+                };
+                document.add_text(self.fields.origin, &origin);
+                document.add_text(self.fields.account, &cache.account);
+                document.add_text(self.fields.mailbox, &name);
+                document.add_u64(self.fields.uid, uid as u64);
+                writer.add_document(document)?;
+                count += 1;
+            }
+        }
+
+        self.commit(writer)?;
+        Ok(count)
+    }
+
+    /// Indexes every message in an mbox file or `.eml` directory, replacing what was indexed
+    /// from it before.
+    ///
+    /// # Returns
+    ///
+    /// - The number of indexed messages if the process succeeds.
+    /// - An `Err` if it fails.
+    pub fn index_archive(&self, path: &Path) -> error::Result<usize> {
+        let path = path.canonicalize()?.to_string_lossy().into_owned();
+        let writer: IndexWriter = self.index.writer(WRITER_MEMORY)?;
+        let origin = format!("archive\t{}", path);
+        writer.delete_term(Term::from_field_text(self.fields.origin, &origin));
+
+        let mut count = 0;
+        for message in read_archive(Path::new(&path))? {
+            let Some(mut document) = self.document(&message.raw) else {
+                continue;
+            };
+            document.add_text(self.fields.origin, &origin);
+            document.add_text(self.fields.path, &path);
+            document.add_text(self.fields.location, &message.location);
+            writer.add_document(document)?;
+            count += 1;
+        }
+
+        self.commit(writer)?;
+        Ok(count)
+    }
+
+    /// Searches the index, best matches first.
+    ///
+    /// `query` uses tantivy's query syntax, eg. `invoice from:alice attachments:pdf`, and
+    /// searches subjects, senders, headers, bodies & attachment filenames by default.
+    pub fn search(&self, query: &str, limit: usize) -> error::Result<Vec<SearchHit>> {
+        let fields = &self.fields;
+        let mut parser = QueryParser::for_index(
+            &self.index,
+            vec![
+                fields.subject,
+                fields.from,
+                fields.headers,
+                fields.body,
+                fields.attachments,
+            ],
+        );
+        parser.set_field_boost(fields.subject, 3.0);
+        parser.set_field_boost(fields.from, 2.0);
+        parser.set_field_boost(fields.attachments, 2.0);
+        let query = parser
+            .parse_query(query)
+            .map_err(tantivy::TantivyError::from)?;
+
+        let searcher = self.index.reader()?.searcher();
+        let mut hits = Vec::new();
+        for (score, address) in searcher.search(&query, &TopDocs::with_limit(limit))? {
+            let document = searcher.doc::<TantivyDocument>(address)?;
+            let text = |field| {
+                document
+                    .get_first(field)
+                    .and_then(|v| v.as_str())
+                    .unwrap_or_default()
+                    .to_string()
+            };
+            hits.push(SearchHit {
+                score,
+                account: text(fields.account),
+                mailbox: text(fields.mailbox),
+                uid: document
+                    .get_first(fields.uid)
+                    .and_then(|v| v.as_u64())
+                    .unwrap_or_default() as u32,
+                path: text(fields.path),
+                location: text(fields.location),
+                subject: text(fields.subject),
+                from: text(fields.from),
+                date: text(fields.date),
+            });
+        }
+
+        Ok(hits)
+    }
+}
+
+/// Moves the index written in plain text before a vault was set up into the sealed index,
+/// shredding its files.
+///
+/// # Returns
+///
+/// - The number of moved files if the process succeeds, 0 if there's no plain index.
+/// - An `Err` if it fails.
+pub fn seal_plain_index() -> io::Result<usize> {
+    let dir = data_dir().join("index");
+    if !dir.join("meta.json").is_file() {
+        return Ok(0);
+    }
+
+    let mut files = Vec::new();
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        // Locks only matter to the writer that held them
+        if path.is_file() && !name.ends_with(".lock") {
+            files.push((name.into_owned(), fs::read(&path)?));
+        }
+    }
+    let path = data_dir().join(SEALED_INDEX);
+    let tmp = path.with_extension("tmp");
+    vault::write_file(&tmp, &pack(&files))?;
+    fs::rename(tmp, path)?;
+
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        if path.is_file() {
+            vault::shred(&path)?;
+        }
+    }
+    fs::remove_dir_all(dir)?;
+    Ok(files.len())
+}
+
+/// Packs named files into one buffer, each as its name's & data's lengths followed by them.
+fn pack(files: &[(String, Vec<u8>)]) -> Vec<u8> {
+    let mut packed = Vec::new();
+    for (name, data) in files {
+        packed.extend_from_slice(&(name.len() as u32).to_be_bytes());
+        packed.extend_from_slice(name.as_bytes());
+        packed.extend_from_slice(&(data.len() as u64).to_be_bytes());
+        packed.extend_from_slice(data);
+    }
+    packed
+}
+
+/// Reverses `pack()`, a truncated buffer yields the files before the truncation.
+fn unpack(mut packed: &[u8]) -> Vec<(String, Vec<u8>)> {
+    let mut files = Vec::new();
+    while let Some((name_len, rest)) = packed.split_first_chunk::<4>() {
+        let name_len = u32::from_be_bytes(*name_len) as usize;
+        let Some((name, rest)) = rest.split_at_checked(name_len) else {
+            break;
+        };
+        let Some((data_len, rest)) = rest.split_first_chunk::<8>() else {
+            break;
+        };
+        let Some((data, rest)) = rest.split_at_checked(u64::from_be_bytes(*data_len) as usize)
+        else {
+            break;
+        };
+        files.push((String::from_utf8_lossy(name).into_owned(), data.to_vec()));
+        packed = rest;
+    }
+    files
+}
+
+/// Formats a message's sender as "Name <address>".
+fn format_from(message: &Message) -> String {
+    message
+        .from()
+        .and_then(|from| from.first())
+        .map(|addr| match (addr.name(), addr.address()) {
+            (Some(name), Some(address)) => format!("{} <{}>", name, address),
+            (name, address) => name.or(address).unwrap_or_default().to_string(),
+        })
+        .unwrap_or_default()
+}
+
+/// Reads the raw bytes of a message found in the index.
+///
+/// An archived message is found by reading its whole archive again, which takes as long as
+/// the archive is large.
+pub fn read_hit(hit: &SearchHit) -> io::Result<Vec<u8>> {
+    if !hit.path.is_empty() {
+        return read_archive(Path::new(&hit.path))?
+            .into_iter()
+            .find(|message| message.location == hit.location)
+            .map(|message| message.raw)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound));
+    }
+
+    match Cache::all()?.into_iter().find(|c| c.account == hit.account) {
+        Some(cache) => cache.mailbox(&hit.mailbox)?.read(hit.uid),
+        None => Err(io::Error::from(io::ErrorKind::NotFound)),
+    }
+}
+
+/// Lets the user update the local index & search it.
+pub fn index_menu(ui: &mut dyn Ui, prompts: &Prompts) -> error::Result<()> {
+    let index = MailIndex::open()?;
+    loop {
+        ui.show(prompts.index_action_list);
+        match ui.read_selection(
+            prompts.action_selection,
+            prompts.invalid_literal,
+            prompts.action_literal,
+            prompts.should_be_one_of_below_literal,
+            &RangeUsize::new(0, 3),
+        )? {
+            0 => return Ok(()),
+            1 => {
+                let query = ui.read_input(prompts.index_query)?;
+                let hits = index.search(&query, SEARCH_LIMIT)?;
+                if hits.is_empty() {
+                    ui.show(prompts.index_no_hits);
+                    continue;
+                }
+
+                ui.show(prompts.index_hits);
+                for (i, hit) in hits.iter().enumerate() {
+                    let place = match hit.path.as_str() {
+                        "" => format!("{}/{}", hit.account, hit.mailbox),
+                        path => path.to_string(),
+                    };
+                    let date = hit.date.get(..10).unwrap_or_default();
+                    ui.show(&format!(
+                        "  [{}] {} - {} ({}, {})",
+                        i + 1,
+                        hit.from,
+                        hit.subject,
+                        place,
+                        date
+                    ));
+                }
+                let selection = ui.read_selection(
+                    prompts.index_hit_selection,
+                    prompts.invalid_literal,
+                    prompts.fetch_message_literal,
+                    prompts.should_be_one_of_below_literal,
+                    &RangeUsize::new(0, hits.len()),
+                )?;
+                if selection > 0 {
+                    let raw = read_hit(&hits[selection - 1])?;
+                    show_body(ui, String::from_utf8_lossy(&raw).into_owned(), prompts);
+                }
+            }
+            2 => {
+                for cache in Cache::all()? {
+                    let count = index.index_cache(&cache)?;
+                    ui.show(&format!(
+                        "{}{}: {}.",
+                        prompts.index_indexed, cache.account, count
+                    ));
+                }
+            }
+            3 => {
+                let path = ui.read_input(prompts.index_archive_path)?;
+                let count = index.index_archive(Path::new(&path))?;
+                ui.show(&format!("{}{}: {}.", prompts.index_indexed, path, count));
+            }
+            _ => unreachable!(), // selection from `read_selection()` should have matched one of the above
+        }
+    }
+}
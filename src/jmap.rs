@@ -0,0 +1,714 @@
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use mail_parser::MessageParser;
+use serde_json::{json, Value};
+use url::{Position, Url};
+
+use crate::attachment::{attachment_menu, collect_parsed_attachments, parsed_attachment_content};
+use crate::thread::{read_thread_selection, thread_locally};
+use crate::trust::{connect_tcp, is_localhost, Trust};
+use crate::ui::Ui;
+use crate::user::User;
+use crate::*;
+
+/// Capabilities every request uses, from RFC 8620 & RFC 8621.
+const USING: [&str; 3] = [
+    "urn:ietf:params:jmap:core",
+    "urn:ietf:params:jmap:mail",
+    "urn:ietf:params:jmap:submission",
+];
+
+/// Number of redirects followed while discovering the JMAP session.
+const MAX_REDIRECTS: usize = 5;
+
+/// Number of most recent emails listed from a mailbox.
+pub const JMAP_QUERY_LIMIT: usize = 200;
+
+/// Represents an HTTP response, with only what JMAP needs of it.
+struct HttpResponse {
+    status: u16,
+    location: Option<String>,
+    body: Vec<u8>,
+}
+
+/// Turns a JSON or protocol problem into an `Error::Jmap`.
+fn jmap_error<E: std::fmt::Display>(e: E) -> error::Error {
+    error::Error::Jmap(e.to_string())
+}
+
+/// Sends an HTTP/1.1 request on its own connection, over TLS for "https" URLs, trusting
+/// the server the way `trust` says.
+///
+/// As every request carries the credentials, plain "http" URLs are refused unless the
+/// server is on this host.
+fn http_request(
+    trust: &Trust,
+    method: &str,
+    url: &Url,
+    authorization: &str,
+    content_type: &str,
+    body: &[u8],
+) -> error::Result<HttpResponse> {
+    let host = url
+        .host_str()
+        .ok_or_else(|| jmap_error("URL has no host"))?;
+    let port = url
+        .port_or_known_default()
+        .ok_or_else(|| jmap_error("URL has no port"))?;
+    let mut stream: Box<dyn ReadWrite> = match url.scheme() {
+        "https" => Box::new(trust.handshake(host, port, connect_tcp(host, port)?)?),
+        "http" if is_localhost(host) => Box::new(connect_tcp(host, port)?),
+        "http" => {
+            return Err(jmap_error(format!(
+                "refusing plain HTTP to {}, use https",
+                host
+            )))
+        }
+        scheme => return Err(jmap_error(format!("unsupported URL scheme {}", scheme))),
+    };
+
+    let request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nAuthorization: {}\r\nAccept: application/json\r\n\
+         Content-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        method,
+        &url[Position::BeforePath..Position::AfterQuery],
+        &url[Position::BeforeHost..Position::AfterPort],
+        authorization,
+        content_type,
+        body.len()
+    );
+    stream.write_all(request.as_bytes())?;
+    stream.write_all(body)?;
+    stream.flush()?;
+
+    // The server closes the connection after the response
+    let mut response = Vec::new();
+    match stream.read_to_end(&mut response) {
+        Ok(_) => {}
+        // Some servers close TLS connections without notifying, the response is complete
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && !response.is_empty() => {}
+        Err(e) => return Err(e.into()),
+    }
+    let split = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| jmap_error("malformed HTTP response"))?;
+    let head = String::from_utf8_lossy(&response[..split]).into_owned();
+    let mut body = response[split + 4..].to_vec();
+
+    let mut lines = head.lines();
+    let status = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| jmap_error("malformed HTTP status line"))?;
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+        .collect::<HashMap<_, _>>();
+    if headers
+        .get("transfer-encoding")
+        .is_some_and(|e| e.eq_ignore_ascii_case("chunked"))
+    {
+        body = dechunk(&body);
+    } else if let Some(len) = headers.get("content-length").and_then(|l| l.parse().ok()) {
+        body.truncate(len);
+    }
+
+    Ok(HttpResponse {
+        status,
+        location: headers.get("location").cloned(),
+        body,
+    })
+}
+
+/// A stream to send an HTTP request on, plain or over TLS.
+trait ReadWrite: Read + Write {}
+
+impl<T: Read + Write> ReadWrite for T {}
+
+/// Decodes a body sent with chunked transfer encoding.
+fn dechunk(mut chunked: &[u8]) -> Vec<u8> {
+    let mut body = Vec::new();
+    while let Some(end) = chunked.windows(2).position(|w| w == b"\r\n") {
+        let size = String::from_utf8_lossy(&chunked[..end]);
+        let size = size.split(';').next().unwrap_or_default().trim();
+        let Ok(size) = usize::from_str_radix(size, 16) else {
+            break;
+        };
+        if size == 0 || chunked.len() < end + 2 + size {
+            break;
+        }
+        body.extend_from_slice(&chunked[end + 2..end + 2 + size]);
+        chunked = &chunked[(end + 4 + size).min(chunked.len())..];
+    }
+    body
+}
+
+/// Represents a mailbox of a JMAP account.
+#[derive(Clone, Debug)]
+pub struct JmapMailbox {
+    pub id: String,
+    /// The mailbox's name with its parents', separated by '/'.
+    pub name: String,
+    pub role: Option<String>,
+}
+
+/// Represents an email of a JMAP account, with its summary & keywords.
+#[derive(Clone, Debug)]
+pub struct JmapEmail {
+    pub id: String,
+    pub blob_id: String,
+    pub summary: MessageSummary,
+    pub keywords: Vec<String>,
+}
+
+/// Represents a JMAP client, RFC 8620, for mail, RFC 8621.
+pub struct JmapClient {
+    api_url: Url,
+    download_url: String,
+    upload_url: String,
+    account_id: String,
+    authorization: String,
+    trust: Trust,
+}
+
+impl JmapClient {
+    /// Discovers a server's JMAP session through "/.well-known/jmap" & authenticates.
+    ///
+    /// HTTP Basic authentication is tried first, then the password as a bearer token, as
+    /// some providers only accept API tokens. Servers are trusted the way `trust` says.
+    pub fn connect(
+        server: &str,
+        user: &str,
+        password: &str,
+        trust: Trust,
+    ) -> error::Result<JmapClient> {
+        let url = Url::parse(server)
+            .and_then(|server| server.join("/.well-known/jmap"))
+            .map_err(jmap_error)?;
+        let basic = format!(
+            "Basic {}",
+            STANDARD.encode(format!("{}:{}", user, password))
+        );
+        let bearer = format!("Bearer {}", password);
+
+        let mut last_status = 0;
+        for authorization in [basic, bearer] {
+            let (status, session) = JmapClient::session(&trust, url.clone(), &authorization)?;
+            last_status = status;
+            let Some(session) = session else {
+                continue;
+            };
+
+            let field = |name: &str| session[name].as_str().map(String::from);
+            let api_url = field("apiUrl").ok_or_else(|| jmap_error("session has no apiUrl"))?;
+            return Ok(JmapClient {
+                api_url: url.join(&api_url).map_err(jmap_error)?,
+                download_url: field("downloadUrl").unwrap_or_default(),
+                upload_url: field("uploadUrl").unwrap_or_default(),
+                account_id: session["primaryAccounts"]["urn:ietf:params:jmap:mail"]
+                    .as_str()
+                    .ok_or_else(|| jmap_error("session has no mail account"))?
+                    .to_string(),
+                authorization,
+                trust,
+            });
+        }
+        Err(jmap_error(format!(
+            "authentication failed: HTTP {}",
+            last_status
+        )))
+    }
+
+    /// Fetches the session resource, following redirects.
+    ///
+    /// Only redirects within the server's origin are followed, so the credentials are never
+    /// sent to another host or over another scheme.
+    ///
+    /// # Returns
+    ///
+    /// - The response's status & the session, `None` if authentication is refused.
+    /// - An `Err` if it fails otherwise.
+    fn session(
+        trust: &Trust,
+        mut url: Url,
+        authorization: &str,
+    ) -> error::Result<(u16, Option<Value>)> {
+        for _ in 0..MAX_REDIRECTS {
+            let response =
+                http_request(trust, "GET", &url, authorization, "application/json", &[])?;
+            match response.status {
+                200 => {
+                    let session = serde_json::from_slice(&response.body).map_err(jmap_error)?;
+                    return Ok((200, Some(session)));
+                }
+                301 | 302 | 307 | 308 => {
+                    let location = response.location.unwrap_or_default();
+                    let target = url.join(&location).map_err(jmap_error)?;
+                    if target.origin() != url.origin() {
+                        return Err(jmap_error(format!(
+                            "refusing redirect to another origin: {}",
+                            target
+                        )));
+                    }
+                    url = target;
+                }
+                401 | 403 => return Ok((response.status, None)),
+                status => return Err(jmap_error(format!("HTTP {}", status))),
+            }
+        }
+        Err(jmap_error("too many redirects"))
+    }
+
+    /// Sends method calls in one request.
+    ///
+    /// # Returns
+    ///
+    /// - The arguments of each method's response, in order.
+    /// - An `Error::Jmap` if the request or any method fails.
+    pub fn call(&self, method_calls: Value) -> error::Result<Vec<Value>> {
+        let request = json!({ "using": USING, "methodCalls": method_calls });
+        let response = http_request(
+            &self.trust,
+            "POST",
+            &self.api_url,
+            &self.authorization,
+            "application/json",
+            request.to_string().as_bytes(),
+        )?;
+        if response.status != 200 {
+            return Err(jmap_error(format!(
+                "HTTP {}: {}",
+                response.status,
+                String::from_utf8_lossy(&response.body)
+            )));
+        }
+
+        let response: Value = serde_json::from_slice(&response.body).map_err(jmap_error)?;
+        let responses = response["methodResponses"]
+            .as_array()
+            .ok_or_else(|| jmap_error("response has no methodResponses"))?;
+        responses
+            .iter()
+            .map(|response| match response[0].as_str() {
+                Some("error") => Err(jmap_error(&response[1])),
+                _ => match &response[1]["notCreated"] {
+                    Value::Object(errors) if !errors.is_empty() => Err(jmap_error(&response[1])),
+                    _ => match &response[1]["notUpdated"] {
+                        Value::Object(errors) if !errors.is_empty() => {
+                            Err(jmap_error(&response[1]))
+                        }
+                        _ => Ok(response[1].clone()),
+                    },
+                },
+            })
+            .collect()
+    }
+
+    /// Lists the account's mailboxes, sorted by name.
+    pub fn mailboxes(&self) -> error::Result<Vec<JmapMailbox>> {
+        let responses = self.call(json!([[
+            "Mailbox/get",
+            { "accountId": self.account_id, "properties": ["id", "name", "parentId", "role"] },
+            "0"
+        ]]))?;
+        let list = responses[0]["list"].as_array().cloned().unwrap_or_default();
+        let parents = list
+            .iter()
+            .filter_map(|m| {
+                let id = m["id"].as_str()?;
+                Some((id, (m["name"].as_str()?, m["parentId"].as_str())))
+            })
+            .collect::<HashMap<_, _>>();
+
+        let mut mailboxes = list
+            .iter()
+            .filter_map(|m| {
+                let id = m["id"].as_str()?;
+                // Walk up to the root, guarding against cycles
+                let mut path = Vec::new();
+                let mut next = Some(id);
+                while let Some((name, parent)) = next.and_then(|id| parents.get(id)) {
+                    if path.len() > parents.len() {
+                        break;
+                    }
+                    path.push(*name);
+                    next = *parent;
+                }
+                path.reverse();
+                Some(JmapMailbox {
+                    id: id.to_string(),
+                    name: path.join("/"),
+                    role: m["role"].as_str().map(String::from),
+                })
+            })
+            .collect::<Vec<_>>();
+        mailboxes.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(mailboxes)
+    }
+
+    /// Queries the ids of a mailbox's most recent emails, oldest first.
+    pub fn query(&self, mailbox_id: &str, limit: usize) -> error::Result<Vec<String>> {
+        let responses = self.call(json!([[
+            "Email/query",
+            {
+                "accountId": self.account_id,
+                "filter": { "inMailbox": mailbox_id },
+                "sort": [{ "property": "receivedAt", "isAscending": false }],
+                "limit": limit
+            },
+            "0"
+        ]]))?;
+        let mut ids = responses[0]["ids"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|id| id.as_str().map(String::from))
+            .collect::<Vec<_>>();
+        ids.reverse();
+        Ok(ids)
+    }
+
+    /// Gets emails by their ids, in the order of `ids`, their summaries numbered from 1.
+    ///
+    /// Emails the server doesn't find are left out.
+    pub fn get_emails(&self, ids: &[String]) -> error::Result<Vec<JmapEmail>> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let responses = self.call(json!([[
+            "Email/get",
+            {
+                "accountId": self.account_id,
+                "ids": ids,
+                "properties": [
+                    "id", "blobId", "subject", "from", "messageId", "inReplyTo", "references",
+                    "keywords"
+                ]
+            },
+            "0"
+        ]]))?;
+        let list = responses[0]["list"].as_array().cloned().unwrap_or_default();
+        let mut emails = list
+            .iter()
+            .filter_map(|e| Some((e["id"].as_str()?.to_string(), e)))
+            .collect::<HashMap<_, _>>();
+
+        let texts = |value: &Value| {
+            value
+                .as_array()
+                .map(|values| {
+                    values
+                        .iter()
+                        .filter_map(|v| v.as_str().map(String::from))
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default()
+        };
+        // Numbered once the emails the server didn't find are left out
+        Ok(ids
+            .iter()
+            .filter_map(|id| {
+                let email = emails.remove(id)?;
+                let from = &email["from"][0];
+                let mut ancestors = texts(&email["references"]);
+                for id in texts(&email["inReplyTo"]) {
+                    if !ancestors.contains(&id) {
+                        ancestors.push(id);
+                    }
+                }
+                Some(JmapEmail {
+                    id: id.clone(),
+                    blob_id: email["blobId"].as_str()?.to_string(),
+                    summary: MessageSummary {
+                        subject: email["subject"].as_str().unwrap_or_default().to_string(),
+                        from: from["name"]
+                            .as_str()
+                            .filter(|name| !name.is_empty())
+                            .or(from["email"].as_str())
+                            .unwrap_or_default()
+                            .to_string(),
+                        message_id: texts(&email["messageId"]).into_iter().next(),
+                        ancestors,
+                        ..Default::default()
+                    },
+                    keywords: email["keywords"]
+                        .as_object()
+                        .map(|k| k.keys().cloned().collect())
+                        .unwrap_or_default(),
+                })
+            })
+            .enumerate()
+            .map(|(i, mut email)| {
+                email.summary.uid = i as u32 + 1;
+                email
+            })
+            .collect())
+    }
+
+    /// Downloads a blob, eg. an email's raw RFC822 bytes.
+    pub fn download(&self, blob_id: &str) -> error::Result<Vec<u8>> {
+        let url = self
+            .download_url
+            .replace("{accountId}", &self.account_id)
+            .replace("{blobId}", blob_id)
+            .replace("{type}", "application%2Foctet-stream")
+            .replace("{name}", "message.eml");
+        let url = self.api_url.join(&url).map_err(jmap_error)?;
+        let response = http_request(
+            &self.trust,
+            "GET",
+            &url,
+            &self.authorization,
+            "application/json",
+            &[],
+        )?;
+        match response.status {
+            200 => Ok(response.body),
+            status => Err(jmap_error(format!("HTTP {}", status))),
+        }
+    }
+
+    /// Uploads a blob, returning its id.
+    pub fn upload(&self, data: &[u8], content_type: &str) -> error::Result<String> {
+        let url = self.upload_url.replace("{accountId}", &self.account_id);
+        let url = self.api_url.join(&url).map_err(jmap_error)?;
+        let response = http_request(
+            &self.trust,
+            "POST",
+            &url,
+            &self.authorization,
+            content_type,
+            data,
+        )?;
+        if !(200..300).contains(&response.status) {
+            return Err(jmap_error(format!("HTTP {}", response.status)));
+        }
+        let uploaded: Value = serde_json::from_slice(&response.body).map_err(jmap_error)?;
+        uploaded["blobId"]
+            .as_str()
+            .map(String::from)
+            .ok_or_else(|| jmap_error("upload has no blobId"))
+    }
+
+    /// Sets or clears a keyword of an email, eg. "$seen" or "$flagged".
+    pub fn set_keyword(&self, id: &str, keyword: &str, on: bool) -> error::Result<()> {
+        let value = match on {
+            true => Value::Bool(true),
+            false => Value::Null,
+        };
+        self.call(json!([[
+            "Email/set",
+            {
+                "accountId": self.account_id,
+                "update": { (id): { (format!("keywords/{}", keyword)): value } }
+            },
+            "0"
+        ]]))?;
+        Ok(())
+    }
+
+    /// Submits a raw message for delivery, storing it in the "sent" mailbox.
+    ///
+    /// The identity with the sender's address is used, or the account's first one.
+    pub fn submit(&self, email_addr: &str, raw: &[u8]) -> error::Result<()> {
+        let responses = self.call(json!([[
+            "Identity/get",
+            { "accountId": self.account_id },
+            "0"
+        ]]))?;
+        let identities = responses[0]["list"].as_array().cloned().unwrap_or_default();
+        let identity = identities
+            .iter()
+            .find(|i| i["email"].as_str() == Some(email_addr))
+            .or(identities.first())
+            .and_then(|i| i["id"].as_str())
+            .ok_or_else(|| jmap_error("account has no identity to send as"))?
+            .to_string();
+
+        // Keep the message in the sent mailbox, or wherever messages can be kept
+        let mailboxes = self.mailboxes()?;
+        let mailbox = ["sent", "drafts", "inbox"]
+            .iter()
+            .find_map(|role| mailboxes.iter().find(|m| m.role.as_deref() == Some(role)))
+            .or(mailboxes.first())
+            .ok_or_else(|| jmap_error("account has no mailbox"))?;
+
+        let blob_id = self.upload(raw, "message/rfc822")?;
+        self.call(json!([
+            [
+                "Email/import",
+                {
+                    "accountId": self.account_id,
+                    "emails": {
+                        "message": {
+                            "blobId": blob_id,
+                            "mailboxIds": { (mailbox.id.clone()): true },
+                            "keywords": { "$seen": true }
+                        }
+                    }
+                },
+                "0"
+            ],
+            [
+                "EmailSubmission/set",
+                {
+                    "accountId": self.account_id,
+                    "create": {
+                        "submission": { "identityId": identity, "emailId": "#message" }
+                    }
+                },
+                "1"
+            ]
+        ]))?;
+        Ok(())
+    }
+}
+
+impl User {
+    /// Connects to a JMAP server & authenticates with user's credentials, trusting the server
+    /// the way the account's trust settings say.
+    pub fn connect_jmap(&self, server: &str) -> error::Result<JmapClient> {
+        let trust = Trust::load(self.email_addr.as_ref())?;
+        JmapClient::connect(server, self.email_addr.as_ref(), &self.password, trust)
+    }
+
+    /// Logins to a JMAP server the user chooses with user's credentials.
+    ///
+    /// # Returns
+    ///
+    /// - A `JmapClient` as the JMAP client if logging in succeeds.
+    /// - An `Err` if reading user input fails, e.g. once it ends.
+    pub fn login_jmap(&mut self, ui: &mut dyn Ui, prompts: &Prompts) -> io::Result<JmapClient> {
+        loop {
+            let server = match ui.read_input(prompts.jmap_server)? {
+                server if server.is_empty() => format!("https://{}", self.email_addr.domain()),
+                server => server,
+            };
+            ui.show(&format!("{}{}...", prompts.login_connecting, server));
+            match self.connect_jmap(&server) {
+                Ok(jmap_cli) => {
+                    ui.show(&format!("{}{}.", prompts.login_connect_succeed, server));
+                    return Ok(jmap_cli);
+                }
+                Err(e) => {
+                    ui.show_error(&format!("{}{}: {}", prompts.login_connect_fail, server, e));
+                    ui.show(prompts.login_retry);
+                    *self = User::build(ui, prompts)?;
+                }
+            }
+        }
+    }
+}
+
+/// Lets the user choose an email of a JMAP mailbox and read its conversation.
+pub fn browse_jmap(jmap_cli: &JmapClient, ui: &mut dyn Ui, prompts: &Prompts) -> error::Result<()> {
+    ui.show(prompts.fetch_mailbox);
+    let mailboxes = jmap_cli.mailboxes()?;
+    for (i, mailbox) in mailboxes.iter().enumerate() {
+        ui.show(&format!("  [{}] {}", i + 1, mailbox.name));
+    }
+    let mailbox = &mailboxes[ui.read_selection(
+        prompts.fetch_mailbox_selection,
+        prompts.invalid_literal,
+        prompts.fetch_mailbox_literal,
+        prompts.should_be_one_of_below_literal,
+        &RangeUsize::new(1, mailboxes.len()),
+    )? - 1];
+
+    let ids = jmap_cli.query(&mailbox.id, JMAP_QUERY_LIMIT)?;
+    let mut emails = jmap_cli.get_emails(&ids)?;
+    if emails.is_empty() {
+        ui.show(&format!(
+            "> \"{}\"{}",
+            mailbox.name, prompts.fetch_mailbox_empty
+        ));
+        return Ok(());
+    }
+    let summaries = emails.iter().map(|e| e.summary.clone()).collect::<Vec<_>>();
+    let threads = thread_locally(&summaries);
+    let (uids, position) = read_thread_selection(ui, &threads, &summaries, prompts)?;
+    view_jmap_conversation(jmap_cli, &mut emails, &uids, position, ui, prompts)
+}
+
+/// Shows a JMAP email and lets the user step through its conversation and change its
+/// keywords, `uids` number `emails` from 1.
+pub fn view_jmap_conversation(
+    jmap_cli: &JmapClient,
+    emails: &mut [JmapEmail],
+    uids: &[u32],
+    mut position: usize,
+    ui: &mut dyn Ui,
+    prompts: &Prompts,
+) -> error::Result<()> {
+    let mut shown = None;
+    loop {
+        let uid = uids[position];
+        let index = uid as usize - 1;
+        if shown != Some(uid) {
+            let raw = jmap_cli.download(&emails[index].blob_id)?;
+            // Reading an email marks it as seen, like fetching it over IMAP does
+            if !emails[index].keywords.iter().any(|k| k == "$seen") {
+                jmap_cli.set_keyword(&emails[index].id, "$seen", true)?;
+                emails[index].keywords.push("$seen".to_string());
+            }
+
+            show_body(ui, String::from_utf8_lossy(&raw).into_owned(), prompts);
+            if let Some(message) = MessageParser::default().parse(&raw) {
+                let attachments = collect_parsed_attachments(&message);
+                let fetch = |attachment: &_| Ok(parsed_attachment_content(&message, attachment));
+                if let Err(e) = attachment_menu(&attachments, fetch, ui, prompts) {
+                    ui.show_error(&format!("{}{:?}", prompts.attachment_fail, e));
+                }
+            }
+            shown = Some(uid);
+        }
+
+        ui.show(&format!(
+            "{}{}/{}.",
+            prompts.conversation_position,
+            position + 1,
+            uids.len()
+        ));
+        ui.show(&format!(
+            "{}{}",
+            prompts.cache_flags,
+            emails[index].keywords.join(" ")
+        ));
+        ui.show(prompts.cache_action_list);
+        let keyword = match ui.read_selection(
+            prompts.action_selection,
+            prompts.invalid_literal,
+            prompts.action_literal,
+            prompts.should_be_one_of_below_literal,
+            &RangeUsize::new(0, 4),
+        )? {
+            0 => return Ok(()),
+            1 => {
+                position = position.saturating_sub(1);
+                continue;
+            }
+            2 => {
+                position = (position + 1).min(uids.len() - 1);
+                continue;
+            }
+            3 => "$seen",
+            4 => "$flagged",
+            _ => unreachable!(), // selection from `read_selection()` should have matched one of the above
+        };
+
+        let email = &mut emails[index];
+        let on = !email.keywords.iter().any(|k| k == keyword);
+        jmap_cli.set_keyword(&email.id, keyword, on)?;
+        match on {
+            true => email.keywords.push(keyword.to_string()),
+            false => email.keywords.retain(|k| k != keyword),
+        }
+    }
+}
@@ -1,9 +1,38 @@
 use crate::read::*;
 use crate::types::*;
 
+use std::{env, path::PathBuf, process::Command};
+
+pub mod attachment;
+pub mod backup;
+pub mod cache;
+pub mod client;
+pub mod dedup;
+pub mod detach;
+pub mod doctor;
+pub mod error;
+pub mod export;
+pub mod html;
+pub mod import;
+pub mod index;
+pub mod jmap;
+pub mod migrate;
+pub mod pop3;
 pub mod read;
+pub mod retention;
+pub mod smtp;
+pub mod store;
+pub mod tap;
+pub mod thread;
+pub mod trace;
+pub mod transcript;
+pub mod transport;
+pub mod trust;
 pub mod types;
+pub mod ui;
 pub mod user;
+pub mod vault;
+pub mod watch;
 
 /// Represents a natural language for CLI.
 pub enum Lang {
@@ -54,6 +83,182 @@ pub struct Prompts {
     pub fetch_message_list: &'static str,
     pub fetch_message_selection: &'static str,
     pub fetch_message_fail: &'static str,
+    pub attachment_literal: &'static str,
+    pub attachment_list: &'static str,
+    pub attachment_action_list: &'static str,
+    pub attachment_selection: &'static str,
+    pub attachment_save_dir: &'static str,
+    pub attachment_saved: &'static str,
+    pub attachment_command: &'static str,
+    pub attachment_command_exit: &'static str,
+    pub attachment_fail: &'static str,
+    pub conversation_position: &'static str,
+    pub conversation_action_list: &'static str,
+    pub watch_mailbox_selection: &'static str,
+    pub watch_hook: &'static str,
+    pub watch_watching: &'static str,
+    pub watch_new_mail: &'static str,
+    pub watch_hook_fail: &'static str,
+    pub watch_fail: &'static str,
+    pub watch_stopped: &'static str,
+    pub cache_mailbox_selection: &'static str,
+    pub cache_syncing: &'static str,
+    pub cache_sync_fetched: &'static str,
+    pub cache_sync_updated: &'static str,
+    pub cache_sync_removed: &'static str,
+    pub cache_sync_pushed: &'static str,
+    pub cache_sync_fail: &'static str,
+    pub cache_empty: &'static str,
+    pub cache_offline: &'static str,
+    pub cache_offline_action_list: &'static str,
+    pub cache_action_list: &'static str,
+    pub cache_flags: &'static str,
+    pub cache_fail: &'static str,
+    pub export_scope_list: &'static str,
+    pub export_scope_selection: &'static str,
+    pub export_search: &'static str,
+    pub export_format_literal: &'static str,
+    pub export_format_list: &'static str,
+    pub export_format_selection: &'static str,
+    pub export_path: &'static str,
+    pub export_exporting: &'static str,
+    pub export_succeed: &'static str,
+    pub export_fail: &'static str,
+    pub import_path: &'static str,
+    pub import_mailbox_selection: &'static str,
+    pub import_progress: &'static str,
+    pub import_appended: &'static str,
+    pub import_duplicates: &'static str,
+    pub import_expunged: &'static str,
+    pub import_resumed: &'static str,
+    pub import_fail: &'static str,
+    pub migrate_destination: &'static str,
+    pub migrate_root: &'static str,
+    pub migrate_parent: &'static str,
+    pub migrate_rules: &'static str,
+    pub migrate_migrated: &'static str,
+    pub migrate_copied: &'static str,
+    pub migrate_duplicates: &'static str,
+    pub migrate_fail: &'static str,
+    pub index_action_list: &'static str,
+    pub index_query: &'static str,
+    pub index_no_hits: &'static str,
+    pub index_hits: &'static str,
+    pub index_hit_selection: &'static str,
+    pub index_indexed: &'static str,
+    pub index_archive_path: &'static str,
+    pub index_fail: &'static str,
+    pub vault_passphrase: &'static str,
+    pub vault_wrong_passphrase: &'static str,
+    pub vault_unlocked: &'static str,
+    pub vault_new_passphrase: &'static str,
+    pub vault_confirm_passphrase: &'static str,
+    pub vault_passphrase_mismatch: &'static str,
+    pub vault_created: &'static str,
+    pub vault_sealed_existing: &'static str,
+    pub vault_saved_login: &'static str,
+    pub vault_remember_list: &'static str,
+    pub vault_fail: &'static str,
+    pub backup_path: &'static str,
+    pub backup_folder: &'static str,
+    pub backup_succeed: &'static str,
+    pub backup_fail: &'static str,
+    pub restore_path: &'static str,
+    pub restore_restoring: &'static str,
+    pub restore_folders: &'static str,
+    pub restore_appended: &'static str,
+    pub restore_duplicates: &'static str,
+    pub restore_fail: &'static str,
+    pub verify_path: &'static str,
+    pub verify_corrupted: &'static str,
+    pub verify_incomplete: &'static str,
+    pub verify_ok_folders: &'static str,
+    pub verify_ok_messages: &'static str,
+    pub verify_fail: &'static str,
+    pub retention_action_list: &'static str,
+    pub retention_rules: &'static str,
+    pub retention_no_rules: &'static str,
+    pub retention_mailbox_selection: &'static str,
+    pub retention_days: &'static str,
+    pub retention_days_literal: &'static str,
+    pub retention_target: &'static str,
+    pub retention_rule_selection: &'static str,
+    pub retention_rule_literal: &'static str,
+    pub retention_older_than: &'static str,
+    pub retention_days_unit: &'static str,
+    pub retention_expunge_literal: &'static str,
+    pub retention_no_changes: &'static str,
+    pub retention_moved: &'static str,
+    pub retention_expunged: &'static str,
+    pub retention_watching: &'static str,
+    pub retention_fail: &'static str,
+    pub dedup_mailbox_selection: &'static str,
+    pub dedup_match_list: &'static str,
+    pub dedup_searching: &'static str,
+    pub dedup_none: &'static str,
+    pub dedup_keep: &'static str,
+    pub dedup_extra: &'static str,
+    pub dedup_found: &'static str,
+    pub dedup_confirm_list: &'static str,
+    pub dedup_removed: &'static str,
+    pub dedup_fail: &'static str,
+    pub largest_mailbox_selection: &'static str,
+    pub largest_none: &'static str,
+    pub largest_list: &'static str,
+    pub largest_selection: &'static str,
+    pub largest_no_attachments: &'static str,
+    pub largest_detached: &'static str,
+    pub largest_fail: &'static str,
+    pub pop3_leave_list: &'static str,
+    pub pop3_action_list: &'static str,
+    pub pop3_retrieved: &'static str,
+    pub pop3_fail: &'static str,
+    pub jmap_server: &'static str,
+    pub jmap_action_list: &'static str,
+    pub jmap_fail: &'static str,
+    pub record_started: &'static str,
+    pub record_saved: &'static str,
+    pub record_fail: &'static str,
+    pub trace_started: &'static str,
+    pub trace_fail: &'static str,
+    pub doctor_checking: &'static str,
+    pub doctor_resolved: &'static str,
+    pub doctor_resolve_fail: &'static str,
+    pub doctor_port_open: &'static str,
+    pub doctor_port_unreachable: &'static str,
+    pub doctor_tls_version: &'static str,
+    pub doctor_tls_fail: &'static str,
+    pub doctor_cert_trusted: &'static str,
+    pub doctor_cert_untrusted: &'static str,
+    pub doctor_cert_subject: &'static str,
+    pub doctor_cert_issuer: &'static str,
+    pub doctor_cert_validity: &'static str,
+    pub doctor_cert_names: &'static str,
+    pub doctor_cert_fingerprint: &'static str,
+    pub doctor_capabilities: &'static str,
+    pub doctor_capabilities_fail: &'static str,
+    pub doctor_login_succeed: &'static str,
+    pub doctor_login_fail: &'static str,
+    pub doctor_diagnosis: &'static str,
+    pub doctor_healthy: &'static str,
+    pub doctor_hint_unresolved: &'static str,
+    pub doctor_hint_unreachable: &'static str,
+    pub doctor_hint_other_port: &'static str,
+    pub doctor_hint_untrusted: &'static str,
+    pub doctor_hint_outdated_tls: &'static str,
+    pub doctor_hint_no_login: &'static str,
+    pub doctor_hint_login: &'static str,
+    pub trust_action_list: &'static str,
+    pub trust_ca_files: &'static str,
+    pub trust_pinning: &'static str,
+    pub trust_localhost: &'static str,
+    pub trust_on: &'static str,
+    pub trust_off: &'static str,
+    pub trust_ca_path: &'static str,
+    pub trust_ca_added: &'static str,
+    pub trust_ca_selection: &'static str,
+    pub trust_ca_literal: &'static str,
+    pub trust_fail: &'static str,
 }
 
 /// A `Prompts` constant containing all prompts in Chinese-Simplified.
@@ -81,7 +286,19 @@ const PROMPTS_ZH: Prompts = Prompts {
 > 操作:
   [0] 登出 & 关闭
   [1] 写信
-  [2] 收信",
+  [2] 收信
+  [3] 监视新邮件
+  [4] 同步离线缓存
+  [5] 导出邮件
+  [6] 导入邮件
+  [7] 迁移到其他账户
+  [8] 搜索本地索引
+  [9] 备份账户
+  [10] 从备份恢复
+  [11] 校验备份
+  [12] 保留规则
+  [13] 清理重复邮件
+  [14] 查找最大的邮件 & 分离附件",
     action_selection: "  选择操作: ",
     compose_new_message: "> 新邮件:",
     compose_to: "  收件人: ",
@@ -106,6 +323,250 @@ const PROMPTS_ZH: Prompts = Prompts {
     fetch_message_list: "✓ 收到邮件:",
     fetch_message_selection: "  选择邮件: ",
     fetch_message_fail: "! 读取失败: ",
+    attachment_literal: "附件",
+    attachment_list: "> 附件:",
+    attachment_action_list: "\
+> 附件操作:
+  [0] 返回
+  [1] 保存一个附件
+  [2] 保存全部附件
+  [3] 用命令打开附件",
+    attachment_selection: "  选择附件: ",
+    attachment_save_dir: "  保存到目录 (留空则为当前目录): ",
+    attachment_saved: "✓ 已保存到 ",
+    attachment_command: "  命令 (从标准输入读取附件): ",
+    attachment_command_exit: "> 命令已退出, ",
+    attachment_fail: "! 附件处理失败: ",
+    conversation_position: "> 会话中的邮件 ",
+    conversation_action_list: "\
+> 会话操作:
+  [0] 返回
+  [1] 上一封邮件
+  [2] 下一封邮件",
+    watch_mailbox_selection: "  选择要监视的收件箱 (例如 \"1,3\"): ",
+    watch_hook: "  每封新邮件执行的命令 (留空则不执行): ",
+    watch_watching: "> 正在监视新邮件, 按下 `Ctrl+C` 停止...",
+    watch_new_mail: "✉ 新邮件 ",
+    watch_hook_fail: "! 命令执行失败: ",
+    watch_fail: "! 停止监视 ",
+    watch_stopped: "> 已停止监视并登出.",
+    cache_mailbox_selection: "  选择要同步的收件箱 (例如 \"1,3\"): ",
+    cache_syncing: "> 正在同步 ",
+    cache_sync_fetched: "✓ 新邮件: ",
+    cache_sync_updated: ", 更新: ",
+    cache_sync_removed: ", 删除: ",
+    cache_sync_pushed: ", 推送: ",
+    cache_sync_fail: "! 同步失败 ",
+    cache_empty: "> 离线缓存为空, 请先在线同步.",
+    cache_offline: "> 离线模式: 浏览已缓存的邮件.",
+    cache_offline_action_list: "\
+> 操作:
+  [0] 关闭
+  [1] 阅读缓存的邮件
+  [2] 搜索本地索引
+  [3] 校验备份",
+    cache_action_list: "\
+> 邮件操作:
+  [0] 返回
+  [1] 上一封邮件
+  [2] 下一封邮件
+  [3] 切换已读/未读
+  [4] 切换星标",
+    cache_flags: "  标记: ",
+    cache_fail: "! 读取缓存失败: ",
+    export_scope_list: "\
+> 导出范围:
+  [1] 整个收件箱
+  [2] 搜索结果
+  [3] 单封邮件",
+    export_scope_selection: "  选择导出范围: ",
+    export_search: "  搜索条件 (IMAP SEARCH 语法, 例如 \"FROM alice SINCE 1-Jan-2024\"): ",
+    export_format_literal: "格式",
+    export_format_list: "\
+> 导出格式:
+  [1] mboxrd 文件
+  [2] .eml 文件目录",
+    export_format_selection: "  选择格式: ",
+    export_path: "  导出到: ",
+    export_exporting: "> 正在导出邮件, 数量: ",
+    export_succeed: "✓ 已导出邮件, 数量: ",
+    export_fail: "! 导出失败: ",
+    import_path: "  要导入的 mbox 文件或 .eml 目录: ",
+    import_mailbox_selection: "  选择导入到的收件箱: ",
+    import_progress: "> 正在导入 ",
+    import_appended: "✓ 已导入: ",
+    import_duplicates: ", 跳过重复: ",
+    import_expunged: ", 跳过 Thunderbird 已删除的邮件: ",
+    import_resumed: ", 此前已导入: ",
+    import_fail: "! 导入失败: ",
+    migrate_destination: "> 登录目标账户:",
+    migrate_root: "  要迁移的源文件夹 (留空则迁移全部文件夹): ",
+    migrate_parent: "  目标账户中的上级文件夹 (留空则为顶层): ",
+    migrate_rules:
+        "  文件夹名称映射 (例如 \"Sent=Sent Items, Trash=Deleted Items\", 留空则不映射): ",
+    migrate_migrated: "✓ 已迁移 ",
+    migrate_copied: ", 复制: ",
+    migrate_duplicates: ", 跳过重复: ",
+    migrate_fail: "! 迁移失败: ",
+    index_action_list: "\
+> 本地索引:
+  [0] 返回
+  [1] 搜索
+  [2] 从离线缓存更新索引
+  [3] 将归档加入索引",
+    index_query: "  搜索 (例如 \"invoice from:alice attachments:pdf\"): ",
+    index_no_hits: "> 没有匹配的邮件.",
+    index_hits: "✓ 匹配的邮件:",
+    index_hit_selection: "  选择邮件 (0 返回): ",
+    index_indexed: "✓ 已索引邮件 ",
+    index_archive_path: "  要索引的 mbox 文件或 .eml 目录: ",
+    index_fail: "! 索引失败: ",
+    vault_passphrase: "  本地数据已加密, 输入口令以解锁: ",
+    vault_wrong_passphrase: "! 口令错误: 请重新输入.",
+    vault_unlocked: "✓ 本地数据已解锁.",
+    vault_new_passphrase: "  设置用于加密本地数据的口令: ",
+    vault_confirm_passphrase: "  再次输入口令: ",
+    vault_passphrase_mismatch: "! 口令为空或两次输入不一致: 请重新输入.",
+    vault_created: "✓ 本地缓存, 导出, 索引与保存的登录信息将被加密.",
+    vault_sealed_existing: "✓ 已加密此前未加密的本地文件: ",
+    vault_saved_login: "> 使用已保存的登录信息: ",
+    vault_remember_list: "\
+> 在加密存储中保存登录信息?
+  [0] 否
+  [1] 是",
+    vault_fail: "! 无法解锁本地数据: ",
+    backup_path: "  备份到文件 (留空则为当前目录下的新文件): ",
+    backup_folder: "✓ 已备份 ",
+    backup_succeed: "✓ 备份完成, 邮件数量: ",
+    backup_fail: "! 备份失败: ",
+    restore_path: "  要恢复的备份文件: ",
+    restore_restoring: "> 正在校验并恢复备份...",
+    restore_folders: "✓ 已恢复文件夹: ",
+    restore_appended: ", 邮件: ",
+    restore_duplicates: ", 跳过重复: ",
+    restore_fail: "! 恢复失败: ",
+    verify_path: "  要校验的备份文件: ",
+    verify_corrupted: "! 已损坏的邮件: ",
+    verify_incomplete: "! 备份不完整或校验和不匹配.",
+    verify_ok_folders: "✓ 备份完好, 文件夹: ",
+    verify_ok_messages: ", 邮件: ",
+    verify_fail: "! 校验失败: ",
+    retention_action_list: "\
+> 保留规则:
+  [0] 返回
+  [1] 添加规则
+  [2] 删除规则
+  [3] 试运行 (仅列出将要进行的更改)
+  [4] 应用规则",
+    retention_rules: "> 当前规则:",
+    retention_no_rules: "> 尚无保留规则.",
+    retention_mailbox_selection: "  选择规则所属的收件箱: ",
+    retention_days: "  处理早于多少天的邮件: ",
+    retention_days_literal: "天数",
+    retention_target:
+        "  移动到文件夹, YYYY 代表邮件的年份 (例如 \"Archive/YYYY\", 留空则彻底删除): ",
+    retention_rule_selection: "  选择要删除的规则: ",
+    retention_rule_literal: "规则",
+    retention_older_than: " 早于 ",
+    retention_days_unit: " 天 ",
+    retention_expunge_literal: "-> 彻底删除",
+    retention_no_changes: "> 没有需要更改的邮件.",
+    retention_moved: "✓ 保留规则已应用, 移动: ",
+    retention_expunged: ", 删除: ",
+    retention_watching: "> 每小时应用一次保留规则.",
+    retention_fail: "! 应用保留规则失败: ",
+    dedup_mailbox_selection: "  选择要查找重复邮件的收件箱 (例如 \"1,3\"): ",
+    dedup_match_list: "\
+> 如何判断重复:
+  [1] 相同的 Message-ID
+  [2] 相同的内容 (需下载完整邮件)",
+    dedup_searching: "> 正在查找重复邮件...",
+    dedup_none: "> 没有重复邮件.",
+    dedup_keep: "保留 ",
+    dedup_extra: "删除 ",
+    dedup_found: "> 重复的副本数量: ",
+    dedup_confirm_list: "\
+> 删除以上重复的副本?
+  [0] 否
+  [1] 是",
+    dedup_removed: "✓ 已删除重复的副本: ",
+    dedup_fail: "! 清理重复邮件失败: ",
+    largest_mailbox_selection: "  选择要查找的收件箱 (例如 \"1,3\"): ",
+    largest_none: "> 没有邮件.",
+    largest_list: "> 最大的邮件:",
+    largest_selection: "  选择要分离附件的邮件 (0 返回): ",
+    largest_no_attachments: "> 这封邮件没有可分离的附件.",
+    largest_detached: "✓ 已分离附件 ",
+    largest_fail: "! 分离附件失败: ",
+    pop3_leave_list: "\
+> 收取后在服务器上保留邮件?
+  [0] 否, 从服务器删除
+  [1] 是, 保留在服务器上",
+    pop3_action_list: "\
+> 操作:
+  [0] 登出 & 关闭
+  [1] 收取新邮件
+  [2] 阅读已收取的邮件
+  [3] 阅读服务器上的邮件",
+    pop3_retrieved: "✓ 已收取新邮件: ",
+    pop3_fail: "! POP3 操作失败: ",
+    jmap_server: "  JMAP 服务器 (留空则为邮箱域名, 例如 \"https://api.fastmail.com\"): ",
+    jmap_action_list: "\
+> 操作:
+  [0] 关闭
+  [1] 写信
+  [2] 收信",
+    jmap_fail: "! JMAP 操作失败: ",
+    record_started: "> 正在记录 SMTP & IMAP 会话, 凭据将被隐去.",
+    record_saved: "✓ 会话记录已保存: ",
+    record_fail: "! 保存会话记录失败: ",
+    trace_started: "> 正在将 SMTP & IMAP 会话追踪到 ",
+    trace_fail: "! 无法开始追踪: ",
+    doctor_checking: "> 正在检查 ",
+    doctor_resolved: "  ✓ 解析为: ",
+    doctor_resolve_fail: "  ! 解析失败: ",
+    doctor_port_open: "  ✓ 端口可连接: ",
+    doctor_port_unreachable: "  ! 端口无法连接: ",
+    doctor_tls_version: "    TLS 版本: ",
+    doctor_tls_fail: "    ! TLS 握手失败: ",
+    doctor_cert_trusted: "    ✓ 证书受信任",
+    doctor_cert_untrusted: "    ! 证书不受信任: ",
+    doctor_cert_subject: "    证书主体: ",
+    doctor_cert_issuer: "    颁发者: ",
+    doctor_cert_validity: "    有效期: ",
+    doctor_cert_names: "    域名: ",
+    doctor_cert_fingerprint: "    SHA-256 指纹: ",
+    doctor_capabilities: "    支持的功能: ",
+    doctor_capabilities_fail: "    ! 读取支持的功能失败: ",
+    doctor_login_succeed: "  ✓ 登录成功, 端口 ",
+    doctor_login_fail: "  ! 登录失败, 端口 ",
+    doctor_diagnosis: "> 诊断:",
+    doctor_healthy: "✓ 未发现问题.",
+    doctor_hint_unresolved: "! 无法解析服务器名称, 请检查名称是否正确, 以及网络的 DNS 设置: ",
+    doctor_hint_unreachable: "! 服务器的所有端口都无法连接, 可能被防火墙或代理拦截了邮件端口: ",
+    doctor_hint_other_port: "! 配置的端口不可用, 但另一个端口可用, 服务商可能要求使用: ",
+    doctor_hint_untrusted: "! 证书不受信任, 请检查系统时间, 以及是否有代理或杀毒软件拦截了 TLS; 若服务器使用内部 CA 或自签名证书, 可用 `trust` 模式添加 CA 或固定证书: ",
+    doctor_hint_outdated_tls: "! 服务器只支持过时的 TLS 版本, 请联系服务商: ",
+    doctor_hint_no_login: "! 该端口不提供登录方式, 请尝试其他端口: ",
+    doctor_hint_login: "! 登录被拒绝. QQ, 163, Gmail 等服务商需要先在网页设置中开启 IMAP/SMTP, 并使用授权码或应用专用密码代替账户密码: ",
+    trust_action_list: "\
+> TLS 信任设置:
+  [0] 返回
+  [1] 添加 CA 证书文件
+  [2] 删除 CA 证书文件
+  [3] 开启/关闭证书固定 (首次连接时记录指纹)
+  [4] 清除已固定的指纹
+  [5] 开启/关闭接受本机服务器的无效证书 (仅用于测试)",
+    trust_ca_files: "> 额外信任的 CA 证书文件:",
+    trust_pinning: "> 证书固定: ",
+    trust_localhost: "> 接受本机服务器的无效证书: ",
+    trust_on: "开启",
+    trust_off: "关闭",
+    trust_ca_path: "  CA 证书文件路径 (PEM 或 DER): ",
+    trust_ca_added: "✓ 已添加 CA 证书, 数量: ",
+    trust_ca_selection: "  选择要删除的 CA 证书文件: ",
+    trust_ca_literal: "CA 证书文件",
+    trust_fail: "! 更新 TLS 信任设置失败: ",
 };
 
 /// A `Prompts` constant containing all prompts in English.
@@ -133,7 +594,19 @@ const PROMPTS_EN: Prompts = Prompts {
 > Actions:
   [0] Logout & quit
   [1] Compose
-  [2] Fetch message",
+  [2] Fetch message
+  [3] Watch for new messages
+  [4] Sync offline cache
+  [5] Export messages
+  [6] Import messages
+  [7] Migrate to another account
+  [8] Search local index
+  [9] Back up account
+  [10] Restore from backup
+  [11] Verify a backup
+  [12] Retention rules
+  [13] Remove duplicates
+  [14] Find largest messages & detach attachments",
     action_selection: "  Select an action: ",
     compose_new_message: "> New message:",
     compose_to: "  To: ",
@@ -158,6 +631,251 @@ const PROMPTS_EN: Prompts = Prompts {
     fetch_message_list: "✓ Fetched message:",
     fetch_message_selection: "  Select a message: ",
     fetch_message_fail: "! Failed to read message: ",
+    attachment_literal: "attachment",
+    attachment_list: "> Attachments:",
+    attachment_action_list: "\
+> Attachment actions:
+  [0] Back
+  [1] Save an attachment
+  [2] Save all attachments
+  [3] Open an attachment with a command",
+    attachment_selection: "  Select an attachment: ",
+    attachment_save_dir: "  Save to directory (empty for current directory): ",
+    attachment_saved: "✓ Saved to ",
+    attachment_command: "  Command (reads the attachment from stdin): ",
+    attachment_command_exit: "> Command exited with ",
+    attachment_fail: "! Failed to handle attachment: ",
+    conversation_position: "> Message in conversation ",
+    conversation_action_list: "\
+> Conversation actions:
+  [0] Back
+  [1] Previous message
+  [2] Next message",
+    watch_mailbox_selection: "  Select mailboxes to watch (eg. \"1,3\"): ",
+    watch_hook: "  Command to run for each new message (empty for none): ",
+    watch_watching: "> Watching for new messages, press `Ctrl+C` to stop...",
+    watch_new_mail: "✉ New message in ",
+    watch_hook_fail: "! Failed to run hook: ",
+    watch_fail: "! Stopped watching ",
+    watch_stopped: "> Stopped watching & logged out.",
+    cache_mailbox_selection: "  Select mailboxes to sync (eg. \"1,3\"): ",
+    cache_syncing: "> Syncing ",
+    cache_sync_fetched: "✓ New: ",
+    cache_sync_updated: ", updated: ",
+    cache_sync_removed: ", removed: ",
+    cache_sync_pushed: ", pushed: ",
+    cache_sync_fail: "! Failed to sync ",
+    cache_empty: "> The offline cache is empty, sync it while online first.",
+    cache_offline: "> Offline mode: browsing cached messages.",
+    cache_offline_action_list: "\
+> Actions:
+  [0] Quit
+  [1] Read cached messages
+  [2] Search local index
+  [3] Verify a backup",
+    cache_action_list: "\
+> Message actions:
+  [0] Back
+  [1] Previous message
+  [2] Next message
+  [3] Toggle read/unread
+  [4] Toggle flagged",
+    cache_flags: "  Flags: ",
+    cache_fail: "! Failed to read cache: ",
+    export_scope_list: "\
+> Export:
+  [1] Whole mailbox
+  [2] Search result
+  [3] Single message",
+    export_scope_selection: "  Select what to export: ",
+    export_search: "  Search criteria (IMAP SEARCH syntax, eg. \"FROM alice SINCE 1-Jan-2024\"): ",
+    export_format_literal: "format",
+    export_format_list: "\
+> Formats:
+  [1] mboxrd file
+  [2] Directory of .eml files",
+    export_format_selection: "  Select a format: ",
+    export_path: "  Export to: ",
+    export_exporting: "> Exporting messages, count: ",
+    export_succeed: "✓ Exported messages, count: ",
+    export_fail: "! Failed to export: ",
+    import_path: "  mbox file or .eml directory to import: ",
+    import_mailbox_selection: "  Select a mailbox to import into: ",
+    import_progress: "> Importing ",
+    import_appended: "✓ Imported: ",
+    import_duplicates: ", duplicates skipped: ",
+    import_expunged: ", skipped as expunged in Thunderbird: ",
+    import_resumed: ", imported before: ",
+    import_fail: "! Failed to import: ",
+    migrate_destination: "> Login to the destination account:",
+    migrate_root: "  Source folder to migrate (empty for all folders): ",
+    migrate_parent: "  Parent folder in the destination (empty for top level): ",
+    migrate_rules:
+        "  Folder name mappings (eg. \"Sent=Sent Items, Trash=Deleted Items\", empty for none): ",
+    migrate_migrated: "✓ Migrated ",
+    migrate_copied: ", copied: ",
+    migrate_duplicates: ", duplicates skipped: ",
+    migrate_fail: "! Failed to migrate: ",
+    index_action_list: "\
+> Local index:
+  [0] Back
+  [1] Search
+  [2] Update index from offline caches
+  [3] Add an archive to the index",
+    index_query: "  Search (eg. \"invoice from:alice attachments:pdf\"): ",
+    index_no_hits: "> No messages match.",
+    index_hits: "✓ Matching messages:",
+    index_hit_selection: "  Select a message (0 to go back): ",
+    index_indexed: "✓ Indexed messages of ",
+    index_archive_path: "  mbox file or .eml directory to index: ",
+    index_fail: "! Index failed: ",
+    vault_passphrase: "  Local data is encrypted, enter the passphrase to unlock it: ",
+    vault_wrong_passphrase: "! Wrong passphrase: please try again.",
+    vault_unlocked: "✓ Local data unlocked.",
+    vault_new_passphrase: "  Set a passphrase to encrypt local data with: ",
+    vault_confirm_passphrase: "  Enter the passphrase again: ",
+    vault_passphrase_mismatch: "! The passphrase is empty or doesn't match: please try again.",
+    vault_created: "✓ Local caches, exports, indexes & saved logins will be encrypted.",
+    vault_sealed_existing: "✓ Encrypted local files written before: ",
+    vault_saved_login: "> Using the saved login of ",
+    vault_remember_list: "\
+> Save this login in the encrypted store?
+  [0] No
+  [1] Yes",
+    vault_fail: "! Failed to unlock local data: ",
+    backup_path: "  Back up to file (empty for a new file in the current directory): ",
+    backup_folder: "✓ Backed up ",
+    backup_succeed: "✓ Backup finished, messages: ",
+    backup_fail: "! Failed to back up: ",
+    restore_path: "  Backup file to restore: ",
+    restore_restoring: "> Verifying & restoring the backup...",
+    restore_folders: "✓ Restored folders: ",
+    restore_appended: ", messages: ",
+    restore_duplicates: ", duplicates skipped: ",
+    restore_fail: "! Failed to restore: ",
+    verify_path: "  Backup file to verify: ",
+    verify_corrupted: "! Corrupted message: ",
+    verify_incomplete: "! The backup is incomplete or its checksum doesn't match.",
+    verify_ok_folders: "✓ The backup is intact, folders: ",
+    verify_ok_messages: ", messages: ",
+    verify_fail: "! Failed to verify: ",
+    retention_action_list: "\
+> Retention rules:
+  [0] Back
+  [1] Add a rule
+  [2] Remove a rule
+  [3] Dry run (list what would change)
+  [4] Apply rules",
+    retention_rules: "> Current rules:",
+    retention_no_rules: "> No retention rules yet.",
+    retention_mailbox_selection: "  Select the mailbox of the rule: ",
+    retention_days: "  Handle messages older than (days): ",
+    retention_days_literal: "days",
+    retention_target:
+        "  Move to folder, YYYY for the message's year (eg. \"Archive/YYYY\", empty to expunge): ",
+    retention_rule_selection: "  Select the rule to remove: ",
+    retention_rule_literal: "rule",
+    retention_older_than: " older than ",
+    retention_days_unit: " days ",
+    retention_expunge_literal: "-> expunge",
+    retention_no_changes: "> No messages to change.",
+    retention_moved: "✓ Retention rules applied, moved: ",
+    retention_expunged: ", expunged: ",
+    retention_watching: "> Applying retention rules every hour.",
+    retention_fail: "! Failed to apply retention rules: ",
+    dedup_mailbox_selection: "  Select mailboxes to look for duplicates in (eg. \"1,3\"): ",
+    dedup_match_list: "\
+> Match duplicates by:
+  [1] Same Message-ID
+  [2] Same content (fetches whole messages)",
+    dedup_searching: "> Looking for duplicates...",
+    dedup_none: "> No duplicates found.",
+    dedup_keep: "Keep ",
+    dedup_extra: "Delete ",
+    dedup_found: "> Duplicate copies: ",
+    dedup_confirm_list: "\
+> Delete the duplicate copies above?
+  [0] No
+  [1] Yes",
+    dedup_removed: "✓ Deleted duplicate copies: ",
+    dedup_fail: "! Failed to remove duplicates: ",
+    largest_mailbox_selection: "  Select mailboxes to look in (eg. \"1,3\"): ",
+    largest_none: "> No messages.",
+    largest_list: "> Largest messages:",
+    largest_selection: "  Select a message to detach attachments from (0 to go back): ",
+    largest_no_attachments: "> The message has no attachments to detach.",
+    largest_detached: "✓ Detached ",
+    largest_fail: "! Failed to detach attachments: ",
+    pop3_leave_list: "\
+> Leave messages on the server after retrieving them?
+  [0] No, delete them from the server
+  [1] Yes, leave them on the server",
+    pop3_action_list: "\
+> Actions:
+  [0] Logout & quit
+  [1] Retrieve new messages
+  [2] Read retrieved messages
+  [3] Read messages on the server",
+    pop3_retrieved: "✓ Retrieved new messages: ",
+    pop3_fail: "! POP3 operation failed: ",
+    jmap_server:
+        "  JMAP server (empty for the address's domain, eg. \"https://api.fastmail.com\"): ",
+    jmap_action_list: "\
+> Actions:
+  [0] Quit
+  [1] Compose
+  [2] Fetch message",
+    jmap_fail: "! JMAP operation failed: ",
+    record_started: "> Recording SMTP & IMAP sessions, credentials will be redacted.",
+    record_saved: "✓ Saved session transcript: ",
+    record_fail: "! Failed to save session transcripts: ",
+    trace_started: "> Tracing SMTP & IMAP sessions to ",
+    trace_fail: "! Failed to start tracing: ",
+    doctor_checking: "> Checking ",
+    doctor_resolved: "  ✓ Resolved to: ",
+    doctor_resolve_fail: "  ! Failed to resolve: ",
+    doctor_port_open: "  ✓ Reachable port: ",
+    doctor_port_unreachable: "  ! Unreachable port: ",
+    doctor_tls_version: "    TLS version: ",
+    doctor_tls_fail: "    ! TLS handshake failed: ",
+    doctor_cert_trusted: "    ✓ Certificate trusted",
+    doctor_cert_untrusted: "    ! Certificate not trusted: ",
+    doctor_cert_subject: "    Certificate subject: ",
+    doctor_cert_issuer: "    Issuer: ",
+    doctor_cert_validity: "    Valid: ",
+    doctor_cert_names: "    Names: ",
+    doctor_cert_fingerprint: "    SHA-256 fingerprint: ",
+    doctor_capabilities: "    Capabilities: ",
+    doctor_capabilities_fail: "    ! Failed to read capabilities: ",
+    doctor_login_succeed: "  ✓ Logged in on port ",
+    doctor_login_fail: "  ! Failed to log in on port ",
+    doctor_diagnosis: "> Diagnosis:",
+    doctor_healthy: "✓ No problems found.",
+    doctor_hint_unresolved: "! The server's name doesn't resolve, check the name & your network's DNS: ",
+    doctor_hint_unreachable: "! No port of the server is reachable, a firewall or proxy may block mail ports: ",
+    doctor_hint_other_port: "! The configured port is unusable but another one works, the provider may expect: ",
+    doctor_hint_untrusted: "! The certificate isn't trusted, check the system clock & whether a proxy or antivirus intercepts TLS; for a server with an internal CA or a self-signed certificate, add the CA or pin the certificate in `trust` mode: ",
+    doctor_hint_outdated_tls: "! The server only accepts an outdated TLS version, contact the provider: ",
+    doctor_hint_no_login: "! The port offers no way to log in, try another port: ",
+    doctor_hint_login: "! Logging in was rejected. Providers such as QQ, 163 & Gmail need IMAP/SMTP enabled in their web settings & an authorization code or app password instead of the account's password: ",
+    trust_action_list: "\
+> TLS trust settings:
+  [0] Back
+  [1] Add a CA certificate file
+  [2] Remove a CA certificate file
+  [3] Turn certificate pinning on/off (fingerprints are pinned on first connection)
+  [4] Forget pinned fingerprints
+  [5] Turn accepting invalid certificates from localhost servers on/off (for testing only)",
+    trust_ca_files: "> CA certificate files trusted besides the system's:",
+    trust_pinning: "> Certificate pinning: ",
+    trust_localhost: "> Accepting invalid certificates from localhost servers: ",
+    trust_on: "on",
+    trust_off: "off",
+    trust_ca_path: "  Path of the CA certificate file (PEM or DER): ",
+    trust_ca_added: "✓ Added CA certificates: ",
+    trust_ca_selection: "  Select the CA certificate file to remove: ",
+    trust_ca_literal: "CA certificate file",
+    trust_fail: "! Failed to update TLS trust settings: ",
 };
 
 /// Returns the `Prompts` constant corresponding to the specified `Lang`.
@@ -169,3 +887,28 @@ pub fn get_prompts(lang: &Lang) -> &'static Prompts {
 }
 
 const RECONFIRMATION: Confirmation = Confirmation::yes_or_no();
+
+/// Builds a `Command` running a user-provided command line through the system shell.
+pub fn shell_command(command: &str) -> Command {
+    let mut shell = if cfg!(windows) {
+        Command::new("cmd")
+    } else {
+        Command::new("sh")
+    };
+    shell.args([if cfg!(windows) { "/C" } else { "-c" }, command]);
+    shell
+}
+
+/// Returns the directory the agent keeps its local data in.
+///
+/// Defaults to ".echo_unity_archivist" in the user's home directory, `EUA_HOME` overrides it.
+pub fn data_dir() -> PathBuf {
+    if let Some(dir) = env::var_os("EUA_HOME") {
+        return PathBuf::from(dir);
+    }
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+        .unwrap_or_default()
+        .join(".echo_unity_archivist")
+}
@@ -1,76 +1,468 @@
+use std::{error::Error, io, process, sync::Arc};
+
+use echo_unity_archivist::backup::*;
+use echo_unity_archivist::cache::*;
+use echo_unity_archivist::client::{self, Client};
+use echo_unity_archivist::index::*;
+use echo_unity_archivist::store::*;
+use echo_unity_archivist::tap::TapSink;
+use echo_unity_archivist::trace::{self, Tracer};
+use echo_unity_archivist::transcript::Recorder;
+use echo_unity_archivist::trust;
 use echo_unity_archivist::types::*;
+use echo_unity_archivist::ui::*;
 use echo_unity_archivist::user::*;
 use echo_unity_archivist::*;
 
 fn main() {
+    let mut ui = Terminal;
+    if let Err(e) = run(&mut ui) {
+        // Input ending ends the session the way quitting does, once servers are logged out
+        if !read::is_input_ended(&e) {
+            ui.show_error(&format!("! {}", e));
+            process::exit(1);
+        }
+        ui.show("");
+    }
+}
+
+/// Shows why an action failed, unless it failed because user input ended.
+///
+/// # Returns
+///
+/// - An `Err` if user input ended, which ends the session.
+fn show_fail(ui: &mut dyn Ui, prompt: &str, e: &(dyn Error + 'static)) -> io::Result<()> {
+    if read::is_input_ended(e) {
+        return Err(read::input_ended());
+    }
+    ui.show_error(&format!("{}{:?}", prompt, e));
+    Ok(())
+}
+
+/// Runs the mode chosen by the command line arguments until the user quits.
+///
+/// # Returns
+///
+/// - An `Err` if reading user input fails, e.g. once it ends, after logging out of the
+///   servers logged in to.
+fn run(ui: &mut dyn Ui) -> io::Result<()> {
     // Select lang
     let lang_list = "\
 > 语言 Languages:
   [1] 简体中文
   [2] English";
-    println!("{}", lang_list);
+    ui.show(lang_list);
     let lang_selection = "  设置语言 Set language: ";
-    let prompts = match read::read_selection(
+    let prompts = match ui.read_selection(
         lang_selection,
         "! 无效语言 Invalid language",
         "",
         "应为下列值之一 should be one of below",
         &RangeUsize::new(1, 2),
-    ) {
+    )? {
         1 => get_prompts(&Lang::ZH),
         2 => get_prompts(&Lang::EN),
         _ => unreachable!(),
     };
 
     // Welcome message
-    println!("{}", prompts.eua_welcome);
+    ui.show(prompts.eua_welcome);
+
+    // Unlock encrypted local data, or set up encryption when run with `encrypt`
+    let args = std::env::args().collect::<Vec<_>>();
+    if let Err(e) = vault::unlock_session(args.iter().any(|a| a == "encrypt"), ui, prompts) {
+        show_fail(ui, prompts.vault_fail, &e)?;
+    }
+
+    // Browse the offline cache without logging in, when run with `offline`
+    if args.iter().any(|a| a == "offline") {
+        run_offline(ui, prompts)?;
+        ui.read_input(prompts.eua_exit)?;
+        return Ok(());
+    }
+
+    // Retrieve & read mail from a POP3-only account, when run with `pop3`
+    if args.iter().any(|a| a == "pop3") {
+        run_pop3(ui, prompts)?;
+        ui.read_input(prompts.eua_exit)?;
+        return Ok(());
+    }
+
+    // Send & read mail over JMAP instead of SMTP & IMAP, when run with `jmap`
+    if args.iter().any(|a| a == "jmap") {
+        run_jmap(ui, prompts)?;
+        ui.read_input(prompts.eua_exit)?;
+        return Ok(());
+    }
+
+    // Diagnose connections to the SMTP & IMAP servers, when run with `doctor`
+    if args.iter().any(|a| a == "doctor") {
+        ui.show(prompts.login);
+        let user = User::build(ui, prompts)?;
+        user.doctor(ui, prompts);
+        ui.read_input(prompts.eua_exit)?;
+        return Ok(());
+    }
+
+    // Change an account's TLS trust settings before logging in, when run with `trust`
+    if args.iter().any(|a| a == "trust") {
+        let email_addr = ui.read_email(prompts.login_email_addr, prompts.email_addr_invalid)?;
+        if let Err(e) = trust::configure_trust(email_addr.as_ref(), ui, prompts) {
+            if read::is_input_ended(&e) {
+                return Err(e);
+            }
+            ui.show_error(&format!("{}{}", prompts.trust_fail, e));
+        }
+        ui.read_input(prompts.eua_exit)?;
+        return Ok(());
+    }
+
+    // Trace the SMTP & IMAP sessions to a file, when run with `--trace` or `--trace=<level>`
+    let mut taps: Vec<Arc<dyn TapSink>> = Vec::new();
+    let trace_level = args.iter().find_map(|a| match a.as_str() {
+        "--trace" => Some(Ok(trace::DEFAULT_LEVEL)),
+        a => a.strip_prefix("--trace=").map(str::parse),
+    });
+    match trace_level {
+        Some(Ok(level)) => {
+            match trace::new_trace_path().and_then(|path| trace::init(&path, level).map(|_| path)) {
+                Ok(path) => {
+                    ui.show(&format!("{}{}.", prompts.trace_started, path.display()));
+                    taps.push(Arc::new(Tracer));
+                }
+                Err(e) => ui.show_error(&format!("{}{:?}", prompts.trace_fail, e)),
+            }
+        }
+        Some(Err(e)) => ui.show_error(&format!("{}{}", prompts.trace_fail, e)),
+        None => {}
+    }
+
+    // Record the SMTP & IMAP sessions as transcripts, when run with `record`
+    let recorder = args.iter().any(|a| a == "record").then(|| {
+        ui.show(prompts.record_started);
+        Arc::new(Recorder::default())
+    });
+    if let Some(recorder) = &recorder {
+        taps.push(recorder.clone());
+    }
 
     // Login to SMTP & IMAP servers to build clients
-    println!("{}", prompts.login);
-    let mut user = User::build(prompts);
-    let smtp_cli = user.login_smtp(prompts);
-    let mut imap_cli = user.login_imap(prompts);
-    println!("{}{}.", prompts.login_succeed, user.email_addr);
+    ui.show(prompts.login);
+    let mut user = match User::saved() {
+        Some(user) => {
+            ui.show(&format!(
+                "{}{}.",
+                prompts.vault_saved_login, user.email_addr
+            ));
+            user
+        }
+        None => User::build(ui, prompts)?,
+    };
+    if !taps.is_empty() {
+        user.set_tap(Arc::new(taps));
+    }
+    let mut client = client::login(user, ui, prompts)?;
+    ui.show(&format!(
+        "{}{}.",
+        prompts.login_succeed, client.user.email_addr
+    ));
+
+    let result = perform_actions(ui, &mut client, prompts);
+
+    // Logout from IMAP server
+    ui.show(&format!(
+        "{}{}...",
+        prompts.eua_logging_out, client.user.imap_domain
+    ));
+    match client.logout() {
+        Ok(_) => ui.show(prompts.eua_logout_succeed),
+        Err(e) => ui.show_error(&format!("{}{:?}", prompts.eua_logout_fail, e)),
+    }
+
+    // Save the recorded transcripts
+    if let Some(recorder) = recorder {
+        match recorder.save() {
+            Ok(paths) => paths
+                .iter()
+                .for_each(|path| ui.show(&format!("{}{}.", prompts.record_saved, path.display()))),
+            Err(e) => ui.show_error(&format!("{}{:?}", prompts.record_fail, e)),
+        }
+    }
+
+    // Wait for user to exit
+    result?;
+    ui.read_input(prompts.eua_exit)?;
+    Ok(())
+}
+
+/// Browses the offline cache of an account without logging in.
+fn run_offline(ui: &mut dyn Ui, prompts: &Prompts) -> io::Result<()> {
+    ui.show(prompts.cache_offline);
+    let email_addr = ui.read_email(prompts.login_email_addr, prompts.email_addr_invalid)?;
+    let cache = match Cache::open(&email_addr) {
+        Ok(cache) => cache,
+        Err(e) => return show_fail(ui, prompts.cache_fail, &e),
+    };
+    loop {
+        ui.show(prompts.cache_offline_action_list);
+        match ui.read_selection(
+            prompts.action_selection,
+            prompts.invalid_literal,
+            prompts.action_literal,
+            prompts.should_be_one_of_below_literal,
+            &RangeUsize::new(0, 3),
+        )? {
+            0 => return Ok(()),
+            1 => {
+                if let Err(e) = browse_cache(&cache, ui, prompts) {
+                    show_fail(ui, prompts.cache_fail, &e)?;
+                }
+            }
+            2 => {
+                if let Err(e) = index_menu(ui, prompts) {
+                    show_fail(ui, prompts.index_fail, &e)?;
+                }
+            }
+            3 => {
+                if let Err(e) = verify(ui, prompts) {
+                    show_fail(ui, prompts.verify_fail, &e)?;
+                }
+            }
+            _ => unreachable!(), // selection from `read_selection()` should have matched one of the above
+        }
+    }
+}
+
+/// Retrieves & reads mail from a POP3-only account, quitting the session however it ends.
+fn run_pop3(ui: &mut dyn Ui, prompts: &Prompts) -> io::Result<()> {
+    ui.show(prompts.login);
+    let mut user = User::build(ui, prompts)?;
+    let mut pop3_cli = user.login_pop3(ui, prompts)?;
+    ui.show(&format!("{}{}.", prompts.login_succeed, user.email_addr));
+
+    let mut perform_actions = || -> io::Result<()> {
+        ui.show(prompts.pop3_leave_list);
+        let leave_on_server = ui.read_selection(
+            prompts.action_selection,
+            prompts.invalid_literal,
+            prompts.action_literal,
+            prompts.should_be_one_of_below_literal,
+            &RangeUsize::new(0, 1),
+        )? == 1;
+
+        loop {
+            ui.show(prompts.pop3_action_list);
+            match ui.read_selection(
+                prompts.action_selection,
+                prompts.invalid_literal,
+                prompts.action_literal,
+                prompts.should_be_one_of_below_literal,
+                &RangeUsize::new(0, 3),
+            )? {
+                0 => return Ok(()),
+                1 => match user.retrieve_pop3(&mut pop3_cli, leave_on_server) {
+                    Ok(count) => ui.show(&format!("{}{}.", prompts.pop3_retrieved, count)),
+                    Err(e) => show_fail(ui, prompts.pop3_fail, &e)?,
+                },
+                2 => {
+                    let cached = Cache::open(&user.email_addr)
+                        .and_then(|cache| cache.mailbox(pop3::POP3_MAILBOX));
+                    let result = match cached {
+                        Ok(mut cached) => browse_cached_mailbox(&mut cached, ui, prompts),
+                        Err(e) => Err(e.into()),
+                    };
+                    if let Err(e) = result {
+                        show_fail(ui, prompts.cache_fail, &e)?;
+                    }
+                }
+                3 => match fetch_message(&mut Pop3Store::new(&mut pop3_cli), ui, prompts) {
+                    Ok(None) => {}
+                    Ok(Some(conversation)) => read::show_body(ui, conversation.body, prompts),
+                    Err(e) => show_fail(ui, prompts.pop3_fail, &e)?,
+                },
+                _ => unreachable!(), // selection from `read_selection()` should have matched one of the above
+            }
+        }
+    };
+    let result = perform_actions();
+
+    // Messages marked as deleted are only removed once the session ends
+    ui.show(&format!(
+        "{}{}...",
+        prompts.eua_logging_out, user.pop3_domain
+    ));
+    match pop3_cli.quit() {
+        Ok(_) => ui.show(prompts.eua_logout_succeed),
+        Err(e) => ui.show_error(&format!("{}{:?}", prompts.eua_logout_fail, e)),
+    }
+    result
+}
+
+/// Sends & reads mail over JMAP instead of SMTP & IMAP.
+fn run_jmap(ui: &mut dyn Ui, prompts: &Prompts) -> io::Result<()> {
+    ui.show(prompts.login);
+    let mut user = User::build(ui, prompts)?;
+    let mut jmap_cli = user.login_jmap(ui, prompts)?;
+    ui.show(&format!("{}{}.", prompts.login_succeed, user.email_addr));
+
+    loop {
+        ui.show(prompts.jmap_action_list);
+        match ui.read_selection(
+            prompts.action_selection,
+            prompts.invalid_literal,
+            prompts.action_literal,
+            prompts.should_be_one_of_below_literal,
+            &RangeUsize::new(0, 2),
+        )? {
+            0 => return Ok(()),
+            1 => match user.compose_and_send(&mut jmap_cli, ui, prompts) {
+                Ok(None) => ui.show(prompts.send_cancel),
+                Ok(Some(to)) => ui.show(&format!("{}{}.", prompts.send_succeed, to)),
+                Err(e) => show_fail(ui, prompts.send_fail, &*e)?,
+            },
+            2 => {
+                if let Err(e) = jmap::browse_jmap(&jmap_cli, ui, prompts) {
+                    show_fail(ui, prompts.jmap_fail, &e)?;
+                }
+            }
+            _ => unreachable!(), // selection from `read_selection()` should have matched one of the above
+        }
+    }
+}
+
+/// Performs the actions the user chooses through a client until the user quits.
+fn perform_actions(ui: &mut dyn Ui, client: &mut Client, prompts: &Prompts) -> io::Result<()> {
+    // Offer to save a new login, only if it can be stored encrypted
+    if vault::session().is_some() && !client.user.is_saved() {
+        ui.show(prompts.vault_remember_list);
+        let remember = ui.read_selection(
+            prompts.action_selection,
+            prompts.invalid_literal,
+            prompts.action_literal,
+            prompts.should_be_one_of_below_literal,
+            &RangeUsize::new(0, 1),
+        )?;
+        if remember == 1 {
+            if let Err(e) = client.user.remember() {
+                show_fail(ui, prompts.vault_fail, &e)?;
+            }
+        }
+    }
 
     // Build `Selection` for actions
-    let actions = RangeUsize { lo: 0, hi: 2 };
+    let actions = RangeUsize { lo: 0, hi: 14 };
 
     // Perform user actions
     loop {
-        println!("{}", prompts.action_list);
-        match read::read_selection(
+        ui.show(prompts.action_list);
+        // Maintenance flows rely on what only IMAP offers
+        let (user, imap_cli) = client.imap();
+        match ui.read_selection(
             prompts.action_selection,
             prompts.invalid_literal,
             prompts.action_literal,
             prompts.should_be_one_of_below_literal,
             &actions,
-        ) {
-            0 => break,
-            1 => match user.compose_and_send(&smtp_cli, prompts) {
+        )? {
+            0 => return Ok(()),
+            1 => match client.compose_and_send(ui, prompts) {
                 Ok(receiver) => match receiver {
-                    None => println!("{}", prompts.send_cancel),
-                    Some(to) => println!("{}{}.", prompts.send_succeed, to),
+                    None => ui.show(prompts.send_cancel),
+                    Some(to) => ui.show(&format!("{}{}.", prompts.send_succeed, to)),
                 },
-                Err(e) => println!("{}{:?}", prompts.send_fail, e),
+                Err(e) => show_fail(ui, prompts.send_fail, &*e)?,
             },
-            2 => match user.fetch_message(&mut imap_cli, prompts) {
-                Ok(message_body) => match message_body {
-                    None => {}
-                    Some(body) => read::print_body(body, prompts),
-                },
-                Err(e) => println!("{}{:?}", prompts.fetch_message_fail, e),
+            2 => {
+                if let Err(e) = client.read_conversation(ui, prompts) {
+                    show_fail(ui, prompts.fetch_message_fail, &e)?;
+                }
+            }
+            3 => {
+                if let Err(e) = user.watch(imap_cli, ui, prompts) {
+                    show_fail(ui, prompts.watch_fail, &e)?;
+                }
+            }
+            4 => {
+                if let Err(e) = user.sync_cache(imap_cli, ui, prompts) {
+                    show_fail(ui, prompts.cache_fail, &e)?;
+                }
+            }
+            5 => match user.export(imap_cli, ui, prompts) {
+                Ok((0, _)) => {}
+                Ok((count, path)) => ui.show(&format!(
+                    "{}{} -> {}.",
+                    prompts.export_succeed,
+                    count,
+                    path.display()
+                )),
+                Err(e) => show_fail(ui, prompts.export_fail, &e)?,
+            },
+            6 => match user.import(imap_cli, ui, prompts) {
+                Ok(report) => ui.show(&format!(
+                    "{}{}{}{}{}{}{}{}.",
+                    prompts.import_appended,
+                    report.appended,
+                    prompts.import_duplicates,
+                    report.duplicates,
+                    prompts.import_expunged,
+                    report.expunged,
+                    prompts.import_resumed,
+                    report.resumed
+                )),
+                Err(e) => show_fail(ui, prompts.import_fail, &e)?,
+            },
+            7 => {
+                if let Err(e) = user.migrate(imap_cli, ui, prompts) {
+                    show_fail(ui, prompts.migrate_fail, &e)?;
+                }
+            }
+            8 => {
+                if let Err(e) = index_menu(ui, prompts) {
+                    show_fail(ui, prompts.index_fail, &e)?;
+                }
+            }
+            9 => match user.backup(imap_cli, ui, prompts) {
+                Ok((count, path)) => ui.show(&format!(
+                    "{}{} -> {}.",
+                    prompts.backup_succeed,
+                    count,
+                    path.display()
+                )),
+                Err(e) => show_fail(ui, prompts.backup_fail, &e)?,
+            },
+            10 => match user.restore(imap_cli, ui, prompts) {
+                Ok(report) => ui.show(&format!(
+                    "{}{}{}{}{}{}.",
+                    prompts.restore_folders,
+                    report.folders,
+                    prompts.restore_appended,
+                    report.appended,
+                    prompts.restore_duplicates,
+                    report.duplicates
+                )),
+                Err(e) => show_fail(ui, prompts.restore_fail, &e)?,
+            },
+            11 => {
+                if let Err(e) = verify(ui, prompts) {
+                    show_fail(ui, prompts.verify_fail, &e)?;
+                }
+            }
+            12 => {
+                if let Err(e) = user.retention(imap_cli, ui, prompts) {
+                    show_fail(ui, prompts.retention_fail, &e)?;
+                }
+            }
+            13 => match user.dedup(imap_cli, ui, prompts) {
+                Ok(0) => {}
+                Ok(count) => ui.show(&format!("{}{}.", prompts.dedup_removed, count)),
+                Err(e) => show_fail(ui, prompts.dedup_fail, &e)?,
             },
+            14 => {
+                if let Err(e) = user.largest_messages(imap_cli, ui, prompts) {
+                    show_fail(ui, prompts.largest_fail, &e)?;
+                }
+            }
             _ => unreachable!(), // selection from `read_selection()` should have matched one of the above
         }
     }
-
-    // Logout from IMAP server
-    println!("{}{}...", prompts.eua_logging_out, user.imap_domain);
-    match imap_cli.logout() {
-        Ok(_) => println!("{}", prompts.eua_logout_succeed),
-        Err(e) => println!("{}{:?}", prompts.eua_logout_fail, e),
-    }
-
-    // Wait for user to exit
-    let _ = read::read_input(prompts.eua_exit);
 }
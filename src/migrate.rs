@@ -0,0 +1,357 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::PathBuf,
+};
+
+use imap::{types::Flag, Connection, Session};
+use imap_proto::NameAttribute;
+
+use crate::cache::escape;
+use crate::ui::Ui;
+use crate::user::User;
+use crate::*;
+
+/// Represents a folder listed on an IMAP server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Folder {
+    pub name: String,
+    pub delimiter: Option<String>,
+    pub selectable: bool,
+}
+
+/// Represents the outcome of migrating a folder.
+#[derive(Debug, Default)]
+pub struct FolderReport {
+    pub copied: usize,
+    pub duplicates: usize,
+}
+
+/// Maps source folder names to destination folder names.
+///
+/// Rules & the parent are written with '/' between hierarchy levels, whatever delimiters
+/// the servers use. The longest rule matching the start of a folder's path applies.
+#[derive(Clone, Debug, Default)]
+pub struct FolderMap {
+    pub rules: Vec<(Vec<String>, Vec<String>)>,
+    pub parent: Vec<String>,
+    pub delimiter: Option<String>,
+}
+
+/// Splits a '/'-separated folder path into its levels.
+fn levels(path: &str) -> Vec<String> {
+    path.split('/')
+        .map(|level| level.trim().to_string())
+        .filter(|level| !level.is_empty())
+        .collect()
+}
+
+impl FolderMap {
+    /// Builds a map from rules like "Sent=Sent Items, INBOX/Archive=Archive" and a parent
+    /// that migrated folders are placed under, `delimiter` is the destination's.
+    pub fn new(rules: &str, parent: &str, delimiter: Option<String>) -> FolderMap {
+        FolderMap {
+            rules: rules
+                .split(',')
+                .filter_map(|rule| rule.split_once('='))
+                .map(|(from, to)| (levels(from), levels(to)))
+                .filter(|(from, _)| !from.is_empty())
+                .collect(),
+            parent: levels(parent),
+            delimiter,
+        }
+    }
+
+    /// Maps a source folder name, split by the source's `delimiter`, to a destination one.
+    ///
+    /// Levels containing the destination's delimiter get it replaced by '_'. A destination
+    /// without hierarchy gets levels joined by '_'.
+    pub fn map(&self, name: &str, delimiter: Option<&str>) -> String {
+        let mut path = match delimiter {
+            Some(delimiter) if !delimiter.is_empty() => {
+                name.split(delimiter).map(String::from).collect::<Vec<_>>()
+            }
+            _ => vec![name.to_string()],
+        };
+
+        let rule = self
+            .rules
+            .iter()
+            .filter(|(from, _)| path.starts_with(from))
+            .max_by_key(|(from, _)| from.len());
+        if let Some((from, to)) = rule {
+            path.splice(..from.len(), to.iter().cloned());
+        }
+
+        let delimiter = self.delimiter.as_deref().unwrap_or("_");
+        self.parent
+            .iter()
+            .chain(path.iter())
+            .map(|level| level.replace(delimiter, "_"))
+            .collect::<Vec<_>>()
+            .join(delimiter)
+    }
+}
+
+/// Records the greatest UID migrated from each source folder to each target folder, keyed
+/// by UIDVALIDITY.
+///
+/// A rerun with another root, parent or rules maps folders to new targets, which start from
+/// scratch.
+struct MigrationState {
+    path: PathBuf,
+    folders: HashMap<(String, String), (u32, u32)>,
+}
+
+impl MigrationState {
+    /// Loads the state of migrations between two accounts.
+    fn load(source: &User, destination: &User) -> io::Result<MigrationState> {
+        let dir = data_dir().join("migrate");
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(format!(
+            "{}_{}",
+            escape(source.email_addr.as_ref()),
+            escape(destination.email_addr.as_ref())
+        ));
+
+        let state = match fs::read_to_string(&path) {
+            Ok(state) => state,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let folders = state
+            .lines()
+            .filter_map(|line| {
+                let mut columns = line.splitn(4, '\t');
+                let uid_validity = columns.next()?.parse().ok()?;
+                let last_uid = columns.next()?.parse().ok()?;
+                let folder = columns.next()?.to_string();
+                let target = columns.next()?.to_string();
+                Some(((folder, target), (uid_validity, last_uid)))
+            })
+            .collect();
+
+        Ok(MigrationState { path, folders })
+    }
+
+    /// Returns the greatest UID migrated from a folder to a target, 0 if its UIDVALIDITY
+    /// changed since.
+    fn last_uid(&self, folder: &str, target: &str, uid_validity: u32) -> u32 {
+        match self.folders.get(&(folder.to_string(), target.to_string())) {
+            Some(&(recorded, last_uid)) if recorded == uid_validity => last_uid,
+            _ => 0,
+        }
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let state = self
+            .folders
+            .iter()
+            .map(|((folder, target), (uid_validity, last_uid))| {
+                format!("{}\t{}\t{}\t{}\n", uid_validity, last_uid, folder, target)
+            })
+            .collect::<String>();
+        fs::write(&self.path, state)
+    }
+}
+
+impl User {
+    /// Lists all folders on the IMAP server, including non-selectable ones, sorted by name.
+    pub fn list_folders(&self, imap_cli: &mut Session<Connection>) -> error::Result<Vec<Folder>> {
+        let mut folders = imap_cli
+            .list(Some(""), Some("*"))?
+            .iter()
+            .map(|name| Folder {
+                name: name.name().to_string(),
+                delimiter: name.delimiter().map(String::from),
+                selectable: !name.attributes().contains(&NameAttribute::NoSelect),
+            })
+            .collect::<Vec<_>>();
+        folders.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(folders)
+    }
+
+    /// Returns the hierarchy delimiter of the IMAP server, `None` if it has a flat namespace.
+    pub fn hierarchy_delimiter(
+        &self,
+        imap_cli: &mut Session<Connection>,
+    ) -> error::Result<Option<String>> {
+        Ok(imap_cli
+            .list(Some(""), Some(""))?
+            .iter()
+            .find_map(|name| name.delimiter().map(String::from)))
+    }
+
+    /// Copies a folder tree of this account into another account, keeping flags & dates.
+    ///
+    /// Folders under `root`, or all folders if it's empty, are mapped with `map` & created
+    /// on the destination when missing. Messages whose "Message-ID" already exists in the
+    /// destination folder are skipped. The greatest migrated UID of each folder is recorded,
+    /// so a repeated run only copies messages that arrived since. `on_folder` is called with
+    /// the source & destination names after each folder.
+    pub fn migrate_folders<F>(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+        destination: &mut User,
+        destination_cli: &mut Session<Connection>,
+        root: &str,
+        map: &FolderMap,
+        mut on_folder: F,
+    ) -> error::Result<()>
+    where
+        F: FnMut(&str, &str, &FolderReport),
+    {
+        let mut state = MigrationState::load(self, destination)?;
+        let mut existing = destination
+            .list_folders(destination_cli)?
+            .into_iter()
+            .map(|f| f.name)
+            .collect::<HashSet<_>>();
+
+        let folders = self.list_folders(imap_cli)?.into_iter().filter(|f| {
+            let subtree = f.delimiter.as_ref().map(|d| format!("{}{}", root, d));
+            root.is_empty()
+                || f.name == root
+                || subtree.is_some_and(|prefix| f.name.starts_with(&prefix))
+        });
+        for folder in folders {
+            let target = map.map(&folder.name, folder.delimiter.as_deref());
+            if existing.insert(target.clone()) {
+                destination_cli.create(&target)?;
+            }
+            if !folder.selectable {
+                continue;
+            }
+
+            // Only copy messages newer than the last run, skipping ones the destination has
+            let mut messages = self.list_messages(imap_cli, &folder.name)?;
+            let uid_validity = self.uid_validity(&folder.name).unwrap_or_default();
+            let last_uid = state.last_uid(&folder.name, &target, uid_validity);
+            messages.retain(|m| m.uid > last_uid);
+
+            let present = destination.list_messages(destination_cli, &target)?;
+            let mut message_ids = destination
+                .fetch_summaries(destination_cli, &present)?
+                .into_iter()
+                .filter_map(|s| s.message_id)
+                .collect::<HashSet<_>>();
+
+            let mut report = FolderReport::default();
+            let mut migrated = last_uid;
+            let result = self.fetch_archived_messages(imap_cli, &messages, |entry, raw| {
+                let is_duplicate = entry
+                    .message_id
+                    .as_ref()
+                    .is_some_and(|id| !message_ids.insert(id.clone()));
+                if is_duplicate {
+                    report.duplicates += 1;
+                } else {
+                    let mut append = destination_cli.append(&target, raw);
+                    append.flags(entry.flags.iter().map(|f| Flag::from(f.as_str())));
+                    if let Some(date) = entry.internal_date {
+                        append.internal_date(date);
+                    }
+                    append.finish()?;
+                    report.copied += 1;
+                }
+                migrated = migrated.max(entry.uid);
+                Ok(())
+            });
+
+            // Record progress even if the folder failed halfway, then give up on errors
+            state.folders.insert(
+                (folder.name.clone(), target.clone()),
+                (uid_validity, migrated),
+            );
+            state.save()?;
+            result?;
+
+            on_folder(&folder.name, &target, &report);
+        }
+
+        Ok(())
+    }
+
+    /// Migrates a folder tree chosen by the user into another account the user logs in to.
+    pub fn migrate(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+        ui: &mut dyn Ui,
+        prompts: &Prompts,
+    ) -> error::Result<()> {
+        // Login to the destination account
+        ui.show(prompts.migrate_destination);
+        let mut destination = User::build(ui, prompts)?;
+        let mut destination_cli = destination.login_imap(ui, prompts)?;
+
+        // Choose folders & how to name them
+        let root = ui.read_input(prompts.migrate_root)?;
+        let parent = ui.read_input(prompts.migrate_parent)?;
+        let rules = ui.read_input(prompts.migrate_rules)?;
+        let delimiter = destination.hierarchy_delimiter(&mut destination_cli)?;
+        let map = FolderMap::new(&rules, &parent, delimiter);
+
+        let result = self.migrate_folders(
+            imap_cli,
+            &mut destination,
+            &mut destination_cli,
+            &root,
+            &map,
+            |source, target, report| {
+                ui.show(&format!(
+                    "{}\"{}\" -> \"{}\"{}{}{}{}.",
+                    prompts.migrate_migrated,
+                    source,
+                    target,
+                    prompts.migrate_copied,
+                    report.copied,
+                    prompts.migrate_duplicates,
+                    report.duplicates
+                ));
+            },
+        );
+
+        let _ = destination_cli.logout();
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_longest_matching_rule_wins() {
+        let map = FolderMap::new(
+            " INBOX = Imported , INBOX/Archive=Archive, Sent=Sent Items",
+            "",
+            Some("/".to_string()),
+        );
+
+        assert_eq!(map.map("INBOX", Some("/")), "Imported");
+        assert_eq!(map.map("INBOX/Work", Some("/")), "Imported/Work");
+        assert_eq!(map.map("INBOX/Archive/2024", Some("/")), "Archive/2024");
+        assert_eq!(map.map("Sent", Some("/")), "Sent Items");
+        // Rules match whole levels only
+        assert_eq!(map.map("Sent Mail", Some("/")), "Sent Mail");
+    }
+
+    #[test]
+    fn folders_go_under_the_parent_with_the_destinations_delimiter() {
+        let map = FolderMap::new("", "Old/Server", Some(".".to_string()));
+
+        assert_eq!(map.map("INBOX/Work", Some("/")), "Old.Server.INBOX.Work");
+        // A level containing the destination's delimiter keeps it as '_'
+        assert_eq!(map.map("v1.2/notes", Some("/")), "Old.Server.v1_2.notes");
+    }
+
+    #[test]
+    fn flat_sources_and_destinations_keep_one_level() {
+        let flat = FolderMap::new("", "", None);
+        assert_eq!(flat.map("INBOX/Work", Some("/")), "INBOX_Work");
+
+        let map = FolderMap::new("", "", Some("/".to_string()));
+        assert_eq!(map.map("INBOX/Work", None), "INBOX_Work");
+        assert_eq!(map.map("INBOX/Work", Some("")), "INBOX_Work");
+    }
+}
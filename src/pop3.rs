@@ -0,0 +1,425 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    path::PathBuf,
+};
+
+use native_tls::TlsStream;
+
+use crate::cache::{escape, Cache};
+use crate::trust::Trust;
+use crate::ui::Ui;
+use crate::user::User;
+use crate::*;
+
+/// Port of POP3 over implicit TLS, RFC 8314.
+pub const POP3S_PORT: u16 = 995;
+
+/// Port of POP3 in plain text, upgraded with STLS.
+pub const POP3_PORT: u16 = 110;
+
+/// Name of the cached mailbox messages retrieved over POP3 are kept in.
+pub const POP3_MAILBOX: &str = "POP3";
+
+/// Represents a connection to a POP3 server, in plain text until upgraded to TLS.
+enum Pop3Stream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl Read for Pop3Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Pop3Stream::Plain(stream) => stream.read(buf),
+            Pop3Stream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Pop3Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Pop3Stream::Plain(stream) => stream.write(buf),
+            Pop3Stream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Pop3Stream::Plain(stream) => stream.flush(),
+            Pop3Stream::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+/// Represents a POP3 client, RFC 1939, with STLS from RFC 2595.
+///
+/// The server's single mailbox has its messages numbered from 1 for the session, UIDLs
+/// identify them across sessions.
+pub struct Pop3Client {
+    stream: BufReader<Pop3Stream>,
+    /// The timestamp in the server's greeting, APOP is only offered with one.
+    timestamp: Option<String>,
+}
+
+impl Pop3Client {
+    /// Connects to a POP3 server, over TLS from the start if `tls` is given, trusting the
+    /// server the way it says.
+    pub fn connect(domain: &str, port: u16, tls: Option<&Trust>) -> error::Result<Pop3Client> {
+        let stream = match tls {
+            Some(trust) => Pop3Stream::Tls(Box::new(trust.connect(domain, port)?)),
+            None => Pop3Stream::Plain(TcpStream::connect((domain, port))?),
+        };
+
+        let mut client = Pop3Client {
+            stream: BufReader::new(stream),
+            timestamp: None,
+        };
+        let greeting = client.read_status()?;
+        client.timestamp = greeting
+            .find('<')
+            .and_then(|start| Some(&greeting[start..=start + greeting[start..].find('>')?]))
+            .map(String::from);
+        Ok(client)
+    }
+
+    /// Upgrades a plain text connection to a server's port to TLS with STLS, trusting the
+    /// server the way `trust` says.
+    pub fn stls(mut self, domain: &str, port: u16, trust: &Trust) -> error::Result<Pop3Client> {
+        self.command("STLS")?;
+        let Pop3Stream::Plain(tcp) = self.stream.into_inner() else {
+            return Err(error::Error::Pop3("connection is already encrypted".into()));
+        };
+        let stream = trust.handshake(domain, port, tcp)?;
+        Ok(Pop3Client {
+            stream: BufReader::new(Pop3Stream::Tls(Box::new(stream))),
+            timestamp: self.timestamp,
+        })
+    }
+
+    /// Checks whether the connection is encrypted.
+    pub fn is_tls(&self) -> bool {
+        matches!(self.stream.get_ref(), Pop3Stream::Tls(_))
+    }
+
+    /// Reads a status line, "+OK" or "-ERR" followed by text.
+    ///
+    /// # Returns
+    ///
+    /// - The text after "+OK".
+    /// - An `Error::Pop3` with the text after "-ERR".
+    /// - An `Err` if reading fails.
+    fn read_status(&mut self) -> error::Result<String> {
+        let mut line = String::new();
+        if self.stream.read_line(&mut line)? == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        let line = line.trim_end();
+        match line.split_once(' ').unwrap_or((line, "")) {
+            ("+OK", text) => Ok(text.to_string()),
+            ("-ERR", text) => Err(error::Error::Pop3(text.to_string())),
+            _ => Err(error::Error::Pop3(format!("unexpected response: {}", line))),
+        }
+    }
+
+    /// Reads the lines of a multi-line response up to its terminating ".", removing the
+    /// dots stuffed before lines starting with one.
+    fn read_multiline(&mut self) -> error::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if self.stream.read_until(b'\n', &mut line)? == 0 {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+            if line == b".\r\n" || line == b".\n" {
+                return Ok(data);
+            }
+            match line.strip_prefix(b".") {
+                Some(rest) => data.extend_from_slice(rest),
+                None => data.extend_from_slice(&line),
+            }
+        }
+    }
+
+    /// Sends a command & reads its status line.
+    fn command(&mut self, command: &str) -> error::Result<String> {
+        let stream = self.stream.get_mut();
+        stream.write_all(command.as_bytes())?;
+        stream.write_all(b"\r\n")?;
+        stream.flush()?;
+        self.read_status()
+    }
+
+    /// Lists the server's capabilities with CAPA, RFC 2449.
+    pub fn capabilities(&mut self) -> error::Result<Vec<String>> {
+        self.command("CAPA")?;
+        Ok(String::from_utf8_lossy(&self.read_multiline()?)
+            .lines()
+            .map(String::from)
+            .collect())
+    }
+
+    /// Logs in with USER & PASS.
+    pub fn login(&mut self, user: &str, password: &str) -> error::Result<()> {
+        self.command(&format!("USER {}", user))?;
+        self.command(&format!("PASS {}", password))?;
+        Ok(())
+    }
+
+    /// Logs in with APOP, sending a digest of the greeting's timestamp & the password.
+    pub fn apop(&mut self, user: &str, password: &str) -> error::Result<()> {
+        let Some(timestamp) = self.timestamp.as_ref() else {
+            return Err(error::Error::Pop3("APOP is not offered".into()));
+        };
+        let digest = md5(format!("{}{}", timestamp, password).as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>();
+        self.command(&format!("APOP {} {}", user, digest))?;
+        Ok(())
+    }
+
+    /// Checks whether the server offers APOP.
+    pub fn offers_apop(&self) -> bool {
+        self.timestamp.is_some()
+    }
+
+    /// Returns the number of messages & their total size in bytes.
+    pub fn stat(&mut self) -> error::Result<(u32, u64)> {
+        let status = self.command("STAT")?;
+        let mut numbers = status.split_whitespace().map(|n| n.parse().unwrap_or(0));
+        let count = numbers.next().unwrap_or(0) as u32;
+        Ok((count, numbers.next().unwrap_or(0)))
+    }
+
+    /// Lists the UIDL of each message by its number.
+    pub fn uidl(&mut self) -> error::Result<Vec<(u32, String)>> {
+        self.command("UIDL")?;
+        Ok(String::from_utf8_lossy(&self.read_multiline()?)
+            .lines()
+            .filter_map(|line| {
+                let (number, uidl) = line.split_once(' ')?;
+                Some((number.parse().ok()?, uidl.trim().to_string()))
+            })
+            .collect())
+    }
+
+    /// Retrieves a whole message by its number.
+    pub fn retr(&mut self, number: u32) -> error::Result<Vec<u8>> {
+        self.command(&format!("RETR {}", number))?;
+        self.read_multiline()
+    }
+
+    /// Retrieves a message's header & the first `lines` lines of its body by its number.
+    pub fn top(&mut self, number: u32, lines: u32) -> error::Result<Vec<u8>> {
+        self.command(&format!("TOP {} {}", number, lines))?;
+        self.read_multiline()
+    }
+
+    /// Marks a message as deleted by its number, it's removed when the session ends.
+    pub fn dele(&mut self, number: u32) -> error::Result<()> {
+        self.command(&format!("DELE {}", number))?;
+        Ok(())
+    }
+
+    /// Ends the session, removing messages marked as deleted.
+    pub fn quit(mut self) -> error::Result<()> {
+        self.command("QUIT")?;
+        Ok(())
+    }
+}
+
+/// Computes the MD5 digest of data, RFC 1321, as APOP requires.
+fn md5(data: &[u8]) -> [u8; 16] {
+    const SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+    let constants = (0..64)
+        .map(|i| ((i as f64 + 1.0).sin().abs() * 4294967296.0) as u32)
+        .collect::<Vec<_>>();
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64).wrapping_mul(8)).to_le_bytes());
+
+    let mut state: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+    for chunk in message.chunks(64) {
+        let words = chunk
+            .chunks(4)
+            .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+            .collect::<Vec<_>>();
+        let [mut a, mut b, mut c, mut d] = state;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let rotated = a
+                .wrapping_add(f)
+                .wrapping_add(constants[i])
+                .wrapping_add(words[g])
+                .rotate_left(SHIFTS[(i / 16) * 4 + i % 4]);
+            (a, d, c) = (d, c, b);
+            b = b.wrapping_add(rotated);
+        }
+        for (word, add) in state.iter_mut().zip([a, b, c, d]) {
+            *word = word.wrapping_add(add);
+        }
+    }
+
+    let mut digest = [0; 16];
+    for (i, word) in state.iter().enumerate() {
+        digest[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+    }
+    digest
+}
+
+/// Records the UIDLs of messages retrieved from a POP3 account, with their cached UIDs.
+///
+/// Messages left on the server are only retrieved once, as their UIDLs are remembered.
+pub struct SeenUidls {
+    path: PathBuf,
+    pub uidls: HashMap<String, u32>,
+}
+
+impl SeenUidls {
+    /// Loads the UIDLs seen on an account.
+    pub fn load(email_addr: &str) -> io::Result<SeenUidls> {
+        let dir = data_dir().join("pop3");
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(escape(email_addr));
+
+        let seen = match fs::read_to_string(&path) {
+            Ok(seen) => seen,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let uidls = seen
+            .lines()
+            .filter_map(|line| {
+                let (uid, uidl) = line.split_once('\t')?;
+                Some((uidl.to_string(), uid.parse().ok()?))
+            })
+            .collect();
+
+        Ok(SeenUidls { path, uidls })
+    }
+
+    /// Saves the seen UIDLs.
+    pub fn save(&self) -> io::Result<()> {
+        let seen = self
+            .uidls
+            .iter()
+            .map(|(uidl, uid)| format!("{}\t{}\n", uid, uidl))
+            .collect::<String>();
+        fs::write(&self.path, seen)
+    }
+}
+
+impl User {
+    /// Connects to the POP3 server & logs in.
+    ///
+    /// POP3 over TLS is tried first, then plain POP3 upgraded with STLS, both trusting the
+    /// server the way the account's trust settings say. APOP is used if the server offers
+    /// it, falling back to USER & PASS if it's refused.
+    pub fn connect_pop3(&self) -> error::Result<Pop3Client> {
+        let domain = self.pop3_domain.as_str();
+        let trust = Trust::load(self.email_addr.as_ref())?;
+        let mut pop3_cli = match Pop3Client::connect(domain, POP3S_PORT, Some(&trust)) {
+            Ok(pop3_cli) => pop3_cli,
+            // A certificate that isn't the pinned one mustn't get pinned on the other port
+            Err(error::Error::Io(e)) if e.kind() == io::ErrorKind::PermissionDenied => {
+                return Err(e.into())
+            }
+            Err(_) => {
+                Pop3Client::connect(domain, POP3_PORT, None)?.stls(domain, POP3_PORT, &trust)?
+            }
+        };
+
+        let user = self.email_addr.as_ref();
+        if pop3_cli.offers_apop() && pop3_cli.apop(user, &self.password).is_ok() {
+            return Ok(pop3_cli);
+        }
+        pop3_cli.login(user, &self.password)?;
+        Ok(pop3_cli)
+    }
+
+    /// Logins to POP3 server with user's credentials.
+    ///
+    /// # Returns
+    ///
+    /// - A `Pop3Client` as the POP3 client if logging in succeeds.
+    /// - An `Err` if reading user input fails, e.g. once it ends.
+    pub fn login_pop3(&mut self, ui: &mut dyn Ui, prompts: &Prompts) -> io::Result<Pop3Client> {
+        loop {
+            ui.show(&format!(
+                "{}{}...",
+                prompts.login_connecting, self.pop3_domain
+            ));
+            match self.connect_pop3() {
+                Ok(pop3_cli) => {
+                    ui.show(&format!(
+                        "{}{}.",
+                        prompts.login_connect_succeed, self.pop3_domain
+                    ));
+                    return Ok(pop3_cli);
+                }
+                Err(e) => {
+                    ui.show_error(&format!(
+                        "{}{}: {}",
+                        prompts.login_connect_fail, self.pop3_domain, e
+                    ));
+                    ui.show(prompts.login_retry);
+                    *self = User::build(ui, prompts)?;
+                }
+            }
+        }
+    }
+
+    /// Retrieves new messages from the POP3 server into the cache.
+    ///
+    /// Messages whose UIDL has been seen before are skipped. With `leave_on_server` false,
+    /// retrieved messages are deleted from the server when the session ends.
+    ///
+    /// # Returns
+    ///
+    /// - The number of retrieved messages if the process succeeds.
+    /// - An `Err` if it fails.
+    pub fn retrieve_pop3(
+        &self,
+        pop3_cli: &mut Pop3Client,
+        leave_on_server: bool,
+    ) -> error::Result<usize> {
+        let cache = Cache::open(&self.email_addr)?;
+        let mut cached = cache.mailbox(POP3_MAILBOX)?;
+        let mut seen = SeenUidls::load(self.email_addr.as_ref())?;
+
+        let listed = pop3_cli.uidl()?;
+        let mut count = 0;
+        for (number, uidl) in listed.iter() {
+            if !seen.uidls.contains_key(uidl) {
+                let raw = pop3_cli.retr(*number)?;
+                let uid = cached.last_uid() + 1;
+                cached.insert(uid, "", &raw)?;
+                seen.uidls.insert(uidl.clone(), uid);
+                count += 1;
+            }
+            if !leave_on_server {
+                pop3_cli.dele(*number)?;
+            }
+        }
+
+        // Forget UIDLs no longer on the server, they can't be listed again
+        seen.uidls
+            .retain(|uidl, _| listed.iter().any(|(_, u)| u == uidl));
+        cached.save()?;
+        seen.save()?;
+        Ok(count)
+    }
+}
@@ -1,110 +1,256 @@
+use crate::html::{html_only_body, render_html, RENDER_WIDTH};
+use crate::ui::Ui;
 use crate::{Confirmation, EnumValues, Prompts, RangeUsize};
 
 use lettre::Address;
-use std::io::{self, Write};
+use mail_parser::MessageParser;
+use std::{
+    error::Error,
+    fmt,
+    io::{self, BufRead, StdinLock, Stdout, Write},
+};
 
-/// Reads user input from command line, with a customized prompt.
-pub fn read_input(prompt: &str) -> String {
-    print!("{}", prompt);
-    io::stdout().flush().expect("failed to flush stdout");
+/// Represents user input having ended, reported inside an `io::ErrorKind::UnexpectedEof`
+/// so it can be told apart from a server closing a connection.
+#[derive(Debug)]
+pub struct InputEnded;
 
-    let mut input = String::new();
-    io::stdin()
-        .read_line(&mut input)
-        .expect("failed to read input");
+impl fmt::Display for InputEnded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "user input ended")
+    }
+}
+
+impl Error for InputEnded {}
 
-    input.trim().to_owned()
+/// Returns the error reading fails with once user input ends.
+pub fn input_ended() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, InputEnded)
 }
 
-/// Prompt the user to enter an email address, loops until a valid value is provided.
-pub fn read_email(prompt_read: &str, prompt_invalid: &str) -> Address {
-    loop {
-        match read_input(prompt_read).trim().parse().ok() {
-            Some(x) => return x,
-            _ => eprintln!("{}", prompt_invalid),
+/// Checks whether an error, or one of its sources, is user input having ended.
+pub fn is_input_ended(e: &(dyn Error + 'static)) -> bool {
+    let mut error = Some(e);
+    while let Some(e) = error {
+        let ended = e
+            .downcast_ref::<io::Error>()
+            .and_then(|e| e.get_ref())
+            .is_some_and(|e| e.is::<InputEnded>());
+        if ended {
+            return true;
         }
+        error = e.source();
     }
+    false
 }
 
-/// Prompt the user to enter a selection of `usize`, loops until a valid value is provided.
-pub fn read_selection(
-    prompt_read: &str,
-    prompt_invalid: &str,
-    prompt_object: &str,
-    prompt_should_be: &str,
-    range_usize: &RangeUsize,
-) -> usize {
-    loop {
-        match read_input(prompt_read).trim().parse::<usize>().ok() {
-            Some(x) if x >= range_usize.lo && x <= range_usize.hi => return x,
-            _ => eprintln!(
-                "\
-{}{}: {}
-  {}",
-                prompt_invalid,
-                prompt_object,
-                prompt_should_be,
-                range_usize.valid_values()
-            ),
-        }
+/// Represents the streams user input is read from & prompts are written to.
+///
+/// Reading fails with `input_ended()` once the input ends, except for a message's body,
+/// which the end of input also finishes.
+pub struct Io<R, W> {
+    reader: R,
+    writer: W,
+}
+
+impl Io<StdinLock<'static>, Stdout> {
+    /// Constructs an `Io` reading stdin & writing stdout.
+    pub fn stdio() -> Io<StdinLock<'static>, Stdout> {
+        Io::new(io::stdin().lock(), io::stdout())
     }
 }
 
-/// Prompt the user to enter the reconfirmation for sending a message, loops until a valid value is provided.
-pub fn read_reconfirmation(prompts: &Prompts, reconfirmation: &Confirmation) -> bool {
-    println!("{}", prompts.send_reconfirm_list);
-    loop {
-        let input = read_input(prompts.send_reconfirm_selection).to_lowercase();
-        if matches!(input.as_str(), "yes" | "no") {
-            return input == "yes";
-        } else {
-            eprintln!(
-                "\
+impl<R: BufRead, W: Write> Io<R, W> {
+    pub fn new(reader: R, writer: W) -> Io<R, W> {
+        Io { reader, writer }
+    }
+
+    /// Returns the streams, e.g. to inspect what has been written.
+    pub fn into_inner(self) -> (R, W) {
+        (self.reader, self.writer)
+    }
+
+    /// Writes a line, prompts are only flushed when input is read.
+    pub fn write_line(&mut self, text: &str) -> io::Result<()> {
+        writeln!(self.writer, "{}", text)
+    }
+
+    /// Writes the message reporting an invalid value.
+    fn write_invalid(
+        &mut self,
+        prompt_invalid: &str,
+        prompt_object: &str,
+        prompt_should_be: &str,
+        valid_values: &impl EnumValues,
+    ) -> io::Result<()> {
+        writeln!(
+            self.writer,
+            "\
 {}{}: {}
   {}",
+            prompt_invalid,
+            prompt_object,
+            prompt_should_be,
+            valid_values.valid_values()
+        )
+    }
+
+    /// Reads a line of user input, with a customized prompt.
+    pub fn input(&mut self, prompt: &str) -> io::Result<String> {
+        write!(self.writer, "{}", prompt)?;
+        self.writer.flush()?;
+
+        let mut input = String::new();
+        if self.reader.read_line(&mut input)? == 0 {
+            return Err(input_ended());
+        }
+
+        Ok(input.trim().to_owned())
+    }
+
+    /// Prompts the user to enter an email address, loops until a valid value is provided.
+    pub fn email(&mut self, prompt_read: &str, prompt_invalid: &str) -> io::Result<Address> {
+        loop {
+            match self.input(prompt_read)?.parse().ok() {
+                Some(x) => return Ok(x),
+                _ => self.write_line(prompt_invalid)?,
+            }
+        }
+    }
+
+    /// Prompts the user to enter a selection of `usize`, loops until a valid value is provided.
+    pub fn selection(
+        &mut self,
+        prompt_read: &str,
+        prompt_invalid: &str,
+        prompt_object: &str,
+        prompt_should_be: &str,
+        range_usize: &RangeUsize,
+    ) -> io::Result<usize> {
+        loop {
+            match self.input(prompt_read)?.parse::<usize>().ok() {
+                Some(x) if x >= range_usize.lo && x <= range_usize.hi => return Ok(x),
+                _ => self.write_invalid(
+                    prompt_invalid,
+                    prompt_object,
+                    prompt_should_be,
+                    range_usize,
+                )?,
+            }
+        }
+    }
+
+    /// Prompts the user to enter one or more selections of `usize` separated by commas or
+    /// spaces, loops until only valid values are provided.
+    pub fn selections(
+        &mut self,
+        prompt_read: &str,
+        prompt_invalid: &str,
+        prompt_object: &str,
+        prompt_should_be: &str,
+        range_usize: &RangeUsize,
+    ) -> io::Result<Vec<usize>> {
+        loop {
+            let selections = self
+                .input(prompt_read)?
+                .split([',', ' '])
+                .filter(|s| !s.is_empty())
+                .map(|s| s.parse::<usize>().ok())
+                .collect::<Option<Vec<_>>>();
+            match selections {
+                Some(mut x)
+                    if !x.is_empty()
+                        && x.iter()
+                            .all(|&x| x >= range_usize.lo && x <= range_usize.hi) =>
+                {
+                    x.sort_unstable();
+                    x.dedup();
+                    return Ok(x);
+                }
+                _ => self.write_invalid(
+                    prompt_invalid,
+                    prompt_object,
+                    prompt_should_be,
+                    range_usize,
+                )?,
+            }
+        }
+    }
+
+    /// Prompts the user to enter the reconfirmation for sending a message, loops until a
+    /// valid value is provided.
+    pub fn reconfirmation(
+        &mut self,
+        prompts: &Prompts,
+        reconfirmation: &Confirmation,
+    ) -> io::Result<bool> {
+        self.write_line(prompts.send_reconfirm_list)?;
+        loop {
+            let input = self.input(prompts.send_reconfirm_selection)?.to_lowercase();
+            if matches!(input.as_str(), "yes" | "no") {
+                return Ok(input == "yes");
+            }
+            self.write_invalid(
                 prompts.invalid_literal,
                 prompts.send_confirm_literal,
                 prompts.should_be_one_of_below_literal,
-                reconfirmation.valid_values()
-            );
+                reconfirmation,
+            )?;
         }
     }
-}
 
-/// Reads the email's body from user input, until 2 consecutive empty lines are met.
-pub fn read_body(prompts: &Prompts) -> String {
-    println!("{}", prompts.compose_content);
-    let mut body = String::new();
-    let mut buf;
-
-    let mut empty_count = 0;
-    while empty_count < 2 {
-        buf = read_input("  ") + "\n";
-        body += &buf;
-        if buf.trim().is_empty() {
-            empty_count += 1;
-        } else {
-            empty_count = 0;
+    /// Reads the email's body from user input, until 2 consecutive empty lines or the end
+    /// of input are met.
+    pub fn body(&mut self, prompts: &Prompts) -> io::Result<String> {
+        self.write_line(prompts.compose_content)?;
+        let mut body = String::new();
+
+        let mut empty_count = 0;
+        while empty_count < 2 {
+            let buf = match self.input("  ") {
+                Ok(line) => line + "\n",
+                Err(e) if is_input_ended(&e) => break,
+                Err(e) => return Err(e),
+            };
+            body += &buf;
+            if buf.trim().is_empty() {
+                empty_count += 1;
+            } else {
+                empty_count = 0;
+            }
         }
-        buf.clear();
-    }
 
-    body.trim_end().to_string()
+        Ok(body.trim_end().to_string())
+    }
 }
 
-/// Prints the real body part of an email, ignores useless headers.
-pub fn print_body(email: String, prompts: &Prompts) {
-    println!("{}", prompts.horizontal_start);
+/// Shows the real body part of an email, ignores useless headers.
+///
+/// An email without a text/plain alternative has its HTML rendered as text.
+pub fn show_body(ui: &mut dyn Ui, email: String, prompts: &Prompts) {
+    ui.show(prompts.horizontal_start);
+    let message = MessageParser::default().parse(email.as_bytes());
+    let html = message.as_ref().and_then(html_only_body);
     let mut body = false;
     for line in email.lines() {
         // Real body starts at line "From: "
         if line.starts_with("From: ") {
             body = true;
         }
+        // Headers end at the first empty line, the markup after them is rendered below
+        if body && html.is_some() && line.is_empty() {
+            ui.show("");
+            break;
+        }
         // Ignore "Content" & "To" headers
         if body && !(line.starts_with("Content") || line.starts_with("To")) {
-            println!("  {}", line);
+            ui.show(&format!("  {}", line));
+        }
+    }
+    if let Some(html) = html {
+        for line in render_html(html, RENDER_WIDTH).lines() {
+            ui.show(&format!("  {}", line));
         }
     }
-    println!("{}", prompts.horizontal_end);
+    ui.show(prompts.horizontal_end);
 }
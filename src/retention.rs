@@ -0,0 +1,515 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap, HashSet},
+    fs, io,
+    path::PathBuf,
+    time::Duration,
+};
+
+use chrono::{DateTime, Datelike, Days, FixedOffset, Local, NaiveDate};
+use imap::{Connection, Session};
+
+use crate::cache::escape;
+use crate::migrate::FolderMap;
+use crate::ui::Ui;
+use crate::user::{uid_set, User};
+use crate::*;
+
+/// Interval between runs of the retention rules while watching.
+pub const RETENTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Placeholder in a rule's target folder, replaced by the year of each message's internal date.
+const YEAR_PLACEHOLDER: &str = "YYYY";
+
+/// Represents a rule for messages of a mailbox older than some days.
+///
+/// A rule with a `target` moves matching messages into it, one without expunges them. The
+/// target is written with '/' between hierarchy levels, whatever delimiter the server uses.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetentionRule {
+    pub mailbox: String,
+    pub days: u32,
+    pub target: Option<String>,
+}
+
+impl RetentionRule {
+    /// Formats the rule as a line of the account's rules file.
+    pub fn to_line(&self) -> String {
+        format!(
+            "{}\t{}\t{}",
+            self.days,
+            self.mailbox,
+            self.target.as_deref().unwrap_or_default()
+        )
+    }
+
+    /// Parses a line of the account's rules file.
+    pub fn parse(line: &str) -> Option<RetentionRule> {
+        let mut columns = line.splitn(3, '\t');
+        Some(RetentionRule {
+            days: columns.next()?.parse().ok()?,
+            mailbox: columns.next()?.to_string(),
+            target: columns
+                .next()
+                .map(String::from)
+                .filter(|target| !target.is_empty()),
+        })
+    }
+}
+
+/// Represents a change a retention rule makes to a message.
+#[derive(Clone, Debug)]
+pub struct RetentionChange {
+    pub message: MessageRef,
+    pub summary: MessageSummary,
+    pub internal_date: Option<DateTime<FixedOffset>>,
+    /// The folder the message is moved into, `None` if it's expunged.
+    pub target: Option<String>,
+}
+
+/// Represents the outcome of applying retention rules.
+#[derive(Debug, Default)]
+pub struct RetentionReport {
+    pub moved: usize,
+    pub expunged: usize,
+}
+
+/// Returns the path of the file an account's retention rules are kept in.
+fn rules_path(email_addr: &str) -> PathBuf {
+    data_dir().join("retention").join(escape(email_addr))
+}
+
+/// Loads an account's retention rules, none if it has no rules file.
+pub fn load_rules(email_addr: &str) -> io::Result<Vec<RetentionRule>> {
+    match fs::read_to_string(rules_path(email_addr)) {
+        Ok(rules) => Ok(rules.lines().filter_map(RetentionRule::parse).collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Saves an account's retention rules, replacing the ones saved before.
+pub fn save_rules(email_addr: &str, rules: &[RetentionRule]) -> io::Result<()> {
+    let path = rules_path(email_addr);
+    fs::create_dir_all(path.parent().expect("rules file should be in a directory"))?;
+    fs::write(
+        path,
+        rules
+            .iter()
+            .map(|rule| rule.to_line() + "\n")
+            .collect::<String>(),
+    )
+}
+
+/// Formats a date the way IMAP SEARCH expects it, eg. "1-Feb-1994".
+pub fn imap_date(date: NaiveDate) -> String {
+    date.format("%-d-%b-%Y").to_string()
+}
+
+/// Represents a message a rule's SEARCH found, with its internal date.
+type Found = (MessageRef, MessageSummary, Option<DateTime<FixedOffset>>);
+
+/// Works out the changes rules make to the messages each one found, `found` holding a rule,
+/// its cutoff date & its messages.
+///
+/// A message found by several rules of its mailbox is changed by the one with the most
+/// days, so that eg. a rule expunging after a year isn't outdone by one archiving after a
+/// month. A target's year placeholder is filled from each message's internal date, the
+/// cutoff's if it has none, and its levels are mapped by `map`.
+fn plan_changes(
+    mut found: Vec<(&RetentionRule, NaiveDate, Vec<Found>)>,
+    map: &FolderMap,
+) -> Vec<RetentionChange> {
+    found.sort_by_key(|(rule, _, _)| Reverse(rule.days));
+
+    let mut planned = HashSet::new();
+    let mut changes = Vec::new();
+    for (rule, cutoff, messages) in found {
+        for (message, summary, internal_date) in messages {
+            if !planned.insert(message.clone()) {
+                continue;
+            }
+            let target = rule.target.as_ref().map(|target| {
+                let year = internal_date.map_or(cutoff.year(), |d| d.year());
+                map.map(
+                    &target.replace(YEAR_PLACEHOLDER, &year.to_string()),
+                    Some("/"),
+                )
+            });
+
+            // A message already where the rule would move it is left alone
+            if target.as_ref() == Some(&message.mailbox) {
+                continue;
+            }
+            changes.push(RetentionChange {
+                message,
+                summary,
+                internal_date,
+                target,
+            });
+        }
+    }
+    changes
+}
+
+impl User {
+    /// Works out what retention rules would change, without changing anything.
+    ///
+    /// Messages whose internal date is before `today` minus a rule's days are found with
+    /// SEARCH, then changed as `plan_changes()` works out, target levels being joined by the
+    /// server's hierarchy delimiter.
+    pub fn plan_retention(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+        rules: &[RetentionRule],
+        today: NaiveDate,
+    ) -> error::Result<Vec<RetentionChange>> {
+        let map = FolderMap::new("", "", self.hierarchy_delimiter(imap_cli)?);
+
+        let mut found = Vec::new();
+        for rule in rules {
+            let cutoff = today.checked_sub_days(Days::new(rule.days.into()));
+            let cutoff = cutoff.unwrap_or(NaiveDate::MIN);
+            let messages = self.search_messages(
+                imap_cli,
+                &rule.mailbox,
+                &format!("UNDELETED BEFORE {}", imap_date(cutoff)),
+            )?;
+            if messages.is_empty() {
+                continue;
+            }
+
+            let summaries = self.fetch_summaries(imap_cli, &messages)?;
+            let uids = messages.iter().map(|m| m.uid).collect::<Vec<_>>();
+            let fetches = imap_cli.uid_fetch(uid_set(&uids), "(UID INTERNALDATE)")?;
+            let internal_dates = fetches
+                .iter()
+                .filter_map(|m| Some((m.uid?, m.internal_date()?)))
+                .collect::<HashMap<_, _>>();
+
+            let messages = messages
+                .into_iter()
+                .zip(summaries)
+                .map(|(message, summary)| {
+                    let internal_date = internal_dates.get(&message.uid).copied();
+                    (message, summary, internal_date)
+                })
+                .collect();
+            found.push((rule, cutoff, messages));
+        }
+
+        Ok(plan_changes(found, &map))
+    }
+
+    /// Applies changes worked out by `plan_retention()`, creating missing target folders.
+    pub fn apply_retention(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+        changes: &[RetentionChange],
+    ) -> error::Result<RetentionReport> {
+        let mut existing = self
+            .list_folders(imap_cli)?
+            .into_iter()
+            .map(|f| f.name)
+            .collect::<HashSet<_>>();
+
+        // Group messages by mailbox & target, so each group takes a single command
+        let mut groups: BTreeMap<(&str, Option<&str>), Vec<u32>> = BTreeMap::new();
+        for change in changes {
+            groups
+                .entry((&change.message.mailbox, change.target.as_deref()))
+                .or_default()
+                .push(change.message.uid);
+        }
+
+        let mut report = RetentionReport::default();
+        for ((mailbox, target), uids) in groups {
+            match target {
+                Some(target) => {
+                    if existing.insert(target.to_string()) {
+                        imap_cli.create(target)?;
+                    }
+                    self.move_messages(imap_cli, mailbox, &uids, target)?;
+                    report.moved += uids.len();
+                }
+                None => {
+                    self.delete_messages(imap_cli, mailbox, &uids)?;
+                    report.expunged += uids.len();
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Applies the account's retention rules as of today.
+    pub fn run_retention(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+    ) -> error::Result<RetentionReport> {
+        let rules = load_rules(self.email_addr.as_ref())?;
+        let changes = self.plan_retention(imap_cli, &rules, Local::now().date_naive())?;
+        self.apply_retention(imap_cli, &changes)
+    }
+
+    /// Lets the user edit the account's retention rules, dry-run them & apply them.
+    pub fn retention(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+        ui: &mut dyn Ui,
+        prompts: &Prompts,
+    ) -> error::Result<()> {
+        let mut rules = load_rules(self.email_addr.as_ref())?;
+        loop {
+            show_rules(&rules, ui, prompts);
+            ui.show(prompts.retention_action_list);
+            match ui.read_selection(
+                prompts.action_selection,
+                prompts.invalid_literal,
+                prompts.action_literal,
+                prompts.should_be_one_of_below_literal,
+                &RangeUsize::new(0, 4),
+            )? {
+                0 => return Ok(()),
+                1 => {
+                    ui.show(prompts.fetch_mailbox);
+                    let mailboxes = self.list_mailboxes(imap_cli)?;
+                    for (i, mailbox) in mailboxes.iter().enumerate() {
+                        ui.show(&format!("  [{}] {}", i + 1, mailbox));
+                    }
+                    let mailbox = &mailboxes[ui.read_selection(
+                        prompts.retention_mailbox_selection,
+                        prompts.invalid_literal,
+                        prompts.fetch_mailbox_literal,
+                        prompts.should_be_one_of_below_literal,
+                        &RangeUsize::new(1, mailboxes.len()),
+                    )? - 1];
+                    let days = ui.read_selection(
+                        prompts.retention_days,
+                        prompts.invalid_literal,
+                        prompts.retention_days_literal,
+                        prompts.should_be_one_of_below_literal,
+                        &RangeUsize::new(1, 36500),
+                    )?;
+                    let target = ui.read_input(prompts.retention_target)?;
+                    rules.push(RetentionRule {
+                        mailbox: mailbox.clone(),
+                        days: days as u32,
+                        target: Some(target).filter(|t| !t.is_empty()),
+                    });
+                    save_rules(self.email_addr.as_ref(), &rules)?;
+                }
+                2 => {
+                    if rules.is_empty() {
+                        continue;
+                    }
+                    let selection = ui.read_selection(
+                        prompts.retention_rule_selection,
+                        prompts.invalid_literal,
+                        prompts.retention_rule_literal,
+                        prompts.should_be_one_of_below_literal,
+                        &RangeUsize::new(1, rules.len()),
+                    )?;
+                    rules.remove(selection - 1);
+                    save_rules(self.email_addr.as_ref(), &rules)?;
+                }
+                3 => {
+                    let changes =
+                        self.plan_retention(imap_cli, &rules, Local::now().date_naive())?;
+                    if changes.is_empty() {
+                        ui.show(prompts.retention_no_changes);
+                    }
+                    for change in changes.iter() {
+                        let date = change
+                            .internal_date
+                            .map(|d| d.format("%Y-%m-%d").to_string())
+                            .unwrap_or_default();
+                        let action = match change.target.as_deref() {
+                            Some(target) => format!("-> \"{}\"", target),
+                            None => prompts.retention_expunge_literal.to_string(),
+                        };
+                        ui.show(&format!(
+                            "  \"{}\" {} {} - {} {}",
+                            change.message.mailbox,
+                            date,
+                            change.summary.from,
+                            change.summary.subject,
+                            action
+                        ));
+                    }
+                }
+                4 => {
+                    let report = self.run_retention(imap_cli)?;
+                    ui.show(&describe_report(&report, prompts));
+                }
+                _ => unreachable!(), // selection from `read_selection()` should have matched one of the above
+            }
+        }
+    }
+}
+
+/// Shows retention rules, numbered from 1.
+fn show_rules(rules: &[RetentionRule], ui: &mut dyn Ui, prompts: &Prompts) {
+    if rules.is_empty() {
+        ui.show(prompts.retention_no_rules);
+        return;
+    }
+
+    ui.show(prompts.retention_rules);
+    for (i, rule) in rules.iter().enumerate() {
+        let action = match rule.target.as_deref() {
+            Some(target) => format!("-> \"{}\"", target),
+            None => prompts.retention_expunge_literal.to_string(),
+        };
+        ui.show(&format!(
+            "  [{}] \"{}\"{}{}{}{}",
+            i + 1,
+            rule.mailbox,
+            prompts.retention_older_than,
+            rule.days,
+            prompts.retention_days_unit,
+            action
+        ));
+    }
+}
+
+/// Describes the outcome of applying retention rules.
+pub fn describe_report(report: &RetentionReport, prompts: &Prompts) -> String {
+    format!(
+        "{}{}{}{}.",
+        prompts.retention_moved, report.moved, prompts.retention_expunged, report.expunged
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(mailbox: &str, days: u32, target: Option<&str>) -> RetentionRule {
+        RetentionRule {
+            mailbox: mailbox.to_string(),
+            days,
+            target: target.map(String::from),
+        }
+    }
+
+    fn found(mailbox: &str, uid: u32, date: Option<&str>) -> Found {
+        let summary = MessageSummary {
+            uid,
+            ..Default::default()
+        };
+        let date = date.map(|d| DateTime::parse_from_rfc3339(d).unwrap());
+        (MessageRef::new(mailbox, 1, uid), summary, date)
+    }
+
+    fn date(date: &str) -> NaiveDate {
+        date.parse().unwrap()
+    }
+
+    /// Lists changes as the UID & target of each.
+    fn targets(changes: &[RetentionChange]) -> Vec<(u32, Option<&str>)> {
+        changes
+            .iter()
+            .map(|c| (c.message.uid, c.target.as_deref()))
+            .collect()
+    }
+
+    #[test]
+    fn rules_round_trip_through_their_lines() {
+        for rule in [
+            rule("INBOX", 30, Some("Archive/YYYY")),
+            rule("Lists/Rust users", 7, None),
+        ] {
+            assert_eq!(RetentionRule::parse(&rule.to_line()), Some(rule));
+        }
+    }
+
+    #[test]
+    fn malformed_rule_lines_are_skipped() {
+        assert_eq!(RetentionRule::parse(""), None);
+        assert_eq!(RetentionRule::parse("thirty\tINBOX\t"), None);
+        assert_eq!(RetentionRule::parse("30"), None);
+        assert_eq!(
+            RetentionRule::parse("30\tINBOX"),
+            Some(rule("INBOX", 30, None))
+        );
+    }
+
+    #[test]
+    fn targets_are_filed_by_year_with_the_servers_delimiter() {
+        let archive = rule("INBOX", 30, Some("Archive/YYYY"));
+        let map = FolderMap::new("", "", Some(".".to_string()));
+
+        let changes = plan_changes(
+            vec![(
+                &archive,
+                date("2024-05-01"),
+                vec![
+                    found("INBOX", 1, Some("2022-12-31T23:00:00+00:00")),
+                    found("INBOX", 2, Some("2024-01-02T08:00:00+02:00")),
+                    found("INBOX", 3, None),
+                ],
+            )],
+            &map,
+        );
+
+        assert_eq!(
+            targets(&changes),
+            [
+                (1, Some("Archive.2022")),
+                (2, Some("Archive.2024")),
+                (3, Some("Archive.2024"))
+            ]
+        );
+    }
+
+    #[test]
+    fn messages_already_in_their_target_are_left_alone() {
+        let archive = rule("Archive/2023", 30, Some("Archive/YYYY"));
+        let map = FolderMap::new("", "", Some("/".to_string()));
+
+        let changes = plan_changes(
+            vec![(
+                &archive,
+                date("2024-05-01"),
+                vec![
+                    found("Archive/2023", 1, Some("2023-03-01T00:00:00+00:00")),
+                    found("Archive/2023", 2, Some("2022-03-01T00:00:00+00:00")),
+                ],
+            )],
+            &map,
+        );
+
+        assert_eq!(targets(&changes), [(2, Some("Archive/2022"))]);
+    }
+
+    #[test]
+    fn overlapping_rules_change_a_message_once_by_the_rule_with_most_days() {
+        let archive = rule("INBOX", 30, Some("Archive"));
+        let expunge = rule("INBOX", 365, None);
+        let other = rule("Sent", 10, Some("Archive"));
+        let map = FolderMap::new("", "", Some("/".to_string()));
+        let old = Some("2022-01-01T00:00:00+00:00");
+        let recent = Some("2024-03-01T00:00:00+00:00");
+
+        // The monthly rule also finds what the yearly one does, as SEARCH would
+        let changes = plan_changes(
+            vec![
+                (
+                    &archive,
+                    date("2024-04-01"),
+                    vec![found("INBOX", 1, old), found("INBOX", 2, recent)],
+                ),
+                (&expunge, date("2023-05-02"), vec![found("INBOX", 1, old)]),
+                (&other, date("2024-04-21"), vec![found("Sent", 1, old)]),
+            ],
+            &map,
+        );
+
+        assert_eq!(
+            targets(&changes),
+            [(1, None), (2, Some("Archive")), (1, Some("Archive"))]
+        );
+        assert_eq!(changes[2].message.mailbox, "Sent");
+    }
+}
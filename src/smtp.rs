@@ -0,0 +1,214 @@
+use std::{
+    fmt::Display,
+    io::{self, BufRead, BufReader, Write},
+};
+
+use imap::Connection;
+use lettre::{
+    address::Envelope,
+    transport::smtp::{
+        authentication::{Credentials, Mechanism},
+        commands::{Auth, Data, Ehlo, Mail, Quit, Rcpt},
+        extension::{ClientId, Extension, MailBodyParameter, MailParameter, ServerInfo},
+        response::Response,
+    },
+};
+
+use crate::transport::MailTransport;
+use crate::user::User;
+use crate::*;
+
+/// Mechanisms tried to authenticate, in order of preference.
+const MECHANISMS: [Mechanism; 2] = [Mechanism::Plain, Mechanism::Login];
+
+/// Number of challenges answered before an authentication is given up.
+const MAX_CHALLENGES: usize = 10;
+
+/// Represents an SMTP client, RFC 5321, logging in with AUTH, RFC 4954.
+///
+/// Each message is sent on a connection of its own, opened by the `User` the way its
+/// account's settings say, so every connection is trusted by the account's TLS settings.
+pub struct SmtpClient {
+    user: User,
+}
+
+impl SmtpClient {
+    /// Constructs a client sending as a user, checking its login on a first connection.
+    ///
+    /// # Returns
+    ///
+    /// - The `SmtpClient` if logging in succeeds.
+    /// - An `Err` if connecting or logging in fails.
+    pub fn connect(user: &User) -> error::Result<SmtpClient> {
+        let client = SmtpClient { user: user.clone() };
+        client.open()?.quit()?;
+        Ok(client)
+    }
+
+    /// Opens a logged-in session on a new connection.
+    fn open(&self) -> error::Result<SmtpSession> {
+        let user = &self.user;
+        let stream = user.connect_server("smtp", &user.smtp_domain, user.smtp_port)?;
+        let mut stream = BufReader::new(stream);
+
+        read_response(&mut stream)?;
+        let ehlo = command(&mut stream, Ehlo::new(ClientId::default()))?;
+        let mut session = SmtpSession {
+            stream,
+            info: ServerInfo::from_response(&ehlo)?,
+        };
+        let credentials = Credentials::new(user.email_addr.to_string(), user.password.clone());
+        session.auth(&credentials)?;
+        Ok(session)
+    }
+}
+
+impl MailTransport for SmtpClient {
+    fn send_raw(&mut self, envelope: &Envelope, raw: &[u8]) -> error::Result<()> {
+        let mut session = self.open()?;
+        session.send(envelope, raw)?;
+        session.quit()
+    }
+}
+
+/// Represents a logged-in SMTP session on a connection.
+struct SmtpSession {
+    stream: BufReader<Connection>,
+    info: ServerInfo,
+}
+
+impl SmtpSession {
+    /// Authenticates with the first mechanism the server offers of `MECHANISMS`.
+    fn auth(&mut self, credentials: &Credentials) -> error::Result<()> {
+        let mechanism = self.info.get_auth_mechanism(&MECHANISMS).ok_or_else(|| {
+            error::Error::Smtp("no compatible authentication mechanism was found".into())
+        })?;
+        let mut response = command(
+            &mut self.stream,
+            Auth::new(mechanism, credentials.clone(), None)?,
+        )?;
+        for _ in 0..MAX_CHALLENGES {
+            if !response.has_code(334) {
+                return Ok(());
+            }
+            let answer = Auth::new_from_response(mechanism, credentials.clone(), &response)?;
+            response = command(&mut self.stream, answer)?;
+        }
+        Err(error::Error::Smtp(
+            "too many authentication challenges".into(),
+        ))
+    }
+
+    /// Sends a message to the recipients of an envelope.
+    ///
+    /// A message with bytes other than ASCII is sent as 8BITMIME, so the server has to
+    /// offer it, as it has to offer SMTPUTF8 for addresses other than ASCII.
+    fn send(&mut self, envelope: &Envelope, raw: &[u8]) -> error::Result<()> {
+        let mut parameters = Vec::new();
+        let mut addresses = envelope.from().into_iter().chain(envelope.to());
+        if addresses.any(|a| !(a.user().is_ascii() && a.domain().is_ascii())) {
+            self.require(Extension::SmtpUtfEight)?;
+            parameters.push(MailParameter::SmtpUtfEight);
+        }
+        if !raw.is_ascii() {
+            self.require(Extension::EightBitMime)?;
+            parameters.push(MailParameter::Body(MailBodyParameter::EightBitMime));
+        }
+
+        command(
+            &mut self.stream,
+            Mail::new(envelope.from().cloned(), parameters),
+        )?;
+        for to in envelope.to() {
+            command(&mut self.stream, Rcpt::new(to.clone(), Vec::new()))?;
+        }
+        command(&mut self.stream, Data)?;
+        let stream = self.stream.get_mut();
+        stream.write_all(&dot_stuff(raw))?;
+        stream.write_all(b".\r\n")?;
+        stream.flush()?;
+        read_response(&mut self.stream)?;
+        Ok(())
+    }
+
+    /// Checks whether the server offers an extension.
+    fn require(&self, extension: Extension) -> error::Result<()> {
+        match self.info.supports_feature(extension) {
+            true => Ok(()),
+            false => Err(error::Error::Smtp(format!(
+                "the server doesn't offer {}",
+                extension
+            ))),
+        }
+    }
+
+    /// Ends the session.
+    fn quit(mut self) -> error::Result<()> {
+        command(&mut self.stream, Quit)?;
+        Ok(())
+    }
+}
+
+/// Sends a command & reads its response.
+fn command<C: Display>(stream: &mut BufReader<Connection>, command: C) -> error::Result<Response> {
+    stream.get_mut().write_all(command.to_string().as_bytes())?;
+    stream.get_mut().flush()?;
+    read_response(stream)
+}
+
+/// Reads a response, the lines of which all but the last have a "-" after the code.
+///
+/// # Returns
+///
+/// - The `Response` if it's positive.
+/// - An `Error::Smtp` with the response if it's negative or can't be understood.
+/// - An `Err` if reading fails.
+fn read_response(stream: &mut BufReader<Connection>) -> error::Result<Response> {
+    let mut text = String::new();
+    loop {
+        let start = text.len();
+        if stream.read_line(&mut text)? == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        if text.as_bytes().get(start + 3) != Some(&b'-') {
+            break;
+        }
+    }
+    let response = text.parse::<Response>()?;
+    match response.is_positive() {
+        true => Ok(response),
+        false => Err(error::Error::Smtp(text.trim_end().to_string())),
+    }
+}
+
+/// Prepares a message to be sent after DATA, doubling the dots starting lines & ending
+/// it with a line break.
+fn dot_stuff(raw: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(raw.len() + 2);
+    for line in raw.split_inclusive(|&b| b == b'\n') {
+        if line.starts_with(b".") {
+            data.push(b'.');
+        }
+        data.extend_from_slice(line);
+    }
+    if !data.ends_with(b"\r\n") {
+        data.extend_from_slice(b"\r\n");
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dots_starting_lines_are_doubled() {
+        let data = dot_stuff(b"Hi.\r\n.\r\n..two\r\nend");
+        assert_eq!(data, b"Hi.\r\n..\r\n...two\r\nend\r\n");
+    }
+
+    #[test]
+    fn messages_ending_with_a_line_break_get_no_other() {
+        assert_eq!(dot_stuff(b"Hello.\r\n"), b"Hello.\r\n");
+    }
+}
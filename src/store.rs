@@ -0,0 +1,364 @@
+use std::{collections::BTreeMap, io};
+
+use imap::{types::Flag, Connection, Session};
+
+use crate::cache::{maildir_flags, with_flag, Cache};
+use crate::pop3::{Pop3Client, POP3_MAILBOX};
+use crate::thread::{thread_locally, Thread};
+use crate::user::User;
+use crate::*;
+
+/// UIDVALIDITY of every mailbox of a `MemoryStore`.
+const MEMORY_UID_VALIDITY: u32 = 1;
+
+/// A place messages are stored in mailboxes, which can be listed, read, flagged & moved.
+///
+/// Flags are named the IMAP way, e.g. "\Seen". Messages are addressed by `MessageRef`s
+/// obtained from `messages()`, a backend without UIDs makes up ones that are stable for as
+/// long as it's in use.
+pub trait MailStore {
+    /// Lists the names of mailboxes.
+    fn mailboxes(&mut self) -> error::Result<Vec<String>>;
+
+    /// Lists all messages in a mailbox, in ascending UID order.
+    fn messages(&mut self, mailbox: &str) -> error::Result<Vec<MessageRef>>;
+
+    /// Fetches the summaries of messages in a mailbox, in the order of `messages`.
+    fn summaries(&mut self, messages: &[MessageRef]) -> error::Result<Vec<MessageSummary>>;
+
+    /// Groups messages of a mailbox into conversations, locally by default.
+    fn threads(
+        &mut self,
+        _messages: &[MessageRef],
+        summaries: &[MessageSummary],
+    ) -> error::Result<Vec<Thread>> {
+        Ok(thread_locally(summaries))
+    }
+
+    /// Fetches the raw RFC822 bytes of a message.
+    fn fetch(&mut self, message: &MessageRef) -> error::Result<Vec<u8>>;
+
+    /// Sets or clears a flag of a message.
+    fn set_flag(&mut self, message: &MessageRef, flag: &str, set: bool) -> error::Result<()>;
+
+    /// Moves a message into another mailbox.
+    fn move_message(&mut self, message: &MessageRef, target: &str) -> error::Result<()>;
+}
+
+/// Represents the mailboxes of a user on the IMAP server.
+pub struct ImapStore<'a> {
+    user: &'a mut User,
+    imap_cli: &'a mut Session<Connection>,
+}
+
+impl<'a> ImapStore<'a> {
+    pub fn new(user: &'a mut User, imap_cli: &'a mut Session<Connection>) -> ImapStore<'a> {
+        ImapStore { user, imap_cli }
+    }
+}
+
+impl MailStore for ImapStore<'_> {
+    fn mailboxes(&mut self) -> error::Result<Vec<String>> {
+        self.user.list_mailboxes(self.imap_cli)
+    }
+
+    fn messages(&mut self, mailbox: &str) -> error::Result<Vec<MessageRef>> {
+        self.user.list_messages(self.imap_cli, mailbox)
+    }
+
+    fn summaries(&mut self, messages: &[MessageRef]) -> error::Result<Vec<MessageSummary>> {
+        self.user.fetch_summaries(self.imap_cli, messages)
+    }
+
+    fn threads(
+        &mut self,
+        messages: &[MessageRef],
+        summaries: &[MessageSummary],
+    ) -> error::Result<Vec<Thread>> {
+        self.user.fetch_threads(self.imap_cli, messages, summaries)
+    }
+
+    fn fetch(&mut self, message: &MessageRef) -> error::Result<Vec<u8>> {
+        self.user.fetch_raw(self.imap_cli, message)
+    }
+
+    fn set_flag(&mut self, message: &MessageRef, flag: &str, set: bool) -> error::Result<()> {
+        self.user.select_message(self.imap_cli, message)?;
+        let sign = if set { '+' } else { '-' };
+        self.imap_cli.uid_store(
+            message.uid.to_string(),
+            format!("{}FLAGS.SILENT ({})", sign, flag),
+        )?;
+        Ok(())
+    }
+
+    fn move_message(&mut self, message: &MessageRef, target: &str) -> error::Result<()> {
+        self.user.select_message(self.imap_cli, message)?;
+        self.user
+            .move_messages(self.imap_cli, &message.mailbox, &[message.uid], target)
+    }
+}
+
+/// Represents the maildrop of a user on the POP3 server, as a single mailbox.
+///
+/// Messages are addressed by their message numbers, which are only stable within a session.
+/// Messages can be deleted by setting "\Deleted", but not moved.
+pub struct Pop3Store<'a> {
+    pop3_cli: &'a mut Pop3Client,
+}
+
+impl<'a> Pop3Store<'a> {
+    pub fn new(pop3_cli: &'a mut Pop3Client) -> Pop3Store<'a> {
+        Pop3Store { pop3_cli }
+    }
+
+    /// Makes sure a message belongs to the maildrop, returning its message number.
+    fn number(message: &MessageRef) -> error::Result<u32> {
+        match message.mailbox == POP3_MAILBOX {
+            true => Ok(message.uid),
+            false => Err(error::Error::MessageNotFound {
+                mailbox: message.mailbox.to_string(),
+                uid: message.uid,
+            }),
+        }
+    }
+}
+
+impl MailStore for Pop3Store<'_> {
+    fn mailboxes(&mut self) -> error::Result<Vec<String>> {
+        Ok(vec![POP3_MAILBOX.to_string()])
+    }
+
+    fn messages(&mut self, mailbox: &str) -> error::Result<Vec<MessageRef>> {
+        if mailbox != POP3_MAILBOX {
+            return Ok(Vec::new());
+        }
+        // Messages deleted in this session aren't listed anymore, though numbers aren't reused
+        Ok(self
+            .pop3_cli
+            .uidl()?
+            .into_iter()
+            .map(|(number, _)| MessageRef::new(POP3_MAILBOX, 0, number))
+            .collect())
+    }
+
+    fn summaries(&mut self, messages: &[MessageRef]) -> error::Result<Vec<MessageSummary>> {
+        messages
+            .iter()
+            .map(|message| {
+                let number = Pop3Store::number(message)?;
+                Ok(MessageSummary::parse(
+                    number,
+                    &self.pop3_cli.top(number, 0)?,
+                ))
+            })
+            .collect()
+    }
+
+    fn fetch(&mut self, message: &MessageRef) -> error::Result<Vec<u8>> {
+        let number = Pop3Store::number(message)?;
+        self.pop3_cli.retr(number)
+    }
+
+    fn set_flag(&mut self, message: &MessageRef, flag: &str, set: bool) -> error::Result<()> {
+        let number = Pop3Store::number(message)?;
+        match (flag, set) {
+            ("\\Deleted", true) => self.pop3_cli.dele(number),
+            _ => Err(error::Error::Unsupported(format!(
+                "POP3 can't {} {}",
+                if set { "set" } else { "clear" },
+                flag
+            ))),
+        }
+    }
+
+    fn move_message(&mut self, _message: &MessageRef, _target: &str) -> error::Result<()> {
+        Err(error::Error::Unsupported(
+            "POP3 can't move messages".to_string(),
+        ))
+    }
+}
+
+/// Represents the local Maildir cache of an account, used without a server.
+///
+/// Flags changed here are pushed to the server on the next sync. Moves only rearrange the
+/// cache, the next sync puts messages back where the server keeps them.
+pub struct FileStore {
+    cache: Cache,
+}
+
+impl FileStore {
+    pub fn new(cache: Cache) -> FileStore {
+        FileStore { cache }
+    }
+
+    /// Reads a cached message, making sure its UID is still valid.
+    fn read(&self, message: &MessageRef) -> error::Result<(String, Vec<u8>)> {
+        let cached = self.cache.mailbox(&message.mailbox)?;
+        if cached.uid_validity != message.uid_validity {
+            return Err(error::Error::UidValidityChanged {
+                mailbox: message.mailbox.to_string(),
+                recorded: message.uid_validity,
+                reported: cached.uid_validity,
+            });
+        }
+        match cached.read(message.uid) {
+            Ok(raw) => Ok((cached.flags(message.uid).unwrap_or_default(), raw)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Err(error::Error::MessageNotFound {
+                mailbox: message.mailbox.to_string(),
+                uid: message.uid,
+            }),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl MailStore for FileStore {
+    fn mailboxes(&mut self) -> error::Result<Vec<String>> {
+        Ok(self.cache.mailboxes()?)
+    }
+
+    fn messages(&mut self, mailbox: &str) -> error::Result<Vec<MessageRef>> {
+        let cached = self.cache.mailbox(mailbox)?;
+        Ok(cached
+            .uids()
+            .into_iter()
+            .map(|uid| MessageRef::new(mailbox, cached.uid_validity, uid))
+            .collect())
+    }
+
+    fn summaries(&mut self, messages: &[MessageRef]) -> error::Result<Vec<MessageSummary>> {
+        messages
+            .iter()
+            .map(|message| Ok(MessageSummary::parse(message.uid, &self.read(message)?.1)))
+            .collect()
+    }
+
+    fn fetch(&mut self, message: &MessageRef) -> error::Result<Vec<u8>> {
+        Ok(self.read(message)?.1)
+    }
+
+    fn set_flag(&mut self, message: &MessageRef, flag: &str, set: bool) -> error::Result<()> {
+        let Some(letter) = maildir_flags(&[Flag::from(flag)]).chars().next() else {
+            return Err(error::Error::Unsupported(format!(
+                "Maildir has no letter for {}",
+                flag
+            )));
+        };
+        let (letters, _) = self.read(message)?;
+        let mut cached = self.cache.mailbox(&message.mailbox)?;
+        cached.set_flags(message.uid, &with_flag(&letters, letter, set))?;
+        Ok(())
+    }
+
+    fn move_message(&mut self, message: &MessageRef, target: &str) -> error::Result<()> {
+        let (letters, raw) = self.read(message)?;
+        let mut destination = self.cache.mailbox(target)?;
+        destination.insert(destination.last_uid() + 1, &letters, &raw)?;
+        destination.save()?;
+
+        let mut source = self.cache.mailbox(&message.mailbox)?;
+        source.remove(message.uid)?;
+        source.save()?;
+        Ok(())
+    }
+}
+
+/// Represents a message kept by a `MemoryStore`.
+#[derive(Clone, Debug)]
+pub struct MemoryMessage {
+    pub uid: u32,
+    pub flags: Vec<String>,
+    pub raw: Vec<u8>,
+}
+
+/// Represents mailboxes kept in memory, e.g. as a stand-in for a server.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStore {
+    pub mailboxes: BTreeMap<String, Vec<MemoryMessage>>,
+    next_uid: u32,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+
+    /// Appends a message to a mailbox, creating the mailbox if needed.
+    pub fn append(&mut self, mailbox: &str, flags: &[&str], raw: &[u8]) -> MessageRef {
+        self.next_uid += 1;
+        self.mailboxes
+            .entry(mailbox.to_string())
+            .or_default()
+            .push(MemoryMessage {
+                uid: self.next_uid,
+                flags: flags.iter().map(|f| f.to_string()).collect(),
+                raw: raw.to_vec(),
+            });
+        MessageRef::new(mailbox, MEMORY_UID_VALIDITY, self.next_uid)
+    }
+
+    /// Returns the position of a message in its mailbox.
+    fn position(&self, message: &MessageRef) -> error::Result<usize> {
+        self.mailboxes
+            .get(&message.mailbox)
+            .and_then(|messages| messages.iter().position(|m| m.uid == message.uid))
+            .ok_or_else(|| error::Error::MessageNotFound {
+                mailbox: message.mailbox.to_string(),
+                uid: message.uid,
+            })
+    }
+}
+
+impl MailStore for MemoryStore {
+    fn mailboxes(&mut self) -> error::Result<Vec<String>> {
+        Ok(self.mailboxes.keys().cloned().collect())
+    }
+
+    fn messages(&mut self, mailbox: &str) -> error::Result<Vec<MessageRef>> {
+        Ok(self
+            .mailboxes
+            .get(mailbox)
+            .map(|messages| {
+                messages
+                    .iter()
+                    .map(|m| MessageRef::new(mailbox, MEMORY_UID_VALIDITY, m.uid))
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    fn summaries(&mut self, messages: &[MessageRef]) -> error::Result<Vec<MessageSummary>> {
+        messages
+            .iter()
+            .map(|message| Ok(MessageSummary::parse(message.uid, &self.fetch(message)?)))
+            .collect()
+    }
+
+    fn fetch(&mut self, message: &MessageRef) -> error::Result<Vec<u8>> {
+        let position = self.position(message)?;
+        Ok(self.mailboxes[&message.mailbox][position].raw.clone())
+    }
+
+    fn set_flag(&mut self, message: &MessageRef, flag: &str, set: bool) -> error::Result<()> {
+        let position = self.position(message)?;
+        let flags = &mut self.mailboxes.get_mut(&message.mailbox).unwrap()[position].flags;
+        flags.retain(|f| f != flag);
+        if set {
+            flags.push(flag.to_string());
+        }
+        Ok(())
+    }
+
+    fn move_message(&mut self, message: &MessageRef, target: &str) -> error::Result<()> {
+        let position = self.position(message)?;
+        let moved = self
+            .mailboxes
+            .get_mut(&message.mailbox)
+            .unwrap()
+            .remove(position);
+        let flags = moved.flags.iter().map(|f| f.as_str()).collect::<Vec<_>>();
+        self.append(target, &flags, &moved.raw);
+        Ok(())
+    }
+}
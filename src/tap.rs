@@ -0,0 +1,210 @@
+use std::{
+    io::{self, Read, Write},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use imap::extensions::idle::SetReadTimeout;
+
+/// Replacement of secrets in tapped lines.
+pub const REDACTED: &str = "<redacted>";
+
+/// Numbers connections across all taps, so sinks can tell them apart.
+static CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Represents which side of a tapped connection sent a line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Client,
+    Server,
+}
+
+/// Receives the lines passing through tapped connections.
+pub trait TapSink: Send + Sync {
+    /// Receives a line, including its line break if it had one, with secrets redacted.
+    ///
+    /// `label` names the tapped server, e.g. "smtp", `connection` numbers the connection.
+    fn line(&self, label: &str, connection: usize, direction: Direction, line: &[u8]);
+}
+
+/// Passes lines on to each of several sinks.
+impl TapSink for Vec<Arc<dyn TapSink>> {
+    fn line(&self, label: &str, connection: usize, direction: Direction, line: &[u8]) {
+        for sink in self {
+            sink.line(label, connection, direction, line);
+        }
+    }
+}
+
+/// Redacts credentials from the client's lines of SMTP, IMAP & POP3 sessions.
+///
+/// Arguments of AUTH, AUTHENTICATE, LOGIN, PASS & APOP are replaced, as is every client
+/// line answering an authentication challenge.
+#[derive(Default)]
+pub struct Redactor {
+    in_auth: bool,
+}
+
+impl Redactor {
+    /// Redacts a line sent by the client.
+    pub fn client(&mut self, line: &[u8]) -> Vec<u8> {
+        let text = String::from_utf8_lossy(line);
+        let content = text.trim_end_matches(['\r', '\n']);
+        let ending = &text[content.len()..];
+        if self.in_auth {
+            return format!("{}{}", REDACTED, ending).into_bytes();
+        }
+
+        // Commands of SMTP & POP3 come first, the ones of IMAP follow a tag
+        let words = content.split(' ').collect::<Vec<_>>();
+        let command = match words.first().map(|w| w.to_uppercase()).as_deref() {
+            Some("AUTH" | "PASS" | "APOP") => 0,
+            _ => 1,
+        };
+        let kept = match words.get(command).map(|w| w.to_uppercase()).as_deref() {
+            Some("AUTH" | "AUTHENTICATE") => {
+                self.in_auth = true;
+                command + 2
+            }
+            Some("LOGIN") => {
+                // Credentials sent as literals follow in the next lines
+                self.in_auth = content.ends_with('}');
+                command + 1
+            }
+            Some("PASS") => command + 1,
+            Some("APOP") => command + 2,
+            _ => return line.to_vec(),
+        };
+        if words.len() <= kept {
+            return line.to_vec();
+        }
+        format!("{} {}{}", words[..kept].join(" "), REDACTED, ending).into_bytes()
+    }
+
+    /// Notes a line sent by the server, an authentication ends with any but a challenge.
+    pub fn server(&mut self, line: &[u8]) {
+        if !(line.starts_with(b"334") || line.starts_with(b"+")) {
+            self.in_auth = false;
+        }
+    }
+}
+
+/// Represents what passed through a tapped connection, cut into lines.
+struct Wire {
+    label: String,
+    connection: usize,
+    sink: Arc<dyn TapSink>,
+    redactor: Redactor,
+    client: Vec<u8>,
+    server: Vec<u8>,
+}
+
+impl Wire {
+    /// Passes bytes on to the sink, as soon as they complete a line.
+    fn feed(&mut self, direction: Direction, bytes: &[u8]) {
+        let buf = match direction {
+            Direction::Client => &mut self.client,
+            Direction::Server => &mut self.server,
+        };
+        buf.extend_from_slice(bytes);
+        let mut lines = Vec::new();
+        while let Some(end) = buf.iter().position(|&b| b == b'\n') {
+            lines.push(buf.drain(..=end).collect::<Vec<_>>());
+        }
+        for line in lines {
+            self.emit(direction, &line);
+        }
+    }
+
+    fn emit(&mut self, direction: Direction, line: &[u8]) {
+        let line = match direction {
+            Direction::Client => self.redactor.client(line),
+            Direction::Server => {
+                self.redactor.server(line);
+                line.to_vec()
+            }
+        };
+        self.sink
+            .line(&self.label, self.connection, direction, &line);
+    }
+
+    /// Passes on what's left once the connection closes.
+    fn finish(&mut self) {
+        for direction in [Direction::Client, Direction::Server] {
+            let rest = match direction {
+                Direction::Client => std::mem::take(&mut self.client),
+                Direction::Server => std::mem::take(&mut self.server),
+            };
+            if !rest.is_empty() {
+                self.emit(direction, &rest);
+            }
+        }
+    }
+}
+
+/// Represents a connection passing what goes through on to a sink, cut into lines.
+///
+/// The connection is tapped inside the process, over TLS the lines are the plain text
+/// inside it. What's left of a line is passed on once it's dropped.
+pub struct Tapped<S> {
+    stream: S,
+    wire: Wire,
+}
+
+impl<S> Tapped<S> {
+    /// Taps a connection to a server named by `label`, e.g. "smtp".
+    pub fn new(label: &str, stream: S, sink: Arc<dyn TapSink>) -> Tapped<S> {
+        Tapped {
+            stream,
+            wire: Wire {
+                label: label.to_string(),
+                connection: CONNECTIONS.fetch_add(1, Ordering::Relaxed),
+                sink,
+                redactor: Redactor::default(),
+                client: Vec::new(),
+                server: Vec::new(),
+            },
+        }
+    }
+
+    /// Returns the name of the connection in sinks, e.g. "imap#3".
+    pub fn name(&self) -> String {
+        format!("{}#{}", self.wire.label, self.wire.connection)
+    }
+}
+
+impl<S: Read> Read for Tapped<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.stream.read(buf)?;
+        self.wire.feed(Direction::Server, &buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<S: Write> Write for Tapped<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.stream.write(buf)?;
+        self.wire.feed(Direction::Client, &buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl<S: SetReadTimeout> SetReadTimeout for Tapped<S> {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> imap::Result<()> {
+        self.stream.set_read_timeout(timeout)
+    }
+}
+
+impl<S> Drop for Tapped<S> {
+    fn drop(&mut self) {
+        self.wire.finish();
+        tracing::info!("{} closed", self.name());
+    }
+}
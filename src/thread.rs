@@ -0,0 +1,425 @@
+use std::{collections::HashMap, io};
+
+use imap::{Connection, Session};
+
+use crate::ui::Ui;
+use crate::user::User;
+use crate::*;
+
+/// Represents a message in a conversation tree, with its replies as children.
+///
+/// A `None` UID stands for a message that is referenced by replies but missing from the mailbox.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Thread {
+    pub uid: Option<u32>,
+    pub children: Vec<Thread>,
+}
+
+impl Thread {
+    /// Flattens the thread into `(depth, uid)` pairs in display order.
+    ///
+    /// Missing messages are skipped, their replies take their place in the tree.
+    pub fn flatten(&self) -> Vec<(usize, u32)> {
+        let mut entries = Vec::new();
+        self.flatten_into(0, &mut entries);
+        entries
+    }
+
+    fn flatten_into(&self, depth: usize, entries: &mut Vec<(usize, u32)>) {
+        let depth = match self.uid {
+            Some(uid) => {
+                entries.push((depth, uid));
+                depth + 1
+            }
+            None => depth,
+        };
+        for child in self.children.iter() {
+            child.flatten_into(depth, entries);
+        }
+    }
+}
+
+/// Represents a fetched message and the conversation it belongs to, in thread order.
+pub struct Conversation {
+    pub messages: Vec<MessageRef>,
+    pub position: usize,
+    pub body: String,
+}
+
+/// Parses the untagged response of a `THREAD` command (RFC 5256) into threads.
+pub fn parse_thread_response(response: &[u8]) -> Vec<Thread> {
+    let response = String::from_utf8_lossy(response);
+    let Some(line) = response
+        .lines()
+        .find_map(|l| l.strip_prefix("* THREAD"))
+        .map(|l| l.as_bytes())
+    else {
+        return Vec::new();
+    };
+
+    let mut threads = Vec::new();
+    let mut pos = 0;
+    while pos < line.len() {
+        pos += 1;
+        if line[pos - 1] == b'(' {
+            threads.push(parse_thread_list(line, &mut pos));
+        }
+    }
+    threads
+}
+
+/// Parses a parenthesized thread list, `pos` is right after its opening parenthesis.
+///
+/// In "(1 2 (3)(4 5))", 1 is the parent of 2, whose replies are 3 and 4, and 5 replies to 4.
+fn parse_thread_list(line: &[u8], pos: &mut usize) -> Thread {
+    let mut uids = Vec::new();
+    let mut children = Vec::new();
+    while *pos < line.len() {
+        match line[*pos] {
+            b'(' => {
+                *pos += 1;
+                children.push(parse_thread_list(line, pos));
+            }
+            b')' => {
+                *pos += 1;
+                break;
+            }
+            b'0'..=b'9' => {
+                let start = *pos;
+                while *pos < line.len() && line[*pos].is_ascii_digit() {
+                    *pos += 1;
+                }
+                if let Ok(uid) = String::from_utf8_lossy(&line[start..*pos]).parse() {
+                    uids.push(uid);
+                }
+            }
+            _ => *pos += 1,
+        }
+    }
+
+    let mut thread = Thread {
+        uid: uids.pop(),
+        children,
+    };
+    while let Some(uid) = uids.pop() {
+        thread = Thread {
+            uid: Some(uid),
+            children: vec![thread],
+        };
+    }
+    thread
+}
+
+/// Threads messages locally from their "Message-ID", "In-Reply-To" & "References" headers.
+///
+/// Each message replies to its closest ancestor present in `summaries`, threads and
+/// replies keep the order of `summaries`.
+pub fn thread_locally(summaries: &[MessageSummary]) -> Vec<Thread> {
+    let ids = summaries
+        .iter()
+        .enumerate()
+        .filter_map(|(i, s)| Some((s.message_id.as_deref()?, i)))
+        .collect::<HashMap<_, _>>();
+
+    let mut parents: Vec<Option<usize>> = vec![None; summaries.len()];
+    for (i, summary) in summaries.iter().enumerate() {
+        parents[i] = summary
+            .ancestors
+            .iter()
+            .rev()
+            .filter_map(|id| ids.get(id.as_str()).copied())
+            .find(|&p| !is_descendant(&parents, p, i));
+    }
+
+    let mut children = vec![Vec::new(); summaries.len()];
+    let mut roots = Vec::new();
+    for (i, parent) in parents.iter().enumerate() {
+        match parent {
+            Some(p) => children[*p].push(i),
+            None => roots.push(i),
+        }
+    }
+
+    roots
+        .iter()
+        .map(|&root| build_thread(summaries, &children, root))
+        .collect()
+}
+
+/// Checks whether `node` is `ancestor` itself or one of its descendants.
+fn is_descendant(parents: &[Option<usize>], node: usize, ancestor: usize) -> bool {
+    let mut node = Some(node);
+    for _ in 0..=parents.len() {
+        match node {
+            Some(n) if n == ancestor => return true,
+            Some(n) => node = parents[n],
+            None => return false,
+        }
+    }
+    true
+}
+
+fn build_thread(summaries: &[MessageSummary], children: &[Vec<usize>], i: usize) -> Thread {
+    Thread {
+        uid: Some(summaries[i].uid),
+        children: children[i]
+            .iter()
+            .map(|&child| build_thread(summaries, children, child))
+            .collect(),
+    }
+}
+
+/// Shows threads as trees of subjects and prompts the user to choose a message.
+///
+/// # Returns
+///
+/// - The UIDs of the chosen message's conversation in thread order, and the position of the
+///   chosen message among them.
+/// - An `Err` if reading user input fails, e.g. once it ends.
+pub fn read_thread_selection(
+    ui: &mut dyn Ui,
+    threads: &[Thread],
+    summaries: &[MessageSummary],
+    prompts: &Prompts,
+) -> io::Result<(Vec<u32>, usize)> {
+    let subjects = summaries
+        .iter()
+        .map(|s| (s.uid, s.subject.as_str()))
+        .collect::<HashMap<_, _>>();
+    let entries = threads
+        .iter()
+        .enumerate()
+        .flat_map(|(i, thread)| thread.flatten().into_iter().map(move |e| (i, e)))
+        .collect::<Vec<_>>();
+
+    ui.show(prompts.fetch_message_list);
+    for (i, (_, (depth, uid))) in entries.iter().enumerate() {
+        let branch = match depth {
+            0 => String::new(),
+            depth => format!("{}↳ ", "  ".repeat(*depth - 1)),
+        };
+        let subject = subjects.get(uid).copied().unwrap_or_default();
+        ui.show(&format!("  [{}] {}{}", i + 1, branch, subject));
+    }
+
+    let selection = ui.read_selection(
+        prompts.fetch_message_selection,
+        prompts.invalid_literal,
+        prompts.fetch_message_literal,
+        prompts.should_be_one_of_below_literal,
+        &RangeUsize::new(1, entries.len()),
+    )? - 1;
+    let (thread, (_, uid)) = entries[selection];
+    let uids = threads[thread]
+        .flatten()
+        .iter()
+        .map(|&(_, uid)| uid)
+        .collect::<Vec<_>>();
+    let position = uids.iter().position(|&u| u == uid).unwrap();
+
+    Ok((uids, position))
+}
+
+/// Runs `UID THREAD REFERENCES` and reads its response up to the tagged status line.
+///
+/// imap-proto can't parse `THREAD` responses, reading them through the session would leave
+/// the status line unread, so lines are read one by one instead.
+///
+/// # Returns
+///
+/// - The response if the server completed the command.
+/// - `None` if the server refused it.
+/// - An `Err` if the connection failed.
+fn read_thread_response(imap_cli: &mut Session<Connection>) -> error::Result<Option<Vec<u8>>> {
+    imap_cli.run_command("UID THREAD REFERENCES UTF-8 ALL")?;
+    let mut response = Vec::new();
+    loop {
+        // The greeting is the only raw line read the session offers
+        imap_cli.greeting_read = false;
+        let line = imap_cli.read_greeting()?;
+        response.extend_from_slice(&line);
+        if !line.starts_with(b"* ") {
+            let status = line.split(|&b| b == b' ').nth(1).unwrap_or_default();
+            return Ok(status.eq_ignore_ascii_case(b"OK").then_some(response));
+        }
+    }
+}
+
+impl User {
+    /// Groups messages of the selected mailbox into conversations.
+    ///
+    /// The server's `THREAD=REFERENCES` extension is used when available, otherwise messages
+    /// are threaded locally from their summaries. So are they when the server's response
+    /// holds no thread for them.
+    pub fn fetch_threads(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+        messages: &[MessageRef],
+        summaries: &[MessageSummary],
+    ) -> error::Result<Vec<Thread>> {
+        if let Some(first) = messages.first() {
+            if imap_cli.capabilities()?.has_str("THREAD=REFERENCES") {
+                self.select_message(imap_cli, first)?;
+                // A server rejecting the charset is threaded locally instead
+                if let Some(response) = read_thread_response(imap_cli)? {
+                    let threads = parse_thread_response(&response);
+                    if !threads.is_empty() {
+                        return Ok(threads);
+                    }
+                }
+            }
+        }
+
+        Ok(thread_locally(summaries))
+    }
+
+    /// Shows a message and lets the user step through the conversation it belongs to.
+    pub fn view_conversation(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+        mut conversation: Conversation,
+        ui: &mut dyn Ui,
+        prompts: &Prompts,
+    ) -> error::Result<()> {
+        let len = conversation.messages.len();
+        loop {
+            let message = &conversation.messages[conversation.position];
+            show_body(ui, conversation.body, prompts);
+            if let Err(e) = self.view_attachments(imap_cli, message, ui, prompts) {
+                ui.show_error(&format!("{}{:?}", prompts.attachment_fail, e));
+            }
+            if len == 1 {
+                return Ok(());
+            }
+
+            ui.show(&format!(
+                "{}{}/{}.",
+                prompts.conversation_position,
+                conversation.position + 1,
+                len
+            ));
+            ui.show(prompts.conversation_action_list);
+            conversation.position = match ui.read_selection(
+                prompts.action_selection,
+                prompts.invalid_literal,
+                prompts.action_literal,
+                prompts.should_be_one_of_below_literal,
+                &RangeUsize::new(0, 2),
+            )? {
+                0 => return Ok(()),
+                1 => conversation.position.saturating_sub(1),
+                2 => (conversation.position + 1).min(len - 1),
+                _ => unreachable!(), // selection from `read_selection()` should have matched one of the above
+            };
+            conversation.body =
+                self.fetch_body(imap_cli, &conversation.messages[conversation.position])?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaf(uid: u32) -> Thread {
+        Thread {
+            uid: Some(uid),
+            children: Vec::new(),
+        }
+    }
+
+    fn summary(uid: u32, message_id: &str, ancestors: &[&str]) -> MessageSummary {
+        MessageSummary {
+            uid,
+            message_id: Some(message_id.to_string()),
+            ancestors: ancestors.iter().map(|a| a.to_string()).collect(),
+            ..MessageSummary::default()
+        }
+    }
+
+    #[test]
+    fn parses_the_rfc_5256_example() {
+        let response = b"* THREAD (2)(3 6 (4 23)(44 7 96))\r\nA1 OK THREAD completed\r\n";
+
+        let threads = parse_thread_response(response);
+
+        assert_eq!(threads.len(), 2);
+        assert_eq!(threads[0], leaf(2));
+        let six = &threads[1].children[0];
+        assert_eq!(threads[1].uid, Some(3));
+        assert_eq!(six.uid, Some(6));
+        assert_eq!(
+            six.children,
+            [
+                Thread {
+                    uid: Some(4),
+                    children: vec![leaf(23)],
+                },
+                Thread {
+                    uid: Some(44),
+                    children: vec![Thread {
+                        uid: Some(7),
+                        children: vec![leaf(96)],
+                    }],
+                },
+            ]
+        );
+        assert_eq!(
+            threads[1].flatten(),
+            [(0, 3), (1, 6), (2, 4), (3, 23), (2, 44), (3, 7), (4, 96)]
+        );
+    }
+
+    #[test]
+    fn missing_parents_are_skipped_when_flattened() {
+        let threads = parse_thread_response(b"* THREAD ((3)(5))\r\n");
+
+        assert_eq!(threads[0].uid, None);
+        assert_eq!(threads[0].children, [leaf(3), leaf(5)]);
+        assert_eq!(threads[0].flatten(), [(0, 3), (0, 5)]);
+    }
+
+    #[test]
+    fn a_response_without_threads_has_none() {
+        assert!(parse_thread_response(b"* THREAD\r\n").is_empty());
+        assert!(parse_thread_response(b"A1 NO no THREAD here\r\n").is_empty());
+    }
+
+    #[test]
+    fn replies_attach_to_their_closest_present_ancestor() {
+        // The message in between isn't in the mailbox
+        let summaries = [
+            summary(1, "a", &[]),
+            summary(3, "c", &["a", "b"]),
+            summary(4, "d", &["a", "b", "c"]),
+            summary(5, "e", &["x"]),
+        ];
+
+        let threads = thread_locally(&summaries);
+
+        assert_eq!(threads.len(), 2);
+        assert_eq!(threads[0].flatten(), [(0, 1), (1, 3), (2, 4)]);
+        assert_eq!(threads[1], leaf(5));
+    }
+
+    #[test]
+    fn reference_cycles_are_broken() {
+        let summaries = [
+            summary(1, "a", &["c"]),
+            summary(2, "b", &["a"]),
+            summary(3, "c", &["b"]),
+            summary(4, "d", &["d"]),
+        ];
+
+        let threads = thread_locally(&summaries);
+
+        let mut uids = threads
+            .iter()
+            .flat_map(|t| t.flatten())
+            .map(|(_, uid)| uid)
+            .collect::<Vec<_>>();
+        uids.sort();
+        assert_eq!(uids, [1, 2, 3, 4]);
+        assert!(threads.contains(&leaf(4)));
+    }
+}
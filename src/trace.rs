@@ -0,0 +1,73 @@
+use std::{
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use chrono::Local;
+use tracing_subscriber::{filter::LevelFilter, EnvFilter};
+
+use crate::tap::{Direction, TapSink};
+use crate::*;
+
+/// Target of the events carrying the lines exchanged with servers.
+pub const WIRE_TARGET: &str = "echo_unity_archivist::wire";
+
+/// Level used when `--trace` is given without one.
+pub const DEFAULT_LEVEL: LevelFilter = LevelFilter::DEBUG;
+
+/// Emits the lines passing through taps as `tracing` events.
+///
+/// Lines are emitted at DEBUG level with `WIRE_TARGET` as their target, tagged with the
+/// tapped server & connection, e.g. "imap#3 C: a1 LOGIN <redacted>".
+pub struct Tracer;
+
+impl TapSink for Tracer {
+    fn line(&self, label: &str, connection: usize, direction: Direction, line: &[u8]) {
+        let side = match direction {
+            Direction::Client => 'C',
+            Direction::Server => 'S',
+        };
+        let text = String::from_utf8_lossy(line);
+        tracing::debug!(
+            target: WIRE_TARGET,
+            "{}#{} {}: {}",
+            label,
+            connection,
+            side,
+            text.trim_end_matches(['\r', '\n'])
+        );
+    }
+}
+
+/// Sends `tracing` events of the agent up to a level to a file, each line starting with a
+/// timestamp.
+///
+/// At "warn", failed connections & logins are traced, "info" adds connections opened &
+/// closed, "debug" adds every command & response. Events of other crates are left out.
+///
+/// # Returns
+///
+/// - `Ok(())` if the process succeeds.
+/// - An `Err` if the file can't be created, or tracing was already set up.
+pub fn init(path: &Path, level: LevelFilter) -> io::Result<()> {
+    let filter = EnvFilter::builder()
+        .parse(format!("off,echo_unity_archivist={}", level))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+    let file = File::create(path)?;
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(Mutex::new(file))
+        .with_ansi(false)
+        .try_init()
+        .map_err(|e| io::Error::other(e.to_string()))
+}
+
+/// Returns the path of a new trace file in "traces" in the data directory, creating the
+/// directory if needed.
+pub fn new_trace_path() -> io::Result<PathBuf> {
+    let dir = data_dir().join("traces");
+    fs::create_dir_all(&dir)?;
+    Ok(dir.join(format!("{}.log", Local::now().format("%Y%m%d-%H%M%S"))))
+}
@@ -0,0 +1,260 @@
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    str,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::Local;
+
+use crate::tap::{Direction, TapSink};
+use crate::*;
+
+/// First line of every transcript file.
+const HEADER: &str = "# echo_unity_archivist transcript";
+
+/// Line starting each connection's section of a transcript file.
+const CONNECTION: &str = "== connection";
+
+/// Represents a line sent by either side of a connection, with its line break if it had one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Entry {
+    Client(Vec<u8>),
+    Server(Vec<u8>),
+}
+
+/// Represents the lines exchanged with a server over one or more connections.
+///
+/// In files, a connection starts with "== connection", followed by a line per entry:
+/// "C: " or "S: " & the text of lines ending in CRLF, "C= " or "S= " & the Base64 of any
+/// other bytes. Lines starting with "#" are comments.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Transcript {
+    pub connections: Vec<Vec<Entry>>,
+}
+
+impl Transcript {
+    /// Parses the text of a transcript file.
+    ///
+    /// # Returns
+    ///
+    /// - The `Transcript` if the text is well-formed.
+    /// - An `Err` naming the first malformed line otherwise.
+    pub fn parse(text: &str) -> io::Result<Transcript> {
+        let mut connections: Vec<Vec<Entry>> = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line == CONNECTION {
+                connections.push(Vec::new());
+                continue;
+            }
+            let malformed = || {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("malformed transcript line {}: {}", i + 1, line),
+                )
+            };
+            let (Some(side), Some(kind), Some(data)) =
+                (line.get(..1), line.get(1..3), line.get(3..))
+            else {
+                return Err(malformed());
+            };
+            let bytes = match kind {
+                ": " => format!("{}\r\n", data).into_bytes(),
+                "= " => STANDARD.decode(data).map_err(|_| malformed())?,
+                _ => return Err(malformed()),
+            };
+            let entry = match side {
+                "C" => Entry::Client(bytes),
+                "S" => Entry::Server(bytes),
+                _ => return Err(malformed()),
+            };
+            connections.last_mut().ok_or_else(malformed)?.push(entry);
+        }
+        Ok(Transcript { connections })
+    }
+
+    /// Formats the transcript as the text of a transcript file.
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{}\n", HEADER);
+        for entries in self.connections.iter() {
+            text.push_str(CONNECTION);
+            text.push('\n');
+            for entry in entries {
+                let (side, bytes) = match entry {
+                    Entry::Client(bytes) => ('C', bytes),
+                    Entry::Server(bytes) => ('S', bytes),
+                };
+                let line = bytes
+                    .strip_suffix(b"\r\n")
+                    .and_then(|line| str::from_utf8(line).ok())
+                    .filter(|line| !line.contains(['\r', '\n']));
+                match line {
+                    Some(line) => text.push_str(&format!("{}: {}\n", side, line)),
+                    None => text.push_str(&format!("{}= {}\n", side, STANDARD.encode(bytes))),
+                }
+            }
+        }
+        text
+    }
+
+    /// Loads a transcript file.
+    pub fn load(path: &Path) -> io::Result<Transcript> {
+        Transcript::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Saves the transcript to a file.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_text())
+    }
+}
+
+/// Records the lines passing through taps, as transcripts keyed by the taps' labels.
+#[derive(Default)]
+pub struct Recorder {
+    lines: Mutex<Vec<(String, usize, Entry)>>,
+}
+
+impl TapSink for Recorder {
+    fn line(&self, label: &str, connection: usize, direction: Direction, line: &[u8]) {
+        let entry = match direction {
+            Direction::Client => Entry::Client(line.to_vec()),
+            Direction::Server => Entry::Server(line.to_vec()),
+        };
+        self.lines
+            .lock()
+            .unwrap()
+            .push((label.to_string(), connection, entry));
+    }
+}
+
+impl Recorder {
+    /// Returns the labels of the taps recorded so far, in the order they were first seen.
+    pub fn labels(&self) -> Vec<String> {
+        let mut labels: Vec<String> = Vec::new();
+        for (label, _, _) in self.lines.lock().unwrap().iter() {
+            if !labels.contains(label) {
+                labels.push(label.clone());
+            }
+        }
+        labels
+    }
+
+    /// Returns what's been recorded by a tap, connections in the order they were opened.
+    pub fn transcript(&self, label: &str) -> Transcript {
+        let mut ids = Vec::new();
+        let mut transcript = Transcript::default();
+        for (l, connection, entry) in self.lines.lock().unwrap().iter() {
+            if l != label {
+                continue;
+            }
+            let i = match ids.iter().position(|id| id == connection) {
+                Some(i) => i,
+                None => {
+                    ids.push(*connection);
+                    transcript.connections.push(Vec::new());
+                    ids.len() - 1
+                }
+            };
+            transcript.connections[i].push(entry.clone());
+        }
+        transcript
+    }
+
+    /// Saves a transcript file per tap to "transcripts" in the data directory.
+    ///
+    /// Transcripts are meant to be shared as test fixtures, so they are never sealed by the
+    /// vault. Credentials are redacted, but the messages read & sent are kept.
+    ///
+    /// # Returns
+    ///
+    /// - The paths of the saved files if the process succeeds.
+    /// - An `Err` if any file can't be written.
+    pub fn save(&self) -> io::Result<Vec<PathBuf>> {
+        let dir = data_dir().join("transcripts");
+        fs::create_dir_all(&dir)?;
+        let timestamp = Local::now().format("%Y%m%d-%H%M%S");
+        let mut paths = Vec::new();
+        for label in self.labels() {
+            let path = dir.join(format!("{}-{}.transcript", timestamp, label));
+            self.transcript(&label).save(&path)?;
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+}
+
+/// Represents a scripted server replaying a transcript.
+pub struct Replay {
+    pub port: u16,
+    received: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Replay {
+    /// Returns the bytes received from clients, from all connections in the order they
+    /// arrived.
+    pub fn received(&self) -> Vec<u8> {
+        self.received.lock().unwrap().clone()
+    }
+}
+
+/// Starts a server on a free localhost port replaying a transcript.
+///
+/// The n-th connection accepted follows the n-th connection of the transcript, sending the
+/// server's lines & reading a line, or as many bytes, where the client's lines were. What
+/// the client sends isn't compared to the transcript, as it may hold redactions.
+///
+/// # Returns
+///
+/// - The `Replay` if the process succeeds.
+/// - An `Err` if the port can't be bound.
+pub fn replay(transcript: Transcript) -> io::Result<Replay> {
+    let listener = TcpListener::bind(("127.0.0.1", 0))?;
+    let port = listener.local_addr()?.port();
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let recorder = received.clone();
+    thread::spawn(move || {
+        let streams = listener.incoming().flatten();
+        for (entries, stream) in transcript.connections.into_iter().zip(streams) {
+            let recorder = recorder.clone();
+            thread::spawn(move || {
+                let _ = play(entries, stream, &recorder);
+            });
+        }
+    });
+    Ok(Replay { port, received })
+}
+
+/// Plays a connection of a transcript to a client, until it ends or the client leaves.
+fn play(entries: Vec<Entry>, stream: TcpStream, received: &Mutex<Vec<u8>>) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    for entry in entries {
+        match entry {
+            Entry::Server(bytes) => writer.write_all(&bytes)?,
+            Entry::Client(bytes) => {
+                let mut line = Vec::new();
+                match bytes.ends_with(b"\n") {
+                    true => {
+                        reader.read_until(b'\n', &mut line)?;
+                    }
+                    false => {
+                        line.resize(bytes.len(), 0);
+                        reader.read_exact(&mut line)?;
+                    }
+                }
+                received.lock().unwrap().extend_from_slice(&line);
+                if line.is_empty() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use chrono::Local;
+use lettre::{address::Envelope, Message, SmtpTransport, Transport};
+
+use crate::jmap::JmapClient;
+use crate::*;
+
+/// A way of delivering messages to their recipients.
+pub trait MailTransport {
+    /// Sends a message's raw RFC822 bytes to the recipients of an envelope.
+    fn send_raw(&mut self, envelope: &Envelope, raw: &[u8]) -> error::Result<()>;
+
+    /// Sends a built message to its recipients.
+    fn send(&mut self, message: &Message) -> error::Result<()> {
+        self.send_raw(message.envelope(), &message.formatted())
+    }
+}
+
+impl MailTransport for SmtpTransport {
+    fn send_raw(&mut self, envelope: &Envelope, raw: &[u8]) -> error::Result<()> {
+        Transport::send_raw(self, envelope, raw)?;
+        Ok(())
+    }
+}
+
+impl MailTransport for JmapClient {
+    /// Submits the message as the identity of the envelope's sender, the recipients are
+    /// taken from the message's headers by the server.
+    fn send_raw(&mut self, envelope: &Envelope, raw: &[u8]) -> error::Result<()> {
+        let from = envelope.from().map(|a| a.to_string()).unwrap_or_default();
+        self.submit(&from, raw)
+    }
+}
+
+/// Represents an outbox directory, messages are written into it as `.eml` files instead of
+/// being delivered.
+pub struct FileTransport {
+    dir: PathBuf,
+}
+
+impl FileTransport {
+    /// Creates a transport writing into a directory, creating the directory if needed.
+    pub fn new(dir: &Path) -> error::Result<FileTransport> {
+        fs::create_dir_all(dir)?;
+        Ok(FileTransport {
+            dir: dir.to_path_buf(),
+        })
+    }
+}
+
+impl MailTransport for FileTransport {
+    /// Writes the message into the outbox, named by the time it's sent.
+    ///
+    /// The envelope isn't kept, the recipients are in the message's headers.
+    fn send_raw(&mut self, _envelope: &Envelope, raw: &[u8]) -> error::Result<()> {
+        let stamp = Local::now().format("%Y%m%d-%H%M%S%.6f");
+        vault::write_file(&self.dir.join(format!("{}.eml", stamp)), raw)?;
+        Ok(())
+    }
+}
+
+/// Represents a transport keeping sent messages in memory, e.g. as a stand-in for a server.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryTransport {
+    pub sent: Vec<(Envelope, Vec<u8>)>,
+}
+
+impl MemoryTransport {
+    pub fn new() -> MemoryTransport {
+        MemoryTransport::default()
+    }
+}
+
+impl MailTransport for MemoryTransport {
+    fn send_raw(&mut self, envelope: &Envelope, raw: &[u8]) -> error::Result<()> {
+        self.sent.push((envelope.clone(), raw.to_vec()));
+        Ok(())
+    }
+}
@@ -0,0 +1,350 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    net::{IpAddr, TcpStream, ToSocketAddrs},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+
+use native_tls::{Certificate, TlsConnector, TlsStream};
+
+use crate::cache::escape;
+use crate::doctor::fingerprint;
+use crate::ui::Ui;
+use crate::*;
+
+/// Time allowed to connect to a server & for each read or write on the connection.
+pub const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Represents how an account's TLS connections decide which servers to trust.
+///
+/// With the defaults, a server is trusted if its certificate chains to one of the system's
+/// CAs & names it, the way the mail libraries do on their own.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TrustSettings {
+    /// Files of CAs trusted on top of the system's, PEM bundles or single DER certificates.
+    pub ca_files: Vec<PathBuf>,
+    /// Whether servers are trusted by the fingerprint of their certificate instead of its
+    /// chain, the fingerprint seen on the first connection being pinned.
+    pub pinning: bool,
+    /// Pinned SHA-256 fingerprints, by "host:port".
+    pub pins: BTreeMap<String, String>,
+    /// Whether invalid certificates are accepted from servers on the local machine.
+    pub accept_invalid_localhost: bool,
+}
+
+impl TrustSettings {
+    /// Parses the text of an account's trust file, skipping lines it doesn't understand.
+    pub fn parse(text: &str) -> TrustSettings {
+        let mut settings = TrustSettings::default();
+        for line in text.lines() {
+            let columns = line.split('\t').collect::<Vec<_>>();
+            match columns.as_slice() {
+                ["ca", path] => settings.ca_files.push(PathBuf::from(path)),
+                ["pinning", "on"] => settings.pinning = true,
+                ["pin", host, fingerprint] => {
+                    settings
+                        .pins
+                        .insert(host.to_string(), fingerprint.to_string());
+                }
+                ["accept_invalid_localhost", "on"] => settings.accept_invalid_localhost = true,
+                _ => {}
+            }
+        }
+        settings
+    }
+
+    /// Formats the settings as the text of an account's trust file.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for path in self.ca_files.iter() {
+            text.push_str(&format!("ca\t{}\n", path.display()));
+        }
+        if self.pinning {
+            text.push_str("pinning\ton\n");
+        }
+        for (host, fingerprint) in self.pins.iter() {
+            text.push_str(&format!("pin\t{}\t{}\n", host, fingerprint));
+        }
+        if self.accept_invalid_localhost {
+            text.push_str("accept_invalid_localhost\ton\n");
+        }
+        text
+    }
+
+    /// Checks whether the settings are the defaults, so connections can be left to the mail
+    /// libraries.
+    pub fn is_default(&self) -> bool {
+        self.ca_files.is_empty() && !self.pinning && !self.accept_invalid_localhost
+    }
+
+    /// Builds a `TlsConnector` for a server, trusting the configured CAs.
+    ///
+    /// Chains aren't verified when pinning, nor for a server on the local machine if invalid
+    /// certificates are accepted there.
+    ///
+    /// # Returns
+    ///
+    /// - The `TlsConnector` if the process succeeds.
+    /// - An `Err` if a CA file can't be read or holds no certificate.
+    pub fn connector(&self, domain: &str) -> io::Result<TlsConnector> {
+        let mut builder = TlsConnector::builder();
+        for path in self.ca_files.iter() {
+            for certificate in load_certificates(path)? {
+                builder.add_root_certificate(certificate);
+            }
+        }
+        let accept_invalid =
+            self.pinning || (self.accept_invalid_localhost && is_localhost(domain));
+        builder
+            .danger_accept_invalid_certs(accept_invalid)
+            .danger_accept_invalid_hostnames(accept_invalid)
+            .build()
+            .map_err(io::Error::other)
+    }
+}
+
+/// Returns the path of the file an account's trust settings are kept in.
+fn trust_path(email_addr: &str) -> PathBuf {
+    data_dir().join("trust").join(escape(email_addr))
+}
+
+/// Loads an account's trust settings, the defaults if it has no trust file.
+pub fn load_trust(email_addr: &str) -> io::Result<TrustSettings> {
+    match fs::read_to_string(trust_path(email_addr)) {
+        Ok(text) => Ok(TrustSettings::parse(&text)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(TrustSettings::default()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Saves an account's trust settings, replacing the ones saved before.
+pub fn save_trust(email_addr: &str, settings: &TrustSettings) -> io::Result<()> {
+    let path = trust_path(email_addr);
+    fs::create_dir_all(path.parent().expect("trust file should be in a directory"))?;
+    fs::write(path, settings.to_text())
+}
+
+/// Loads the certificates of a CA file, a PEM bundle or a single DER certificate.
+pub fn load_certificates(path: &Path) -> io::Result<Vec<Certificate>> {
+    let data = fs::read(path)?;
+    let invalid = |reason: String| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), reason),
+        )
+    };
+    let certificates = match data.starts_with(b"-----BEGIN") {
+        true => Certificate::stack_from_pem(&data).map_err(|e| invalid(e.to_string()))?,
+        false => vec![Certificate::from_der(&data).map_err(|e| invalid(e.to_string()))?],
+    };
+    match certificates.is_empty() {
+        true => Err(invalid("no certificate found".to_string())),
+        false => Ok(certificates),
+    }
+}
+
+/// Connects to a server, giving up on each of its addresses after `TIMEOUT`, reads &
+/// writes on the connection timing out the same.
+pub fn connect_tcp(domain: &str, port: u16) -> io::Result<TcpStream> {
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no address to connect to");
+    for address in (domain, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, TIMEOUT) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(TIMEOUT))?;
+                stream.set_write_timeout(Some(TIMEOUT))?;
+                return Ok(stream);
+            }
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+/// Checks whether a server's name or address is the local machine's.
+pub fn is_localhost(domain: &str) -> bool {
+    domain.eq_ignore_ascii_case("localhost")
+        || domain.to_ascii_lowercase().ends_with(".localhost")
+        || domain
+            .trim_matches(['[', ']'])
+            .parse::<IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback())
+}
+
+/// Represents an account's trust settings in use by its connections, which may pin
+/// certificates as they go.
+pub struct Trust {
+    email_addr: String,
+    settings: Mutex<TrustSettings>,
+    /// Whether servers without a pin get theirs pinned on first use.
+    pins_first_use: bool,
+}
+
+impl Trust {
+    /// Constructs the trust of an account's connections, pins being saved as its settings.
+    pub fn new(email_addr: &str, settings: TrustSettings) -> Trust {
+        Trust {
+            email_addr: email_addr.to_string(),
+            settings: Mutex::new(settings),
+            pins_first_use: true,
+        }
+    }
+
+    /// Stops pinning servers on first use, for connections only meant to check them.
+    ///
+    /// A server without a pin then has to be trusted by its certificate's chain.
+    pub fn checking_only(mut self) -> Trust {
+        self.pins_first_use = false;
+        self
+    }
+
+    /// Loads an account's trust settings for its connections.
+    pub fn load(email_addr: &str) -> io::Result<Trust> {
+        Ok(Trust::new(email_addr, load_trust(email_addr)?))
+    }
+
+    /// Returns the settings in use.
+    pub fn settings(&self) -> TrustSettings {
+        self.settings.lock().unwrap().clone()
+    }
+
+    /// Connects to a server over TLS, trusting it the way the settings say.
+    pub fn connect(&self, domain: &str, port: u16) -> io::Result<TlsStream<TcpStream>> {
+        self.handshake(domain, port, connect_tcp(domain, port)?)
+    }
+
+    /// Performs a TLS handshake on a connection to a server, e.g. after STARTTLS, trusting
+    /// the server the way the settings say.
+    ///
+    /// When pinning, the fingerprint of a server without one is pinned & saved, unless the
+    /// trust is only checking.
+    ///
+    /// # Returns
+    ///
+    /// - The `TlsStream` if the server is trusted.
+    /// - An `Err` if the handshake fails, the server isn't trusted, or its certificate
+    ///   isn't the pinned one.
+    pub fn handshake(
+        &self,
+        domain: &str,
+        port: u16,
+        tcp: TcpStream,
+    ) -> io::Result<TlsStream<TcpStream>> {
+        let host = format!("{}:{}", domain, port);
+        let mut settings = self.settings();
+        if !self.pins_first_use && !settings.pins.contains_key(&host) {
+            settings.pinning = false;
+        }
+        let stream = settings
+            .connector(domain)?
+            .connect(domain, tcp)
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        if !settings.pinning {
+            return Ok(stream);
+        }
+
+        let der = stream
+            .peer_certificate()
+            .map_err(io::Error::other)?
+            .ok_or_else(|| io::Error::other("the server presented no certificate"))?
+            .to_der()
+            .map_err(io::Error::other)?;
+        let fingerprint = fingerprint(&der);
+        let mut settings = self.settings.lock().unwrap();
+        match settings.pins.get(&host) {
+            Some(pinned) if *pinned != fingerprint => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!(
+                    "certificate of {} isn't the pinned one, pinned {} but got {}",
+                    host, pinned, fingerprint
+                ),
+            )),
+            Some(_) => Ok(stream),
+            None => {
+                tracing::warn!("pinned the certificate of {}: {}", host, fingerprint);
+                settings.pins.insert(host, fingerprint);
+                save_trust(&self.email_addr, &settings)?;
+                Ok(stream)
+            }
+        }
+    }
+}
+
+/// Shows an account's trust settings.
+fn show_trust(ui: &mut dyn Ui, settings: &TrustSettings, prompts: &Prompts) {
+    let on_off = |on: bool| match on {
+        true => prompts.trust_on,
+        false => prompts.trust_off,
+    };
+    ui.show(prompts.trust_ca_files);
+    for (i, path) in settings.ca_files.iter().enumerate() {
+        ui.show(&format!("  [{}] {}", i + 1, path.display()));
+    }
+    ui.show(&format!(
+        "{}{}",
+        prompts.trust_pinning,
+        on_off(settings.pinning)
+    ));
+    for (host, fingerprint) in settings.pins.iter() {
+        ui.show(&format!("  {} {}", host, fingerprint));
+    }
+    ui.show(&format!(
+        "{}{}",
+        prompts.trust_localhost,
+        on_off(settings.accept_invalid_localhost)
+    ));
+}
+
+/// Lets the user change an account's trust settings, saving each change.
+pub fn configure_trust(email_addr: &str, ui: &mut dyn Ui, prompts: &Prompts) -> io::Result<()> {
+    let mut settings = load_trust(email_addr)?;
+    loop {
+        show_trust(ui, &settings, prompts);
+        ui.show(prompts.trust_action_list);
+        match ui.read_selection(
+            prompts.action_selection,
+            prompts.invalid_literal,
+            prompts.action_literal,
+            prompts.should_be_one_of_below_literal,
+            &RangeUsize::new(0, 5),
+        )? {
+            0 => return Ok(()),
+            1 => {
+                let path = PathBuf::from(ui.read_input(prompts.trust_ca_path)?);
+                match load_certificates(&path) {
+                    Ok(certificates) => {
+                        ui.show(&format!(
+                            "{}{}.",
+                            prompts.trust_ca_added,
+                            certificates.len()
+                        ));
+                        settings.ca_files.push(path);
+                    }
+                    Err(e) => {
+                        ui.show_error(&format!("{}{}", prompts.trust_fail, e));
+                        continue;
+                    }
+                }
+            }
+            2 => {
+                if settings.ca_files.is_empty() {
+                    continue;
+                }
+                let selection = ui.read_selection(
+                    prompts.trust_ca_selection,
+                    prompts.invalid_literal,
+                    prompts.trust_ca_literal,
+                    prompts.should_be_one_of_below_literal,
+                    &RangeUsize::new(1, settings.ca_files.len()),
+                )?;
+                settings.ca_files.remove(selection - 1);
+            }
+            3 => settings.pinning = !settings.pinning,
+            4 => settings.pins.clear(),
+            5 => settings.accept_invalid_localhost = !settings.accept_invalid_localhost,
+            _ => unreachable!(), // selection from `read_selection()` should have matched one of the above
+        }
+        save_trust(email_addr, &settings)?;
+    }
+}
@@ -1,3 +1,5 @@
+use mail_parser::MessageParser;
+
 /// Types whose valid values are enumerable.
 pub trait EnumValues {
     /// Build a custom message representing valid values.
@@ -48,3 +50,80 @@ impl EnumValues for Confirmation {
         format!("[{}, {}]", self.confirm, self.cancel)
     }
 }
+
+/// Represents a stable address of a message on the IMAP server.
+///
+/// A UID is only meaningful together with the mailbox it belongs to and the
+/// mailbox's UIDVALIDITY at the time the UID was obtained.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MessageRef {
+    pub mailbox: String,
+    pub uid_validity: u32,
+    pub uid: u32,
+}
+
+impl MessageRef {
+    pub fn new(mailbox: &str, uid_validity: u32, uid: u32) -> MessageRef {
+        MessageRef {
+            mailbox: mailbox.to_string(),
+            uid_validity,
+            uid,
+        }
+    }
+}
+
+/// Represents the headers of a message used for listing and threading.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MessageSummary {
+    pub uid: u32,
+    pub subject: String,
+    pub from: String,
+    pub message_id: Option<String>,
+    /// Message-IDs of the message's ancestors from "References" & "In-Reply-To", oldest first.
+    pub ancestors: Vec<String>,
+}
+
+impl MessageSummary {
+    /// Parses a `MessageSummary` from a message's raw header.
+    pub fn parse(uid: u32, header: &[u8]) -> MessageSummary {
+        let message = match MessageParser::default().parse_headers(header) {
+            Some(message) => message,
+            None => {
+                return MessageSummary {
+                    uid,
+                    ..Default::default()
+                }
+            }
+        };
+
+        let from = message
+            .from()
+            .and_then(|from| from.first())
+            .map(|addr| match (addr.name(), addr.address()) {
+                (Some(name), _) => name.to_string(),
+                (None, Some(address)) => address.to_string(),
+                (None, None) => String::new(),
+            })
+            .unwrap_or_default();
+        let mut ancestors = message
+            .references()
+            .as_text_list()
+            .unwrap_or_default()
+            .iter()
+            .map(|id| id.to_string())
+            .collect::<Vec<_>>();
+        for id in message.in_reply_to().as_text_list().unwrap_or_default() {
+            if !ancestors.iter().any(|a| a == id) {
+                ancestors.push(id.to_string());
+            }
+        }
+
+        MessageSummary {
+            uid,
+            subject: message.subject().unwrap_or_default().to_string(),
+            from,
+            message_id: message.message_id().map(String::from),
+            ancestors,
+        }
+    }
+}
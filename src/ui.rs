@@ -0,0 +1,224 @@
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use lettre::Address;
+
+use crate::read::{self, Io};
+use crate::*;
+
+/// A front end the interactive parts of the user agent talk to the user through.
+///
+/// Library code that needs the user to choose or type something asks a `Ui`, so the same
+/// logic can be driven by the terminal, a script, or anything else that answers.
+pub trait Ui {
+    /// Shows a line of text.
+    fn show(&mut self, text: &str);
+
+    /// Shows a line of text reporting an error.
+    fn show_error(&mut self, text: &str);
+
+    /// Shows how far a long task has got, each call replacing the progress shown before if
+    /// the front end can.
+    fn show_progress(&mut self, text: &str) {
+        self.show(text);
+    }
+
+    /// Reads a line of input, with a customized prompt.
+    ///
+    /// Reading fails with `read::input_ended()` once the input ends, so does every other
+    /// way of reading.
+    fn read_input(&mut self, prompt: &str) -> io::Result<String>;
+
+    /// Reads a secret such as a password, without echoing it if the front end can.
+    fn read_password(&mut self, prompt: &str) -> io::Result<String> {
+        self.read_input(prompt)
+    }
+
+    /// Reads an email address, until a valid value is provided.
+    fn read_email(&mut self, prompt_read: &str, prompt_invalid: &str) -> io::Result<Address>;
+
+    /// Reads a selection of `usize` within a range, until a valid value is provided.
+    fn read_selection(
+        &mut self,
+        prompt_read: &str,
+        prompt_invalid: &str,
+        prompt_object: &str,
+        prompt_should_be: &str,
+        range_usize: &RangeUsize,
+    ) -> io::Result<usize>;
+
+    /// Reads one or more selections of `usize` within a range, separated by commas or
+    /// spaces, until only valid values are provided.
+    ///
+    /// # Returns
+    ///
+    /// - The selections, sorted & without duplicates, if any are read.
+    /// - An `Err` if reading fails.
+    fn read_selections(
+        &mut self,
+        prompt_read: &str,
+        prompt_invalid: &str,
+        prompt_object: &str,
+        prompt_should_be: &str,
+        range_usize: &RangeUsize,
+    ) -> io::Result<Vec<usize>>;
+
+    /// Reads the body of an email, the end of input also finishing it.
+    fn read_body(&mut self, prompts: &Prompts) -> io::Result<String>;
+
+    /// Reads the reconfirmation for sending a message.
+    fn read_reconfirmation(
+        &mut self,
+        prompts: &Prompts,
+        reconfirmation: &Confirmation,
+    ) -> io::Result<bool>;
+}
+
+/// Represents the terminal the CLI runs in, reading stdin & writing stdout.
+pub struct Terminal;
+
+impl Ui for Terminal {
+    fn show(&mut self, text: &str) {
+        println!("{}", text);
+    }
+
+    fn show_error(&mut self, text: &str) {
+        eprintln!("{}", text);
+    }
+
+    fn show_progress(&mut self, text: &str) {
+        print!("\r{}", text);
+        let _ = io::stdout().flush();
+    }
+
+    fn read_input(&mut self, prompt: &str) -> io::Result<String> {
+        Io::stdio().input(prompt)
+    }
+
+    fn read_password(&mut self, prompt: &str) -> io::Result<String> {
+        // Only a terminal can hide what's typed, piped input is read like any other
+        if !io::stdin().is_terminal() {
+            return self.read_input(prompt);
+        }
+        match rpassword::prompt_password(prompt) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Err(read::input_ended()),
+            result => result.map(|password| password.trim().to_owned()),
+        }
+    }
+
+    fn read_email(&mut self, prompt_read: &str, prompt_invalid: &str) -> io::Result<Address> {
+        Io::stdio().email(prompt_read, prompt_invalid)
+    }
+
+    fn read_selection(
+        &mut self,
+        prompt_read: &str,
+        prompt_invalid: &str,
+        prompt_object: &str,
+        prompt_should_be: &str,
+        range_usize: &RangeUsize,
+    ) -> io::Result<usize> {
+        Io::stdio().selection(
+            prompt_read,
+            prompt_invalid,
+            prompt_object,
+            prompt_should_be,
+            range_usize,
+        )
+    }
+
+    fn read_selections(
+        &mut self,
+        prompt_read: &str,
+        prompt_invalid: &str,
+        prompt_object: &str,
+        prompt_should_be: &str,
+        range_usize: &RangeUsize,
+    ) -> io::Result<Vec<usize>> {
+        Io::stdio().selections(
+            prompt_read,
+            prompt_invalid,
+            prompt_object,
+            prompt_should_be,
+            range_usize,
+        )
+    }
+
+    fn read_body(&mut self, prompts: &Prompts) -> io::Result<String> {
+        Io::stdio().body(prompts)
+    }
+
+    fn read_reconfirmation(
+        &mut self,
+        prompts: &Prompts,
+        reconfirmation: &Confirmation,
+    ) -> io::Result<bool> {
+        Io::stdio().reconfirmation(prompts, reconfirmation)
+    }
+}
+
+/// Drives the user agent from any streams, e.g. a script of answers in tests.
+///
+/// Writing failures are treated as bugs, which panic like printing to the terminal does.
+impl<R: BufRead, W: Write> Ui for Io<R, W> {
+    fn show(&mut self, text: &str) {
+        self.write_line(text).expect("failed to write output");
+    }
+
+    fn show_error(&mut self, text: &str) {
+        self.write_line(text).expect("failed to write output");
+    }
+
+    fn read_input(&mut self, prompt: &str) -> io::Result<String> {
+        self.input(prompt)
+    }
+
+    fn read_email(&mut self, prompt_read: &str, prompt_invalid: &str) -> io::Result<Address> {
+        self.email(prompt_read, prompt_invalid)
+    }
+
+    fn read_selection(
+        &mut self,
+        prompt_read: &str,
+        prompt_invalid: &str,
+        prompt_object: &str,
+        prompt_should_be: &str,
+        range_usize: &RangeUsize,
+    ) -> io::Result<usize> {
+        self.selection(
+            prompt_read,
+            prompt_invalid,
+            prompt_object,
+            prompt_should_be,
+            range_usize,
+        )
+    }
+
+    fn read_selections(
+        &mut self,
+        prompt_read: &str,
+        prompt_invalid: &str,
+        prompt_object: &str,
+        prompt_should_be: &str,
+        range_usize: &RangeUsize,
+    ) -> io::Result<Vec<usize>> {
+        self.selections(
+            prompt_read,
+            prompt_invalid,
+            prompt_object,
+            prompt_should_be,
+            range_usize,
+        )
+    }
+
+    fn read_body(&mut self, prompts: &Prompts) -> io::Result<String> {
+        self.body(prompts)
+    }
+
+    fn read_reconfirmation(
+        &mut self,
+        prompts: &Prompts,
+        reconfirmation: &Confirmation,
+    ) -> io::Result<bool> {
+        self.reconfirmation(prompts, reconfirmation)
+    }
+}
@@ -1,58 +1,182 @@
-use std::{error::Error, str};
+use std::{collections::HashMap, error::Error, io, str, sync::Arc};
 
-use imap::{self, Connection, Session};
-use lettre::{
-    message::header::ContentType, message::Mailbox, transport::smtp::authentication::Credentials,
-    Address, Message, SmtpTransport, Transport,
-};
+use imap::{self, types::Mailbox as ImapMailbox, Connection, Session};
+use lettre::{message::header::ContentType, message::Mailbox, Address, Message};
 
+use crate::smtp::SmtpClient;
+use crate::store::MailStore;
+use crate::tap::{TapSink, Tapped};
+use crate::thread::{read_thread_selection, Conversation};
+use crate::transport::MailTransport;
+use crate::trust::{connect_tcp, Trust};
+use crate::ui::Ui;
 use crate::*;
 
+/// Port of SMTP submission over TLS.
+pub const SMTPS_PORT: u16 = 465;
+
+/// Port of IMAP over TLS.
+pub const IMAPS_PORT: u16 = 993;
+
+/// Represents how connections to the SMTP & IMAP servers are secured.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Security {
+    /// TLS from the first byte, the way the default ports expect.
+    Tls,
+    /// No encryption at all, credentials are sent in the clear. Only meant for servers on
+    /// the local machine, e.g. in tests.
+    Plaintext,
+}
+
 /// Represents a user.
+#[derive(Clone)]
 pub struct User {
     pub smtp_domain: String,
     pub imap_domain: String,
+    pub pop3_domain: String,
+    pub smtp_port: u16,
+    pub imap_port: u16,
+    pub security: Security,
     pub email_addr: Address,
-    password: String,
+    pub(crate) password: String,
+    uid_validities: HashMap<String, u32>,
+    tap: Option<Arc<dyn TapSink>>,
+    /// Whether connections pin servers on first use, if the account's trust settings pin.
+    pub(crate) pins_first_use: bool,
 }
 
 impl User {
     /// Constructs a new `User` from user input.
-    pub fn build(prompts: &Prompts) -> User {
-        let email = read_email(prompts.login_email_addr, prompts.email_addr_invalid);
-        let password = read_input(prompts.login_password);
+    pub fn build(ui: &mut dyn Ui, prompts: &Prompts) -> io::Result<User> {
+        let email = ui.read_email(prompts.login_email_addr, prompts.email_addr_invalid)?;
+        let password = ui.read_password(prompts.login_password)?;
+        Ok(User::new(email, password))
+    }
+
+    /// Constructs a new `User` from an email address & password.
+    pub fn new(email: Address, password: String) -> User {
         let domain = email.domain();
 
         User {
             smtp_domain: format!("smtp.{}", domain),
             imap_domain: format!("imap.{}", domain),
+            pop3_domain: format!("pop.{}", domain),
+            smtp_port: SMTPS_PORT,
+            imap_port: IMAPS_PORT,
+            security: Security::Tls,
             email_addr: email,
             password,
+            uid_validities: HashMap::new(),
+            tap: None,
+            pins_first_use: true,
         }
     }
 
+    /// Passes every line later exchanged with the SMTP & IMAP servers to a sink, with
+    /// credentials redacted.
+    pub fn set_tap(&mut self, sink: Arc<dyn TapSink>) {
+        self.tap = Some(sink);
+    }
+
+    /// Replaces the user with one built from user input, keeping the tap if any.
+    fn rebuild(&mut self, ui: &mut dyn Ui, prompts: &Prompts) -> io::Result<()> {
+        let tap = self.tap.take();
+        *self = User::build(ui, prompts)?;
+        self.tap = tap;
+        Ok(())
+    }
+
+    /// Returns the trust of the account's connections, `None` if they're in plaintext.
+    fn trust(&self) -> io::Result<Option<Trust>> {
+        match self.security {
+            Security::Tls => {
+                let trust = Trust::load(self.email_addr.as_ref())?;
+                match self.pins_first_use {
+                    true => Ok(Some(trust)),
+                    false => Ok(Some(trust.checking_only())),
+                }
+            }
+            Security::Plaintext => Ok(None),
+        }
+    }
+
+    /// Connects to a server named by `label`, e.g. "smtp", tapped if there's a tap.
+    ///
+    /// The server is reached over TLS unless the security is plaintext, trusting it the way
+    /// the account's trust settings say.
+    pub(crate) fn connect_server(
+        &self,
+        label: &str,
+        domain: &str,
+        port: u16,
+    ) -> io::Result<Connection> {
+        let tcp = connect_tcp(domain, port)?;
+        let trust = self.trust()?;
+        let over_tls = trust.is_some();
+        let stream: Connection = match trust {
+            Some(trust) => Box::new(trust.handshake(domain, port, tcp)?),
+            None => Box::new(tcp),
+        };
+        let Some(sink) = &self.tap else {
+            return Ok(stream);
+        };
+        let tapped = Tapped::new(label, stream, sink.clone());
+        tracing::info!(
+            "{} connected to {}:{}{}",
+            tapped.name(),
+            domain,
+            port,
+            if over_tls { " over TLS" } else { "" }
+        );
+        Ok(Box::new(tapped))
+    }
+
+    /// Constructs the `User` whose login is saved in the session's vault, if any.
+    pub fn saved() -> Option<User> {
+        let (email, password) = vault::load_credentials()?;
+        Some(User::new(email.parse().ok()?, password))
+    }
+
+    /// Checks whether the user's login is the one saved in the session's vault.
+    pub fn is_saved(&self) -> bool {
+        User::saved()
+            .is_some_and(|u| u.email_addr == self.email_addr && u.password == self.password)
+    }
+
+    /// Saves the user's login in the session's vault.
+    pub fn remember(&self) -> io::Result<()> {
+        vault::save_credentials(self.email_addr.as_ref(), &self.password)
+    }
+
     /// Logins to SMTP server with user's credentials.
     ///
     /// # Returns
     ///
-    /// An `SmtpTransport` as the SMTP client.
-    pub fn login_smtp(&mut self, prompts: &Prompts) -> SmtpTransport {
+    /// - An `SmtpClient` as the SMTP client if logging in succeeds.
+    /// - An `Err` if reading user input fails, e.g. once it ends.
+    pub fn login_smtp(&mut self, ui: &mut dyn Ui, prompts: &Prompts) -> io::Result<SmtpClient> {
         loop {
-            println!("{}{}...", prompts.login_connecting, self.smtp_domain);
+            ui.show(&format!(
+                "{}{}...",
+                prompts.login_connecting, self.smtp_domain
+            ));
             match self.connect_smtp() {
                 Ok(transport) => {
-                    println!("{}{}.", prompts.login_connect_succeed, self.smtp_domain);
-                    return transport;
+                    ui.show(&format!(
+                        "{}{}.",
+                        prompts.login_connect_succeed, self.smtp_domain
+                    ));
+                    return Ok(transport);
                 }
                 Err(e) => {
-                    eprintln!(
-                        "{}{}: {:?}",
+                    ui.show_error(&format!(
+                        "{}{}: {}",
                         prompts.login_connect_fail,
                         self.smtp_domain,
-                        e.source().unwrap()
-                    );
-                    println!("{}", prompts.login_retry);
-                    *self = User::build(prompts);
+                        error::chain(&e)
+                    ));
+                    ui.show(prompts.login_retry);
+                    self.rebuild(ui, prompts)?;
                 }
             }
         }
@@ -62,24 +186,35 @@ impl User {
     ///
     /// # Returns
     ///
-    /// A `Session<Connection>` as the IMAP client.
-    pub fn login_imap(&mut self, prompts: &Prompts) -> Session<Connection> {
+    /// - A `Session<Connection>` as the IMAP client if logging in succeeds.
+    /// - An `Err` if reading user input fails, e.g. once it ends.
+    pub fn login_imap(
+        &mut self,
+        ui: &mut dyn Ui,
+        prompts: &Prompts,
+    ) -> io::Result<Session<Connection>> {
         loop {
-            println!("{}{}...", prompts.login_connecting, self.imap_domain);
+            ui.show(&format!(
+                "{}{}...",
+                prompts.login_connecting, self.imap_domain
+            ));
             match self.connect_imap() {
                 Ok(session) => {
-                    println!("{}{}.", prompts.login_connect_succeed, self.imap_domain);
-                    return session;
+                    ui.show(&format!(
+                        "{}{}.",
+                        prompts.login_connect_succeed, self.imap_domain
+                    ));
+                    return Ok(session);
                 }
                 Err(e) => {
-                    eprintln!(
-                        "{}{}: {:?}",
+                    ui.show_error(&format!(
+                        "{}{}: {}",
                         prompts.login_connect_fail,
                         self.imap_domain,
-                        e.source().unwrap()
-                    );
-                    println!("{}", prompts.login_retry);
-                    *self = User::build(prompts);
+                        error::chain(&e)
+                    ));
+                    ui.show(prompts.login_retry);
+                    self.rebuild(ui, prompts)?;
                 }
             }
         }
@@ -89,23 +224,18 @@ impl User {
     ///
     /// # Returns
     ///
-    /// - An `SmtpTransport` if the connection succeeds.
+    /// - An `SmtpClient` if the connection succeeds.
     /// - An `Err` if the connection fails.
-    fn connect_smtp(&self) -> Result<SmtpTransport, Box<dyn Error>> {
-        // Open a remote connection to server
-        let smtp_cli = SmtpTransport::relay(self.smtp_domain.as_str())
-            .unwrap()
-            .credentials(Credentials::new(
-                self.email_addr.to_string(),
-                self.password.to_string(),
-            ))
-            .build();
-
-        // Connectivity test & return
-        match smtp_cli.test_connection() {
-            Ok(_) => Ok(smtp_cli),
-            Err(e) => Err(Box::new(e)),
-        }
+    pub fn connect_smtp(&self) -> error::Result<SmtpClient> {
+        SmtpClient::connect(self).inspect_err(|e| {
+            tracing::warn!(
+                "SMTP login to {}:{} as {} failed: {}",
+                self.smtp_domain,
+                self.smtp_port,
+                self.email_addr,
+                error::chain(e)
+            )
+        })
     }
 
     /// Connects to the IMAP server.
@@ -114,9 +244,30 @@ impl User {
     ///
     /// - A `Session<Connection>` if the connection succeeds.
     /// - An `Err` if the connection fails.
-    fn connect_imap(&self) -> imap::error::Result<Session<Connection>> {
-        let domain = self.imap_domain.as_str();
-        let imap_cli = imap::ClientBuilder::new(domain, 993).connect().unwrap();
+    pub fn connect_imap(&self) -> imap::error::Result<Session<Connection>> {
+        self.open_imap().inspect_err(|e| {
+            tracing::warn!(
+                "IMAP login to {}:{} as {} failed: {}",
+                self.imap_domain,
+                self.imap_port,
+                self.email_addr,
+                error::chain(e)
+            )
+        })
+    }
+
+    fn open_imap(&self) -> imap::error::Result<Session<Connection>> {
+        let stream = self.connect_server("imap", &self.imap_domain, self.imap_port)?;
+        self.login_imap_on(stream)
+    }
+
+    /// Logs in to the IMAP server on a connection to it, e.g. one of `connect_server`.
+    pub(crate) fn login_imap_on(
+        &self,
+        stream: Connection,
+    ) -> imap::error::Result<Session<Connection>> {
+        let mut imap_cli = imap::Client::new(stream);
+        imap_cli.read_greeting()?;
 
         match imap_cli.login(&self.email_addr, &self.password) {
             Ok(session) => Ok(session),
@@ -124,140 +275,394 @@ impl User {
         }
     }
 
-    /// Sends an email within user input.
+    /// Builds a plain text message from the user to a receiver.
+    pub fn build_message(&self, to: &Address, subject: &str, body: String) -> Message {
+        Message::builder()
+            .from(Mailbox::from(self.email_addr.clone()))
+            .to(Mailbox::from(to.clone()))
+            .subject(subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(body)
+            .unwrap()
+    }
+
+    /// Composes an email within user input, reconfirming it before it's sent.
     ///
     /// # Returns
     ///
-    /// - An `Option<String>` if the process succeeds.
-    ///     - A `Some` containing the receiver's email address if sending succeeds.
-    ///     - A `None` if the user cancels sending during reconfirmation.
-    /// - An `Error` if it fails.
-    pub fn compose_and_send(
+    /// - A `Some` containing the message & its receiver's email address if the user confirms.
+    /// - A `None` if the user cancels sending during reconfirmation.
+    /// - An `Err` if reading user input fails, e.g. once it ends.
+    pub fn compose_message(
         &self,
-        smtp_cli: &SmtpTransport,
+        ui: &mut dyn Ui,
         prompts: &Prompts,
-    ) -> Result<Option<String>, Box<dyn Error>> {
-        println!("{}", prompts.compose_new_message);
-        println!("{}", prompts.horizontal_start);
+    ) -> io::Result<Option<(Message, Address)>> {
+        ui.show(prompts.compose_new_message);
+        ui.show(prompts.horizontal_start);
 
         // Read & save `to` for returning
-        let to = read_email(prompts.compose_to, prompts.email_addr_invalid);
+        let to = ui.read_email(prompts.compose_to, prompts.email_addr_invalid)?;
 
         // Build the message
-        let email = Message::builder()
-            .from(Mailbox::from(self.email_addr.clone()))
-            .to(Mailbox::from(to.clone()))
-            .subject(read_input(prompts.compose_subject))
-            .header(ContentType::TEXT_PLAIN)
-            .body(read_body(prompts))
-            .unwrap();
-        println!("{}", prompts.horizontal_end);
-        println!("{}", prompts.compose_editing_finish);
+        let subject = ui.read_input(prompts.compose_subject)?;
+        let email = self.build_message(&to, &subject, ui.read_body(prompts)?);
+        ui.show(prompts.horizontal_end);
+        ui.show(prompts.compose_editing_finish);
 
         // Reconfirm
-        if !read_reconfirmation(prompts, &RECONFIRMATION) {
+        if !ui.read_reconfirmation(prompts, &RECONFIRMATION)? {
             return Ok(None);
         }
-
-        // Send the message
-        println!("{}", prompts.send_sending);
-        match smtp_cli.send(&email) {
-            Ok(_) => Ok(Some(to.to_string())),
-            Err(e) => Err(Box::new(e)),
-        }
+        Ok(Some((email, to)))
     }
 
-    /// Fetches an email from a specific mailbox on the imap server.
+    /// Sends an email within user input.
     ///
     /// # Returns
     ///
     /// - An `Option<String>` if the process succeeds.
-    ///     - A `Some` containing the email's body if an email exists.
-    ///     - A `None` if not.
-    /// - An `Err` if it fails.
-    pub fn fetch_message(
+    ///     - A `Some` containing the receiver's email address if sending succeeds.
+    ///     - A `None` if the user cancels sending during reconfirmation.
+    /// - An `Error` if it fails.
+    pub fn compose_and_send<T: MailTransport + ?Sized>(
         &self,
-        imap_cli: &mut Session<Connection>,
+        transport: &mut T,
+        ui: &mut dyn Ui,
         prompts: &Prompts,
-    ) -> imap::error::Result<Option<String>> {
-        // Fetch available mailboxes from IMAP server
-        println!("{}", prompts.fetch_mailbox);
-        let mailboxes = imap_cli
+    ) -> Result<Option<String>, Box<dyn Error>> {
+        let Some((email, to)) = self.compose_message(ui, prompts)? else {
+            return Ok(None);
+        };
+
+        // Send the message
+        ui.show(prompts.send_sending);
+        transport.send(&email)?;
+        Ok(Some(to.to_string()))
+    }
+
+    /// Lists the names of available mailboxes on the IMAP server.
+    pub fn list_mailboxes(&self, imap_cli: &mut Session<Connection>) -> error::Result<Vec<String>> {
+        Ok(imap_cli
             .list(Some(""), Some("*"))?
             .iter()
             .filter(|&s| !s.name().contains('&'))
             .map(|s| s.name().to_string())
-            .collect::<Vec<_>>();
-        for (i, mailbox) in mailboxes.iter().enumerate() {
-            println!("  [{}] {}", i + 1, mailbox);
+            .collect())
+    }
+
+    /// Selects a mailbox on the IMAP server, recording its UIDVALIDITY.
+    ///
+    /// # Returns
+    ///
+    /// - The selected mailbox's status if the process succeeds.
+    /// - An `Error::UidValidityChanged` if the server reports a UIDVALIDITY other than the
+    ///   recorded one, the new value is recorded so UIDs obtained afterwards are valid.
+    /// - An `Err` if it fails.
+    pub fn select_mailbox(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+        mailbox: &str,
+    ) -> error::Result<ImapMailbox> {
+        let selected = imap_cli.select(mailbox)?;
+
+        // A server not reporting UIDVALIDITY is treated as having a constant one
+        let reported = selected.uid_validity.unwrap_or(0);
+        match self.uid_validities.insert(mailbox.to_string(), reported) {
+            Some(recorded) if recorded != reported => Err(error::Error::UidValidityChanged {
+                mailbox: mailbox.to_string(),
+                recorded,
+                reported,
+            }),
+            _ => Ok(selected),
         }
+    }
 
-        // Select mailbox
-        let size = mailboxes.len();
-        let mailbox = read_selection(
-            prompts.fetch_mailbox_selection,
-            prompts.invalid_literal,
-            prompts.fetch_mailbox_literal,
-            prompts.should_be_one_of_below_literal,
-            &RangeUsize { lo: 1, hi: size },
-        ) - 1;
-        imap_cli.select(&mailboxes[mailbox])?;
-
-        // Fetch all messages in the mailbox and print their "Subject: " line
-        let mut i = 1;
-        loop {
-            let message = imap_cli.fetch(i.to_string(), "RFC822")?;
-            if message.is_empty() {
-                if i == 1 {
-                    println!(
-                        "> \"{}\"{}",
-                        mailboxes[mailbox], prompts.fetch_mailbox_empty
-                    );
-                    return Ok(None);
-                } else {
-                    break;
-                }
-            }
-            if i == 1 {
-                println!("{}", prompts.fetch_message_list);
-            }
-            let subject = message
-                .iter()
-                .flat_map(|m| {
-                    str::from_utf8(m.body().expect("message did not have a body!"))
-                        .unwrap()
-                        .lines()
-                        .map(String::from)
+    /// Returns the recorded UIDVALIDITY of a mailbox, if it has been selected before.
+    pub fn uid_validity(&self, mailbox: &str) -> Option<u32> {
+        self.uid_validities.get(mailbox).copied()
+    }
+
+    /// Lists all messages in a mailbox by their stable addresses, in ascending UID order.
+    pub fn list_messages(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+        mailbox: &str,
+    ) -> error::Result<Vec<MessageRef>> {
+        self.select_mailbox(imap_cli, mailbox)?;
+        let uid_validity = self.uid_validities[mailbox];
+
+        let mut uids = imap_cli.uid_search("ALL")?.into_iter().collect::<Vec<_>>();
+        uids.sort_unstable();
+
+        Ok(uids
+            .into_iter()
+            .map(|uid| MessageRef::new(mailbox, uid_validity, uid))
+            .collect())
+    }
+
+    /// Selects the mailbox a message belongs to, making sure the message's UID is still valid.
+    ///
+    /// # Returns
+    ///
+    /// - An `Ok` if the message's UID can be used in the selected mailbox.
+    /// - An `Error::UidValidityChanged` if the message's UID has become stale.
+    /// - An `Err` if it fails otherwise.
+    pub fn select_message(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+        message: &MessageRef,
+    ) -> error::Result<()> {
+        self.select_mailbox(imap_cli, &message.mailbox)?;
+        let reported = self.uid_validities[&message.mailbox];
+        if reported != message.uid_validity {
+            return Err(error::Error::UidValidityChanged {
+                mailbox: message.mailbox.to_string(),
+                recorded: message.uid_validity,
+                reported,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Fetches the raw RFC822 bytes of a message by its stable address.
+    ///
+    /// # Returns
+    ///
+    /// - The message's bytes if the process succeeds.
+    /// - An `Error::UidValidityChanged` if the message's UID has become stale.
+    /// - An `Error::MessageNotFound` if the message has been expunged.
+    /// - An `Err` if it fails otherwise.
+    pub fn fetch_raw(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+        message: &MessageRef,
+    ) -> error::Result<Vec<u8>> {
+        self.select_message(imap_cli, message)?;
+
+        imap_cli
+            .uid_fetch(message.uid.to_string(), "RFC822")?
+            .iter()
+            .find(|m| m.uid == Some(message.uid))
+            .and_then(|m| m.body())
+            .map(|body| body.to_vec())
+            .ok_or_else(|| error::Error::MessageNotFound {
+                mailbox: message.mailbox.to_string(),
+                uid: message.uid,
+            })
+    }
+
+    /// Fetches the body of a message as text, replacing bytes that aren't UTF-8.
+    pub fn fetch_body(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+        message: &MessageRef,
+    ) -> error::Result<String> {
+        let body = self.fetch_raw(imap_cli, message)?;
+        Ok(String::from_utf8_lossy(&body).into_owned())
+    }
+
+    /// Fetches the summaries of messages in a mailbox, in the order of `messages`.
+    pub fn fetch_summaries(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+        messages: &[MessageRef],
+    ) -> error::Result<Vec<MessageSummary>> {
+        let Some(first) = messages.first() else {
+            return Ok(Vec::new());
+        };
+        self.select_message(imap_cli, first)?;
+
+        let uids = messages.iter().map(|m| m.uid).collect::<Vec<_>>();
+        let fetches = imap_cli.uid_fetch(uid_set(&uids), "BODY.PEEK[HEADER]")?;
+        let mut summaries = fetches
+            .iter()
+            .filter_map(|m| Some((m.uid?, MessageSummary::parse(m.uid?, m.header()?))))
+            .collect::<HashMap<_, _>>();
+
+        Ok(uids
+            .iter()
+            .map(|&uid| {
+                summaries.remove(&uid).unwrap_or(MessageSummary {
+                    uid,
+                    ..Default::default()
                 })
-                .find(|l| l.starts_with("Subject:"))
-                .map(|s| s[9..].to_string())
-                .unwrap();
-            println!("  [{}] {}", i, subject);
-            i += 1;
+            })
+            .collect())
+    }
+
+    /// Checks that expunging messages of the selected mailbox can't take others with them.
+    ///
+    /// Without UIDPLUS, EXPUNGE removes every message flagged `\Deleted`, so it's only safe
+    /// when no other message is, e.g. one flagged by another client.
+    ///
+    /// # Returns
+    ///
+    /// - Whether the server has UIDPLUS if expunging is safe.
+    /// - An `Err` of `Unsupported` if other messages are flagged `\Deleted`.
+    /// - An `Err` if querying the server fails.
+    fn check_expunge(
+        &self,
+        imap_cli: &mut Session<Connection>,
+        mailbox: &str,
+        uids: &[u32],
+    ) -> error::Result<bool> {
+        if imap_cli.capabilities()?.has_str("UIDPLUS") {
+            return Ok(true);
+        }
+        let others = imap_cli
+            .uid_search("DELETED")?
+            .into_iter()
+            .filter(|uid| !uids.contains(uid))
+            .count();
+        match others {
+            0 => Ok(false),
+            _ => Err(error::Error::Unsupported(format!(
+                "expunging without UIDPLUS, {} other messages of \"{}\" are flagged \\Deleted",
+                others, mailbox
+            ))),
         }
+    }
 
-        // Fetch the chosen message
-        let message = imap_cli.fetch(
-            read_selection(
-                prompts.fetch_message_selection,
-                prompts.invalid_literal,
-                prompts.fetch_message_literal,
-                prompts.should_be_one_of_below_literal,
-                &RangeUsize { lo: 1, hi: i - 1 },
-            )
-            .to_string(),
-            "RFC822",
-        )?;
-        let message = message.iter().next().unwrap();
+    /// Deletes messages from a mailbox on the IMAP server by their UIDs.
+    ///
+    /// Without UIDPLUS, nothing is deleted if other messages of the mailbox are flagged
+    /// `\Deleted`, as expunging would remove them too.
+    pub fn delete_messages(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+        mailbox: &str,
+        uids: &[u32],
+    ) -> error::Result<()> {
+        if uids.is_empty() {
+            return Ok(());
+        }
+        self.select_mailbox(imap_cli, mailbox)?;
+
+        let has_uidplus = self.check_expunge(imap_cli, mailbox, uids)?;
+        let uids = uid_set(uids);
+        imap_cli.uid_store(&uids, "+FLAGS.SILENT (\\Deleted)")?;
+        if has_uidplus {
+            imap_cli.uid_expunge(&uids)?;
+        } else {
+            imap_cli.expunge()?;
+        }
+        Ok(())
+    }
+
+    /// Moves messages from a mailbox into another one on the IMAP server by their UIDs.
+    ///
+    /// Servers without MOVE get the messages copied, then deleted from the source mailbox.
+    pub fn move_messages(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+        mailbox: &str,
+        uids: &[u32],
+        target: &str,
+    ) -> error::Result<()> {
+        if uids.is_empty() {
+            return Ok(());
+        }
+        self.select_mailbox(imap_cli, mailbox)?;
+
+        if imap_cli.capabilities()?.has_str("MOVE") {
+            imap_cli.uid_mv(uid_set(uids), target)?;
+        } else {
+            // Check before copying, so messages aren't left in both mailboxes
+            self.check_expunge(imap_cli, mailbox, uids)?;
+            imap_cli.uid_copy(uid_set(uids), target)?;
+            self.delete_messages(imap_cli, mailbox, uids)?;
+        }
+        Ok(())
+    }
+}
+
+/// Fetches an email from a specific mailbox of a mail store.
+///
+/// Messages are listed as conversation trees, the chosen message is returned along with
+/// the conversation it belongs to.
+///
+/// # Returns
+///
+/// - An `Option<Conversation>` if the process succeeds.
+///     - A `Some` containing the email's conversation and body if an email exists.
+///     - A `None` if not.
+/// - An `Err` if it fails.
+pub fn fetch_message<S: MailStore + ?Sized>(
+    store: &mut S,
+    ui: &mut dyn Ui,
+    prompts: &Prompts,
+) -> error::Result<Option<Conversation>> {
+    // Fetch available mailboxes from the store
+    ui.show(prompts.fetch_mailbox);
+    let mailboxes = store.mailboxes()?;
+    for (i, mailbox) in mailboxes.iter().enumerate() {
+        ui.show(&format!("  [{}] {}", i + 1, mailbox));
+    }
 
-        // Parse `Body`
-        // todo: support non-ASCII characters
-        let body = message.body().expect("message did not have a body!");
-        let body = str::from_utf8(body)
-            .expect("message was not valid utf-8")
-            .to_string();
+    // Select mailbox
+    let size = mailboxes.len();
+    let mailbox = ui.read_selection(
+        prompts.fetch_mailbox_selection,
+        prompts.invalid_literal,
+        prompts.fetch_mailbox_literal,
+        prompts.should_be_one_of_below_literal,
+        &RangeUsize { lo: 1, hi: size },
+    )? - 1;
 
-        // Return message body
-        Ok(Some(body))
+    // List all messages in the mailbox by UID
+    let messages = store.messages(&mailboxes[mailbox])?;
+    if messages.is_empty() {
+        ui.show(&format!(
+            "> \"{}\"{}",
+            mailboxes[mailbox], prompts.fetch_mailbox_empty
+        ));
+        return Ok(None);
     }
+
+    // Group messages into conversations, print them as trees & fetch the chosen one by UID
+    let summaries = store.summaries(&messages)?;
+    let threads = store.threads(&messages, &summaries)?;
+    let (uids, position) = read_thread_selection(ui, &threads, &summaries, prompts)?;
+    let uid_validity = messages[0].uid_validity;
+    let conversation = uids
+        .iter()
+        .map(|&uid| MessageRef::new(&mailboxes[mailbox], uid_validity, uid))
+        .collect::<Vec<_>>();
+    let body = String::from_utf8_lossy(&store.fetch(&conversation[position])?).into_owned();
+
+    // Return message's conversation & body
+    Ok(Some(Conversation {
+        messages: conversation,
+        position,
+        body,
+    }))
+}
+
+/// Builds an IMAP sequence set from UIDs, collapsing consecutive runs into ranges.
+pub fn uid_set(uids: &[u32]) -> String {
+    let mut uids = uids.to_vec();
+    uids.sort_unstable();
+    uids.dedup();
+
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    for uid in uids {
+        match ranges.last_mut() {
+            Some((_, hi)) if *hi + 1 == uid => *hi = uid,
+            _ => ranges.push((uid, uid)),
+        }
+    }
+
+    ranges
+        .iter()
+        .map(|&(lo, hi)| {
+            if lo == hi {
+                lo.to_string()
+            } else {
+                format!("{}:{}", lo, hi)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
 }
@@ -0,0 +1,378 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use argon2::Argon2;
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, OsRng},
+    AeadCore, KeyInit, XChaCha20Poly1305, XNonce,
+};
+
+use crate::ui::Ui;
+use crate::*;
+
+/// Bytes starting every sealed file, so plain files written before encryption was set up
+/// can still be read.
+const MAGIC: &[u8; 4] = b"EUA\x01";
+
+/// Length in bytes of an XChaCha20-Poly1305 nonce.
+const NONCE_LEN: usize = 24;
+
+/// Length in bytes of the salt the key is derived with.
+const SALT_LEN: usize = 16;
+
+/// Plaintext sealed into the vault file, opening it tells whether a passphrase is right.
+const CHECK: &[u8] = b"echo_unity_archivist vault";
+
+/// The vault unlocked for the session.
+static SESSION: OnceLock<Vault> = OnceLock::new();
+
+/// Represents the key local data is encrypted with, derived from the user's passphrase.
+///
+/// Data is sealed with XChaCha20-Poly1305 under a random nonce, the key is derived with
+/// Argon2id from the passphrase & a random salt kept in the vault file.
+///
+/// Cached messages, the index, exports, backups & saved credentials are sealed. Metadata
+/// stays in plain text: Maildir filenames (UIDs & flags), mailbox directory names, migration
+/// state, import journals, POP3 UIDLs, retention rules & trust pins. So do traces &
+/// transcripts, which are only written when asked for, to be read as they are.
+pub struct Vault {
+    cipher: XChaCha20Poly1305,
+}
+
+impl Vault {
+    fn path() -> PathBuf {
+        data_dir().join("vault")
+    }
+
+    /// Checks whether a vault has been set up.
+    pub fn exists() -> bool {
+        Vault::path().is_file()
+    }
+
+    fn derive(passphrase: &str, salt: &[u8]) -> io::Result<Vault> {
+        let mut key = [0; 32];
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        Ok(Vault {
+            cipher: XChaCha20Poly1305::new(&key.into()),
+        })
+    }
+
+    /// Sets up a new vault protected by a passphrase.
+    pub fn create(passphrase: &str) -> io::Result<Vault> {
+        let mut salt = [0; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let vault = Vault::derive(passphrase, &salt)?;
+
+        fs::create_dir_all(data_dir())?;
+        fs::write(Vault::path(), [&salt[..], &vault.seal(CHECK)].concat())?;
+        Ok(vault)
+    }
+
+    /// Unlocks the vault with a passphrase.
+    ///
+    /// # Returns
+    ///
+    /// - The vault if the passphrase is right.
+    /// - An `Err` of kind `PermissionDenied` if it's wrong.
+    /// - An `Err` if reading the vault fails otherwise.
+    pub fn unlock(passphrase: &str) -> io::Result<Vault> {
+        let file = fs::read(Vault::path())?;
+        let (salt, check) = file.split_at(SALT_LEN.min(file.len()));
+        let vault = Vault::derive(passphrase, salt)?;
+        match vault.open(check) {
+            Ok(check) if check == CHECK => Ok(vault),
+            _ => Err(io::Error::from(io::ErrorKind::PermissionDenied)),
+        }
+    }
+
+    /// Encrypts data.
+    pub fn seal(&self, plaintext: &[u8]) -> Vec<u8> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext)
+            .expect("encryption failed");
+        [&MAGIC[..], &nonce, &ciphertext].concat()
+    }
+
+    /// Decrypts data sealed by `seal()`, failing if it has been tampered with.
+    pub fn open(&self, sealed: &[u8]) -> io::Result<Vec<u8>> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "sealed data is corrupted");
+        let sealed = sealed.strip_prefix(MAGIC).ok_or_else(invalid)?;
+        if sealed.len() < NONCE_LEN {
+            return Err(invalid());
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        self.cipher
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| invalid())
+    }
+}
+
+/// Makes a vault the session's, data is sealed with it from then on.
+pub fn start_session(vault: Vault) {
+    let _ = SESSION.set(vault);
+}
+
+/// Returns the vault unlocked for the session, if any.
+pub fn session() -> Option<&'static Vault> {
+    SESSION.get()
+}
+
+/// Writes a file, sealed if a vault is unlocked for the session.
+pub fn write_file(path: &Path, data: &[u8]) -> io::Result<()> {
+    match session() {
+        Some(vault) => fs::write(path, vault.seal(data)),
+        None => fs::write(path, data),
+    }
+}
+
+/// Reads a file written by `write_file()`, opening it if it's sealed.
+pub fn read_file(path: &Path) -> io::Result<Vec<u8>> {
+    let data = fs::read(path)?;
+    if !data.starts_with(MAGIC) {
+        return Ok(data);
+    }
+
+    match session() {
+        Some(vault) => vault.open(&data),
+        None => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "file is encrypted & the vault is locked",
+        )),
+    }
+}
+
+/// Represents a file being written, sealed as a whole on `finish()` if a vault is unlocked.
+pub enum VaultWriter {
+    Plain(BufWriter<File>),
+    Sealed(PathBuf, Vec<u8>),
+}
+
+impl VaultWriter {
+    /// Creates a file, replacing an existing one.
+    pub fn create(path: &Path) -> io::Result<VaultWriter> {
+        VaultWriter::open(
+            path,
+            File::options().write(true).create(true).truncate(true),
+        )
+    }
+
+    /// Creates a file that doesn't exist yet.
+    ///
+    /// # Returns
+    ///
+    /// - The `VaultWriter` if the process succeeds.
+    /// - An `Err` of kind `AlreadyExists` if the file exists.
+    /// - An `Err` if creating the file fails otherwise.
+    pub fn create_new(path: &Path) -> io::Result<VaultWriter> {
+        VaultWriter::open(path, File::options().write(true).create_new(true))
+    }
+
+    fn open(path: &Path, options: &OpenOptions) -> io::Result<VaultWriter> {
+        // Opened even when sealed to fail early on unwritable paths, as the file is only
+        // written on `finish()`
+        let file = options.open(path)?;
+        match session() {
+            Some(_) => Ok(VaultWriter::Sealed(path.to_path_buf(), Vec::new())),
+            None => Ok(VaultWriter::Plain(BufWriter::new(file))),
+        }
+    }
+
+    /// Flushes the file, sealing it if needed.
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            VaultWriter::Plain(mut writer) => writer.flush(),
+            VaultWriter::Sealed(path, data) => write_file(&path, &data),
+        }
+    }
+}
+
+impl Write for VaultWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            VaultWriter::Plain(writer) => writer.write(buf),
+            VaultWriter::Sealed(_, data) => data.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            VaultWriter::Plain(writer) => writer.flush(),
+            VaultWriter::Sealed(..) => Ok(()),
+        }
+    }
+}
+
+/// Seals the cache & index left in plain text from before the session's vault was set up.
+///
+/// Each file is sealed in place, its plain text overwritten before being replaced. This is
+/// best effort, as file systems that copy on write or journal data may keep old blocks.
+///
+/// # Returns
+///
+/// - The number of sealed files if the process succeeds, 0 if no vault is unlocked.
+/// - An `Err` if it fails.
+pub fn seal_existing() -> io::Result<usize> {
+    if session().is_none() {
+        return Ok(0);
+    }
+    let count = seal_dir(&data_dir().join("cache"))?;
+    Ok(count + index::seal_plain_index()?)
+}
+
+/// Seals every plain file under a directory.
+fn seal_dir(dir: &Path) -> io::Result<usize> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+
+    let mut count = 0;
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_dir() {
+            count += seal_dir(&path)?;
+            continue;
+        }
+        let data = fs::read(&path)?;
+        if data.starts_with(MAGIC) {
+            continue;
+        }
+
+        // A name starting with "." is never taken for a cached message's
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let tmp = path.with_file_name(format!(".{}.sealing", name));
+        write_file(&tmp, &data)?;
+        overwrite(&path)?;
+        fs::rename(tmp, &path)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Overwrites a file's contents with zeros, so they don't linger once it's removed.
+fn overwrite(path: &Path) -> io::Result<()> {
+    let mut file = fs::OpenOptions::new().write(true).open(path)?;
+    let len = file.metadata()?.len();
+    io::copy(&mut io::repeat(0).take(len), &mut file)?;
+    file.sync_all()
+}
+
+/// Overwrites a plain file, then removes it.
+pub fn shred(path: &Path) -> io::Result<()> {
+    overwrite(path)?;
+    fs::remove_file(path)
+}
+
+/// Saves login credentials, they're only ever stored sealed.
+pub fn save_credentials(email_addr: &str, password: &str) -> io::Result<()> {
+    if session().is_none() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "credentials are only stored in an unlocked vault",
+        ));
+    }
+    write_file(
+        &data_dir().join("credentials"),
+        format!("{}\n{}", email_addr, password).as_bytes(),
+    )
+}
+
+/// Loads the saved login credentials, `None` if there are none or the vault is locked.
+pub fn load_credentials() -> Option<(String, String)> {
+    session()?;
+    let credentials = String::from_utf8(read_file(&data_dir().join("credentials")).ok()?).ok()?;
+    let (email_addr, password) = credentials.split_once('\n')?;
+    Some((email_addr.to_string(), password.to_string()))
+}
+
+/// Unlocks the vault for the session, prompting for its passphrase until it's right.
+///
+/// Without a vault, one is set up if `setup` is true, sealing the local data written so far,
+/// otherwise local data stays plain.
+pub fn unlock_session(setup: bool, ui: &mut dyn Ui, prompts: &Prompts) -> io::Result<()> {
+    if Vault::exists() {
+        loop {
+            match Vault::unlock(&ui.read_password(prompts.vault_passphrase)?) {
+                Ok(vault) => {
+                    start_session(vault);
+                    ui.show(prompts.vault_unlocked);
+                    return Ok(());
+                }
+                Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                    ui.show_error(prompts.vault_wrong_passphrase)
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    if setup {
+        loop {
+            let passphrase = ui.read_password(prompts.vault_new_passphrase)?;
+            let confirmation = ui.read_password(prompts.vault_confirm_passphrase)?;
+            if !passphrase.is_empty() && passphrase == confirmation {
+                start_session(Vault::create(&passphrase)?);
+                ui.show(prompts.vault_created);
+                let count = seal_existing()?;
+                if count > 0 {
+                    ui.show(&format!("{}{}.", prompts.vault_sealed_existing, count));
+                }
+                return Ok(());
+            }
+            ui.show_error(prompts.vault_passphrase_mismatch);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vault(passphrase: &str) -> Vault {
+        Vault::derive(passphrase, &[7; SALT_LEN]).unwrap()
+    }
+
+    #[test]
+    fn sealed_data_opens_with_the_same_key() {
+        let vault = vault("correct horse");
+
+        let sealed = vault.seal(b"secret mail");
+
+        assert!(sealed.starts_with(MAGIC));
+        assert!(!sealed.windows(6).any(|w| w == b"secret"));
+        assert_eq!(vault.open(&sealed).unwrap(), b"secret mail");
+        // Nonces are random, sealing twice doesn't repeat ciphertexts
+        assert_ne!(vault.seal(b"secret mail"), sealed);
+    }
+
+    #[test]
+    fn opening_fails_with_another_key() {
+        let sealed = vault("correct horse").seal(b"secret mail");
+
+        let e = vault("battery staple").open(&sealed).unwrap_err();
+
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn opening_fails_once_tampered_with() {
+        let vault = vault("correct horse");
+        let mut sealed = vault.seal(b"secret mail");
+        *sealed.last_mut().unwrap() ^= 1;
+
+        assert!(vault.open(&sealed).is_err());
+        assert!(vault.open(&sealed[..MAGIC.len() + NONCE_LEN - 1]).is_err());
+        assert!(vault.open(b"secret mail").is_err());
+    }
+}
@@ -0,0 +1,415 @@
+use std::{
+    io::{self, Read, Write},
+    process::{self, ExitStatus},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Once,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use imap::{extensions::idle::SetReadTimeout, types::UnsolicitedResponse, Connection, Session};
+
+use crate::retention::{describe_report, load_rules, RETENTION_INTERVAL};
+use crate::ui::Ui;
+use crate::user::User;
+use crate::*;
+
+/// Interval after which IDLE is re-issued, safely below the 29-minute limit of RFC 2177.
+pub const IDLE_INTERVAL: Duration = Duration::from_secs(25 * 60);
+
+/// How often a connection waiting in IDLE checks whether watching has been stopped.
+const STOP_POLL: Duration = Duration::from_millis(200);
+
+/// Whether mailboxes are being watched, Ctrl+C quits the agent otherwise.
+static WATCHING: AtomicBool = AtomicBool::new(false);
+
+/// Set by Ctrl+C while mailboxes are watched, so their connections end IDLE & log out.
+static STOPPED: AtomicBool = AtomicBool::new(false);
+
+/// Guards installing the Ctrl+C handler, which can only be done once.
+static CTRL_C: Once = Once::new();
+
+/// Represents a message that arrived while watching a mailbox.
+pub struct NewMail {
+    pub message: MessageRef,
+    pub summary: MessageSummary,
+}
+
+/// Represents a line for the user from a connection watching in a thread of its own, shown
+/// by the thread the `Ui` belongs to.
+enum Notice {
+    Show(String),
+    Error(String),
+}
+
+impl User {
+    /// Lists messages in a mailbox whose UID is greater than `last_uid`.
+    pub fn list_messages_after(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+        mailbox: &str,
+        last_uid: u32,
+    ) -> error::Result<Vec<MessageRef>> {
+        self.select_mailbox(imap_cli, mailbox)?;
+        let uid_validity = self.uid_validity(mailbox).unwrap_or_default();
+
+        // "n:*" always contains the greatest UID, even if it is less than n
+        let mut uids = imap_cli
+            .uid_search(format!("UID {}:*", last_uid + 1))?
+            .into_iter()
+            .filter(|&uid| uid > last_uid)
+            .collect::<Vec<_>>();
+        uids.sort_unstable();
+
+        Ok(uids
+            .into_iter()
+            .map(|uid| MessageRef::new(mailbox, uid_validity, uid))
+            .collect())
+    }
+
+    /// Keeps the session in IDLE on a mailbox, calling `on_new_mail` for each arriving message,
+    /// until `stop` is set.
+    ///
+    /// IDLE is re-issued every `interval`, `IDLE_INTERVAL` being safely below the server's
+    /// limit. The stop is noticed as IDLE ends, at once on a connection that polls for it.
+    pub fn watch_mailbox<F>(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+        mailbox: &str,
+        stop: &AtomicBool,
+        interval: Duration,
+        mut on_new_mail: F,
+    ) -> error::Result<()>
+    where
+        F: FnMut(&mut User, &mut Session<Connection>, NewMail),
+    {
+        let mut last_uid = self
+            .list_messages(imap_cli, mailbox)?
+            .last()
+            .map_or(0, |m| m.uid);
+
+        loop {
+            // Messages arriving between two IDLEs are only found by looking, so it's done
+            // after a timeout too
+            imap_cli
+                .idle()
+                .timeout(interval)
+                .keepalive(false)
+                .wait_while(|response| !matches!(response, UnsolicitedResponse::Exists(_)))?;
+            if stop.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+
+            let messages = self.list_messages_after(imap_cli, mailbox, last_uid)?;
+            let summaries = self.fetch_summaries(imap_cli, &messages)?;
+            for (message, summary) in messages.into_iter().zip(summaries) {
+                last_uid = last_uid.max(message.uid);
+                on_new_mail(self, imap_cli, NewMail { message, summary });
+            }
+        }
+    }
+
+    /// Watches mailboxes chosen by the user for new messages, until Ctrl+C is pressed.
+    ///
+    /// Each mailbox is watched on its own connection, a line is shown for each new message
+    /// and the user's hook command, if any, is run. Ctrl+C ends every IDLE & logs each
+    /// connection out. The account's retention rules, if any, are
+    /// applied every `RETENTION_INTERVAL` while a mailbox is watched, each time on a new
+    /// connection, as the server would log out one idle for so long.
+    pub fn watch(
+        &mut self,
+        imap_cli: &mut Session<Connection>,
+        ui: &mut dyn Ui,
+        prompts: &Prompts,
+    ) -> error::Result<()> {
+        // Choose mailboxes & hook
+        ui.show(prompts.fetch_mailbox);
+        let mailboxes = self.list_mailboxes(imap_cli)?;
+        for (i, mailbox) in mailboxes.iter().enumerate() {
+            ui.show(&format!("  [{}] {}", i + 1, mailbox));
+        }
+        let selections = ui.read_selections(
+            prompts.watch_mailbox_selection,
+            prompts.invalid_literal,
+            prompts.fetch_mailbox_literal,
+            prompts.should_be_one_of_below_literal,
+            &RangeUsize::new(1, mailboxes.len()),
+        )?;
+        let hook = Some(ui.read_input(prompts.watch_hook)?).filter(|h| !h.is_empty());
+
+        // Watch each mailbox on its own connection
+        stop_on_ctrl_c();
+        STOPPED.store(false, Ordering::Relaxed);
+        let mut sessions = Vec::new();
+        for selection in selections {
+            let user = self.clone();
+            match connect_watching(&user) {
+                Ok(session) => sessions.push((mailboxes[selection - 1].clone(), user, session)),
+                Err(e) => {
+                    for (_, _, mut session) in sessions {
+                        let _ = session.logout();
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        let has_rules = !load_rules(self.email_addr.as_ref())?.is_empty();
+
+        ui.show(prompts.watch_watching);
+        WATCHING.store(true, Ordering::Relaxed);
+        let (notices, received) = mpsc::channel();
+        // Dropped by each watching connection as it ends, so retention ends with the last one
+        let (watching, watched) = mpsc::channel::<()>();
+        thread::scope(|scope| {
+            if has_rules {
+                ui.show(prompts.retention_watching);
+                let mut user = self.clone();
+                let notices = notices.clone();
+                scope.spawn(move || loop {
+                    let result =
+                        user.connect_imap()
+                            .map_err(error::Error::from)
+                            .and_then(|mut session| {
+                                let report = user.run_retention(&mut session);
+                                let _ = session.logout();
+                                report
+                            });
+                    match result {
+                        Ok(report) if report.moved + report.expunged > 0 => {
+                            let _ = notices.send(Notice::Show(describe_report(&report, prompts)));
+                        }
+                        Ok(_) => {}
+                        // The next run may well succeed, eg. once the server is back
+                        Err(e) => {
+                            let text = format!("{}{:?}", prompts.retention_fail, e);
+                            let _ = notices.send(Notice::Error(text));
+                        }
+                    }
+                    if let Err(RecvTimeoutError::Disconnected) =
+                        watched.recv_timeout(RETENTION_INTERVAL)
+                    {
+                        break;
+                    }
+                });
+            }
+            for (mailbox, mut user, mut session) in sessions {
+                let hook = hook.as_deref();
+                let notices = notices.clone();
+                let watching = watching.clone();
+                scope.spawn(move || {
+                    let on_new_mail = |_: &mut User, _: &mut Session<Connection>, mail| {
+                        let _ = notices.send(Notice::Show(describe_new_mail(&mail, prompts)));
+                        if let Some(hook) = hook {
+                            if let Err(e) = run_hook(hook, &mail) {
+                                let text = format!("{}{:?}", prompts.watch_hook_fail, e);
+                                let _ = notices.send(Notice::Error(text));
+                            }
+                        }
+                    };
+                    let result = user.watch_mailbox(
+                        &mut session,
+                        &mailbox,
+                        &STOPPED,
+                        IDLE_INTERVAL,
+                        on_new_mail,
+                    );
+                    if let Err(e) = result {
+                        let text = format!("{}\"{}\": {:?}", prompts.watch_fail, mailbox, e);
+                        let _ = notices.send(Notice::Error(text));
+                    }
+                    let _ = session.logout();
+                    drop(watching);
+                });
+            }
+
+            // Show what the connections notice, until they all end
+            drop(notices);
+            drop(watching);
+            for notice in received {
+                match notice {
+                    Notice::Show(text) => ui.show(&text),
+                    Notice::Error(text) => ui.show_error(&text),
+                }
+            }
+        });
+        WATCHING.store(false, Ordering::Relaxed);
+        ui.show(prompts.watch_stopped);
+
+        Ok(())
+    }
+}
+
+/// Installs the handler of Ctrl+C, which stops watching while mailboxes are watched & quits
+/// the agent otherwise, the way it would without a handler.
+fn stop_on_ctrl_c() {
+    CTRL_C.call_once(|| {
+        let handled = ctrlc::set_handler(|| match WATCHING.load(Ordering::Relaxed) {
+            true => STOPPED.store(true, Ordering::Relaxed),
+            false => process::exit(130),
+        });
+        if let Err(e) = handled {
+            tracing::warn!("Ctrl+C can't stop watching: {}", e);
+        }
+    });
+}
+
+/// Connects to the IMAP server to watch a mailbox, IDLE ending as soon as watching stops.
+fn connect_watching(user: &User) -> error::Result<Session<Connection>> {
+    let stream = user.connect_server("imap", &user.imap_domain, user.imap_port)?;
+    let stream = Stoppable {
+        stream,
+        stop: &STOPPED,
+        timeout: None,
+    };
+    Ok(user.login_imap_on(Box::new(stream))?)
+}
+
+/// Represents a connection whose reads with a timeout, the ones of IDLE, end once `stop` is
+/// set, as if they timed out.
+///
+/// The connection is left as it is, so IDLE can be ended with DONE & the session logged out.
+struct Stoppable<S> {
+    stream: S,
+    stop: &'static AtomicBool,
+    /// The read timeout asked for, the connection's own is at most `STOP_POLL`.
+    timeout: Option<Duration>,
+}
+
+impl<S: Read> Read for Stoppable<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(timeout) = self.timeout else {
+            return self.stream.read(buf);
+        };
+        let deadline = Instant::now() + timeout;
+        loop {
+            if self.stop.load(Ordering::Relaxed) {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            match self.stream.read(buf) {
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) && Instant::now() < deadline => {}
+                result => return result,
+            }
+        }
+    }
+}
+
+impl<S: Write> Write for Stoppable<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl<S: SetReadTimeout> SetReadTimeout for Stoppable<S> {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> imap::Result<()> {
+        self.timeout = timeout;
+        self.stream
+            .set_read_timeout(timeout.map(|t| t.min(STOP_POLL)))
+    }
+}
+
+/// Describes a new message as a line for the user, e.g. "✉ New message in \"INBOX\":
+/// bob@example.test - Hi".
+pub fn describe_new_mail(mail: &NewMail, prompts: &Prompts) -> String {
+    format!(
+        "{}\"{}\": {} - {}",
+        prompts.watch_new_mail, mail.message.mailbox, mail.summary.from, mail.summary.subject
+    )
+}
+
+/// Runs the user's hook command for a new message.
+///
+/// The message's details are passed through the environment variables `EUA_MAILBOX`,
+/// `EUA_UID`, `EUA_FROM` and `EUA_SUBJECT`.
+pub fn run_hook(command: &str, mail: &NewMail) -> io::Result<ExitStatus> {
+    shell_command(command)
+        .env("EUA_MAILBOX", &mail.message.mailbox)
+        .env("EUA_UID", mail.message.uid.to_string())
+        .env("EUA_FROM", &mail.summary.from)
+        .env("EUA_SUBJECT", &mail.summary.subject)
+        .status()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env, fs,
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::*;
+
+    static STOP: AtomicBool = AtomicBool::new(false);
+
+    fn new_mail() -> NewMail {
+        let header = b"From: Bob <bob@example.test>\r\nSubject: Lunch? \"Noon\"\r\n\r\n";
+        NewMail {
+            message: MessageRef::new("Work/Team", 7, 42),
+            summary: MessageSummary::parse(42, header),
+        }
+    }
+
+    #[test]
+    fn hooks_get_the_message_in_their_environment() {
+        let path = env::temp_dir().join(format!("eua-hook-{}", process::id()));
+        let hook = format!(
+            "printf '%s|%s|%s|%s' \"$EUA_MAILBOX\" \"$EUA_UID\" \"$EUA_FROM\" \"$EUA_SUBJECT\" > '{}'",
+            path.display()
+        );
+        assert!(run_hook(&hook, &new_mail()).unwrap().success());
+        let written = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(written, "Work/Team|42|Bob|Lunch? \"Noon\"");
+    }
+
+    #[test]
+    fn new_mail_is_described_with_its_mailbox_sender_and_subject() {
+        let prompts = get_prompts(&Lang::EN);
+        assert_eq!(
+            describe_new_mail(&new_mail(), prompts),
+            "✉ New message in \"Work/Team\": Bob - Lunch? \"Noon\""
+        );
+    }
+
+    #[test]
+    fn stopping_ends_a_wait_with_a_timeout_at_once() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let tcp = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+        let mut stream = Stoppable {
+            stream: tcp,
+            stop: &STOP,
+            timeout: None,
+        };
+        stream.set_read_timeout(Some(IDLE_INTERVAL)).unwrap();
+
+        // Data still arrives while polling
+        server.write_all(b"* 1 EXISTS\r\n").unwrap();
+        let mut buf = [0; 64];
+        assert_eq!(stream.read(&mut buf).unwrap(), 12);
+
+        let started = Instant::now();
+        thread::spawn(|| {
+            thread::sleep(Duration::from_millis(300));
+            STOP.store(true, Ordering::Relaxed);
+        });
+        let error = stream.read(&mut buf).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        // Without a timeout, e.g. once IDLE is done, reads go on as usual
+        stream.set_read_timeout(None).unwrap();
+        server.write_all(b"a5 OK IDLE terminated\r\n").unwrap();
+        assert_eq!(stream.read(&mut buf).unwrap(), 23);
+    }
+}
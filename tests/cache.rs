@@ -0,0 +1,137 @@
+//! Syncing mailboxes of the fake server of `support` into the offline cache.
+//!
+//! Every test shares the data directory of `EUA_HOME` & syncs a mailbox of its own.
+
+mod support;
+
+use std::{
+    env, process,
+    sync::{Mutex, MutexGuard},
+};
+
+use echo_unity_archivist::cache::Cache;
+use echo_unity_archivist::user::{Security, User};
+use support::{FakeImap, EMAIL_ADDR, PASSWORD};
+
+static DATA_DIR: Mutex<()> = Mutex::new(());
+
+/// Points `EUA_HOME` at this run's data directory & takes its turn at it.
+fn data_dir() -> MutexGuard<'static, ()> {
+    let guard = DATA_DIR.lock().unwrap_or_else(|e| e.into_inner());
+    let home = env::temp_dir().join(format!("eua-cache-{}", process::id()));
+    env::set_var("EUA_HOME", home);
+    guard
+}
+
+/// Builds the test account reaching a fake IMAP server over plaintext.
+fn user(imap: &FakeImap) -> User {
+    let mut user = User::new(EMAIL_ADDR.parse().unwrap(), PASSWORD.to_string());
+    user.imap_domain = "127.0.0.1".to_string();
+    user.imap_port = imap.port;
+    user.security = Security::Plaintext;
+    user
+}
+
+/// Returns a message numbered `n`.
+fn message(n: u32) -> Vec<u8> {
+    format!("From: bob@example.test\r\nSubject: Message {n}\r\n\r\nBody {n}.\r\n").into_bytes()
+}
+
+/// Starts a server with a mailbox of 3 messages, syncs it & changes it on the server.
+///
+/// The message of UID 1 gets seen, the one of UID 2 is expunged & one of UID 4 arrives.
+fn synced_then_changed(capabilities: &str, mailbox: &str) -> (FakeImap, User, Cache) {
+    let imap = FakeImap::start_with(
+        capabilities,
+        vec![(mailbox, (1..=3).map(message).collect())],
+    );
+    let mut user = user(&imap);
+    let cache = Cache::open(&user.email_addr).unwrap();
+    let mut session = user.connect_imap().unwrap();
+    let report = user.sync_mailbox(&mut session, &cache, mailbox).unwrap();
+    assert_eq!(report.fetched, 3);
+    session.logout().unwrap();
+
+    imap.set_flags(mailbox, 1, &["\\Seen"]);
+    imap.expunge(mailbox, 2);
+    imap.deliver(mailbox, &message(4));
+    (imap, user, cache)
+}
+
+/// Syncs the changes of `synced_then_changed()` & checks they reached the cache.
+fn sync_changes(imap: &FakeImap, user: &mut User, cache: &Cache, mailbox: &str) {
+    let mut session = user.connect_imap().unwrap();
+    let report = user.sync_mailbox(&mut session, cache, mailbox).unwrap();
+    session.logout().unwrap();
+    assert_eq!(
+        (
+            report.fetched,
+            report.updated,
+            report.removed,
+            report.pushed
+        ),
+        (1, 1, 1, 0)
+    );
+
+    let cached = cache.mailbox(mailbox).unwrap();
+    assert_eq!(cached.uids(), [1, 3, 4]);
+    assert_eq!(cached.flags(1).as_deref(), Some("S"));
+    assert_eq!(cached.synced_flags(1), Some("S"));
+    assert_eq!(cached.read(4).unwrap(), message(4));
+    assert!(imap.received.text().contains("BODY.PEEK[]"));
+}
+
+#[test]
+fn changes_are_pulled_with_changedsince_and_vanished() {
+    let _data_dir = data_dir();
+    let (imap, mut user, cache) =
+        synced_then_changed("IMAP4rev1 ENABLE CONDSTORE QRESYNC", "Qresync");
+    sync_changes(&imap, &mut user, &cache, "Qresync");
+
+    let received = imap.received.text();
+    assert!(received.contains("ENABLE QRESYNC"));
+    assert!(received.contains("(UID FLAGS) (CHANGEDSINCE 4 VANISHED)"));
+    // Expunges came with VANISHED, so the UIDs weren't compared
+    assert!(!received.contains("UID SEARCH UID 1:3"));
+}
+
+#[test]
+fn changes_are_pulled_by_comparing_uids_without_condstore() {
+    let _data_dir = data_dir();
+    let (imap, mut user, cache) = synced_then_changed("IMAP4rev1", "Plain");
+    sync_changes(&imap, &mut user, &cache, "Plain");
+
+    let received = imap.received.text();
+    assert!(!received.contains("CHANGEDSINCE"));
+    assert!(received.contains("UID FETCH 1:3 (UID FLAGS)"));
+    assert!(received.contains("UID SEARCH UID 1:3"));
+}
+
+#[test]
+fn flags_changed_offline_are_pushed() {
+    let _data_dir = data_dir();
+    let imap = FakeImap::start(vec![("Offline", (1..=2).map(message).collect())]);
+    let mut user = user(&imap);
+    let cache = Cache::open(&user.email_addr).unwrap();
+    let mut session = user.connect_imap().unwrap();
+    user.sync_mailbox(&mut session, &cache, "Offline").unwrap();
+
+    let mut cached = cache.mailbox("Offline").unwrap();
+    cached.set_flags(1, "FS").unwrap();
+    let report = user.sync_mailbox(&mut session, &cache, "Offline").unwrap();
+    assert_eq!((report.pushed, report.updated), (1, 0));
+    assert_eq!(imap.flags("Offline", 1), ["\\Flagged", "\\Seen"]);
+    assert!(imap.flags("Offline", 2).is_empty());
+
+    let mut cached = cache.mailbox("Offline").unwrap();
+    cached.set_flags(1, "S").unwrap();
+    let report = user.sync_mailbox(&mut session, &cache, "Offline").unwrap();
+    assert_eq!(report.pushed, 1);
+    assert_eq!(imap.flags("Offline", 1), ["\\Seen"]);
+    assert_eq!(cache.mailbox("Offline").unwrap().synced_flags(1), Some("S"));
+    session.logout().unwrap();
+
+    let received = imap.received.text();
+    assert!(received.contains("UID STORE 1 +FLAGS.SILENT (\\Flagged \\Seen)"));
+    assert!(received.contains("UID STORE 1 -FLAGS.SILENT (\\Flagged)"));
+}
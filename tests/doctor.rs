@@ -0,0 +1,171 @@
+//! Diagnosing connections to the fake servers of `support`.
+
+mod support;
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+};
+
+use echo_unity_archivist::doctor::{self, Finding, Service};
+use echo_unity_archivist::read::Io;
+use echo_unity_archivist::user::{Security, User};
+use echo_unity_archivist::{get_prompts, Lang};
+use support::{tls_acceptor, FakeImap, FakeSmtp, EMAIL_ADDR, PASSWORD};
+
+/// Builds the test account pointing at localhost ports over plaintext.
+fn user(password: &str, smtp_port: u16, imap_port: u16) -> User {
+    let mut user = User::new(EMAIL_ADDR.parse().unwrap(), password.to_string());
+    user.smtp_domain = "127.0.0.1".to_string();
+    user.imap_domain = "127.0.0.1".to_string();
+    user.smtp_port = smtp_port;
+    user.imap_port = imap_port;
+    user.security = Security::Plaintext;
+    user
+}
+
+/// Returns a localhost port nothing listens on.
+fn closed_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+/// Serves a fake IMAP server in plaintext on a free localhost port, which offers STARTTLS
+/// with the certificate & key of a fixture but supports nothing after it.
+fn starttls_imap(fixture: &str) -> u16 {
+    let acceptor = tls_acceptor(fixture);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let acceptor = acceptor.clone();
+            thread::spawn(move || {
+                let mut writer: &TcpStream = &stream;
+                let mut reader = BufReader::new(&stream);
+                writer.write_all(b"* OK ready\r\n").unwrap();
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap_or(0) > 0 {
+                    let (tag, command) = line.trim_end().split_once(' ').unwrap_or(("*", ""));
+                    let answer = match command {
+                        "CAPABILITY" => format!(
+                            "* CAPABILITY IMAP4rev1 STARTTLS LOGINDISABLED\r\n{} OK done\r\n",
+                            tag
+                        ),
+                        "STARTTLS" => format!("{} OK begin TLS\r\n", tag),
+                        _ => format!("{} NO unsupported\r\n", tag),
+                    };
+                    writer.write_all(answer.as_bytes()).unwrap();
+                    if command == "STARTTLS" {
+                        if let Ok(mut tls) = acceptor.accept(&stream) {
+                            let _ = tls.read(&mut [0; 1]);
+                        }
+                        return;
+                    }
+                    line.clear();
+                }
+            });
+        }
+    });
+    port
+}
+
+#[test]
+fn doctor_finds_no_problem_with_working_servers() {
+    let (smtp, imap) = (
+        FakeSmtp::start(),
+        FakeImap::start(vec![("INBOX", Vec::new())]),
+    );
+    let user = user(PASSWORD, smtp.port, imap.port);
+
+    let report = doctor::examine(&user, Service::Smtp);
+    assert_eq!(report.ports.len(), 1);
+    let capabilities = report.ports[0].capabilities.clone().unwrap().unwrap();
+    assert!(capabilities.contains(&"AUTH PLAIN LOGIN".to_string()));
+    assert_eq!(report.login, Some((smtp.port, Ok(()))));
+    let report = doctor::examine(&user, Service::Imap);
+    let capabilities = report.ports[0].capabilities.clone().unwrap().unwrap();
+    assert!(capabilities.contains(&"IMAP4rev1".to_string()));
+    assert_eq!(report.login, Some((imap.port, Ok(()))));
+
+    let mut io = Io::new(&b""[..], Vec::new());
+    let findings = user.doctor(&mut io, get_prompts(&Lang::EN));
+    assert!(findings.is_empty());
+    let output = String::from_utf8(io.into_inner().1).unwrap();
+    assert!(output.contains("> Checking SMTP 127.0.0.1..."));
+    assert!(output.contains("Capabilities: IMAP4rev1, UIDPLUS"));
+    assert!(output.contains("✓ No problems found."));
+}
+
+#[test]
+fn doctor_explains_a_rejected_login() {
+    let (smtp, imap) = (
+        FakeSmtp::start(),
+        FakeImap::start(vec![("INBOX", Vec::new())]),
+    );
+    let user = user("wrong", smtp.port, imap.port);
+
+    let mut io = Io::new(&b""[..], Vec::new());
+    let findings = user.doctor(&mut io, get_prompts(&Lang::EN));
+    assert!(matches!(
+        findings.as_slice(),
+        [Finding::LoginRejected { domain, error }]
+            if domain == "127.0.0.1" && error.contains("AUTHENTICATIONFAILED")
+    ));
+    let output = String::from_utf8(io.into_inner().1).unwrap();
+    assert!(output.contains("authorization code or app password"));
+}
+
+#[test]
+fn doctor_reports_unreachable_and_unresolved_servers() {
+    let mut user = user(PASSWORD, closed_port(), closed_port());
+    user.imap_domain = "imap.nonexistent.invalid".to_string();
+
+    let findings = doctor::diagnose(&[
+        doctor::examine(&user, Service::Smtp),
+        doctor::examine(&user, Service::Imap),
+    ]);
+    assert_eq!(findings.len(), 2);
+    assert_eq!(
+        findings[0],
+        Finding::Unreachable {
+            domain: "127.0.0.1".to_string()
+        }
+    );
+    assert!(
+        matches!(&findings[1], Finding::Unresolved { domain, .. } if domain == "imap.nonexistent.invalid")
+    );
+}
+
+#[test]
+fn doctor_looks_at_tls_after_starttls() {
+    let imap_port = starttls_imap("self-signed");
+    let user = user(PASSWORD, closed_port(), imap_port);
+
+    let report = doctor::examine(&user, Service::Imap);
+    let port = &report.ports[0];
+    assert!(port
+        .capabilities
+        .clone()
+        .unwrap()
+        .unwrap()
+        .contains(&"STARTTLS".to_string()));
+    let tls = port.tls.clone().unwrap().unwrap();
+    assert!(tls.version.is_some());
+    assert!(tls.trusted.is_err());
+    let certificate = tls.certificate.unwrap();
+    assert!(certificate.names.contains(&"localhost".to_string()));
+
+    // Both the certificate & the plaintext port refusing logins are found
+    let findings = doctor::diagnose(&[report]);
+    assert!(findings.contains(&Finding::NoLogin {
+        domain: "127.0.0.1".to_string(),
+        port: imap_port,
+    }));
+    assert!(findings
+        .iter()
+        .any(|f| matches!(f, Finding::Untrusted { port, .. } if *port == imap_port)));
+}
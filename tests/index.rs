@@ -0,0 +1,133 @@
+//! Indexing cached mail in other charsets & searching it across accounts.
+//!
+//! Every test shares the data directory of `EUA_HOME`, they take turns at its index.
+
+use std::{
+    env, process,
+    sync::{Mutex, MutexGuard},
+};
+
+use echo_unity_archivist::cache::Cache;
+use echo_unity_archivist::index::{read_hit, MailIndex};
+
+/// Subject "会議" & body "会議の資料です" in ISO-2022-JP.
+const ISO_2022_JP: &[u8] = b"From: Taro <taro@example.test>\r\n\
+Subject: =?ISO-2022-JP?B?GyRCMnE1RBsoQg==?=\r\n\
+Message-ID: <jp@example.test>\r\n\
+MIME-Version: 1.0\r\n\
+Content-Type: text/plain; charset=ISO-2022-JP\r\n\
+Content-Transfer-Encoding: 7bit\r\n\
+\r\n\
+\x1b$B2q5D$N;qNA$G$9\x1b(B\r\n";
+
+/// Body "Café crème brûlée" in windows-1252, sent as 8 bits.
+const WINDOWS_1252: &[u8] = b"From: Chef <chef@example.test>\r\n\
+Subject: Dessert\r\n\
+Message-ID: <cp@example.test>\r\n\
+MIME-Version: 1.0\r\n\
+Content-Type: text/plain; charset=windows-1252\r\n\
+Content-Transfer-Encoding: 8bit\r\n\
+\r\n\
+Caf\xe9 cr\xe8me br\xfbl\xe9e\r\n";
+
+const WITH_ATTACHMENT: &[u8] = b"From: Bob <bob@example.test>\r\n\
+Subject: Numbers\r\n\
+Message-ID: <att@example.test>\r\n\
+MIME-Version: 1.0\r\n\
+Content-Type: multipart/mixed; boundary=\"b\"\r\n\
+\r\n\
+--b\r\n\
+Content-Type: text/plain\r\n\
+\r\n\
+See attached.\r\n\
+--b\r\n\
+Content-Type: application/vnd.ms-excel\r\n\
+Content-Disposition: attachment; filename=\"Spreadsheet_Q3.xls\"\r\n\
+Content-Transfer-Encoding: base64\r\n\
+\r\n\
+AAAA\r\n\
+--b--\r\n";
+
+static DATA_DIR: Mutex<()> = Mutex::new(());
+
+/// Points `EUA_HOME` at this run's data directory & takes its turn at it.
+fn data_dir() -> MutexGuard<'static, ()> {
+    let guard = DATA_DIR.lock().unwrap_or_else(|e| e.into_inner());
+    let home = env::temp_dir().join(format!("eua-index-{}", process::id()));
+    env::set_var("EUA_HOME", home);
+    guard
+}
+
+/// Caches messages in an account's INBOX, UIDs counting from 1, & indexes the cache.
+fn index(account: &str, messages: &[&[u8]]) {
+    let cache = Cache::open(&account.parse().unwrap()).unwrap();
+    let mut inbox = cache.mailbox("INBOX").unwrap();
+    inbox.uid_validity = 1;
+    for (uid, raw) in (1..).zip(messages) {
+        inbox.insert(uid, "", raw).unwrap();
+    }
+    inbox.save().unwrap();
+    let count = MailIndex::open().unwrap().index_cache(&cache).unwrap();
+    assert_eq!(count, messages.len());
+}
+
+#[test]
+fn messages_in_other_charsets_are_found_by_their_decoded_text() {
+    let _turn = data_dir();
+    index("charsets@example.test", &[ISO_2022_JP, WINDOWS_1252]);
+    let index = MailIndex::open().unwrap();
+
+    let hits = index.search("会議", 10).unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].subject, "会議");
+    assert_eq!(read_hit(&hits[0]).unwrap(), ISO_2022_JP);
+    // Text without spaces is a single term
+    assert_eq!(index.search("会議の資料です", 10).unwrap().len(), 1);
+
+    let hits = index.search("crème", 10).unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].uid, 2);
+}
+
+#[test]
+fn attachments_are_found_by_their_filename() {
+    let _turn = data_dir();
+    index("attachments@example.test", &[WITH_ATTACHMENT]);
+    let index = MailIndex::open().unwrap();
+
+    let hits = index.search("spreadsheet", 10).unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].subject, "Numbers");
+    assert_eq!(index.search("attachments:numbers", 10).unwrap().len(), 0);
+}
+
+#[test]
+fn search_ranks_hits_across_accounts() {
+    let _turn = data_dir();
+    index(
+        "first@example.test",
+        &[b"From: Ann <ann@example.test>\r\nSubject: Lunch\r\n\r\nThe invoice is late.\r\n"],
+    );
+    index(
+        "second@example.test",
+        &[
+            b"From: Bob <bob@example.test>\r\nSubject: Weather\r\n\r\nSunny.\r\n",
+            b"From: Bob <bob@example.test>\r\nSubject: Invoice 42\r\n\r\nAttached.\r\n",
+        ],
+    );
+
+    let hits = MailIndex::open().unwrap().search("invoice", 10).unwrap();
+
+    let found = hits
+        .iter()
+        .map(|hit| (hit.account.as_str(), hit.uid))
+        .collect::<Vec<_>>();
+    // A match in the subject weighs more than one in the body
+    assert_eq!(
+        found,
+        [("second@example.test", 2), ("first@example.test", 1)]
+    );
+    assert!(hits[0].score > hits[1].score);
+    let raw = read_hit(&hits[1]).unwrap();
+    assert!(raw.ends_with(b"The invoice is late.\r\n"));
+}
@@ -0,0 +1,158 @@
+//! Talking JMAP to the fake server of `support` over plain HTTP.
+
+mod support;
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::TcpListener,
+    thread,
+};
+
+use echo_unity_archivist::error;
+use echo_unity_archivist::jmap::JmapClient;
+use echo_unity_archivist::trust::{Trust, TrustSettings};
+use support::{tls_front, FakeJmap, EMAIL_ADDR, JMAP_ACCOUNT_ID, PASSWORD};
+
+/// Returns the trust of the test account's connections with the default settings.
+fn default_trust() -> Trust {
+    Trust::new(EMAIL_ADDR, TrustSettings::default())
+}
+
+/// Connects the test account to a fake server.
+fn connect(jmap: &FakeJmap) -> JmapClient {
+    let server = format!("http://127.0.0.1:{}", jmap.port);
+    JmapClient::connect(&server, EMAIL_ADDR, PASSWORD, default_trust()).unwrap()
+}
+
+#[test]
+fn session_is_discovered_through_redirects() {
+    let jmap = FakeJmap::start();
+    connect(&jmap);
+    let requests = jmap.received.lines();
+    assert!(requests.contains(&"GET /.well-known/jmap HTTP/1.1".to_string()));
+    assert!(requests.contains(&"GET /jmap/session HTTP/1.1".to_string()));
+    assert!(requests.contains(&format!("Host: 127.0.0.1:{}", jmap.port)));
+
+    // Basic is refused, then the password is tried as a bearer token
+    let server = format!("http://127.0.0.1:{}", jmap.port);
+    let result = JmapClient::connect(&server, EMAIL_ADDR, "wrong", default_trust());
+    match result {
+        Err(error::Error::Jmap(e)) => assert_eq!(e, "authentication failed: HTTP 401"),
+        _ => panic!("connected with a wrong password"),
+    }
+    assert!(jmap.received.text().contains("Authorization: Bearer wrong"));
+}
+
+#[test]
+fn mailboxes_are_named_by_their_path_from_chunked_responses() {
+    let jmap = FakeJmap::start();
+    let mailboxes = connect(&jmap).mailboxes().unwrap();
+    let names = mailboxes
+        .iter()
+        .map(|m| m.name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["Inbox", "Projects", "Projects/2024", "Sent"]);
+    assert_eq!(mailboxes[3].role.as_deref(), Some("sent"));
+    assert!(jmap
+        .received
+        .text()
+        .contains(&format!("\"accountId\":\"{}\"", JMAP_ACCOUNT_ID)));
+}
+
+#[test]
+fn emails_not_found_are_left_out_of_the_numbering() {
+    let jmap = FakeJmap::start();
+    let jmap_cli = connect(&jmap);
+    let ids = jmap_cli.query("m1", 10).unwrap();
+    assert_eq!(ids, ["e1", "e2", "e3"]);
+
+    let emails = jmap_cli.get_emails(&ids).unwrap();
+    let numbered = emails
+        .iter()
+        .map(|e| (e.id.as_str(), e.summary.uid, e.summary.subject.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(numbered, [("e1", 1, "First"), ("e3", 2, "Third")]);
+    assert_eq!(emails[0].summary.from, "bob@example.test");
+    assert_eq!(emails[0].keywords, ["$seen"]);
+    let raw = jmap_cli.download(&emails[0].blob_id).unwrap();
+    assert!(raw.ends_with(b"Hello.\r\n"));
+}
+
+#[test]
+fn submitted_messages_are_kept_in_sent_as_the_sender() {
+    let jmap = FakeJmap::start();
+    let raw = b"From: me@example.test\r\nTo: bob@example.test\r\nSubject: Hi\r\n\r\nHello.\r\n";
+    connect(&jmap).submit(EMAIL_ADDR, raw).unwrap();
+
+    let received = jmap.received.text();
+    let upload = format!("POST /jmap/upload/{}/ HTTP/1.1", JMAP_ACCOUNT_ID);
+    assert!(received.contains(&upload));
+    assert!(received.contains("Content-Type: message/rfc822"));
+    assert!(received.contains(std::str::from_utf8(raw).unwrap()));
+    assert!(received.contains("\"blobId\":\"b-upload\""));
+    assert!(received.contains("\"mailboxIds\":{\"m4\":true}"));
+    assert!(received.contains("\"identityId\":\"i1\""));
+    assert!(received.contains("\"emailId\":\"#message\""));
+}
+
+#[test]
+fn https_servers_are_trusted_by_the_account_settings() {
+    let jmap = FakeJmap::start();
+    let server = format!("https://localhost:{}", tls_front("localhost", jmap.port));
+    let result = JmapClient::connect(&server, EMAIL_ADDR, PASSWORD, default_trust());
+    assert!(result.is_err());
+
+    let ca = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/ca.pem");
+    let settings = TrustSettings {
+        ca_files: vec![ca.into()],
+        ..Default::default()
+    };
+    let trust = Trust::new(EMAIL_ADDR, settings);
+    let jmap_cli = JmapClient::connect(&server, EMAIL_ADDR, PASSWORD, trust).unwrap();
+    assert_eq!(jmap_cli.mailboxes().unwrap().len(), 4);
+}
+
+#[test]
+fn plain_http_is_refused_off_this_host() {
+    let result = JmapClient::connect(
+        "http://jmap.example.test",
+        EMAIL_ADDR,
+        PASSWORD,
+        default_trust(),
+    );
+    match result {
+        Err(error::Error::Jmap(e)) => assert!(e.starts_with("refusing plain HTTP")),
+        _ => panic!("connected over plain HTTP"),
+    }
+}
+
+#[test]
+fn redirects_to_another_origin_are_refused() {
+    let jmap = FakeJmap::start();
+    // Sends the session request to the fake server, on another port
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let location = format!("http://127.0.0.1:{}/jmap/session", jmap.port);
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let mut reader = BufReader::new(&stream);
+            let mut line = String::new();
+            while reader.read_line(&mut line).is_ok_and(|n| n > 2) {
+                line.clear();
+            }
+            let response = format!(
+                "HTTP/1.1 307 Temporary Redirect\r\nLocation: {}\r\nContent-Length: 0\r\n\r\n",
+                location
+            );
+            let _ = (&stream).write_all(response.as_bytes());
+        }
+    });
+
+    let server = format!("http://127.0.0.1:{}", port);
+    let result = JmapClient::connect(&server, EMAIL_ADDR, PASSWORD, default_trust());
+    match result {
+        Err(error::Error::Jmap(e)) => assert!(e.starts_with("refusing redirect")),
+        _ => panic!("followed a redirect to another origin"),
+    }
+    assert!(jmap.received.text().is_empty());
+}
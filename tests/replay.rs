@@ -0,0 +1,238 @@
+//! Recording sessions through a tap & replaying transcripts as scripted servers.
+
+mod support;
+
+use std::{env, process, sync::Arc};
+
+use echo_unity_archivist::client;
+use echo_unity_archivist::error;
+use echo_unity_archivist::store::{ImapStore, MailStore};
+use echo_unity_archivist::tap::{Redactor, REDACTED};
+use echo_unity_archivist::transcript::{self, Entry, Recorder, Transcript};
+use echo_unity_archivist::trust::{self, TrustSettings};
+use echo_unity_archivist::user::{Security, User};
+use support::{tls_front, FakeImap, FakeSmtp, EMAIL_ADDR, PASSWORD};
+
+const MESSAGE: &[u8] = b"From: Bob <bob@example.test>\r\n\
+To: me@example.test\r\n\
+Subject: Lunch?\r\n\
+Message-ID: <1@example.test>\r\n\
+\r\n\
+Are you free at noon?\r\n";
+
+/// Builds the test account pointing at localhost ports over plaintext.
+fn localhost(smtp_port: u16, imap_port: u16) -> User {
+    let mut user = User::new(EMAIL_ADDR.parse().unwrap(), PASSWORD.to_string());
+    user.smtp_domain = "127.0.0.1".to_string();
+    user.imap_domain = "127.0.0.1".to_string();
+    user.smtp_port = smtp_port;
+    user.imap_port = imap_port;
+    user.security = Security::Plaintext;
+    user
+}
+
+/// Logs in, lists & fetches the inbox, sends a message & logs out.
+///
+/// # Returns
+///
+/// The subjects listed & the raw messages fetched.
+fn session(user: User) -> (Vec<String>, Vec<Vec<u8>>) {
+    let mut client = client::connect(user).unwrap();
+    let listed = client.list("INBOX", 0..10).unwrap();
+    let subjects = listed.iter().map(|(_, s)| s.subject.clone()).collect();
+    let fetched = listed
+        .iter()
+        .map(|(message, _)| client.fetch(message).unwrap())
+        .collect();
+    let to = "bob@example.test".parse().unwrap();
+    let message = client
+        .user
+        .build_message(&to, "Re: Lunch?", "Sure.".to_string());
+    client.send(&message).unwrap();
+    client.logout().unwrap();
+    (subjects, fetched)
+}
+
+/// Returns the text lines of every connection of a transcript.
+fn lines(transcript: &Transcript) -> Vec<String> {
+    transcript
+        .connections
+        .iter()
+        .flatten()
+        .map(|entry| match entry {
+            Entry::Client(bytes) => format!("C: {}", String::from_utf8_lossy(bytes)),
+            Entry::Server(bytes) => format!("S: {}", String::from_utf8_lossy(bytes)),
+        })
+        .collect()
+}
+
+#[test]
+fn recorded_sessions_redact_credentials() {
+    let smtp = FakeSmtp::start();
+    let imap = FakeImap::start(vec![("INBOX", vec![MESSAGE.to_vec()])]);
+    let recorder = Arc::new(Recorder::default());
+    let mut user = localhost(smtp.port, imap.port);
+    user.set_tap(recorder.clone());
+    session(user);
+
+    // The servers still got the credentials, the transcripts don't hold them
+    assert!(imap.received.text().contains(PASSWORD));
+    let imap_lines = lines(&recorder.transcript("imap"));
+    let smtp_lines = lines(&recorder.transcript("smtp"));
+    for line in imap_lines.iter().chain(smtp_lines.iter()) {
+        assert!(!line.contains(PASSWORD), "leaked in {:?}", line);
+    }
+    assert!(imap_lines
+        .iter()
+        .any(|l| l.ends_with(&format!("LOGIN {}\r\n", REDACTED))));
+    assert!(smtp_lines.contains(&format!("C: AUTH PLAIN {}\r\n", REDACTED)));
+    assert!(smtp_lines.contains(&"C: Subject: Re: Lunch?\r\n".to_string()));
+}
+
+#[test]
+fn recorded_sessions_replay_the_same() {
+    let smtp = FakeSmtp::start();
+    let imap = FakeImap::start(vec![("INBOX", vec![MESSAGE.to_vec()])]);
+    let recorder = Arc::new(Recorder::default());
+    let mut user = localhost(smtp.port, imap.port);
+    user.set_tap(recorder.clone());
+    let recorded = session(user);
+
+    // Round trip the transcripts through their file format, as fixtures would be
+    let imap_transcript = Transcript::parse(&recorder.transcript("imap").to_text()).unwrap();
+    let smtp_transcript = Transcript::parse(&recorder.transcript("smtp").to_text()).unwrap();
+    assert_eq!(imap_transcript, recorder.transcript("imap"));
+    let imap_replay = transcript::replay(imap_transcript).unwrap();
+    let smtp_replay = transcript::replay(smtp_transcript).unwrap();
+    let replayed = session(localhost(smtp_replay.port, imap_replay.port));
+
+    assert_eq!(replayed, recorded);
+    assert_eq!(replayed.1, vec![MESSAGE.to_vec()]);
+    let sent = String::from_utf8_lossy(&smtp_replay.received()).into_owned();
+    assert!(sent.contains("RCPT TO:<bob@example.test>"));
+}
+
+#[test]
+fn tapped_tls_connections_record_the_lines_inside() {
+    env::set_var(
+        "EUA_HOME",
+        env::temp_dir().join(format!("eua-replay-{}", process::id())),
+    );
+    let ca = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/ca.pem");
+    let settings = TrustSettings {
+        ca_files: vec![ca.into()],
+        ..Default::default()
+    };
+    trust::save_trust(EMAIL_ADDR, &settings).unwrap();
+    let imap = FakeImap::start(vec![("INBOX", Vec::new())]);
+    let recorder = Arc::new(Recorder::default());
+    let mut user = User::new(EMAIL_ADDR.parse().unwrap(), PASSWORD.to_string());
+    user.imap_domain = "localhost".to_string();
+    user.imap_port = tls_front("localhost", imap.port);
+    user.set_tap(recorder.clone());
+    user.connect_imap().unwrap().logout().unwrap();
+
+    let imap_lines = lines(&recorder.transcript("imap"));
+    assert!(imap_lines[0].starts_with("S: * OK"), "{:?}", imap_lines);
+    assert!(imap_lines
+        .iter()
+        .any(|l| l.ends_with(&format!("LOGIN {}\r\n", REDACTED))));
+    assert!(imap_lines.iter().any(|l| l.starts_with("C: a2 LOGOUT")));
+}
+
+#[test]
+fn gmail_fixture_lists_and_fetches() {
+    let path = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/gmail-imap.transcript"
+    );
+    let replay = transcript::replay(Transcript::load(path.as_ref()).unwrap()).unwrap();
+    let mut user = User::new(EMAIL_ADDR.parse().unwrap(), PASSWORD.to_string());
+    user.imap_domain = "127.0.0.1".to_string();
+    user.imap_port = replay.port;
+    user.security = Security::Plaintext;
+    let mut session = user.connect_imap().unwrap();
+    let mut store = ImapStore::new(&mut user, &mut session);
+
+    let mailboxes = store.mailboxes().unwrap();
+    assert!(mailboxes.contains(&"[Gmail]/Sent Mail".to_string()));
+    let messages = store.messages("INBOX").unwrap();
+    assert_eq!(messages.len(), 2);
+    let summaries = store.summaries(&messages).unwrap();
+    assert_eq!(summaries[1].subject, "Re: Lunch?");
+    let raw = store.fetch(&messages[0]).unwrap();
+    assert!(raw.ends_with(b"Are you free at noon?\r\n"));
+}
+
+#[test]
+fn redactor_covers_challenges_and_literals() {
+    let mut redactor = Redactor::default();
+    let client =
+        |r: &mut Redactor, line: &str| String::from_utf8(r.client(line.as_bytes())).unwrap();
+
+    assert_eq!(client(&mut redactor, "AUTH LOGIN\r\n"), "AUTH LOGIN\r\n");
+    redactor.server(b"334 VXNlcm5hbWU6\r\n");
+    assert_eq!(
+        client(&mut redactor, "bWVAZXhhbXBsZQ==\r\n"),
+        format!("{}\r\n", REDACTED)
+    );
+    redactor.server(b"235 2.7.0 Accepted\r\n");
+    assert_eq!(
+        client(&mut redactor, "MAIL FROM:<me>\r\n"),
+        "MAIL FROM:<me>\r\n"
+    );
+
+    assert_eq!(
+        client(&mut redactor, "a1 LOGIN {15}\r\n"),
+        format!("a1 LOGIN {}\r\n", REDACTED)
+    );
+    redactor.server(b"+ go ahead\r\n");
+    assert_eq!(
+        client(&mut redactor, "me@example.test {6}\r\n"),
+        format!("{}\r\n", REDACTED)
+    );
+    redactor.server(b"a1 OK LOGIN completed\r\n");
+    assert_eq!(
+        client(&mut redactor, "PASS hunter2\r\n"),
+        format!("PASS {}\r\n", REDACTED)
+    );
+}
+
+#[test]
+fn deleting_without_uidplus_spares_other_flagged_messages() {
+    let transcript = Transcript::parse(
+        "# echo_unity_archivist transcript\n\
+         == connection\n\
+         S: * OK [CAPABILITY IMAP4rev1] ready\r\n\
+         C: a1 LOGIN <redacted>\r\n\
+         S: a1 OK LOGIN completed\r\n\
+         C: a2 SELECT \"INBOX\"\r\n\
+         S: * 3 EXISTS\r\n\
+         S: * OK [UIDVALIDITY 7] UIDs valid\r\n\
+         S: a2 OK [READ-WRITE] SELECT completed\r\n\
+         C: a3 CAPABILITY\r\n\
+         S: * CAPABILITY IMAP4rev1\r\n\
+         S: a3 OK CAPABILITY completed\r\n\
+         C: a4 UID SEARCH DELETED\r\n\
+         S: * SEARCH 2 3\r\n\
+         S: a4 OK SEARCH completed\r\n\
+         C: a5 LOGOUT\r\n\
+         S: * BYE logging out\r\n\
+         S: a5 OK LOGOUT completed\r\n",
+    )
+    .unwrap();
+    let replay = transcript::replay(transcript).unwrap();
+    let mut user = User::new(EMAIL_ADDR.parse().unwrap(), PASSWORD.to_string());
+    user.imap_domain = "127.0.0.1".to_string();
+    user.imap_port = replay.port;
+    user.security = Security::Plaintext;
+    let mut session = user.connect_imap().unwrap();
+
+    // UID 3 was flagged by someone else, expunging would take it along
+    let result = user.delete_messages(&mut session, "INBOX", &[2]);
+    assert!(matches!(result, Err(error::Error::Unsupported(_))));
+    session.logout().unwrap();
+    let sent = String::from_utf8_lossy(&replay.received()).into_owned();
+    assert!(sent.contains("UID SEARCH DELETED"));
+    assert!(!sent.contains("STORE") && !sent.contains("EXPUNGE"));
+}
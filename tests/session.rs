@@ -0,0 +1,242 @@
+//! Login, send & fetch flows against the fake servers of `support`.
+
+mod support;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use echo_unity_archivist::read::{self, Io};
+use echo_unity_archivist::user::fetch_message;
+use echo_unity_archivist::{client, get_prompts, Lang};
+use support::{FakeImap, FakeSmtp, EMAIL_ADDR, PASSWORD, UID_VALIDITY};
+
+const FIRST: &[u8] = b"From: Bob <bob@example.test>\r\n\
+To: me@example.test\r\n\
+Subject: Lunch?\r\n\
+Message-ID: <1@example.test>\r\n\
+\r\n\
+Are you free at noon?\r\n";
+
+const REPLY: &[u8] = b"From: Carol <carol@example.test>\r\n\
+To: me@example.test\r\n\
+Subject: Re: Lunch?\r\n\
+Message-ID: <2@example.test>\r\n\
+In-Reply-To: <1@example.test>\r\n\
+\r\n\
+Count me in.\r\n";
+
+fn servers() -> (FakeSmtp, FakeImap) {
+    let smtp = FakeSmtp::start();
+    let imap = FakeImap::start(vec![
+        ("INBOX", vec![FIRST.to_vec(), REPLY.to_vec()]),
+        ("Sent", Vec::new()),
+    ]);
+    (smtp, imap)
+}
+
+#[test]
+fn login_authenticates_with_both_servers() {
+    let (smtp, imap) = servers();
+    client::connect(support::account(&smtp, &imap, PASSWORD)).unwrap();
+
+    let plain = STANDARD.encode(format!("\0{}\0{}", EMAIL_ADDR, PASSWORD));
+    assert!(smtp.received.lines().iter().any(|l| l.starts_with("EHLO ")));
+    assert!(smtp
+        .received
+        .lines()
+        .contains(&format!("AUTH PLAIN {}", plain)));
+    assert!(imap
+        .received
+        .lines()
+        .iter()
+        .any(|l| l.ends_with(&format!("LOGIN \"{}\" \"{}\"", EMAIL_ADDR, PASSWORD))));
+}
+
+#[test]
+fn login_fails_with_a_wrong_password() {
+    let (smtp, imap) = servers();
+    let user = support::account(&smtp, &imap, "wrong");
+
+    // The fake SMTP server accepts anyone, the IMAP one doesn't
+    assert!(client::connect(user).is_err());
+}
+
+#[test]
+fn send_delivers_envelope_and_message() {
+    let (smtp, imap) = servers();
+    let mut client = client::connect(support::account(&smtp, &imap, PASSWORD)).unwrap();
+    let to = "bob@example.test".parse().unwrap();
+    let message = client
+        .user
+        .build_message(&to, "Re: Lunch?", "Sure, see you.".to_string());
+    client.send(&message).unwrap();
+
+    let lines = smtp.received.lines();
+    assert!(lines.contains(&format!("MAIL FROM:<{}>", EMAIL_ADDR)));
+    assert!(lines.contains(&"RCPT TO:<bob@example.test>".to_string()));
+    let data = lines.iter().position(|l| l == "DATA").unwrap();
+    let end = data + lines[data..].iter().position(|l| l == ".").unwrap();
+    let sent = &lines[data + 1..end];
+    assert!(sent.contains(&"Subject: Re: Lunch?".to_string()));
+    assert!(sent.contains(&format!("From: {}", EMAIL_ADDR)));
+    assert!(sent.contains(&"Sure, see you.".to_string()));
+}
+
+#[test]
+fn compose_and_send_follows_the_scripted_answers() {
+    let (smtp, imap) = servers();
+    let mut client = client::connect(support::account(&smtp, &imap, PASSWORD)).unwrap();
+    let prompts = get_prompts(&Lang::EN);
+    let script = b"carol@example.test\nDinner\nAt seven?\n\n\nyes\n";
+    let mut io = Io::new(&script[..], Vec::new());
+
+    let to = client.compose_and_send(&mut io, prompts).unwrap();
+
+    assert_eq!(to.as_deref(), Some("carol@example.test"));
+    let text = smtp.received.text();
+    assert!(text.contains("RCPT TO:<carol@example.test>\r\n"));
+    assert!(text.contains("Subject: Dinner\r\n"));
+    assert!(text.contains("\r\nAt seven?\r\n.\r\n"));
+}
+
+#[test]
+fn compose_and_send_sends_nothing_when_cancelled() {
+    let (smtp, imap) = servers();
+    let mut client = client::connect(support::account(&smtp, &imap, PASSWORD)).unwrap();
+    let prompts = get_prompts(&Lang::EN);
+    let script = b"carol@example.test\nDinner\nAt seven?\n\n\nno\n";
+    let mut io = Io::new(&script[..], Vec::new());
+
+    let to = client.compose_and_send(&mut io, prompts).unwrap();
+
+    assert_eq!(to, None);
+    assert!(!smtp.received.text().contains("DATA"));
+}
+
+#[test]
+fn list_returns_summaries_in_uid_order() {
+    let (smtp, imap) = servers();
+    let mut client = client::connect(support::account(&smtp, &imap, PASSWORD)).unwrap();
+
+    assert_eq!(client.mailboxes().unwrap(), ["INBOX", "Sent"]);
+    let listed = client.list("INBOX", 0..10).unwrap();
+    let subjects = listed
+        .iter()
+        .map(|(_, summary)| summary.subject.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(subjects, ["Lunch?", "Re: Lunch?"]);
+    assert_eq!(listed[1].0.uid_validity, UID_VALIDITY);
+    assert_eq!(listed[1].1.ancestors, ["1@example.test"]);
+    assert_eq!(client.list("INBOX", 1..10).unwrap().len(), 1);
+    assert!(imap
+        .received
+        .lines()
+        .iter()
+        .any(|l| l.ends_with("UID FETCH 1:2 BODY.PEEK[HEADER]")));
+}
+
+#[test]
+fn fetch_returns_the_raw_message() {
+    let (smtp, imap) = servers();
+    let mut client = client::connect(support::account(&smtp, &imap, PASSWORD)).unwrap();
+
+    let listed = client.list("INBOX", 0..1).unwrap();
+    assert_eq!(client.fetch(&listed[0].0).unwrap(), FIRST);
+    assert!(imap
+        .received
+        .lines()
+        .iter()
+        .any(|l| l.ends_with("UID FETCH 1 RFC822")));
+}
+
+#[test]
+fn fetch_message_walks_mailbox_and_conversation_menus() {
+    let (smtp, imap) = servers();
+    let mut client = client::connect(support::account(&smtp, &imap, PASSWORD)).unwrap();
+    let prompts = get_prompts(&Lang::EN);
+    // An out-of-range mailbox is asked again, then the reply in the thread is chosen
+    let mut io = Io::new(&b"3\n1\n2\n"[..], Vec::new());
+
+    let conversation = fetch_message(&mut client.store(), &mut io, prompts)
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(conversation.position, 1);
+    assert_eq!(
+        conversation
+            .messages
+            .iter()
+            .map(|m| m.uid)
+            .collect::<Vec<_>>(),
+        [1, 2]
+    );
+    assert_eq!(conversation.body.as_bytes(), REPLY);
+    let (_, output) = io.into_inner();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains(prompts.invalid_literal));
+    assert!(output.contains("  [2] ↳ Re: Lunch?"));
+}
+
+#[test]
+fn fetch_message_threads_locally_when_the_server_returns_no_thread() {
+    let smtp = FakeSmtp::start();
+    let imap = FakeImap::start_with(
+        "IMAP4rev1 UIDPLUS THREAD=REFERENCES",
+        vec![("INBOX", vec![FIRST.to_vec(), REPLY.to_vec()])],
+    );
+    let mut client = client::connect(support::account(&smtp, &imap, PASSWORD)).unwrap();
+    let prompts = get_prompts(&Lang::EN);
+    let mut io = Io::new(&b"1\n2\n"[..], Vec::new());
+
+    let conversation = fetch_message(&mut client.store(), &mut io, prompts)
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(conversation.body.as_bytes(), REPLY);
+    assert!(imap
+        .received
+        .lines()
+        .iter()
+        .any(|l| l.ends_with("UID THREAD REFERENCES UTF-8 ALL")));
+}
+
+#[test]
+fn read_conversation_steps_through_the_thread() {
+    let (smtp, imap) = servers();
+    let mut client = client::connect(support::account(&smtp, &imap, PASSWORD)).unwrap();
+    let prompts = get_prompts(&Lang::EN);
+    // The reply is chosen & shown first, then the message it answers, then the menu is left
+    let mut io = Io::new(&b"1\n2\n1\n0\n"[..], Vec::new());
+    client.read_conversation(&mut io, prompts).unwrap();
+    let output = String::from_utf8(io.into_inner().1).unwrap();
+    let reply = output.find("Count me in.").unwrap();
+    let first = output.find("Are you free at noon?").unwrap();
+    assert!(reply < first);
+    assert!(output.contains(&format!("{}2/2.", prompts.conversation_position)));
+    assert!(output.contains(&format!("{}1/2.", prompts.conversation_position)));
+}
+
+#[test]
+fn fetch_message_returns_an_error_once_input_ends() {
+    let (smtp, imap) = servers();
+    let mut client = client::connect(support::account(&smtp, &imap, PASSWORD)).unwrap();
+    let prompts = get_prompts(&Lang::EN);
+    // Input ends at the conversation menu, after a mailbox is chosen
+    let mut io = Io::new(&b"1\n"[..], Vec::new());
+
+    let Err(e) = fetch_message(&mut client.store(), &mut io, prompts) else {
+        panic!("fetching a message should fail once input ends");
+    };
+
+    assert!(read::is_input_ended(&e));
+    // The session is still usable to log out
+    client.logout().unwrap();
+    assert!(imap.received.lines().last().unwrap().ends_with("LOGOUT"));
+}
+
+#[test]
+fn logout_says_goodbye() {
+    let (smtp, imap) = servers();
+    let client = client::connect(support::account(&smtp, &imap, PASSWORD)).unwrap();
+    client.logout().unwrap();
+
+    assert!(imap.received.lines().last().unwrap().ends_with("LOGOUT"));
+}
@@ -0,0 +1,184 @@
+//! `fetch_message` & `compose_and_send` against the POP3, file & in-memory backends.
+
+mod support;
+
+use std::{env, fs, process};
+
+use echo_unity_archivist::cache::Cache;
+use echo_unity_archivist::pop3::{Pop3Client, POP3_MAILBOX};
+use echo_unity_archivist::read::Io;
+use echo_unity_archivist::store::{FileStore, MailStore, MemoryStore, Pop3Store};
+use echo_unity_archivist::transport::{FileTransport, MemoryTransport};
+use echo_unity_archivist::user::{fetch_message, User};
+use echo_unity_archivist::{get_prompts, Lang};
+use support::{FakePop3, EMAIL_ADDR, PASSWORD};
+
+const FIRST: &[u8] = b"From: Bob <bob@example.test>\r\n\
+To: me@example.test\r\n\
+Subject: Lunch?\r\n\
+Message-ID: <1@example.test>\r\n\
+\r\n\
+Are you free at noon?\r\n";
+
+const REPLY: &[u8] = b"From: Carol <carol@example.test>\r\n\
+To: me@example.test\r\n\
+Subject: Re: Lunch?\r\n\
+Message-ID: <2@example.test>\r\n\
+In-Reply-To: <1@example.test>\r\n\
+\r\n\
+Count me in.\r\n";
+
+/// Answers to `compose_and_send`'s prompts: recipient, subject, body, then confirmation.
+const COMPOSE_SCRIPT: &[u8] = b"carol@example.test\nDinner\nAt seven?\n\n\nyes\n";
+
+fn user() -> User {
+    User::new(EMAIL_ADDR.parse().unwrap(), PASSWORD.to_string())
+}
+
+#[test]
+fn fetch_message_walks_a_memory_store() {
+    let mut store = MemoryStore::new();
+    store.append("INBOX", &[], FIRST);
+    store.append("INBOX", &["\\Seen"], REPLY);
+    store.append("Sent", &[], b"Subject: Hi\r\n\r\nHi\r\n");
+    let prompts = get_prompts(&Lang::EN);
+    let mut io = Io::new(&b"1\n2\n"[..], Vec::new());
+
+    let conversation = fetch_message(&mut store, &mut io, prompts)
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(conversation.position, 1);
+    assert_eq!(
+        conversation
+            .messages
+            .iter()
+            .map(|m| m.uid)
+            .collect::<Vec<_>>(),
+        [1, 2]
+    );
+    assert_eq!(conversation.body.as_bytes(), REPLY);
+    let output = String::from_utf8(io.into_inner().1).unwrap();
+    assert!(output.contains("  [2] Sent"));
+    assert!(output.contains("  [2] ↳ Re: Lunch?"));
+}
+
+#[test]
+fn memory_store_flags_and_moves_messages() {
+    let mut store = MemoryStore::new();
+    let first = store.append("INBOX", &[], FIRST);
+
+    store.set_flag(&first, "\\Flagged", true).unwrap();
+    store.move_message(&first, "Archive").unwrap();
+
+    assert!(store.messages("INBOX").unwrap().is_empty());
+    let moved = store.messages("Archive").unwrap();
+    assert_eq!(store.fetch(&moved[0]).unwrap(), FIRST);
+    assert_eq!(store.mailboxes["Archive"][0].flags, ["\\Flagged"]);
+    assert!(store.fetch(&first).is_err());
+}
+
+#[test]
+fn file_store_reads_and_rearranges_the_cache() {
+    let home = env::temp_dir().join(format!("eua-store-{}", process::id()));
+    env::set_var("EUA_HOME", &home);
+    let cache = Cache::open(&EMAIL_ADDR.parse().unwrap()).unwrap();
+    let mut inbox = cache.mailbox("INBOX").unwrap();
+    inbox.uid_validity = 7;
+    inbox.insert(1, "", FIRST).unwrap();
+    inbox.insert(2, "S", REPLY).unwrap();
+    inbox.save().unwrap();
+    let mut store = FileStore::new(cache);
+    let prompts = get_prompts(&Lang::EN);
+    let mut io = Io::new(&b"1\n1\n"[..], Vec::new());
+
+    let conversation = fetch_message(&mut store, &mut io, prompts)
+        .unwrap()
+        .unwrap();
+    assert_eq!(conversation.body.as_bytes(), FIRST);
+    let first = &conversation.messages[0];
+    assert_eq!(first.uid_validity, 7);
+
+    store.set_flag(first, "\\Flagged", true).unwrap();
+    store.move_message(first, "Archive").unwrap();
+    let cache = Cache::open(&EMAIL_ADDR.parse().unwrap()).unwrap();
+    assert_eq!(cache.mailbox("INBOX").unwrap().uids(), [2]);
+    let archive = cache.mailbox("Archive").unwrap();
+    assert_eq!(archive.read(1).unwrap(), FIRST);
+    assert_eq!(archive.flags(1).as_deref(), Some("F"));
+    fs::remove_dir_all(&home).unwrap();
+}
+
+#[test]
+fn fetch_message_reads_the_pop3_maildrop() {
+    let pop3 = FakePop3::start(vec![FIRST.to_vec(), REPLY.to_vec()]);
+    let mut pop3_cli = Pop3Client::connect("127.0.0.1", pop3.port, None).unwrap();
+    pop3_cli.login(EMAIL_ADDR, PASSWORD).unwrap();
+    let prompts = get_prompts(&Lang::EN);
+    let mut io = Io::new(&b"1\n2\n"[..], Vec::new());
+
+    let mut store = Pop3Store::new(&mut pop3_cli);
+    let conversation = fetch_message(&mut store, &mut io, prompts)
+        .unwrap()
+        .unwrap();
+    assert_eq!(conversation.body.as_bytes(), REPLY);
+    assert_eq!(conversation.messages[0].mailbox, POP3_MAILBOX);
+
+    // A deleted message is no longer listed, the other keeps its number
+    store
+        .set_flag(&conversation.messages[0], "\\Deleted", true)
+        .unwrap();
+    let messages = store.messages(POP3_MAILBOX).unwrap();
+    assert_eq!(messages.iter().map(|m| m.uid).collect::<Vec<_>>(), [2]);
+    assert!(store.move_message(&messages[0], "Archive").is_err());
+    pop3_cli.quit().unwrap();
+
+    let lines = pop3.received.lines();
+    assert!(lines.contains(&"TOP 1 0".to_string()));
+    assert!(lines.contains(&"RETR 2".to_string()));
+    assert!(lines.contains(&"DELE 1".to_string()));
+}
+
+#[test]
+fn compose_and_send_hands_the_message_to_a_memory_transport() {
+    let mut transport = MemoryTransport::new();
+    let prompts = get_prompts(&Lang::EN);
+    let mut io = Io::new(COMPOSE_SCRIPT, Vec::new());
+
+    let to = user()
+        .compose_and_send(&mut transport, &mut io, prompts)
+        .unwrap();
+
+    assert_eq!(to.as_deref(), Some("carol@example.test"));
+    let [(envelope, raw)] = &transport.sent[..] else {
+        panic!("one message should have been sent");
+    };
+    assert_eq!(envelope.from().unwrap().to_string(), EMAIL_ADDR);
+    assert_eq!(envelope.to()[0].to_string(), "carol@example.test");
+    let raw = String::from_utf8_lossy(raw);
+    assert!(raw.contains("Subject: Dinner\r\n"));
+    assert!(raw.contains("At seven?"));
+}
+
+#[test]
+fn compose_and_send_writes_into_a_file_transport() {
+    let outbox = env::temp_dir().join(format!("eua-outbox-{}", process::id()));
+    let mut transport = FileTransport::new(&outbox).unwrap();
+    let prompts = get_prompts(&Lang::EN);
+    let mut io = Io::new(COMPOSE_SCRIPT, Vec::new());
+
+    user()
+        .compose_and_send(&mut transport, &mut io, prompts)
+        .unwrap();
+
+    let written = fs::read_dir(&outbox)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect::<Vec<_>>();
+    assert_eq!(written.len(), 1);
+    assert_eq!(written[0].extension().unwrap(), "eml");
+    let raw = fs::read_to_string(&written[0]).unwrap();
+    assert!(raw.contains("To: carol@example.test\r\n"));
+    assert!(raw.contains("Subject: Dinner\r\n"));
+    fs::remove_dir_all(&outbox).unwrap();
+}
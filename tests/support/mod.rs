@@ -0,0 +1,820 @@
+//! Minimal in-process SMTP, IMAP, POP3 & JMAP servers speaking plaintext on localhost, or TLS
+//! behind a front.
+//!
+//! They understand just enough of each protocol for the user agent's login, send & fetch
+//! flows, and record every byte received from clients so tests can assert on them.
+
+#![allow(dead_code)]
+
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use native_tls::{Identity, TlsAcceptor};
+use serde_json::{json, Value};
+
+use echo_unity_archivist::user::{Security, User};
+
+/// Address of the test account.
+pub const EMAIL_ADDR: &str = "me@example.test";
+
+/// Password of the test account.
+pub const PASSWORD: &str = "s3cret";
+
+/// UIDVALIDITY reported for every mailbox of `FakeImap`.
+pub const UID_VALIDITY: u32 = 7;
+
+/// Bytes received by a fake server, from all connections in the order they arrived.
+#[derive(Clone, Default)]
+pub struct Received(Arc<Mutex<Vec<u8>>>);
+
+impl Received {
+    fn record(&self, bytes: &[u8]) {
+        self.0.lock().unwrap().extend_from_slice(bytes);
+    }
+
+    /// Returns the received bytes as text.
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+    }
+
+    /// Returns the received lines, without their line breaks.
+    pub fn lines(&self) -> Vec<String> {
+        self.text().lines().map(String::from).collect()
+    }
+}
+
+/// Reads a line from a client, recording it.
+fn read_line(reader: &mut BufReader<TcpStream>, received: &Received) -> io::Result<String> {
+    let mut line = Vec::new();
+    reader.read_until(b'\n', &mut line)?;
+    received.record(&line);
+    Ok(String::from_utf8_lossy(&line).into_owned())
+}
+
+/// Binds a listener on a free localhost port & serves each connection on its own thread.
+fn serve<F>(session: F) -> u16
+where
+    F: Fn(BufReader<TcpStream>, TcpStream) -> io::Result<()> + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let session = Arc::new(session);
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let session = session.clone();
+            thread::spawn(move || {
+                let reader = BufReader::new(stream.try_clone().unwrap());
+                let _ = session(reader, stream);
+            });
+        }
+    });
+    port
+}
+
+/// Represents a fake SMTP server accepting any login & message.
+pub struct FakeSmtp {
+    pub port: u16,
+    pub received: Received,
+}
+
+impl FakeSmtp {
+    pub fn start() -> FakeSmtp {
+        let received = Received::default();
+        let recorder = received.clone();
+        let port = serve(move |mut reader, mut writer| {
+            writer.write_all(b"220 localhost ESMTP fake\r\n")?;
+            loop {
+                let line = read_line(&mut reader, &recorder)?;
+                if line.is_empty() {
+                    return Ok(());
+                }
+                let verb = line.split_whitespace().next().unwrap_or("").to_uppercase();
+                let reply: &[u8] = match verb.as_str() {
+                    "EHLO" => b"250-localhost\r\n250-AUTH PLAIN LOGIN\r\n250 8BITMIME\r\n",
+                    "HELO" => b"250 localhost\r\n",
+                    "AUTH" => b"235 2.7.0 Authentication successful\r\n",
+                    "MAIL" | "RCPT" | "RSET" | "NOOP" => b"250 2.0.0 OK\r\n",
+                    "DATA" => {
+                        writer.write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n")?;
+                        while read_line(&mut reader, &recorder)? != ".\r\n" {}
+                        b"250 2.0.0 Queued\r\n"
+                    }
+                    "QUIT" => {
+                        writer.write_all(b"221 2.0.0 Bye\r\n")?;
+                        return Ok(());
+                    }
+                    _ => b"502 5.5.2 Command not recognized\r\n",
+                };
+                writer.write_all(reply)?;
+            }
+        });
+        FakeSmtp { port, received }
+    }
+}
+
+/// Represents a message of `FakeImap`.
+struct FakeMessage {
+    uid: u32,
+    raw: Vec<u8>,
+    flags: Vec<String>,
+    mod_seq: u64,
+}
+
+/// Represents a mailbox of `FakeImap`, remembering when each expunged UID went away.
+struct FakeMailbox {
+    name: String,
+    messages: Vec<FakeMessage>,
+    expunged: Vec<(u32, u64)>,
+    next_uid: u32,
+    mod_seq: u64,
+}
+
+impl FakeMailbox {
+    fn new(name: &str, messages: Vec<Vec<u8>>) -> FakeMailbox {
+        let mut mailbox = FakeMailbox {
+            name: name.to_string(),
+            messages: Vec::new(),
+            expunged: Vec::new(),
+            next_uid: 1,
+            mod_seq: 1,
+        };
+        for raw in messages {
+            mailbox.append(raw);
+        }
+        mailbox
+    }
+
+    /// Adds a message with the next UID.
+    fn append(&mut self, raw: Vec<u8>) {
+        self.mod_seq += 1;
+        self.messages.push(FakeMessage {
+            uid: self.next_uid,
+            raw,
+            flags: Vec::new(),
+            mod_seq: self.mod_seq,
+        });
+        self.next_uid += 1;
+    }
+
+    /// Returns the message of a UID, changes to it counting as a new modification.
+    fn modify(&mut self, uid: u32) -> Option<&mut FakeMessage> {
+        self.mod_seq += 1;
+        let mod_seq = self.mod_seq;
+        let message = self.messages.iter_mut().find(|m| m.uid == uid)?;
+        message.mod_seq = mod_seq;
+        Some(message)
+    }
+
+    fn expunge(&mut self, uid: u32) {
+        self.mod_seq += 1;
+        self.messages.retain(|m| m.uid != uid);
+        self.expunged.push((uid, self.mod_seq));
+    }
+}
+
+/// Represents a fake IMAP server, accepting only the test account.
+///
+/// With CONDSTORE among its capabilities it keeps a mod-sequence per message, with QRESYNC
+/// it reports UIDs expunged since one with VANISHED once a client has enabled it.
+pub struct FakeImap {
+    pub port: u16,
+    pub received: Received,
+    mailboxes: Arc<Mutex<Vec<FakeMailbox>>>,
+}
+
+/// Parses an IMAP sequence set of UIDs, "*" isn't supported.
+fn parse_uid_set(set: &str) -> Vec<u32> {
+    set.split(',')
+        .flat_map(|range| match range.split_once(':') {
+            Some((lo, hi)) => (lo.parse().unwrap()..=hi.parse().unwrap()).collect(),
+            None => vec![range.parse().unwrap()],
+        })
+        .collect()
+}
+
+/// Returns the number following a word of a command's arguments, e.g. of "CHANGEDSINCE".
+fn number_after(args: &str, word: &str) -> Option<u64> {
+    let mut words = args.split([' ', '(', ')']);
+    words.find(|w| w.eq_ignore_ascii_case(word))?;
+    words.next()?.parse().ok()
+}
+
+/// Formats the data items of a FETCH response for a message.
+fn fetch_items(message: &FakeMessage, query: &str, condstore: bool) -> Vec<u8> {
+    let raw = &message.raw;
+    let header_end = raw
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .map(|i| i + 4)
+        .unwrap_or(raw.len());
+    let query = query.to_uppercase();
+
+    let mut items = format!("UID {}", message.uid).into_bytes();
+    if query.contains("FLAGS") {
+        items.extend(format!(" FLAGS ({})", message.flags.join(" ")).into_bytes());
+    }
+    if condstore && query.contains("CHANGEDSINCE") {
+        items.extend(format!(" MODSEQ ({})", message.mod_seq).into_bytes());
+    }
+    if query.contains("RFC822.SIZE") {
+        items.extend(format!(" RFC822.SIZE {}", raw.len()).into_bytes());
+    }
+    let literals: [(&str, &str, &[u8]); 3] = [
+        ("BODY.PEEK[HEADER]", "BODY[HEADER]", &raw[..header_end]),
+        ("BODY.PEEK[]", "BODY[]", raw),
+        ("RFC822", "RFC822", raw),
+    ];
+    for (asked, name, data) in literals {
+        let asked = query.split([' ', '(', ')']).any(|item| item == asked);
+        if asked {
+            items.extend(format!(" {} {{{}}}\r\n", name, data.len()).into_bytes());
+            items.extend_from_slice(data);
+        }
+    }
+    items
+}
+
+impl FakeImap {
+    /// Starts a server holding mailboxes of raw messages, UIDs counting from 1.
+    pub fn start(mailboxes: Vec<(&str, Vec<Vec<u8>>)>) -> FakeImap {
+        FakeImap::start_with("IMAP4rev1 UIDPLUS", mailboxes)
+    }
+
+    /// Starts a server advertising the given capabilities.
+    ///
+    /// `THREAD` is answered without any thread, as a server might for a search it can't
+    /// thread.
+    pub fn start_with(capabilities: &str, mailboxes: Vec<(&str, Vec<Vec<u8>>)>) -> FakeImap {
+        let capabilities = capabilities.to_string();
+        let condstore = capabilities
+            .split(' ')
+            .any(|c| c == "CONDSTORE" || c == "QRESYNC");
+        let mailboxes = mailboxes
+            .into_iter()
+            .map(|(name, messages)| FakeMailbox::new(name, messages))
+            .collect::<Vec<_>>();
+        let mailboxes = Arc::new(Mutex::new(mailboxes));
+        let shared = mailboxes.clone();
+        let received = Received::default();
+        let recorder = received.clone();
+        let port = serve(move |mut reader, mut writer| {
+            writer.write_all(
+                format!("* OK [CAPABILITY {}] fake ready\r\n", capabilities).as_bytes(),
+            )?;
+            let mut selected: Option<usize> = None;
+            let mut qresync = false;
+            loop {
+                let line = read_line(&mut reader, &recorder)?;
+                if line.is_empty() {
+                    return Ok(());
+                }
+                let line = line.trim_end();
+                let (tag, rest) = line.split_once(' ').unwrap_or((line, ""));
+                let (command, args) = rest.split_once(' ').unwrap_or((rest, ""));
+                let mut response = Vec::new();
+                let mut mailboxes = shared.lock().unwrap();
+                let status = match command.to_uppercase().as_str() {
+                    "CAPABILITY" => {
+                        response.extend(format!("* CAPABILITY {}\r\n", capabilities).into_bytes());
+                        "OK CAPABILITY completed"
+                    }
+                    "LOGIN" => match args == format!("\"{}\" \"{}\"", EMAIL_ADDR, PASSWORD) {
+                        true => "OK LOGIN completed",
+                        false => "NO [AUTHENTICATIONFAILED] Invalid credentials",
+                    },
+                    "ENABLE" => {
+                        let offered = capabilities.split(' ').any(|c| c == "QRESYNC");
+                        if offered && args.eq_ignore_ascii_case("QRESYNC") {
+                            qresync = true;
+                            response.extend_from_slice(b"* ENABLED QRESYNC\r\n");
+                        }
+                        "OK ENABLE completed"
+                    }
+                    "LIST" => {
+                        for mailbox in mailboxes.iter() {
+                            response.extend(
+                                format!("* LIST (\\HasNoChildren) \"/\" \"{}\"\r\n", mailbox.name)
+                                    .into_bytes(),
+                            );
+                        }
+                        "OK LIST completed"
+                    }
+                    "SELECT" | "EXAMINE" => {
+                        let name = args.trim_matches('"');
+                        match mailboxes.iter().position(|m| m.name == name) {
+                            Some(i) => {
+                                selected = Some(i);
+                                response.extend(
+                                    format!(
+                                        "* {} EXISTS\r\n* 0 RECENT\r\n* FLAGS (\\Seen \\Deleted)\r\n* OK [UIDVALIDITY {}] UIDs valid\r\n",
+                                        mailboxes[i].messages.len(),
+                                        UID_VALIDITY
+                                    )
+                                    .into_bytes(),
+                                );
+                                if condstore {
+                                    response.extend(
+                                        format!(
+                                            "* OK [HIGHESTMODSEQ {}] Highest\r\n",
+                                            mailboxes[i].mod_seq
+                                        )
+                                        .into_bytes(),
+                                    );
+                                }
+                                "OK [READ-WRITE] SELECT completed"
+                            }
+                            None => "NO Mailbox doesn't exist",
+                        }
+                    }
+                    "UID" => {
+                        let (sub, sub_args) = args.split_once(' ').unwrap_or((args, ""));
+                        let mailbox = &mut mailboxes[selected.unwrap_or(0)];
+                        match sub.to_uppercase().as_str() {
+                            "SEARCH" => {
+                                let uids = mailbox
+                                    .messages
+                                    .iter()
+                                    .map(|m| m.uid.to_string())
+                                    .collect::<Vec<_>>();
+                                response.extend(
+                                    format!("* SEARCH {}\r\n", uids.join(" ")).into_bytes(),
+                                );
+                                "OK SEARCH completed"
+                            }
+                            "FETCH" => {
+                                let (set, query) =
+                                    sub_args.split_once(' ').unwrap_or((sub_args, ""));
+                                let uids = parse_uid_set(set);
+                                let since = number_after(query, "CHANGEDSINCE");
+                                let vanished = query.to_uppercase().contains("VANISHED");
+                                if let (Some(since), true, true) = (since, vanished, qresync) {
+                                    let gone = mailbox
+                                        .expunged
+                                        .iter()
+                                        .filter(|(uid, at)| *at > since && uids.contains(uid))
+                                        .map(|(uid, _)| uid.to_string())
+                                        .collect::<Vec<_>>();
+                                    if !gone.is_empty() {
+                                        response.extend(
+                                            format!("* VANISHED (EARLIER) {}\r\n", gone.join(","))
+                                                .into_bytes(),
+                                        );
+                                    }
+                                }
+                                for (i, message) in mailbox.messages.iter().enumerate() {
+                                    let changed = since.is_none_or(|s| message.mod_seq > s);
+                                    if !uids.contains(&message.uid) || !changed {
+                                        continue;
+                                    }
+                                    response.extend(format!("* {} FETCH (", i + 1).into_bytes());
+                                    response.extend(fetch_items(message, query, condstore));
+                                    response.extend_from_slice(b")\r\n");
+                                }
+                                "OK FETCH completed"
+                            }
+                            // Only silent changes of flags are supported
+                            "STORE" => {
+                                let mut words = sub_args.splitn(3, ' ');
+                                let (set, item, list) =
+                                    (words.next().unwrap(), words.next().unwrap(), words.next());
+                                let flags = list
+                                    .unwrap_or_default()
+                                    .trim_matches(['(', ')'])
+                                    .split(' ')
+                                    .filter(|f| !f.is_empty())
+                                    .map(String::from)
+                                    .collect::<Vec<_>>();
+                                for uid in parse_uid_set(set) {
+                                    let Some(message) = mailbox.modify(uid) else {
+                                        continue;
+                                    };
+                                    match item.to_uppercase().as_str() {
+                                        "+FLAGS.SILENT" => message.flags.extend(flags.clone()),
+                                        "-FLAGS.SILENT" => {
+                                            message.flags.retain(|f| !flags.contains(f))
+                                        }
+                                        _ => message.flags.clone_from(&flags),
+                                    }
+                                    message.flags.sort();
+                                    message.flags.dedup();
+                                }
+                                "OK STORE completed"
+                            }
+                            "THREAD" => {
+                                response.extend_from_slice(b"* THREAD\r\n");
+                                "OK THREAD completed"
+                            }
+                            _ => "BAD Unsupported UID command",
+                        }
+                    }
+                    "NOOP" => "OK NOOP completed",
+                    // Reports messages delivered meanwhile to the selected mailbox
+                    "IDLE" => {
+                        let selected = selected.unwrap_or(0);
+                        let mut known = mailboxes[selected].messages.len();
+                        drop(mailboxes);
+                        writer.write_all(b"+ idling\r\n")?;
+                        reader
+                            .get_ref()
+                            .set_read_timeout(Some(Duration::from_millis(20)))?;
+                        loop {
+                            let count = shared.lock().unwrap()[selected].messages.len();
+                            if count > known {
+                                writer.write_all(format!("* {} EXISTS\r\n", count).as_bytes())?;
+                                known = count;
+                            }
+                            match read_line(&mut reader, &recorder) {
+                                Ok(line) if line.is_empty() => return Ok(()),
+                                Ok(line) if line.trim_end() == "DONE" => break,
+                                Ok(_) => {}
+                                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                                Err(e) if e.kind() == io::ErrorKind::TimedOut => {}
+                                Err(e) => return Err(e),
+                            }
+                        }
+                        reader.get_ref().set_read_timeout(None)?;
+                        "OK IDLE terminated"
+                    }
+                    "LOGOUT" => {
+                        response.extend_from_slice(b"* BYE fake logging out\r\n");
+                        writer.write_all(&response)?;
+                        writer.write_all(format!("{} OK LOGOUT completed\r\n", tag).as_bytes())?;
+                        return Ok(());
+                    }
+                    _ => "BAD Command not recognized",
+                };
+                response.extend(format!("{} {}\r\n", tag, status).into_bytes());
+                writer.write_all(&response)?;
+            }
+        });
+        FakeImap {
+            port,
+            received,
+            mailboxes,
+        }
+    }
+
+    /// Runs a change on a mailbox by its name.
+    fn change<T>(&self, mailbox: &str, change: impl FnOnce(&mut FakeMailbox) -> T) -> T {
+        let mut mailboxes = self.mailboxes.lock().unwrap();
+        change(mailboxes.iter_mut().find(|m| m.name == mailbox).unwrap())
+    }
+
+    /// Adds a message to a mailbox, a connection in IDLE on it is told so.
+    pub fn deliver(&self, mailbox: &str, raw: &[u8]) {
+        self.change(mailbox, |m| m.append(raw.to_vec()));
+    }
+
+    /// Replaces the flags of a message, as another client would.
+    pub fn set_flags(&self, mailbox: &str, uid: u32, flags: &[&str]) {
+        self.change(mailbox, |m| {
+            let message = m.modify(uid).unwrap();
+            message.flags = flags.iter().map(|f| f.to_string()).collect();
+            message.flags.sort();
+        });
+    }
+
+    /// Expunges a message, as another client would.
+    pub fn expunge(&self, mailbox: &str, uid: u32) {
+        self.change(mailbox, |m| m.expunge(uid));
+    }
+
+    /// Returns the flags of a message, in ASCII order.
+    pub fn flags(&self, mailbox: &str, uid: u32) -> Vec<String> {
+        self.change(mailbox, |m| {
+            let message = m.messages.iter().find(|message| message.uid == uid);
+            message.unwrap().flags.clone()
+        })
+    }
+}
+
+/// Represents a fake POP3 server with a fixed maildrop, accepting any login.
+///
+/// Messages deleted in a session are no longer listed in it, each connection starts over
+/// with the whole maildrop.
+pub struct FakePop3 {
+    pub port: u16,
+    pub received: Received,
+}
+
+impl FakePop3 {
+    pub fn start(messages: Vec<Vec<u8>>) -> FakePop3 {
+        let received = Received::default();
+        let recorder = received.clone();
+        let port = serve(move |mut reader, mut writer| {
+            writer.write_all(b"+OK fake POP3 ready\r\n")?;
+            let mut deleted = vec![false; messages.len()];
+            loop {
+                let line = read_line(&mut reader, &recorder)?;
+                if line.is_empty() {
+                    return Ok(());
+                }
+                let mut words = line.split_whitespace();
+                let verb = words.next().unwrap_or("").to_uppercase();
+                let message = words
+                    .next()
+                    .and_then(|n| n.parse::<usize>().ok())
+                    .filter(|&n| n >= 1 && n <= messages.len() && !deleted[n - 1])
+                    .map(|n| (n, &messages[n - 1]));
+                let reply = match (verb.as_str(), message) {
+                    ("USER" | "PASS" | "NOOP", _) => b"+OK\r\n".to_vec(),
+                    ("UIDL", _) => {
+                        let mut reply = b"+OK\r\n".to_vec();
+                        for n in (1..=messages.len()).filter(|&n| !deleted[n - 1]) {
+                            reply.extend(format!("{} uid-{}\r\n", n, n).into_bytes());
+                        }
+                        reply.extend_from_slice(b".\r\n");
+                        reply
+                    }
+                    ("RETR", Some((_, raw))) => [b"+OK\r\n", &raw[..], b".\r\n"].concat(),
+                    ("TOP", Some((_, raw))) => {
+                        let end = raw
+                            .windows(4)
+                            .position(|w| w == b"\r\n\r\n")
+                            .map_or(raw.len(), |i| i + 4);
+                        [b"+OK\r\n", &raw[..end], b".\r\n"].concat()
+                    }
+                    ("DELE", Some((n, _))) => {
+                        deleted[n - 1] = true;
+                        b"+OK\r\n".to_vec()
+                    }
+                    ("QUIT", _) => {
+                        writer.write_all(b"+OK bye\r\n")?;
+                        return Ok(());
+                    }
+                    ("RETR" | "TOP" | "DELE", None) => b"-ERR no such message\r\n".to_vec(),
+                    _ => b"-ERR command not recognized\r\n".to_vec(),
+                };
+                writer.write_all(&reply)?;
+            }
+        });
+        FakePop3 { port, received }
+    }
+}
+
+/// Id of the test account on `FakeJmap`.
+pub const JMAP_ACCOUNT_ID: &str = "a1";
+
+/// Represents a fake JMAP server over plain HTTP, accepting only the test account.
+///
+/// It has mailboxes "Inbox", "Projects", "Projects/2024" & "Sent", and emails "e1" & "e3"
+/// in the inbox. "/.well-known/jmap" redirects to the session, API responses are chunked.
+pub struct FakeJmap {
+    pub port: u16,
+    pub received: Received,
+}
+
+/// Represents an HTTP response of `FakeJmap`.
+struct HttpResponse {
+    status: &'static str,
+    location: Option<&'static str>,
+    body: Vec<u8>,
+    chunked: bool,
+}
+
+impl HttpResponse {
+    fn new(status: &'static str, body: Vec<u8>) -> HttpResponse {
+        HttpResponse {
+            status,
+            location: None,
+            body,
+            chunked: false,
+        }
+    }
+
+    /// Writes the response, its body chunked if asked to.
+    fn write(&self, writer: &mut TcpStream) -> io::Result<()> {
+        let mut head = format!(
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\n",
+            self.status
+        );
+        if let Some(location) = self.location {
+            head.push_str(&format!("Location: {}\r\n", location));
+        }
+        if !self.chunked {
+            head.push_str(&format!("Content-Length: {}\r\n\r\n", self.body.len()));
+            writer.write_all(head.as_bytes())?;
+            return writer.write_all(&self.body);
+        }
+        head.push_str("Transfer-Encoding: chunked\r\n\r\n");
+        let mut response = head.into_bytes();
+        for chunk in self.body.chunks(16) {
+            response.extend(format!("{:x};ext=1\r\n", chunk.len()).into_bytes());
+            response.extend_from_slice(chunk);
+            response.extend_from_slice(b"\r\n");
+        }
+        response.extend_from_slice(b"0\r\n\r\n");
+        writer.write_all(&response)
+    }
+}
+
+/// Answers a JMAP method call of `FakeJmap`.
+fn jmap_method(name: &str, arguments: &Value) -> Value {
+    match name {
+        "Mailbox/get" => json!({ "list": [
+            { "id": "m3", "name": "2024", "parentId": "m2", "role": null },
+            { "id": "m1", "name": "Inbox", "parentId": null, "role": "inbox" },
+            { "id": "m4", "name": "Sent", "parentId": null, "role": "sent" },
+            { "id": "m2", "name": "Projects", "parentId": null, "role": null }
+        ] }),
+        // Newest first, "e2" has been deleted since
+        "Email/query" => json!({ "ids": ["e3", "e2", "e1"] }),
+        "Email/get" => {
+            let email = |id: &str, subject: &str, keywords: Value| {
+                json!({
+                    "id": id, "blobId": format!("b-{}", id), "subject": subject,
+                    "from": [{ "name": "", "email": "bob@example.test" }],
+                    "messageId": [format!("{}@example.test", id)], "inReplyTo": null,
+                    "references": null, "keywords": keywords
+                })
+            };
+            let found = [
+                email("e1", "First", json!({ "$seen": true })),
+                email("e3", "Third", json!({})),
+            ];
+            let ids = arguments["ids"].as_array().cloned().unwrap_or_default();
+            let list = found
+                .into_iter()
+                .filter(|e| ids.contains(&e["id"]))
+                .collect::<Vec<_>>();
+            let not_found = ids
+                .into_iter()
+                .filter(|id| !list.iter().any(|e| &e["id"] == id))
+                .collect::<Vec<_>>();
+            json!({ "list": list, "notFound": not_found })
+        }
+        "Identity/get" => json!({ "list": [
+            { "id": "i0", "email": "other@example.test" },
+            { "id": "i1", "email": EMAIL_ADDR }
+        ] }),
+        "Email/import" => json!({ "created": { "message": { "id": "e9" } } }),
+        "EmailSubmission/set" => json!({ "created": { "submission": { "id": "s1" } } }),
+        "Email/set" => json!({ "updated": {} }),
+        _ => json!({ "type": "unknownMethod" }),
+    }
+}
+
+/// Answers an authorized request of `FakeJmap`.
+fn jmap_route(request_line: &str, body: &[u8]) -> HttpResponse {
+    let mut words = request_line.split_whitespace();
+    let (method, target) = (words.next().unwrap_or(""), words.next().unwrap_or(""));
+    let upload = format!("/jmap/upload/{}/", JMAP_ACCOUNT_ID);
+    match (method, target) {
+        ("GET", "/.well-known/jmap") => HttpResponse {
+            location: Some("/jmap/session"),
+            ..HttpResponse::new("307 Temporary Redirect", Vec::new())
+        },
+        ("GET", "/jmap/session") => {
+            let session = json!({
+                "apiUrl": "/jmap/api/",
+                "downloadUrl": "/jmap/download/{accountId}/{blobId}/{name}?type={type}",
+                "uploadUrl": "/jmap/upload/{accountId}/",
+                "primaryAccounts": { "urn:ietf:params:jmap:mail": JMAP_ACCOUNT_ID }
+            });
+            HttpResponse::new("200 OK", session.to_string().into_bytes())
+        }
+        ("POST", "/jmap/api/") => {
+            let request: Value = serde_json::from_slice(body).unwrap_or_default();
+            let calls = request["methodCalls"]
+                .as_array()
+                .cloned()
+                .unwrap_or_default();
+            let responses = calls
+                .iter()
+                .map(|call| {
+                    let arguments = jmap_method(call[0].as_str().unwrap_or(""), &call[1]);
+                    json!([call[0], arguments, call[2]])
+                })
+                .collect::<Vec<_>>();
+            let response = json!({ "methodResponses": responses, "sessionState": "0" });
+            HttpResponse {
+                chunked: true,
+                ..HttpResponse::new("200 OK", response.to_string().into_bytes())
+            }
+        }
+        ("POST", target) if target == upload => {
+            let uploaded = json!({
+                "accountId": JMAP_ACCOUNT_ID, "blobId": "b-upload", "size": body.len()
+            });
+            HttpResponse::new("201 Created", uploaded.to_string().into_bytes())
+        }
+        ("GET", target) if target.starts_with("/jmap/download/") => {
+            let message = b"From: bob@example.test\r\nSubject: First\r\n\r\nHello.\r\n";
+            HttpResponse::new("200 OK", message.to_vec())
+        }
+        _ => HttpResponse::new("404 Not Found", Vec::new()),
+    }
+}
+
+impl FakeJmap {
+    /// Starts a server, each connection carrying a request.
+    pub fn start() -> FakeJmap {
+        let received = Received::default();
+        let recorder = received.clone();
+        let port = serve(move |mut reader, mut writer| {
+            let request_line = read_line(&mut reader, &recorder)?;
+            let mut headers = Vec::new();
+            loop {
+                let line = read_line(&mut reader, &recorder)?;
+                if line.trim_end().is_empty() {
+                    break;
+                }
+                let (name, value) = line.split_once(':').unwrap_or((&line, ""));
+                headers.push((name.trim().to_lowercase(), value.trim().to_string()));
+            }
+            let header = |name: &str| {
+                headers
+                    .iter()
+                    .find(|(n, _)| n == name)
+                    .map(|(_, v)| v.clone())
+                    .unwrap_or_default()
+            };
+            let mut body = vec![0; header("content-length").parse().unwrap_or(0)];
+            reader.read_exact(&mut body)?;
+            recorder.record(&body);
+            recorder.record(b"\r\n");
+
+            let basic = STANDARD.encode(format!("{}:{}", EMAIL_ADDR, PASSWORD));
+            let response = match header("authorization") == format!("Basic {}", basic) {
+                true => jmap_route(&request_line, &body),
+                false => HttpResponse::new("401 Unauthorized", Vec::new()),
+            };
+            response.write(&mut writer)
+        });
+        FakeJmap { port, received }
+    }
+}
+
+/// Builds the test account, logging in with `password`, pointing at fake servers over
+/// plaintext.
+pub fn account(smtp: &FakeSmtp, imap: &FakeImap, password: &str) -> User {
+    let mut user = User::new(EMAIL_ADDR.parse().unwrap(), password.to_string());
+    user.smtp_domain = "127.0.0.1".to_string();
+    user.imap_domain = "127.0.0.1".to_string();
+    user.smtp_port = smtp.port;
+    user.imap_port = imap.port;
+    user.security = Security::Plaintext;
+    user
+}
+
+/// Forwards a TLS connection to a fake server in plaintext until either side closes it.
+fn forward(acceptor: &TlsAcceptor, stream: TcpStream, backend: u16) -> io::Result<()> {
+    let mut tls = acceptor.accept(stream).map_err(io::Error::other)?;
+    let mut plain = TcpStream::connect(("127.0.0.1", backend))?;
+    let interval = Some(Duration::from_millis(10));
+    tls.get_ref().set_read_timeout(interval)?;
+    plain.set_read_timeout(interval)?;
+    let idle = |e: &io::Error| {
+        matches!(
+            e.kind(),
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+        )
+    };
+    let mut buf = [0; 8192];
+    loop {
+        match tls.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => plain.write_all(&buf[..n])?,
+            Err(e) if idle(&e) => {}
+            Err(e) => return Err(e),
+        }
+        match plain.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => tls.write_all(&buf[..n])?,
+            Err(e) if idle(&e) => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Builds a `TlsAcceptor` with the certificate & key of a fixture, e.g. "self-signed" for
+/// "tests/fixtures/self-signed.pem" & ".key".
+pub fn tls_acceptor(fixture: &str) -> Arc<TlsAcceptor> {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+    let certificate = fs::read(format!("{}/{}.pem", dir, fixture)).unwrap();
+    let key = fs::read(format!("{}/{}.key", dir, fixture)).unwrap();
+    let identity = Identity::from_pkcs8(&certificate, &key).unwrap();
+    Arc::new(TlsAcceptor::new(identity).unwrap())
+}
+
+/// Serves a fake server over TLS on a free localhost port, with the certificate & key of a
+/// fixture.
+pub fn tls_front(fixture: &str, backend: u16) -> u16 {
+    let acceptor = tls_acceptor(fixture);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let acceptor = acceptor.clone();
+            thread::spawn(move || forward(&acceptor, stream, backend));
+        }
+    });
+    port
+}
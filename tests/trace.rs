@@ -0,0 +1,41 @@
+//! Tracing sessions to a file, in a test binary of its own as tracing is set up globally.
+
+mod support;
+
+use std::{env, fs, process, sync::Arc};
+
+use echo_unity_archivist::client;
+use echo_unity_archivist::trace::{self, Tracer};
+use support::{FakeImap, FakeSmtp, PASSWORD};
+
+#[test]
+fn trace_logs_sessions_with_credentials_redacted() {
+    let path = env::temp_dir().join(format!("eua-trace-{}.log", process::id()));
+    trace::init(&path, trace::DEFAULT_LEVEL).unwrap();
+
+    let smtp = FakeSmtp::start();
+    let imap = FakeImap::start(vec![("INBOX", Vec::new())]);
+    let mut user = support::account(&smtp, &imap, PASSWORD);
+    user.set_tap(Arc::new(Tracer));
+    let client = client::connect(user).unwrap();
+    client.logout().unwrap();
+    let mut user = support::account(&smtp, &imap, "wrong");
+    user.set_tap(Arc::new(Tracer));
+    assert!(client::connect(user).is_err());
+
+    let log = fs::read_to_string(&path).unwrap();
+    let _ = fs::remove_file(&path);
+    assert!(!log.contains(PASSWORD));
+    assert!(!log.contains("wrong"));
+    assert!(
+        log.lines().all(|l| l.starts_with("20")),
+        "untimed line in {}",
+        log
+    );
+    assert!(log.contains(" INFO ") && log.contains("connected to 127.0.0.1"));
+    assert!(log.contains("smtp#") && log.contains("C: AUTH PLAIN <redacted>"));
+    assert!(log.contains("S: 235 2.7.0 Authentication successful"));
+    assert!(log.contains("imap#") && log.contains("C: a1 LOGIN <redacted>"));
+    assert!(log.contains(" WARN ") && log.contains("IMAP login to 127.0.0.1"));
+    assert!(log.contains("No Response: [AUTHENTICATIONFAILED] Invalid credentials"));
+}
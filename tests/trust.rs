@@ -0,0 +1,224 @@
+//! Trusting servers over TLS by CA files, pinned fingerprints & invalid localhost
+//! certificates, against the fake servers of `support` behind TLS fronts.
+
+mod support;
+
+use std::{
+    env, process,
+    sync::{Mutex, MutexGuard},
+};
+
+use echo_unity_archivist::client;
+use echo_unity_archivist::doctor::{self, fingerprint, Service};
+use echo_unity_archivist::error;
+use echo_unity_archivist::trust::{self, TrustSettings};
+use echo_unity_archivist::user::{Security, User};
+use native_tls::Certificate;
+use support::{tls_front, FakeImap, FakeSmtp, EMAIL_ADDR, PASSWORD};
+
+/// Serializes the tests, which share the test account's trust settings.
+static ACCOUNT: Mutex<()> = Mutex::new(());
+
+/// Saves the test account's trust settings in a data directory of this process.
+///
+/// # Returns
+///
+/// A guard keeping other tests from changing the settings until it's dropped.
+fn trusting(settings: &TrustSettings) -> MutexGuard<'static, ()> {
+    let guard = ACCOUNT.lock().unwrap_or_else(|e| e.into_inner());
+    env::set_var(
+        "EUA_HOME",
+        env::temp_dir().join(format!("eua-trust-{}", process::id())),
+    );
+    trust::save_trust(EMAIL_ADDR, settings).unwrap();
+    guard
+}
+
+/// Returns the path of a fixture.
+fn fixture(name: &str) -> String {
+    format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+/// Returns the fingerprint of a fixture's certificate.
+fn fixture_fingerprint(name: &str) -> String {
+    let pem = std::fs::read(fixture(&format!("{}.pem", name))).unwrap();
+    fingerprint(&Certificate::from_pem(&pem).unwrap().to_der().unwrap())
+}
+
+/// Builds the test account reaching a fake IMAP server over TLS with a fixture's
+/// certificate.
+///
+/// # Returns
+///
+/// The `User` & the port of the TLS front.
+fn imap_user(imap: &FakeImap, fixture: &str) -> (User, u16) {
+    let port = tls_front(fixture, imap.port);
+    let mut user = User::new(EMAIL_ADDR.parse().unwrap(), PASSWORD.to_string());
+    user.imap_domain = "127.0.0.1".to_string();
+    user.imap_port = port;
+    user.security = Security::Tls;
+    (user, port)
+}
+
+#[test]
+fn untrusted_certificates_are_refused_by_default() {
+    let _guard = trusting(&TrustSettings::default());
+    let imap = FakeImap::start(vec![("INBOX", Vec::new())]);
+
+    for fixture in ["self-signed", "localhost"] {
+        let (user, _) = imap_user(&imap, fixture);
+        assert!(user.connect_imap().is_err(), "{} was trusted", fixture);
+    }
+    assert!(!imap.received.text().contains(PASSWORD));
+}
+
+#[test]
+fn ca_files_trust_an_internal_ca() {
+    let _guard = trusting(&TrustSettings {
+        ca_files: vec![fixture("ca.pem").into()],
+        ..Default::default()
+    });
+    let smtp = FakeSmtp::start();
+    let imap = FakeImap::start(vec![("INBOX", Vec::new())]);
+    let mut user = User::new(EMAIL_ADDR.parse().unwrap(), PASSWORD.to_string());
+    user.smtp_domain = "localhost".to_string();
+    user.imap_domain = "localhost".to_string();
+    user.smtp_port = tls_front("localhost", smtp.port);
+    user.imap_port = tls_front("localhost", imap.port);
+    user.security = Security::Tls;
+
+    let mut client = client::connect(user).unwrap();
+    let to = "bob@example.test".parse().unwrap();
+    let message = client
+        .user
+        .build_message(&to, "Over TLS", "Hello.".to_string());
+    client.send(&message).unwrap();
+    client.logout().unwrap();
+    assert!(smtp.received.text().contains("Subject: Over TLS"));
+
+    // The CA vouches for its own server only
+    let (user, _) = imap_user(&imap, "self-signed");
+    assert!(user.connect_imap().is_err());
+}
+
+#[test]
+fn invalid_certificates_are_accepted_only_from_localhost() {
+    let _guard = trusting(&TrustSettings {
+        accept_invalid_localhost: true,
+        ..Default::default()
+    });
+    let imap = FakeImap::start(vec![("INBOX", Vec::new())]);
+    let (user, _) = imap_user(&imap, "self-signed");
+    user.connect_imap().unwrap().logout().unwrap();
+
+    for domain in [
+        "localhost",
+        "LOCALHOST",
+        "mail.localhost",
+        "127.0.0.1",
+        "::1",
+        "[::1]",
+    ] {
+        assert!(trust::is_localhost(domain), "{}", domain);
+    }
+    for domain in ["imap.example.com", "localhost.example.com", "10.0.0.1"] {
+        assert!(!trust::is_localhost(domain), "{}", domain);
+    }
+}
+
+#[test]
+fn pinning_trusts_on_first_use_and_refuses_other_certificates() {
+    let _guard = trusting(&TrustSettings {
+        pinning: true,
+        ..Default::default()
+    });
+    let imap = FakeImap::start(vec![("INBOX", Vec::new())]);
+    let (user, port) = imap_user(&imap, "self-signed");
+    user.connect_imap().unwrap().logout().unwrap();
+
+    let host = format!("127.0.0.1:{}", port);
+    let settings = trust::load_trust(EMAIL_ADDR).unwrap();
+    assert_eq!(
+        settings.pins.get(&host),
+        Some(&fixture_fingerprint("self-signed"))
+    );
+    user.connect_imap().unwrap().logout().unwrap();
+
+    // Another certificate on the same server is refused, with the reason
+    let mut changed = settings.clone();
+    changed.pins.insert(host, fixture_fingerprint("localhost"));
+    trust::save_trust(EMAIL_ADDR, &changed).unwrap();
+    let error = user.connect_imap().err().unwrap();
+    assert!(
+        error::chain(&error).contains("isn't the pinned one"),
+        "{}",
+        error::chain(&error)
+    );
+    let logins = imap.received.lines();
+    assert_eq!(logins.iter().filter(|l| l.contains("LOGIN")).count(), 2);
+}
+
+#[test]
+fn smtp_is_pinned_before_logging_in() {
+    let _guard = trusting(&TrustSettings {
+        pinning: true,
+        ..Default::default()
+    });
+    let smtp = FakeSmtp::start();
+    let port = tls_front("self-signed", smtp.port);
+    let mut user = User::new(EMAIL_ADDR.parse().unwrap(), PASSWORD.to_string());
+    user.smtp_domain = "127.0.0.1".to_string();
+    user.smtp_port = port;
+    user.connect_smtp().unwrap();
+    let host = format!("127.0.0.1:{}", port);
+    let mut settings = trust::load_trust(EMAIL_ADDR).unwrap();
+    assert_eq!(
+        settings.pins.get(&host),
+        Some(&fixture_fingerprint("self-signed"))
+    );
+
+    settings.pins.insert(host, fixture_fingerprint("localhost"));
+    trust::save_trust(EMAIL_ADDR, &settings).unwrap();
+    let error = user.connect_smtp().err().unwrap();
+    assert!(error::chain(&error).contains("isn't the pinned one"));
+    let logins = smtp.received.lines();
+    assert_eq!(logins.iter().filter(|l| l.starts_with("AUTH")).count(), 1);
+}
+
+#[test]
+fn doctor_logs_in_without_pinning() {
+    let _guard = trusting(&TrustSettings {
+        ca_files: vec![fixture("ca.pem").into()],
+        pinning: true,
+        ..Default::default()
+    });
+    let imap = FakeImap::start(vec![("INBOX", Vec::new())]);
+    let (mut user, port) = imap_user(&imap, "localhost");
+    user.imap_domain = "localhost".to_string();
+
+    // Without a pin, the server is trusted by its chain
+    let report = doctor::examine(&user, Service::Imap);
+    assert_eq!(report.login, Some((port, Ok(()))));
+    assert!(trust::load_trust(EMAIL_ADDR).unwrap().pins.is_empty());
+
+    let (user, port) = imap_user(&imap, "self-signed");
+    let report = doctor::examine(&user, Service::Imap);
+    assert!(matches!(report.login, Some((p, Err(_))) if p == port));
+    assert!(trust::load_trust(EMAIL_ADDR).unwrap().pins.is_empty());
+}
+
+#[test]
+fn settings_survive_their_file_format() {
+    let mut settings = TrustSettings {
+        ca_files: vec!["/etc/corp/ca.pem".into(), "C:\\certs\\ca.der".into()],
+        pinning: true,
+        accept_invalid_localhost: true,
+        ..Default::default()
+    };
+    settings
+        .pins
+        .insert("imap.example.com:993".to_string(), "AB:CD".to_string());
+    assert_eq!(TrustSettings::parse(&settings.to_text()), settings);
+    assert!(!settings.is_default());
+    assert!(TrustSettings::parse("unknown\tline\n").is_default());
+}
@@ -0,0 +1,65 @@
+//! Setting up encryption over local data written in plain text.
+//!
+//! The vault unlocked for a session can't be locked again, so this file keeps to one test.
+
+use std::{env, fs, path::Path, process};
+
+use echo_unity_archivist::cache::Cache;
+use echo_unity_archivist::index::MailIndex;
+use echo_unity_archivist::read::Io;
+use echo_unity_archivist::{data_dir, get_prompts, vault, Lang};
+
+const MESSAGE: &[u8] = b"From: Bob <bob@example.test>\r\n\
+To: me@example.test\r\n\
+Subject: Quarterly figures\r\n\
+Message-ID: <1@example.test>\r\n\
+\r\n\
+The numbers are in.\r\n";
+
+/// Collects the files under a directory.
+fn files(dir: &Path) -> Vec<Vec<u8>> {
+    let mut found = Vec::new();
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        match path.is_dir() {
+            true => found.extend(files(&path)),
+            false => found.push(fs::read(&path).unwrap()),
+        }
+    }
+    found
+}
+
+#[test]
+fn setting_up_encryption_seals_the_plain_cache_and_index() {
+    let home = env::temp_dir().join(format!("eua-vault-{}", process::id()));
+    env::set_var("EUA_HOME", &home);
+    let cache = Cache::open(&"me@example.test".parse().unwrap()).unwrap();
+    let mut inbox = cache.mailbox("INBOX").unwrap();
+    inbox.uid_validity = 7;
+    inbox.insert(1, "S", MESSAGE).unwrap();
+    inbox.save().unwrap();
+    MailIndex::open().unwrap().index_cache(&cache).unwrap();
+    assert!(data_dir().join("index").is_dir());
+
+    let prompts = get_prompts(&Lang::EN);
+    let mut io = Io::new(&b"correct horse\ncorrect horse\n"[..], Vec::new());
+    vault::unlock_session(true, &mut io, prompts).unwrap();
+
+    let output = String::from_utf8(io.into_inner().1).unwrap();
+    assert!(output.contains(prompts.vault_sealed_existing));
+    let cached = files(&home.join("cache"));
+    assert!(!cached.is_empty());
+    assert!(cached
+        .iter()
+        .all(|file| !file.windows(9).any(|w| w == b"Quarterly") && !file.starts_with(b"mailbox")));
+    assert!(!data_dir().join("index").exists());
+
+    // Sealed data reads back the same, with the index still finding the message
+    let inbox = cache.mailbox("INBOX").unwrap();
+    assert_eq!(inbox.uid_validity, 7);
+    assert_eq!(inbox.read(1).unwrap(), MESSAGE);
+    let hits = MailIndex::open().unwrap().search("quarterly", 10).unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].uid, 1);
+    fs::remove_dir_all(&home).unwrap();
+}
@@ -0,0 +1,104 @@
+//! Watching a mailbox in IDLE for new messages on the fake server of `support`.
+
+mod support;
+
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::Duration,
+};
+
+use echo_unity_archivist::user::{Security, User};
+use echo_unity_archivist::watch::describe_new_mail;
+use echo_unity_archivist::{get_prompts, Lang};
+use support::{FakeImap, EMAIL_ADDR, PASSWORD};
+
+const FIRST: &[u8] = b"From: bob@example.test\r\nSubject: First\r\n\r\nHello.\r\n";
+
+const SECOND: &[u8] = b"From: ann@example.test\r\nSubject: Second\r\n\r\nHi again.\r\n";
+
+/// Builds the test account reaching a fake IMAP server over plaintext.
+fn user(imap: &FakeImap) -> User {
+    let mut user = User::new(EMAIL_ADDR.parse().unwrap(), PASSWORD.to_string());
+    user.imap_domain = "127.0.0.1".to_string();
+    user.imap_port = imap.port;
+    user.security = Security::Plaintext;
+    user
+}
+
+/// Counts the lines a fake server received that are exactly a command without its tag.
+fn count(imap: &FakeImap, command: &str) -> usize {
+    imap.received
+        .lines()
+        .iter()
+        .filter(|l| l.split_once(' ').map_or(l.as_str(), |(_, c)| c) == command)
+        .count()
+}
+
+/// Waits until a condition holds, for a few seconds at most.
+fn wait_until(condition: impl Fn() -> bool) {
+    for _ in 0..200 {
+        if condition() {
+            return;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    panic!("waited too long");
+}
+
+#[test]
+fn new_messages_are_noticed_and_idle_is_reissued() {
+    let imap = FakeImap::start(vec![("INBOX", vec![FIRST.to_vec()])]);
+    let stop = AtomicBool::new(false);
+    let prompts = get_prompts(&Lang::EN);
+
+    let noticed = thread::scope(|scope| {
+        let watcher = scope.spawn(|| {
+            let mut user = user(&imap);
+            let mut session = user.connect_imap().unwrap();
+            let mut noticed = Vec::new();
+            let interval = Duration::from_millis(300);
+            user.watch_mailbox(&mut session, "INBOX", &stop, interval, |_, _, mail| {
+                noticed.push((mail.message.uid, describe_new_mail(&mail, prompts)));
+                stop.store(true, Ordering::Relaxed);
+            })
+            .unwrap();
+            session.logout().unwrap();
+            noticed
+        });
+
+        // IDLE times out & is issued again before the message arrives
+        wait_until(|| count(&imap, "IDLE") >= 2);
+        imap.deliver("INBOX", SECOND);
+        watcher.join().unwrap()
+    });
+
+    assert_eq!(
+        noticed,
+        [(
+            2,
+            "✉ New message in \"INBOX\": ann@example.test - Second".to_string()
+        )]
+    );
+    // Every IDLE was ended before the session logged out
+    let lines = imap.received.lines();
+    let done = lines.iter().filter(|l| *l == "DONE").count();
+    assert_eq!(count(&imap, "IDLE"), done);
+    assert!(lines.last().unwrap().ends_with(" LOGOUT"));
+}
+
+#[test]
+fn a_stop_is_noticed_once_idle_times_out() {
+    let imap = FakeImap::start(vec![("INBOX", Vec::new())]);
+    let stop = AtomicBool::new(true);
+    let mut user = user(&imap);
+    let mut session = user.connect_imap().unwrap();
+    let interval = Duration::from_millis(100);
+    user.watch_mailbox(&mut session, "INBOX", &stop, interval, |_, _, _| {
+        panic!("no message was delivered")
+    })
+    .unwrap();
+    session.logout().unwrap();
+    assert_eq!(count(&imap, "IDLE"), 1);
+    assert!(imap.received.lines().contains(&"DONE".to_string()));
+}